@@ -0,0 +1,134 @@
+//! Many producer tasks pushing into a shared `AutoBatcher`, behind a mutex,
+//! versus into the sharded queue of a `Worker`, flushed by a single task,
+//! with a queue large enough or overflowing.
+
+use std::time::Duration;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use segment::message::{Message, SerializedBatch, Track, User};
+use segment::{
+    AutoBatcher, Batcher, Client, OverflowPolicy, SharedAutoBatcher, Worker, WorkerConfig,
+};
+
+const EVENTS_PER_PRODUCER: usize = 1_000;
+
+/// A client discarding the batches, so that only the hot path is measured.
+#[derive(Clone, Debug)]
+struct NullClient;
+
+impl Client for NullClient {
+    async fn send(&self, _write_key: String, _msg: Message) -> segment::Result<()> {
+        Ok(())
+    }
+
+    async fn send_batch(
+        &self,
+        _write_key: String,
+        _batch: &SerializedBatch,
+    ) -> segment::Result<()> {
+        Ok(())
+    }
+}
+
+fn track(i: usize) -> Track {
+    Track {
+        user: User::UserId {
+            user_id: format!("user-{}", i % 100),
+        },
+        event: "Benchmarked".to_owned(),
+        properties: serde_json::json!({ "i": i }),
+        ..Default::default()
+    }
+}
+
+fn auto_batcher() -> AutoBatcher<NullClient> {
+    AutoBatcher::new(NullClient, Batcher::new(None), "key".to_owned())
+}
+
+fn producers(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+
+    let mut group = c.benchmark_group("producers");
+    for producers in [1, 4, 16] {
+        group.throughput(Throughput::Elements(
+            (producers * EVENTS_PER_PRODUCER) as u64,
+        ));
+
+        group.bench_with_input(
+            BenchmarkId::new("shared_auto_batcher", producers),
+            &producers,
+            |b, &producers| {
+                b.to_async(&runtime).iter(|| async move {
+                    let shared = SharedAutoBatcher::new(auto_batcher());
+                    let tasks: Vec<_> = (0..producers)
+                        .map(|_| {
+                            let shared = shared.clone();
+                            tokio::spawn(async move {
+                                for i in 0..EVENTS_PER_PRODUCER {
+                                    shared.push(track(i)).await.unwrap();
+                                }
+                            })
+                        })
+                        .collect();
+                    for task in tasks {
+                        task.await.unwrap();
+                    }
+                    shared.flush().await.unwrap();
+                })
+            },
+        );
+
+        let workers = [
+            ("worker", WorkerConfig::default()),
+            // a queue too small for the producers, evicting the oldest
+            // messages of every producer
+            (
+                "worker_drop_oldest",
+                WorkerConfig {
+                    capacity: 64,
+                    overflow_policy: OverflowPolicy::DropOldest,
+                    ..Default::default()
+                },
+            ),
+        ];
+        for (name, config) in workers {
+            let config = WorkerConfig {
+                flush_interval: None,
+                ..config
+            };
+            group.bench_with_input(
+                BenchmarkId::new(name, producers),
+                &producers,
+                |b, &producers| {
+                    b.to_async(&runtime).iter(|| {
+                        let config = config.clone();
+                        async move {
+                            let worker = Worker::spawn(auto_batcher(), config);
+                            let tasks: Vec<_> = (0..producers)
+                                .map(|_| {
+                                    let handle = worker.handle();
+                                    tokio::spawn(async move {
+                                        for i in 0..EVENTS_PER_PRODUCER {
+                                            handle.push(track(i)).await.unwrap();
+                                        }
+                                    })
+                                })
+                                .collect();
+                            for task in tasks {
+                                task.await.unwrap();
+                            }
+                            assert!(worker.shutdown(Duration::from_secs(10)).await.is_empty());
+                        }
+                    })
+                },
+            );
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, producers);
+criterion_main!(benches);
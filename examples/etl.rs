@@ -31,7 +31,7 @@ async fn main() {
         // equivalent.
         if let Some(msg) = batcher.push(msg).unwrap() {
             client
-                .send(write_key.to_string(), batcher.into_message())
+                .send(write_key.to_string(), batcher.into_message().unwrap())
                 .await
                 .unwrap();
 
@@ -41,7 +41,7 @@ async fn main() {
     }
 
     client
-        .send(write_key.to_string(), batcher.into_message())
+        .send(write_key.to_string(), batcher.into_message().unwrap())
         .await
         .unwrap();
 }
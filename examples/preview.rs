@@ -0,0 +1,35 @@
+//! A command previewing the payload of a message, and which destinations
+//! would receive it.
+//!
+//! ```sh
+//! echo '{"type": "track", "userId": "user", "event": "Signed Up", "properties": {}}' \
+//!     | cargo run --example preview -- Amplitude Mixpanel
+//! ```
+
+use std::io::Read;
+
+use segment::message::BatchMessage;
+use segment::Batcher;
+
+fn main() {
+    let destinations: Vec<String> = std::env::args().skip(1).collect();
+
+    let mut input = String::new();
+    std::io::stdin()
+        .read_to_string(&mut input)
+        .expect("could not read the message");
+    let msg: BatchMessage = serde_json::from_str(&input).expect("invalid message");
+
+    let preview = Batcher::new(None)
+        .preview(msg, destinations.iter().map(String::as_str))
+        .expect("the message would be rejected");
+
+    match &preview.payload {
+        Some(payload) => println!("{}", serde_json::to_string_pretty(payload).unwrap()),
+        None => println!("the message would be dropped"),
+    }
+    for (destination, enabled) in &preview.destinations {
+        let status = if *enabled { "sent" } else { "skipped" };
+        println!("{destination}: {status}");
+    }
+}
@@ -0,0 +1,62 @@
+//! Derive macros of the [`segment`](https://docs.rs/segment) crate, use them
+//! through its `derive` feature.
+
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Error, Fields, LitStr};
+
+/// Implement `segment::TrackEvent` for a struct with named fields, named
+/// after the `#[track(event = "...")]` attribute.
+#[proc_macro_derive(TrackEvent, attributes(track))]
+pub fn derive_track_event(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    track_event(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+fn track_event(input: DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    // the properties of a `track` must be an object
+    match &input.data {
+        Data::Struct(data) if matches!(data.fields, Fields::Named(_)) => {}
+        _ => {
+            return Err(Error::new_spanned(
+                &input.ident,
+                "`TrackEvent` can only be derived for structs with named fields",
+            ))
+        }
+    }
+
+    let mut event = None;
+    for attr in input
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("track"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("event") {
+                event = Some(meta.value()?.parse::<LitStr>()?);
+                Ok(())
+            } else {
+                Err(meta.error("unsupported `track` attribute, expected `event`"))
+            }
+        })?;
+    }
+    let Some(event) = event else {
+        return Err(Error::new_spanned(
+            &input.ident,
+            "missing the name of the event, add `#[track(event = \"...\")]`",
+        ));
+    };
+    if event.value().is_empty() {
+        return Err(Error::new_spanned(event, "the name of the event is empty"));
+    }
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::segment::TrackEvent for #name #ty_generics #where_clause {
+            const EVENT: &'static str = #event;
+        }
+    })
+}
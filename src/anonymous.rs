@@ -0,0 +1,145 @@
+//! Stable anonymous IDs generated server-side.
+
+use std::fmt;
+use std::sync::Arc;
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::hooks::catch_panic;
+use crate::logging;
+use crate::message::{self, User};
+
+/// Derives stable anonymous users from a fingerprint of an incoming request,
+/// for pre-signup funnel tracking.
+///
+/// The fingerprint (a cookie, a device ID, a combination of headers...) is
+/// extracted by a user-provided function, and hashed with an HMAC-SHA256 keyed
+/// by a secret salt, formatted as a UUID like [`User::anonymous_from_hash`]
+/// does. Without the salt, the IDs can't be recomputed from candidate
+/// fingerprints. The same fingerprint always yields the same
+/// anonymous ID, which therefore never needs to be persisted, and the raw
+/// fingerprint is never sent to Segment.
+///
+/// ```
+/// use std::collections::HashMap;
+/// use segment::AnonymousIds;
+/// use segment::message::User;
+///
+/// // pretend these are the cookies of an HTTP request
+/// type Cookies = HashMap<&'static str, &'static str>;
+///
+/// let ids = AnonymousIds::new("some secret salt", |cookies: &Cookies| {
+///     cookies.get("session").map(|session| session.to_string())
+/// });
+///
+/// let cookies = Cookies::from([("session", "f00")]);
+/// let anonymous = ids.user(&cookies).unwrap();
+/// assert_eq!(Some(anonymous), ids.user(&cookies));
+///
+/// // once the visitor signs up, both IDs can be sent to link the funnel
+/// let user = ids.identified(&cookies, "user-42");
+/// assert!(matches!(user, User::Both { .. }));
+/// ```
+pub struct AnonymousIds<T: ?Sized> {
+    salt: String,
+    fingerprint: Fingerprint<T>,
+}
+
+type Fingerprint<T> = Arc<dyn Fn(&T) -> Option<String> + Send + Sync>;
+
+impl<T: ?Sized> AnonymousIds<T> {
+    /// Construct a generator hashing the fingerprints returned by
+    /// `fingerprint` with `salt`.
+    ///
+    /// The salt must stay the same across restarts and replicas for the IDs
+    /// to be stable.
+    pub fn new(
+        salt: impl Into<String>,
+        fingerprint: impl Fn(&T) -> Option<String> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            salt: salt.into(),
+            fingerprint: Arc::new(fingerprint),
+        }
+    }
+
+    /// Returns the anonymous ID of `source`, or `None` if it has no
+    /// fingerprint.
+    ///
+    /// A fingerprint function which panics is logged, and the source is
+    /// considered without a fingerprint.
+    pub fn anonymous_id(&self, source: &T) -> Option<String> {
+        let fingerprint = match catch_panic(|| (self.fingerprint)(source)) {
+            Ok(fingerprint) => fingerprint?,
+            Err(reason) => {
+                logging::warn!(%reason, "anonymous ID fingerprint skipped");
+                return None;
+            }
+        };
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.salt.as_bytes())
+            .expect("HMAC accepts keys of any size");
+        mac.update(fingerprint.as_bytes());
+        Some(message::uuid_from_digest(&mac.finalize().into_bytes()))
+    }
+
+    /// Returns the anonymous user of `source`, or `None` if it has no
+    /// fingerprint.
+    pub fn user(&self, source: &T) -> Option<User> {
+        self.anonymous_id(source)
+            .map(|anonymous_id| User::AnonymousId { anonymous_id })
+    }
+
+    /// Returns a user identified by both `user_id` and the anonymous ID of
+    /// `source`, to link the anonymous activity to a signed up user. Falls
+    /// back to the user ID alone if `source` has no fingerprint.
+    pub fn identified(&self, source: &T, user_id: impl Into<String>) -> User {
+        let user_id = user_id.into();
+        match self.anonymous_id(source) {
+            Some(anonymous_id) => User::Both {
+                user_id,
+                anonymous_id,
+            },
+            None => User::UserId { user_id },
+        }
+    }
+}
+
+impl<T: ?Sized> Clone for AnonymousIds<T> {
+    fn clone(&self) -> Self {
+        Self {
+            salt: self.salt.clone(),
+            fingerprint: self.fingerprint.clone(),
+        }
+    }
+}
+
+impl<T: ?Sized> fmt::Debug for AnonymousIds<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AnonymousIds").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_salted_ids() {
+        let ids = AnonymousIds::new("salt", |fingerprint: &str| Some(fingerprint.to_owned()));
+        let id = ids.anonymous_id("f00").unwrap();
+        assert_eq!(
+            Some(id.as_str()),
+            ids.clone().anonymous_id("f00").as_deref()
+        );
+        assert_eq!(uuid::Uuid::parse_str(&id).unwrap().get_version_num(), 8);
+
+        let other = AnonymousIds::new("other salt", |fingerprint: &str| {
+            Some(fingerprint.to_owned())
+        });
+        assert_ne!(Some(id), other.anonymous_id("f00"));
+
+        let buggy = AnonymousIds::new("salt", |_: &str| -> Option<String> { panic!("buggy") });
+        assert_eq!(buggy.anonymous_id("f00"), None);
+    }
+}
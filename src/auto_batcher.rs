@@ -1,14 +1,44 @@
 //! Utilities for batching up messages.
 //! When a batch is full it is automatically sent over the network
 
-use serde_json::Map;
+use std::num::NonZeroUsize;
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
+use serde_json::value::RawValue;
+use serde_json::Value;
+use time::OffsetDateTime;
+use tokio::sync::{broadcast, OwnedSemaphorePermit, Semaphore};
+#[cfg(feature = "tracing")]
+use tracing::field::Empty;
+#[cfg(feature = "tracing")]
+use tracing::Instrument;
+
+#[cfg(feature = "profiling")]
+use crate::profiling::{self, Phase, ProfileSink, Profiler};
+#[cfg(feature = "metrics")]
+use crate::telemetry;
 use crate::{
-    batcher::Batcher,
+    batcher::{Admission, Batcher},
+    builder::AutoBatcherBuilder,
     client::Client,
-    errors::Result,
+    delivery::{
+        DeliveryEvent, DeliveryObserver, DeliveryOutcome, Observer, DELIVERY_CHANNEL_CAPACITY,
+    },
+    diagnostics::{self, Diagnostics, MessageMetadata},
+    errors::{Error, Errors, Result, UndeliveredBatch},
+    geoip::{GeoEnricher, GeoResolver},
     http::HttpClient,
-    message::{Batch, BatchMessage, Message},
+    logging,
+    message::{BatchMessage, SerializedBatch},
+    prepared::PreparedBatch,
+    queue::OverflowPolicy,
+    rate_limit::RateLimiter,
+    retry::{RetryBudget, RetryPolicy},
+    runtime::{Instant, Runtime, SharedRuntime},
+    stats::{Counters, Stats},
 };
 
 /// A batcher can accept messages into an internal buffer, and report when
@@ -44,14 +74,215 @@ use crate::{
 ///
 /// If this delay is a concern, it is recommended that you periodically flush
 /// the batcher on your own by calling [Self::flush].
+///
+/// If Segment throttles your source, a [`RateLimiter`] can be configured with
+/// [Self::set_rate_limiter] to delay the flushes instead of getting rejected.
+///
+/// # Background uploads
+///
+/// When the buffer is full, [Self::push] hands the full batch over to an
+/// upload running in the background and immediately starts filling a fresh
+/// buffer, so pushing does not wait on the network. The errors of the
+/// background uploads are returned by the next call to [Self::flush], which
+/// waits for all of them to complete. The uploads and the timers run on the
+/// [`Runtime`] of the batcher, the tokio runtime it is used from by default,
+/// see [Self::set_runtime]. Where the runtime can't spawn the uploads, for
+/// instance outside of a tokio runtime, the batches are uploaded in the
+/// foreground instead.
+///
+/// # Ordering
+///
+/// By default a single batch is uploaded at a time, [Self::push] waits for the
+/// previous upload to complete before handing over the next batch: batches
+/// reach Segment in the order they were filled. High-volume producers can
+/// allow several uploads to run concurrently with
+/// [Self::set_max_concurrent_flushes], in which case the batches may reach
+/// Segment in any order. Segment orders events by their `timestamp`, so this
+/// only matters if you rely on the receive time.
+///
+/// # Overflow
+///
+/// When the buffer is full while all the upload slots are busy, [Self::push]
+/// waits for an upload to complete. Another [`OverflowPolicy`] can be
+/// configured with [Self::set_overflow_policy].
+///
+/// # Retries and shutdown
+///
+/// The uploads failing with a transient error can be retried according to a
+/// [`RetryPolicy`], see [Self::set_retry_policy]; they are not by default.
+/// Before exiting, call [Self::shutdown] to send the buffered messages within
+/// a deadline and get back the ones that could not be delivered.
+///
+/// A clone of an `AutoBatcher` shares its configuration, its upload slots
+/// and its counters, but starts with an empty buffer: each buffered message
+/// belongs to a single clone.
+///
+/// Dropping a clone while it still buffers messages logs a warning with the
+/// number of lost messages. With the `flush-on-drop` feature, they are
+/// instead sent by a best-effort background upload, if the batcher is
+/// dropped where its [`Runtime`] can spawn it.
+#[derive(Debug)]
+pub struct AutoBatcher<C = HttpClient> {
+    pub(crate) client: C,
+    pub(crate) batcher: Batcher,
+    pub(crate) key: String,
+    rate_limiter: Option<RateLimiter>,
+    retry_policy: RetryPolicy,
+    retry_budget: Option<RetryBudget>,
+    deliveries: Option<broadcast::Sender<DeliveryEvent>>,
+    observer: Option<Observer>,
+    in_flight: InFlight,
+    geo: Option<GeoEnricher>,
+    overflow_policy: OverflowPolicy,
+    counters: Arc<Counters>,
+    pub(crate) runtime: SharedRuntime,
+    #[cfg(feature = "profiling")]
+    profiler: Option<Profiler>,
+    /// Handles the messages still buffered by a dropped clone, set where the
+    /// client is known to be a [`Client`].
+    on_drop: fn(&mut AutoBatcher<C>),
+}
+
+/// Bookkeeping of the uploads running in the background, shared by the
+/// clones of a batcher and by the batchers of a
+/// [`MultiSourceBatcher`](crate::MultiSourceBatcher).
 #[derive(Clone, Debug)]
-pub struct AutoBatcher {
-    client: HttpClient,
-    batcher: Batcher,
-    key: String,
+struct InFlight {
+    permits: Arc<Semaphore>,
+    limits: Arc<Mutex<Limits>>,
+    failures: Arc<Mutex<Failures>>,
+}
+
+/// The number of upload slots.
+#[derive(Debug)]
+struct Limits {
+    max: u32,
+    /// The permits still to forget after the limit was lowered while uploads
+    /// were holding them.
+    excess: u32,
+}
+
+/// The background uploads which failed since the last flush.
+#[derive(Debug, Default)]
+struct Failures {
+    /// The batches which could not be delivered, with their last error.
+    batches: Vec<(Error, SerializedBatch)>,
+}
+
+impl InFlight {
+    fn new(max: NonZeroUsize) -> Self {
+        let max = Self::limit(max);
+        Self {
+            permits: Arc::new(Semaphore::new(max as usize)),
+            limits: Arc::new(Mutex::new(Limits { max, excess: 0 })),
+            failures: Arc::default(),
+        }
+    }
+
+    fn limit(max: NonZeroUsize) -> u32 {
+        u32::try_from(max.get().min(Semaphore::MAX_PERMITS)).unwrap_or(u32::MAX)
+    }
+
+    /// Returns the number of upload slots.
+    fn max(&self) -> u32 {
+        self.limits.lock().unwrap().max
+    }
+
+    /// Change the number of upload slots, without waiting for the running
+    /// uploads.
+    fn resize(&self, max: NonZeroUsize) {
+        let max = Self::limit(max);
+        let mut limits = self.limits.lock().unwrap();
+        let total = limits.max + limits.excess;
+        if max >= total {
+            self.permits.add_permits((max - total) as usize);
+            limits.excess = 0;
+        } else {
+            limits.excess = total - max;
+            limits.excess -= self.permits.forget_permits(limits.excess as usize) as u32;
+        }
+        limits.max = max;
+    }
+
+    /// Forget the excess permits once the uploads holding them complete.
+    async fn settle(&self) {
+        let excess = self.limits.lock().unwrap().excess;
+        if excess == 0 {
+            return;
+        }
+        let permits = self
+            .permits
+            .acquire_many(excess)
+            .await
+            .expect("the semaphore is never closed");
+        permits.forget();
+        // the limit may have changed meanwhile
+        let mut limits = self.limits.lock().unwrap();
+        let forgotten = excess.min(limits.excess);
+        limits.excess -= forgotten;
+        self.permits.add_permits((excess - forgotten) as usize);
+    }
+
+    /// Forget the excess permits which are available, without waiting.
+    /// Returns whether there are no excess permits anymore.
+    fn try_settle(&self) -> bool {
+        let mut limits = self.limits.lock().unwrap();
+        limits.excess -= self.permits.forget_permits(limits.excess as usize) as u32;
+        limits.excess == 0
+    }
+
+    /// Returns the number of uploads running.
+    fn running(&self) -> u32 {
+        let limits = self.limits.lock().unwrap();
+        (limits.max + limits.excess).saturating_sub(self.permits.available_permits() as u32)
+    }
+
+    /// Wait for all the uploads to complete.
+    async fn wait(&self) {
+        self.settle().await;
+        let _all = self
+            .permits
+            .acquire_many(self.max())
+            .await
+            .expect("the semaphore is never closed");
+    }
+
+    /// Take the failures recorded since the last call, as an
+    /// [`Error::Undelivered`].
+    fn take_failures(&self) -> Option<Error> {
+        let failures = std::mem::take(&mut *self.failures.lock().unwrap());
+        (!failures.batches.is_empty()).then(|| undelivered(failures.batches))
+    }
+}
+
+/// Returns an [`Error::Undelivered`] with the messages of the `failed`
+/// batches.
+pub(crate) fn undelivered(failed: Vec<(Error, SerializedBatch)>) -> Error {
+    Error::Undelivered {
+        batches: failed
+            .into_iter()
+            .map(|(error, batch)| UndeliveredBatch {
+                error,
+                messages: batch
+                    .messages()
+                    .expect("the batcher only holds serialized messages"),
+            })
+            .collect(),
+    }
 }
 
 impl AutoBatcher {
+    /// Returns a builder checking at compile time that the client, the write
+    /// key and the batcher are provided, see [`AutoBatcherBuilder`].
+    pub fn builder() -> AutoBatcherBuilder {
+        AutoBatcherBuilder::new()
+    }
+}
+
+impl<C> AutoBatcher<C>
+where
+    C: Client + Clone + Send + Sync + 'static,
+{
     /// Construct a new, empty batcher.
     ///
     /// ```
@@ -61,14 +292,173 @@ impl AutoBatcher {
     /// let batcher = Batcher::new(None);
     /// let mut batcher = AutoBatcher::new(client, batcher, "your_write_key".to_string());
     /// ```
-    pub fn new(client: HttpClient, batcher: Batcher, key: String) -> Self {
+    pub fn new(client: C, batcher: Batcher, key: String) -> Self {
         Self {
             batcher,
             client,
             key,
+            rate_limiter: None,
+            retry_policy: RetryPolicy::default(),
+            retry_budget: None,
+            deliveries: None,
+            observer: None,
+            in_flight: InFlight::new(NonZeroUsize::MIN),
+            geo: None,
+            overflow_policy: OverflowPolicy::Block,
+            counters: Arc::default(),
+            runtime: SharedRuntime::default(),
+            #[cfg(feature = "profiling")]
+            profiler: None,
+            on_drop: Self::drop_buffered,
         }
     }
 
+    /// Allow up to `max` batches to be uploaded concurrently, instead of one.
+    ///
+    /// [Self::push] only waits when `max` uploads are already running. Batches
+    /// may then reach Segment in any order, see the [ordering](Self#ordering)
+    /// section.
+    pub fn set_max_concurrent_flushes(&mut self, max: NonZeroUsize) {
+        self.in_flight.resize(max);
+    }
+
+    /// Send the batches flushed from now on with the write `key`, for
+    /// instance to rotate the key without recreating the batcher.
+    ///
+    /// The buffered messages are sent with the new key, the uploads already
+    /// running keep the previous one.
+    pub fn set_write_key(&mut self, key: String) {
+        self.key = key;
+    }
+
+    /// Throttle the flushes with the given [`RateLimiter`].
+    ///
+    /// Flushing will wait until the limiter allows the batch to be sent. The
+    /// clones of this batcher share the limiter.
+    pub fn set_rate_limiter(&mut self, rate_limiter: RateLimiter) {
+        self.rate_limiter = Some(rate_limiter);
+    }
+
+    /// Choose how the failed uploads are retried, instead of
+    /// [`RetryPolicy::default`].
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.retry_policy = policy;
+    }
+
+    /// Limit the retries with `budget`, which may be shared with other
+    /// batchers, see [`RetryBudget`].
+    pub fn set_retry_budget(&mut self, budget: RetryBudget) {
+        self.retry_budget = Some(budget);
+    }
+
+    /// Choose what [Self::push] does when the buffer is full while all the
+    /// upload slots are busy.
+    pub fn set_overflow_policy(&mut self, policy: OverflowPolicy) {
+        self.overflow_policy = policy;
+    }
+
+    /// Run the background uploads and the timers of the retries, of the
+    /// [`RateLimiter`] and of the shutdown deadlines on `runtime`, instead of
+    /// the current tokio runtime, see [`Runtime`].
+    pub fn set_runtime(&mut self, runtime: impl Runtime + 'static) {
+        self.runtime = SharedRuntime::new(runtime);
+    }
+
+    /// Returns a snapshot of the counters of this batcher.
+    pub fn stats(&self) -> Stats {
+        self.counters.snapshot()
+    }
+
+    pub(crate) fn counters(&self) -> Arc<Counters> {
+        self.counters.clone()
+    }
+
+    /// Returns a redacted snapshot of the configuration and state of this
+    /// batcher, see [`Diagnostics`].
+    pub fn diagnostics(&self) -> Diagnostics {
+        let failures = self.in_flight.failures.lock().unwrap();
+        Diagnostics {
+            generated_at: OffsetDateTime::now_utc(),
+            version: env!("CARGO_PKG_VERSION"),
+            write_key: diagnostics::redact(&self.key),
+            batch_limits: self.batcher.limits,
+            overflow_policy: self.overflow_policy,
+            retry_policy: self.retry_policy.clone(),
+            retry_budget_balance: self.retry_budget.as_ref().map(RetryBudget::balance),
+            max_concurrent_flushes: self.in_flight.max(),
+            stats: self.stats(),
+            uploads_in_flight: self.in_flight.running(),
+            undelivered_batches: failures.batches.len(),
+            last_error: failures.batches.last().map(|(error, _)| error.to_string()),
+            buffered: self.batcher.len(),
+            buffered_bytes: self.batcher.size_bytes(),
+            sample: self
+                .batcher
+                .iter()
+                .take(diagnostics::SAMPLE_SIZE)
+                .filter_map(MessageMetadata::of)
+                .collect(),
+        }
+    }
+
+    /// Write the [Self::diagnostics] to `path` as JSON, to attach it to a bug
+    /// report about delivery problems.
+    ///
+    /// ```no_run
+    /// use segment::{AutoBatcher, Batcher, HttpClient};
+    ///
+    /// let client = HttpClient::default();
+    /// let batcher = AutoBatcher::new(client, Batcher::new(None), "your_write_key".to_string());
+    /// batcher.dump_diagnostics("segment-diagnostics.json").unwrap();
+    /// ```
+    pub fn dump_diagnostics(&self, path: impl AsRef<Path>) -> Result<()> {
+        let json = serde_json::to_vec_pretty(&self.diagnostics())?;
+        std::fs::write(path, json)?;
+        Ok(())
+    }
+
+    /// Add the location of `context.ip` to the `context.location` of every
+    /// pushed message, see [`GeoResolver`].
+    pub fn set_geo_resolver(&mut self, resolver: impl GeoResolver + 'static) {
+        self.geo = Some(GeoEnricher(Arc::new(resolver)));
+    }
+
+    /// Report the duration of the phases of the pipeline to `sink`, see the
+    /// [`profiling`](crate::profiling) module.
+    #[cfg(feature = "profiling")]
+    pub fn set_profiler(&mut self, sink: impl ProfileSink + 'static) {
+        self.profiler = Some(Profiler(Arc::new(sink)));
+    }
+
+    /// Subscribe to the [`DeliveryEvent`]s emitted after each upload attempt.
+    ///
+    /// Events are only emitted once there is at least one subscriber. A
+    /// subscriber lagging too far behind misses the oldest events, see
+    /// [`broadcast::Receiver::recv`].
+    ///
+    /// ```
+    /// use segment::{AutoBatcher, Batcher, HttpClient};
+    ///
+    /// let client = HttpClient::default();
+    /// let batcher = Batcher::new(None);
+    /// let mut batcher = AutoBatcher::new(client, batcher, "your_write_key".to_string());
+    ///
+    /// let mut deliveries = batcher.subscribe();
+    /// // while let Ok(event) = deliveries.recv().await { ... }
+    /// ```
+    pub fn subscribe(&mut self) -> broadcast::Receiver<DeliveryEvent> {
+        self.deliveries
+            .get_or_insert_with(|| broadcast::channel(DELIVERY_CHANNEL_CAPACITY).0)
+            .subscribe()
+    }
+
+    /// Call `observer` with the `messageId`s of each batch once it is
+    /// delivered, or once it could not be delivered even after the retries,
+    /// see [`DeliveryObserver`].
+    pub fn set_delivery_observer(&mut self, observer: impl DeliveryObserver + 'static) {
+        self.observer = Some(Observer(Arc::new(observer)));
+    }
+
     /// Returns the length of the buffer, the number of messages in the batch buffer.
     #[inline]
     pub fn len(&self) -> usize {
@@ -81,11 +471,20 @@ impl AutoBatcher {
         self.batcher.is_empty()
     }
 
+    /// Iterate over the messages buffered in the current batch, which were
+    /// not sent yet, see [Batcher::iter].
+    pub fn buffered(&self) -> impl ExactSizeIterator<Item = &RawValue> + '_ {
+        self.batcher.iter()
+    }
+
     /// Push a message into the batcher.
-    /// If the batcher is full, send it and create a new batcher with the message.
+    /// If the batcher is full, send it in the background and create a new
+    /// batcher with the message.
     ///
     /// Returns an error if the message is too large to be sent to Segment's
-    /// API.
+    /// API, or if the buffer is full with [`OverflowPolicy::Error`]. Errors
+    /// happening while sending the batch are returned by the next call to
+    /// [Self::flush].
     ///
     /// ```
     /// use serde_json::json;
@@ -105,22 +504,280 @@ impl AutoBatcher {
     ///
     /// batcher.push(msg); // .await
     /// ```
-    #[tracing::instrument(skip_all)]
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
     pub async fn push(&mut self, msg: impl Into<BatchMessage>) -> Result<()> {
-        if let Some(msg) = self.batcher.push(msg)? {
-            self.flush().await?;
-            // this can't return None: the batcher is empty and if the message is
-            // larger than the max size of the batcher it's supposed to throw an error
-            self.batcher.push(msg)?;
+        self.push_one(msg.into()).await
+    }
+
+    /// Push several messages into the batcher, sending the batches as they
+    /// fill up.
+    ///
+    /// All the messages are pushed even if some of them fail, see
+    /// [Self::push]. The first error is returned, except for the batches
+    /// which could not be delivered, which are all merged into a single
+    /// [`Error::Undelivered`] if no other error occurred.
+    ///
+    /// ```
+    /// use segment::{AutoBatcher, Batcher, HttpClient};
+    /// use segment::message::{Track, User};
+    ///
+    /// let client = HttpClient::default();
+    /// let mut batcher = AutoBatcher::new(client, Batcher::new(None), "your_write_key".to_string());
+    ///
+    /// let msgs = (0..100).map(|i| Track {
+    ///     user: User::UserId { user_id: format!("user-{i}") },
+    ///     event: "Example".to_owned(),
+    ///     ..Default::default()
+    /// });
+    /// batcher.push_many(msgs); // .await
+    /// ```
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    pub async fn push_many<I>(&mut self, msgs: I) -> Result<()>
+    where
+        I: IntoIterator,
+        I::Item: Into<BatchMessage>,
+    {
+        let mut errors = Errors::default();
+        for msg in msgs {
+            if let Err(err) = self.push_one(msg.into()).await {
+                errors.push(err);
+            }
         }
+        errors.into_result()
+    }
+
+    /// Push a message into the batcher, with its properties (or traits)
+    /// computed by `properties` only if the message is not dropped, see
+    /// [Batcher::push_with] and [Self::push].
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    pub async fn push_with<F>(&mut self, msg: impl Into<BatchMessage>, properties: F) -> Result<()>
+    where
+        F: FnOnce() -> Value + Send,
+    {
+        self.push_one_with(msg.into(), Some(properties)).await
+    }
 
+    /// Enqueue again the messages returned by an [`Error::Undelivered`], to
+    /// be sent with the next batches.
+    ///
+    /// The messages were processed when they were first pushed, so they are
+    /// enqueued as is: unlike with [Self::push_many], the enrichers, the
+    /// hashing of the user IDs, the filters and the other stages of the
+    /// batcher don't run on them again. All the messages are enqueued even if
+    /// some of them fail, the first error is returned.
+    ///
+    /// ```
+    /// use segment::{AutoBatcher, Batcher, Error, HttpClient};
+    ///
+    /// # async fn run() -> segment::Result<()> {
+    /// let client = HttpClient::default();
+    /// let mut batcher = AutoBatcher::new(client, Batcher::new(None), "your_write_key".to_string());
+    ///
+    /// if let Err(Error::Undelivered { batches }) = batcher.flush().await {
+    ///     for batch in batches {
+    ///         batcher.requeue(batch.messages).await?;
+    ///     }
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    pub async fn requeue<I>(&mut self, msgs: I) -> Result<()>
+    where
+        I: IntoIterator<Item = BatchMessage>,
+    {
+        let mut result = Ok(());
+        for msg in msgs {
+            let requeued = match serde_json::value::to_raw_value(&msg) {
+                Ok(msg) => match self.batcher.check_size(&msg) {
+                    // already counted by the stages when first enqueued
+                    Ok(()) => self
+                        .enqueue(msg, Admission::default(), Self::make_room)
+                        .await
+                        .map(drop),
+                    Err(err) => Err(err),
+                },
+                Err(err) => Err(err.into()),
+            };
+            if let Err(err) = requeued {
+                if result.is_ok() {
+                    result = Err(err);
+                }
+            }
+        }
+        result
+    }
+
+    async fn push_one(&mut self, msg: BatchMessage) -> Result<()> {
+        self.push_one_with(msg, None::<fn() -> Value>).await
+    }
+
+    /// Process and serialize a new message, returns `None` if it is dropped
+    /// by the processing of the batcher.
+    pub(crate) async fn prepare(
+        &mut self,
+        mut msg: BatchMessage,
+        properties: Option<impl FnOnce() -> Value + Send>,
+    ) -> Result<Option<(Box<RawValue>, Admission)>> {
+        // when pushed, before the geolocation, which may take a while
+        if self.batcher.auto_timestamp {
+            msg.stamp();
+        }
+
+        #[cfg(feature = "profiling")]
+        let start = std::time::Instant::now();
+        let msg = self.batcher.process_with(msg, properties);
+        #[cfg(feature = "profiling")]
+        profiling::record(&self.profiler, Phase::Batch, start);
+        let Some((mut msg, admission)) = msg else {
+            logging::debug!("message dropped by the processing of the batcher");
+            return Ok(None);
+        };
+        // once filtered, with the message context and the consent applied
+        if let Some(geo) = &self.geo {
+            geo.enrich(&mut msg, &self.batcher.warnings).await;
+        }
+        #[cfg(feature = "profiling")]
+        let start = std::time::Instant::now();
+        let msg = self.batcher.serialize(&msg);
+        #[cfg(feature = "profiling")]
+        profiling::record(&self.profiler, Phase::Serialize, start);
+        let msg = msg.inspect_err(|err| {
+            logging::warn!(
+                err = err as &(dyn std::error::Error + 'static),
+                "message rejected"
+            )
+        })?;
+        Ok(Some((msg, admission)))
+    }
+
+    /// Count a message accepted in the buffer, or committed.
+    pub(crate) fn count_pushed(&self) {
+        self.counters.pushed.fetch_add(1, Ordering::Relaxed);
+        #[cfg(feature = "metrics")]
+        telemetry::event_pushed();
+    }
+
+    async fn push_one_with(
+        &mut self,
+        msg: BatchMessage,
+        properties: Option<impl FnOnce() -> Value + Send>,
+    ) -> Result<()> {
+        self.push_one_into(msg, properties, Self::make_room).await
+    }
+
+    /// Process a new message and add it to the buffer, calling `full` when
+    /// the buffer is full, see [Self::enqueue].
+    async fn push_one_into<F>(
+        &mut self,
+        msg: BatchMessage,
+        properties: Option<impl FnOnce() -> Value + Send>,
+        full: F,
+    ) -> Result<()>
+    where
+        F: AsyncFnOnce(&mut Self) -> Result<bool>,
+    {
+        let Some((msg, admission)) = self.prepare(msg, properties).await? else {
+            return Ok(());
+        };
+        if self.enqueue(msg, admission, full).await? {
+            self.count_pushed();
+        }
         Ok(())
     }
 
-    /// Send all the message currently contained in the batcher, full or empty.
+    /// Add a serialized message to the buffer. When the buffer is full,
+    /// `full` is called first to take the buffered messages away, and
+    /// returns `false` if it can't, for the overflow policy to apply.
     ///
-    /// Returns an error if the message is too large to be sent to Segment's
-    /// API.
+    /// Returns whether the message was enqueued, rather than dropped by the
+    /// overflow policy.
+    pub(crate) async fn enqueue<F>(
+        &mut self,
+        msg: Box<RawValue>,
+        admission: Admission,
+        full: F,
+    ) -> Result<bool>
+    where
+        F: AsyncFnOnce(&mut Self) -> Result<bool>,
+    {
+        if let Some(msg) = self.batcher.enqueue(msg) {
+            if !full(self).await? {
+                return self.overflow(msg, admission, self.overflow_policy);
+            }
+            // the batcher is empty, and the message is not larger than the max
+            // size of a batch, otherwise it would have been rejected by
+            // `serialize`
+            self.batcher.enqueue(msg);
+        }
+        self.batcher.admit(admission);
+
+        Ok(true)
+    }
+
+    /// Upload the full buffer in the background, once an upload slot is
+    /// available, see [Self::enqueue].
+    ///
+    /// Returns `false` without waiting if no slot is available and the
+    /// overflow policy doesn't wait for one.
+    async fn make_room(&mut self) -> Result<bool> {
+        let permit = match self.overflow_policy {
+            OverflowPolicy::Block => self.upload_slot().await,
+            _ => {
+                let settled = self.in_flight.try_settle();
+                match self.in_flight.permits.clone().try_acquire_owned() {
+                    Ok(permit) if settled => permit,
+                    _ => return Ok(false),
+                }
+            }
+        };
+        self.dispatch(permit).await;
+        Ok(true)
+    }
+
+    /// Apply a non-blocking overflow policy to `msg`, which doesn't fit in the
+    /// full buffer.
+    ///
+    /// Returns whether the message was enqueued, like [Self::enqueue].
+    fn overflow(
+        &mut self,
+        mut msg: Box<RawValue>,
+        admission: Admission,
+        policy: OverflowPolicy,
+    ) -> Result<bool> {
+        match policy {
+            OverflowPolicy::DropNewest => {
+                self.counters.dropped.fetch_add(1, Ordering::Relaxed);
+                logging::warn!(
+                    bytes = msg.get().len(),
+                    "buffer full, newest message dropped"
+                );
+                Ok(false)
+            }
+            OverflowPolicy::DropOldest => {
+                while let Some(rejected) = self.batcher.enqueue(msg) {
+                    msg = rejected;
+                    if let Some(oldest) = self.batcher.pop_oldest() {
+                        logging::warn!(
+                            bytes = oldest.get().len(),
+                            "buffer full, oldest message dropped"
+                        );
+                    }
+                    self.counters.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+                self.batcher.admit(admission);
+                Ok(true)
+            }
+            // `Error`, `Block` waits for an upload slot instead, see `make_room`
+            _ => Err(Error::QueueFull),
+        }
+    }
+
+    /// Send all the message currently contained in the batcher, full or empty,
+    /// and wait for the background uploads to complete.
+    ///
+    /// Returns the batches which could not be delivered since the last flush,
+    /// each with its error, in an [`Error::Undelivered`].
     /// ```
     /// use serde_json::json;
     /// use segment::{AutoBatcher, Batcher, HttpClient};
@@ -140,20 +797,1252 @@ impl AutoBatcher {
     /// batcher.push(msg); // .await
     /// batcher.flush(); // .await
     /// ```
-    #[tracing::instrument(skip_all)]
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(buffered = self.batcher.len(), elapsed_ms = Empty))
+    )]
     pub async fn flush(&mut self) -> Result<()> {
-        if self.batcher.is_empty() {
-            return Ok(());
+        let start = Instant::now();
+        if !self.batcher.is_empty() {
+            let permit = self.upload_slot().await;
+            self.dispatch(permit).await;
         }
 
-        let message = Message::Batch(Batch {
-            batch: self.batcher.take(),
-            context: self.batcher.context.clone(),
-            integrations: None,
-            extra: Map::default(),
-        });
+        // once every permit is available no upload is running anymore
+        self.in_flight.wait().await;
+        logging::record!("elapsed_ms", start.elapsed().as_millis() as u64);
+        #[cfg(feature = "metrics")]
+        telemetry::flushed(start.elapsed());
+        match self.in_flight.take_failures() {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+
+    /// Start a transaction: returns an empty [`PreparedBatch`] holding the
+    /// messages pushed with [Self::push_prepared] until the surrounding
+    /// business transaction either commits, see [Self::commit], or rolls
+    /// back, see [Self::rollback].
+    ///
+    /// The prepared messages are sent iff the transaction commits. They are
+    /// kept apart from the messages pushed with [Self::push], which are
+    /// neither sent by the commit nor discarded by the rollback.
+    ///
+    /// ```
+    /// use segment::{AutoBatcher, Batcher, HttpClient};
+    /// use segment::message::{Track, User};
+    ///
+    /// # async fn run() -> segment::Result<()> {
+    /// # let committed = true;
+    /// let client = HttpClient::default();
+    /// let mut batcher = AutoBatcher::new(client, Batcher::new(None), "your_write_key".to_string());
+    ///
+    /// let mut prepared = batcher.flush_prepared();
+    /// batcher.push_prepared(&mut prepared, Track {
+    ///     user: User::UserId { user_id: "user".to_owned() },
+    ///     event: "Order Completed".to_owned(),
+    ///     ..Default::default()
+    /// }).await?;
+    ///
+    /// // commit the business transaction, then
+    /// if committed {
+    ///     batcher.commit(prepared).await?;
+    /// } else {
+    ///     batcher.rollback(prepared);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn flush_prepared(&mut self) -> PreparedBatch {
+        PreparedBatch::new(self.batcher.empty_clone())
+    }
 
-        self.client.send(self.key.to_string(), message).await?;
+    /// Push a message into the `prepared` messages of a transaction, see
+    /// [Self::flush_prepared].
+    ///
+    /// The message is processed like by [Self::push], but it is only
+    /// deduplicated and counted in the event limits once committed.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    pub async fn push_prepared(
+        &mut self,
+        prepared: &mut PreparedBatch,
+        msg: impl Into<BatchMessage>,
+    ) -> Result<()> {
+        if let Some((msg, admission)) = self.prepare(msg.into(), None::<fn() -> Value>).await? {
+            prepared.push(msg, admission);
+        }
         Ok(())
     }
+
+    /// Send the `prepared` messages, and wait for their upload.
+    ///
+    /// Returns an [`Error::Undelivered`] with the messages of the batches
+    /// which could not be delivered, even after the retries.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    pub async fn commit(&mut self, mut prepared: PreparedBatch) -> Result<()> {
+        let (batches, admissions) = prepared.take();
+        for admission in admissions {
+            self.batcher.admit(admission);
+            self.count_pushed();
+        }
+        let mut failed = Vec::new();
+        for batch in batches {
+            if let Err(batch) = self.send_now(batch).await {
+                failed.push(batch);
+            }
+        }
+        match failed.is_empty() {
+            true => Ok(()),
+            false => Err(undelivered(failed)),
+        }
+    }
+
+    /// Send `batch` in the foreground, and wait for its upload.
+    pub(crate) async fn send_now(
+        &mut self,
+        batch: SerializedBatch,
+    ) -> std::result::Result<(), (Error, SerializedBatch)> {
+        if let Some(rate_limiter) = &self.rate_limiter {
+            rate_limiter.acquire(batch.len(), &self.runtime).await;
+        }
+        self.upload(batch).send().await
+    }
+
+    /// Discard the `prepared` messages.
+    pub fn rollback(&mut self, mut prepared: PreparedBatch) {
+        let (batches, _) = prepared.take();
+        let discarded: usize = batches.iter().map(SerializedBatch::len).sum();
+        if discarded > 0 {
+            logging::debug!(discarded, "prepared batch rolled back");
+        }
+    }
+
+    /// Stop the batcher: wait for the background uploads, then send the
+    /// buffered messages and retry the failed batches until `deadline`
+    /// elapses.
+    ///
+    /// Returns the messages that could not be delivered, for instance to
+    /// persist them until the next start. The uploads still running when the
+    /// deadline elapses are left running in the background, and their
+    /// messages are not returned.
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use segment::{AutoBatcher, Batcher, HttpClient};
+    ///
+    /// # async fn run() {
+    /// let client = HttpClient::default();
+    /// let batcher = Batcher::new(None);
+    /// let batcher = AutoBatcher::new(client, batcher, "your_write_key".to_string());
+    ///
+    /// let undelivered = batcher.shutdown(Duration::from_secs(5)).await;
+    /// # }
+    /// ```
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    pub async fn shutdown(self, deadline: Duration) -> Vec<BatchMessage> {
+        self.shutdown_at(Vec::new(), Instant::now() + deadline)
+            .await
+    }
+
+    /// Like [Self::shutdown], also pushing and sending the `pending` messages
+    /// which were not pushed yet.
+    pub(crate) async fn shutdown_at(
+        mut self,
+        pending: Vec<BatchMessage>,
+        deadline: Instant,
+    ) -> Vec<BatchMessage> {
+        let runtime = self.runtime.clone();
+        if runtime
+            .timeout_at(deadline, self.in_flight.wait())
+            .await
+            .is_none()
+        {
+            logging::warn!("uploads still running at the shutdown deadline");
+        }
+        let failures = std::mem::take(&mut *self.in_flight.failures.lock().unwrap());
+        let mut batches: Vec<_> = failures
+            .batches
+            .into_iter()
+            .map(|(_, batch)| batch)
+            .collect();
+
+        for msg in pending {
+            // the full batches are sent below, within the deadline
+            let full = async |batcher: &mut Self| {
+                batches.push(batcher.batcher.take());
+                Ok(true)
+            };
+            if let Err(err) = self.push_one_into(msg, None::<fn() -> Value>, full).await {
+                logging::error!(
+                    err = &err as &(dyn std::error::Error + 'static),
+                    "dropping message"
+                );
+            }
+        }
+        if !self.batcher.is_empty() {
+            batches.push(self.batcher.take());
+        }
+
+        let mut undelivered = Vec::new();
+        for batch in batches {
+            let upload = self.upload(batch.clone());
+            let rate_limiter = &self.rate_limiter;
+            let runtime = &self.runtime;
+            let send = async {
+                if let Some(rate_limiter) = rate_limiter {
+                    rate_limiter.acquire(batch.len(), runtime).await;
+                }
+                upload.send().await
+            };
+            match runtime.timeout_at(deadline, send).await {
+                Some(Ok(())) => {}
+                Some(Err((err, _))) => {
+                    logging::error!(
+                        err = &err as &(dyn std::error::Error + 'static),
+                        "failed to send batch during shutdown"
+                    );
+                    undelivered.push(batch);
+                }
+                None => undelivered.push(batch),
+            }
+        }
+
+        let mut messages = Vec::new();
+        for batch in undelivered {
+            match batch.messages() {
+                Ok(batch) => messages.extend(batch),
+                Err(err) => logging::error!(
+                    err = &err as &(dyn std::error::Error + 'static),
+                    lost = batch.len(),
+                    "failed to deserialize undelivered messages"
+                ),
+            }
+        }
+        messages
+    }
+
+    /// Hand the buffered messages over to `next`, a batcher with another
+    /// configuration (limits, context, client...), and returns it to be used
+    /// from now on.
+    ///
+    /// The buffered messages keep the fields set when they were pushed, and
+    /// are sent in the batches of `next`, with its batch context, its write
+    /// key and through its client. Those too large for its limits are logged
+    /// and dropped. The batches which failed to upload are reported by the
+    /// next [flush](Self::flush) of `next`, while the uploads still running
+    /// complete with the previous configuration.
+    ///
+    /// ```
+    /// use segment::{AutoBatcher, Batcher, BatchLimits, HttpClient};
+    ///
+    /// # async fn run() {
+    /// let client = HttpClient::default();
+    /// let batcher = AutoBatcher::new(client.clone(), Batcher::new(None), "your_write_key".to_string());
+    ///
+    /// let mut smaller = Batcher::new(None);
+    /// smaller
+    ///     .set_batch_limits(BatchLimits {
+    ///         max_messages: Some(10),
+    ///         ..Default::default()
+    ///     })
+    ///     .unwrap();
+    /// let batcher = batcher
+    ///     .replace(AutoBatcher::new(client, smaller, "your_write_key".to_string()))
+    ///     .await;
+    /// # }
+    /// ```
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    pub async fn replace<D>(mut self, mut next: AutoBatcher<D>) -> AutoBatcher<D>
+    where
+        D: Client + Clone + Send + Sync + 'static,
+    {
+        // the uploads still running record their failures in the failures of
+        // `self`, shared with `next` once its own uploads are complete
+        next.in_flight.wait().await;
+        let failures = std::mem::take(&mut *next.in_flight.failures.lock().unwrap());
+        self.in_flight
+            .failures
+            .lock()
+            .unwrap()
+            .batches
+            .extend(failures.batches);
+        next.in_flight.failures = self.in_flight.failures.clone();
+
+        for msg in self.batcher.take().batch.iter() {
+            if let Err(err) = next.batcher.check_size(msg) {
+                logging::error!(
+                    err = &err as &(dyn std::error::Error + 'static),
+                    "dropping message"
+                );
+                next.counters.dropped.fetch_add(1, Ordering::Relaxed);
+                continue;
+            }
+            if let Some(msg) = next.batcher.enqueue(msg.clone()) {
+                let permit = next.upload_slot().await;
+                next.dispatch(permit).await;
+                // the batcher is empty, the message fits
+                next.batcher.enqueue(msg);
+            }
+        }
+        next
+    }
+
+    /// Send or report the buffered messages of a dropped clone.
+    fn drop_buffered(&mut self) {
+        #[cfg(feature = "flush-on-drop")]
+        if self.runtime.can_spawn() {
+            let upload = Upload {
+                client: self.client.clone(),
+                key: std::mem::take(&mut self.key),
+                batch: self.batcher.take(),
+                retry_policy: self.retry_policy.clone(),
+                retry_budget: self.retry_budget.take(),
+                deliveries: self.deliveries.take(),
+                observer: self.observer.take(),
+                counters: self.counters.clone(),
+                runtime: self.runtime.clone(),
+                #[cfg(feature = "profiling")]
+                profiler: self.profiler.take(),
+            };
+            self.runtime.spawn(async move {
+                if let Err((err, batch)) = upload.send().await {
+                    logging::warn!(
+                        err = &err as &(dyn std::error::Error + 'static),
+                        lost = batch.len(),
+                        "failed to send the messages of a dropped batcher"
+                    );
+                }
+            });
+            return;
+        }
+
+        logging::warn!(
+            lost = self.batcher.len(),
+            "batcher dropped with buffered messages, call `flush` or `shutdown` before dropping it"
+        );
+    }
+
+    /// Returns a batcher with the configuration of this one, sending through
+    /// `client`, with an empty buffer and its own counters.
+    pub(crate) fn with_client<D>(&self, client: D) -> AutoBatcher<D>
+    where
+        D: Client + Clone + Send + Sync + 'static,
+    {
+        AutoBatcher {
+            client,
+            batcher: self.batcher.empty_clone(),
+            key: self.key.clone(),
+            rate_limiter: None,
+            retry_policy: self.retry_policy.clone(),
+            retry_budget: None,
+            deliveries: None,
+            observer: None,
+            in_flight: InFlight::new(
+                NonZeroUsize::new(self.in_flight.max() as usize).unwrap_or(NonZeroUsize::MIN),
+            ),
+            geo: self.geo.clone(),
+            overflow_policy: self.overflow_policy,
+            counters: Arc::default(),
+            runtime: self.runtime.clone(),
+            #[cfg(feature = "profiling")]
+            profiler: self.profiler.clone(),
+            on_drop: AutoBatcher::drop_buffered,
+        }
+    }
+
+    /// Returns a batcher with the configuration of this one, sending to the
+    /// source of `key`, with an empty buffer. The upload slots, the counters,
+    /// the deliveries and the retry budget are shared with this one, the
+    /// rate limiter only has the same rates.
+    pub(crate) fn for_key(&self, key: String) -> Self {
+        let mut batcher = self.with_client(self.client.clone());
+        batcher.key = key;
+        batcher.in_flight = InFlight {
+            failures: Arc::default(),
+            ..self.in_flight.clone()
+        };
+        batcher.rate_limiter = self.rate_limiter.as_ref().map(RateLimiter::unshared);
+        batcher.retry_budget = self.retry_budget.clone();
+        batcher.deliveries = self.deliveries.clone();
+        batcher.observer = self.observer.clone();
+        batcher.counters = self.counters.clone();
+        batcher
+    }
+
+    /// Wait for an upload slot to be available.
+    async fn upload_slot(&mut self) -> OwnedSemaphorePermit {
+        self.in_flight.settle().await;
+        self.in_flight
+            .permits
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("the semaphore is never closed")
+    }
+
+    /// Hand the current batch over to a background upload, holding the
+    /// `permit` of its upload slot.
+    async fn dispatch(&mut self, permit: OwnedSemaphorePermit) {
+        if let Some(rate_limiter) = &self.rate_limiter {
+            rate_limiter
+                .acquire(self.batcher.len(), &self.runtime)
+                .await;
+        }
+
+        let batch = self.batcher.take();
+        let upload = self.upload(batch);
+        let failures = self.in_flight.failures.clone();
+        let upload = async move {
+            if let Err(failed) = upload.send().await {
+                failures.lock().unwrap().batches.push(failed);
+            }
+            drop(permit);
+        };
+        #[cfg(feature = "tracing")]
+        let upload = upload.in_current_span();
+        if !self.runtime.can_spawn() {
+            // for instance outside of a tokio runtime, see `Runtime`
+            return upload.await;
+        }
+        self.runtime.spawn(upload);
+    }
+
+    fn upload(&self, batch: SerializedBatch) -> Upload<C> {
+        Upload {
+            client: self.client.clone(),
+            key: self.key.clone(),
+            batch,
+            retry_policy: self.retry_policy.clone(),
+            retry_budget: self.retry_budget.clone(),
+            deliveries: self.deliveries.clone(),
+            observer: self.observer.clone(),
+            counters: self.counters.clone(),
+            runtime: self.runtime.clone(),
+            #[cfg(feature = "profiling")]
+            profiler: self.profiler.clone(),
+        }
+    }
+}
+
+impl<C: Clone> Clone for AutoBatcher<C> {
+    fn clone(&self) -> Self {
+        Self {
+            client: self.client.clone(),
+            batcher: self.batcher.empty_clone(),
+            key: self.key.clone(),
+            rate_limiter: self.rate_limiter.clone(),
+            retry_policy: self.retry_policy.clone(),
+            retry_budget: self.retry_budget.clone(),
+            deliveries: self.deliveries.clone(),
+            observer: self.observer.clone(),
+            in_flight: self.in_flight.clone(),
+            geo: self.geo.clone(),
+            overflow_policy: self.overflow_policy,
+            counters: self.counters.clone(),
+            runtime: self.runtime.clone(),
+            #[cfg(feature = "profiling")]
+            profiler: self.profiler.clone(),
+            on_drop: self.on_drop,
+        }
+    }
+}
+
+impl<C> Drop for AutoBatcher<C> {
+    fn drop(&mut self) {
+        if !self.batcher.is_empty() {
+            (self.on_drop)(self);
+        }
+    }
+}
+
+/// Everything needed to upload a batch, detached from the [`AutoBatcher`] so
+/// that it can run in the background.
+struct Upload<C> {
+    client: C,
+    key: String,
+    batch: SerializedBatch,
+    retry_policy: RetryPolicy,
+    retry_budget: Option<RetryBudget>,
+    deliveries: Option<broadcast::Sender<DeliveryEvent>>,
+    observer: Option<Observer>,
+    counters: Arc<Counters>,
+    runtime: SharedRuntime,
+    #[cfg(feature = "profiling")]
+    profiler: Option<Profiler>,
+}
+
+impl<C: Client + Sync> Upload<C> {
+    /// Send the batch, retrying the transient errors.
+    ///
+    /// Returns the last error and the messages of the batch if it could not be
+    /// delivered.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "upload",
+            skip_all,
+            fields(
+                batch.messages = self.batch.len(),
+                batch.bytes = Empty,
+                attempts = Empty,
+                elapsed_ms = Empty,
+            )
+        )
+    )]
+    async fn send(self) -> std::result::Result<(), (Error, SerializedBatch)> {
+        let start = Instant::now();
+        let bytes: usize = self.batch.batch.iter().map(|msg| msg.get().len()).sum();
+        logging::record!("batch.bytes", bytes);
+        if let Some(budget) = &self.retry_budget {
+            budget.deposit();
+        }
+
+        let message_ids = if self.deliveries.is_some() || self.observer.is_some() {
+            self.batch.message_ids()
+        } else {
+            Vec::new()
+        };
+        let mut attempt = 1;
+        let result = loop {
+            // stamped at every attempt, lets Segment correct the timestamps
+            // for the time spent queued
+            let batch = self.batch.at_send_time();
+            #[cfg(feature = "profiling")]
+            let start = std::time::Instant::now();
+            let sent = self.client.send_batch(self.key.clone(), &batch).await;
+            #[cfg(feature = "profiling")]
+            profiling::record(&self.profiler, Phase::Send, start);
+            match sent {
+                Err(err) if err.is_retryable() && attempt < self.retry_policy.max_attempts => {
+                    if !self.retry_budget.as_ref().is_none_or(RetryBudget::withdraw) {
+                        self.counters
+                            .retry_budget_exhausted
+                            .fetch_add(1, Ordering::Relaxed);
+                        logging::warn!(
+                            err = &err as &(dyn std::error::Error + 'static),
+                            "failed to send batch, retry budget exhausted"
+                        );
+                        break Err(err);
+                    }
+                    self.emit(&message_ids, attempt, || DeliveryOutcome::Retrying {
+                        error: err.to_string(),
+                    });
+                    let backoff = self.retry_policy.backoff(attempt);
+                    #[cfg(feature = "metrics")]
+                    telemetry::retry();
+                    logging::warn!(
+                        err = &err as &(dyn std::error::Error + 'static),
+                        attempt,
+                        ?backoff,
+                        "failed to send batch, retrying"
+                    );
+                    self.runtime.sleep(backoff).await;
+                    attempt += 1;
+                }
+                result => break result,
+            }
+        };
+
+        logging::record!("attempts", attempt);
+        logging::record!("elapsed_ms", start.elapsed().as_millis() as u64);
+        self.counters
+            .uploaded(self.batch.len(), bytes, result.as_ref().err());
+        #[cfg(feature = "metrics")]
+        match &result {
+            Ok(()) => telemetry::batch_sent(bytes),
+            Err(_) => telemetry::send_failure(),
+        }
+
+        if let Some(observer) = &self.observer {
+            observer.notify(&message_ids, &result);
+        }
+        self.emit(&message_ids, attempt, || match &result {
+            Ok(()) => DeliveryOutcome::Delivered,
+            Err(err) => DeliveryOutcome::Failed {
+                error: err.to_string(),
+            },
+        });
+
+        result.map_err(|err| (err, self.batch))
+    }
+
+    /// Broadcast the outcome of an attempt to the subscribers, if any.
+    fn emit(
+        &self,
+        message_ids: &[String],
+        attempt: u32,
+        outcome: impl FnOnce() -> DeliveryOutcome,
+    ) {
+        if let Some(deliveries) = &self.deliveries {
+            // an error only means that nobody is listening anymore
+            let _ = deliveries.send(DeliveryEvent {
+                message_ids: message_ids.to_vec(),
+                outcome: outcome(),
+                attempt,
+                timestamp: OffsetDateTime::now_utc(),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::{Track, User};
+    use crate::testing::{large_track, MockClient};
+    use crate::{BatchLimits, Deduplication, Message};
+    use serde_json::json;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+    use time::format_description::well_known::Rfc3339;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_sequential_flushes() {
+        let client = MockClient::default();
+        let mut batcher = AutoBatcher::new(client.clone(), Batcher::new(None), "key".to_owned());
+
+        for i in 0..100 {
+            batcher.push(large_track(i)).await.unwrap();
+        }
+        batcher.flush().await.unwrap();
+
+        assert_eq!(client.running.load(Ordering::SeqCst), 0);
+        assert_eq!(client.max_running.load(Ordering::SeqCst), 1);
+        assert_eq!(client.sent_count(), 100);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_push_many() {
+        let client = MockClient::default();
+        let mut batcher = AutoBatcher::new(client.clone(), Batcher::new(None), "key".to_owned());
+
+        let too_large = Track {
+            event: "a".repeat(1024 * 33),
+            ..Default::default()
+        };
+        let msgs = (0..40)
+            .map(|i| BatchMessage::from(large_track(i)))
+            .chain([too_large.into(), Track::default().into()]);
+        assert!(matches!(
+            batcher.push_many(msgs).await,
+            Err(Error::MessageTooLarge)
+        ));
+        assert_eq!(batcher.buffered().len(), batcher.len());
+        batcher.flush().await.unwrap();
+        assert_eq!(client.sent_count(), 41);
+        assert_eq!(client.sent.lock().unwrap().len(), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_push_does_not_wait_for_upload() {
+        let client = MockClient::default();
+        let mut batcher = AutoBatcher::new(client.clone(), Batcher::new(None), "key".to_owned());
+
+        // fill the first buffer, the next push hands it over to an upload
+        let mut i = 0;
+        while client.running.load(Ordering::SeqCst) == 0 {
+            batcher.push(large_track(i)).await.unwrap();
+            tokio::task::yield_now().await;
+            i += 1;
+        }
+        let start = tokio::time::Instant::now();
+        batcher.push(large_track(i)).await.unwrap();
+        assert_eq!(start.elapsed(), Duration::ZERO);
+        assert_eq!(batcher.len(), 2);
+
+        batcher.flush().await.unwrap();
+        assert_eq!(client.sent.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_clones_share_rate_limiter() {
+        let client = MockClient::default();
+        let mut batcher = AutoBatcher::new(client.clone(), Batcher::new(None), "key".to_owned());
+        batcher.set_rate_limiter(RateLimiter::new(Some(10), Some(1)).unwrap());
+        let mut clone = batcher.clone();
+        let start = Instant::now();
+
+        for _ in 0..10 {
+            batcher.push(Track::default()).await.unwrap();
+            clone.push(Track::default()).await.unwrap();
+        }
+        batcher.flush().await.unwrap();
+        assert!(start.elapsed() < Duration::from_secs(1));
+        // the first flush took the tokens of both clones
+        clone.flush().await.unwrap();
+        assert!(start.elapsed() >= Duration::from_secs(1));
+
+        assert_eq!(client.sent_count(), 20);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_overflow_policies() {
+        for policy in [
+            OverflowPolicy::DropNewest,
+            OverflowPolicy::DropOldest,
+            OverflowPolicy::Error,
+        ] {
+            let client = MockClient::default();
+            let mut batcher =
+                AutoBatcher::new(client.clone(), Batcher::new(None), "key".to_owned());
+            batcher.set_overflow_policy(policy);
+
+            // 17 messages fill the buffer, the 18th starts an upload and the
+            // 35th finds the buffer full while the upload is running
+            let mut result = Ok(());
+            for i in 0..35 {
+                result = batcher.push(large_track(i)).await;
+            }
+            match policy {
+                OverflowPolicy::Error => assert!(matches!(result, Err(Error::QueueFull))),
+                _ => assert!(result.is_ok()),
+            }
+            let dropped = batcher.stats().dropped;
+            let expected = if policy == OverflowPolicy::Error {
+                0
+            } else {
+                1
+            };
+            assert_eq!(dropped, expected, "{policy:?}");
+            // only the enqueued messages are counted as pushed
+            let pushed = if policy == OverflowPolicy::DropOldest {
+                35
+            } else {
+                34
+            };
+            assert_eq!(batcher.stats().pushed, pushed, "{policy:?}");
+
+            batcher.flush().await.unwrap();
+            assert_eq!(client.sent_count(), 34);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_concurrent_flushes() {
+        let client = MockClient::default();
+        let mut batcher = AutoBatcher::new(client.clone(), Batcher::new(None), "key".to_owned());
+        batcher.set_max_concurrent_flushes(NonZeroUsize::new(3).unwrap());
+
+        for i in 0..200 {
+            batcher.push(large_track(i)).await.unwrap();
+        }
+        batcher.flush().await.unwrap();
+
+        assert_eq!(client.running.load(Ordering::SeqCst), 0);
+        assert_eq!(client.max_running.load(Ordering::SeqCst), 3);
+        assert_eq!(client.sent_count(), 200);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_resize_concurrent_flushes() {
+        let client = MockClient::default();
+        let mut batcher = AutoBatcher::new(client.clone(), Batcher::new(None), "key".to_owned());
+        batcher.set_max_concurrent_flushes(NonZeroUsize::new(3).unwrap());
+        for i in 0..60 {
+            batcher.push(large_track(i)).await.unwrap();
+        }
+        tokio::task::yield_now().await;
+        assert_eq!(client.running.load(Ordering::SeqCst), 3);
+
+        // the running uploads keep their slots, the flush still waits for them
+        batcher.set_max_concurrent_flushes(NonZeroUsize::MIN);
+        batcher.flush().await.unwrap();
+        assert_eq!(client.running.load(Ordering::SeqCst), 0);
+        assert_eq!(client.sent_count(), 60);
+        assert_eq!(batcher.diagnostics().uploads_in_flight, 0);
+
+        client.max_running.store(0, Ordering::SeqCst);
+        for i in 0..60 {
+            batcher.push(large_track(i)).await.unwrap();
+        }
+        batcher.flush().await.unwrap();
+        assert_eq!(client.max_running.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_failures_per_batch() {
+        let client = MockClient::default();
+        let mut batcher = AutoBatcher::new(client.clone(), Batcher::new(None), "key".to_owned());
+        batcher.set_retry_policy(RetryPolicy::never());
+        batcher.set_max_concurrent_flushes(NonZeroUsize::new(2).unwrap());
+
+        client.failures.store(2, Ordering::SeqCst);
+        for i in 0..20 {
+            batcher.push(large_track(i)).await.unwrap();
+        }
+        let Err(Error::Undelivered { batches }) = batcher.flush().await else {
+            panic!("expected undelivered messages");
+        };
+        assert_eq!(batches.len(), 2);
+        assert!(batches.iter().all(|batch| batch.error.is_retryable()));
+        let undelivered: usize = batches.iter().map(|batch| batch.messages.len()).sum();
+        assert_eq!(undelivered + client.sent_count(), 20);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_sent_at() {
+        let client = MockClient::default();
+        let mut batcher = AutoBatcher::new(client.clone(), Batcher::new(None), "key".to_owned());
+
+        let before = OffsetDateTime::now_utc();
+        batcher.push(Track::default()).await.unwrap();
+        batcher.flush().await.unwrap();
+
+        let Message::Batch(batch) = client.sent.lock().unwrap()[0].clone() else {
+            panic!("expected a batch");
+        };
+        let sent_at = batch.extra["sentAt"].as_str().unwrap();
+        let sent_at = OffsetDateTime::parse(sent_at, &Rfc3339).unwrap();
+        assert!(sent_at >= before);
+    }
+
+    #[test]
+    fn test_outside_of_tokio() {
+        /// Fails the first request, without the timers of tokio.
+        #[derive(Clone, Default)]
+        struct Flaky(Arc<AtomicUsize>);
+
+        impl Client for Flaky {
+            async fn send(&self, _: String, _: Message) -> Result<()> {
+                match self.0.fetch_add(1, Ordering::SeqCst) {
+                    0 => Err(crate::testing::status_error(503)),
+                    _ => Ok(()),
+                }
+            }
+        }
+
+        let client = Flaky::default();
+        let mut batcher = AutoBatcher::new(client.clone(), Batcher::new(None), "key".to_owned());
+        batcher.set_retry_policy(RetryPolicy {
+            max_attempts: 2,
+            initial_backoff: Duration::from_millis(1),
+            ..RetryPolicy::default()
+        });
+        // the batch is uploaded in the foreground, and the backoff waits on
+        // a thread
+        crate::runtime::block_on(async {
+            batcher.push(Track::default()).await.unwrap();
+            batcher.flush().await.unwrap();
+        });
+        assert_eq!(client.0.load(Ordering::SeqCst), 2);
+        assert_eq!(batcher.stats().sent, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_geo_resolver_after_processing() {
+        struct CountingResolver(Arc<AtomicUsize>);
+
+        impl GeoResolver for CountingResolver {
+            async fn resolve(&self, _: std::net::IpAddr) -> Option<crate::GeoLocation> {
+                self.0.fetch_add(1, Ordering::SeqCst);
+                Some(crate::GeoLocation {
+                    country: Some("France".to_owned()),
+                    ..Default::default()
+                })
+            }
+        }
+
+        let client = MockClient::default();
+        let mut inner = Batcher::new(None);
+        inner.set_message_context(json!({ "ip": "127.0.0.1" }), crate::MergeStrategy::Shallow);
+        inner.set_event_filter(crate::EventFilter::deny(["Dropped"]));
+        let mut batcher = AutoBatcher::new(client.clone(), inner, "key".to_owned());
+        let resolved = Arc::new(AtomicUsize::new(0));
+        batcher.set_geo_resolver(CountingResolver(resolved.clone()));
+
+        for event in ["Dropped", "Kept"] {
+            batcher
+                .push(Track {
+                    event: event.to_owned(),
+                    ..Default::default()
+                })
+                .await
+                .unwrap();
+        }
+        batcher.flush().await.unwrap();
+
+        // the filtered message is not resolved, the `ip` of the message context is
+        assert_eq!(resolved.load(Ordering::SeqCst), 1);
+        let Message::Batch(batch) = client.sent.lock().unwrap()[0].clone() else {
+            panic!("expected a batch");
+        };
+        let BatchMessage::Track(track) = &batch.batch[0] else {
+            panic!("expected a track");
+        };
+        assert_eq!(
+            track.context.as_ref().unwrap()["location"],
+            json!({ "country": "France" })
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retries() {
+        let client = MockClient::default();
+        let mut batcher = AutoBatcher::new(client.clone(), Batcher::new(None), "key".to_owned());
+
+        // not retried by default
+        client.failures.store(1, Ordering::SeqCst);
+        batcher.push(Track::default()).await.unwrap();
+        assert!(batcher.flush().await.is_err());
+        assert_eq!(client.sent_count(), 0);
+
+        batcher.set_retry_policy(RetryPolicy {
+            max_attempts: 3,
+            ..RetryPolicy::default()
+        });
+        client.failures.store(2, Ordering::SeqCst);
+        batcher.push(Track::default()).await.unwrap();
+        batcher.flush().await.unwrap();
+        assert_eq!(client.sent_count(), 1);
+
+        client.failures.store(3, Ordering::SeqCst);
+        batcher.push(Track::default()).await.unwrap();
+        let Err(Error::Undelivered { mut batches }) = batcher.flush().await else {
+            panic!("expected undelivered messages");
+        };
+        let UndeliveredBatch { error, messages } = batches.pop().unwrap();
+        assert!(batches.is_empty());
+        assert!(error.is_retryable());
+        assert_eq!(messages.len(), 1);
+        assert_eq!(client.sent_count(), 1);
+
+        // the messages can be enqueued again, without being processed again
+        batcher.batcher.add_enricher("twice", |msg| {
+            msg.properties_mut().unwrap()["enriched"] = json!(true)
+        });
+        batcher.requeue(messages).await.unwrap();
+        batcher.flush().await.unwrap();
+        assert_eq!(client.sent_count(), 2);
+        let sent = client.sent.lock().unwrap();
+        let Message::Batch(batch) = &sent[1] else {
+            unreachable!()
+        };
+        assert!(
+            matches!(&batch.batch[0], BatchMessage::Track(track) if track.properties.get("enriched").is_none())
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retry_budget() {
+        let client = MockClient::default();
+        let mut batcher = AutoBatcher::new(client.clone(), Batcher::new(None), "key".to_owned());
+        batcher.set_retry_policy(RetryPolicy {
+            max_attempts: 3,
+            ..RetryPolicy::default()
+        });
+        batcher.set_retry_budget(RetryBudget::new(0.0, 0).unwrap());
+
+        client.failures.store(1, Ordering::SeqCst);
+        batcher.push(Track::default()).await.unwrap();
+        assert!(batcher.flush().await.is_err());
+        assert_eq!(batcher.stats().retry_budget_exhausted, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_stats() {
+        let client = MockClient::default();
+        let mut batcher = AutoBatcher::new(client.clone(), Batcher::new(None), "key".to_owned());
+        assert_eq!(batcher.stats(), Stats::default());
+
+        batcher.push(Track::default()).await.unwrap();
+        batcher.push(Track::default()).await.unwrap();
+        batcher.flush().await.unwrap();
+        let stats = batcher.stats();
+        assert_eq!(stats.pushed, 2);
+        assert_eq!(stats.sent, 2);
+        assert!(stats.bytes_sent > 0);
+        assert_eq!(stats.failed, 0);
+        assert_eq!(stats.last_error, None);
+        let delivered_at = stats.last_flush.unwrap();
+
+        client.failures.store(3, Ordering::SeqCst);
+        batcher.push(Track::default()).await.unwrap();
+        assert!(batcher.flush().await.is_err());
+        let stats = batcher.stats();
+        assert_eq!(stats.pushed, 3);
+        assert_eq!(stats.sent, 2);
+        assert_eq!(stats.failed, 1);
+        assert!(stats.last_error.unwrap().contains("503"));
+        assert!(stats.last_flush.unwrap() >= delivered_at);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_dump_diagnostics() {
+        let client = MockClient::default();
+        let mut batcher = AutoBatcher::new(
+            client.clone(),
+            Batcher::new(None),
+            "secret_write_key".to_owned(),
+        );
+        batcher
+            .push(Track {
+                user: User::UserId {
+                    user_id: "secret_user".to_owned(),
+                },
+                event: "Signed Up".to_owned(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        let path =
+            std::env::temp_dir().join(format!("segment-diagnostics-{}.json", std::process::id()));
+        batcher.dump_diagnostics(&path).unwrap();
+        let dumped = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(!dumped.contains("secret"), "{dumped}");
+        let dumped: Value = serde_json::from_str(&dumped).unwrap();
+        assert_eq!(dumped["write_key"], "****_key");
+        assert_eq!(dumped["buffered"], 1);
+        assert_eq!(dumped["sample"][0]["event"], "Signed Up");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_prepared_batches() {
+        let client = MockClient::default();
+        let mut inner = Batcher::new(None);
+        inner.set_deduplication(Deduplication::default());
+        let mut batcher = AutoBatcher::new(client.clone(), inner, "key".to_owned());
+        batcher.push(large_track(0)).await.unwrap();
+
+        let mut prepared = batcher.flush_prepared();
+        batcher
+            .push_prepared(&mut prepared, large_track(1))
+            .await
+            .unwrap();
+        assert_eq!(prepared.len(), 1);
+        assert_eq!(prepared.message_ids().len(), 1);
+        let ids = prepared.message_ids();
+        batcher.rollback(prepared);
+        // the messages pushed before the transaction are kept
+        assert_eq!(batcher.len(), 1);
+        batcher.flush().await.unwrap();
+        assert_eq!(client.sent_count(), 1);
+
+        // more messages than a batch holds are not sent before the commit
+        let mut prepared = batcher.flush_prepared();
+        for i in 0..40 {
+            batcher
+                .push_prepared(&mut prepared, large_track(i))
+                .await
+                .unwrap();
+        }
+        batcher.push(large_track(40)).await.unwrap();
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert_eq!(client.sent_count(), 1);
+        batcher.commit(prepared).await.unwrap();
+        assert_eq!(client.sent_count(), 41);
+        assert_eq!(batcher.len(), 1);
+
+        // the rolled back messages were not remembered by the deduplication
+        let mut track = large_track(1);
+        track.set_message_id(&ids[0]);
+        let mut prepared = batcher.flush_prepared();
+        batcher.push_prepared(&mut prepared, track).await.unwrap();
+        assert_eq!(prepared.len(), 1);
+
+        client.failures.store(usize::MAX, Ordering::SeqCst);
+        match batcher.commit(prepared).await {
+            Err(Error::Undelivered { batches }) => assert_eq!(batches[0].messages.len(), 1),
+            result => panic!("unexpected {result:?}"),
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_set_write_key() {
+        let client = MockClient::default();
+        let mut batcher = AutoBatcher::new(client.clone(), Batcher::new(None), "old".to_owned());
+
+        batcher.push(Track::default()).await.unwrap();
+        batcher.flush().await.unwrap();
+        batcher.push(Track::default()).await.unwrap();
+        batcher.set_write_key("new".to_owned());
+        batcher.flush().await.unwrap();
+        assert_eq!(*client.keys.lock().unwrap(), ["old", "new"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_replace() {
+        let client = MockClient::default();
+        let mut batcher = AutoBatcher::new(client.clone(), Batcher::new(None), "key".to_owned());
+        for i in 0..5 {
+            batcher.push(large_track(i)).await.unwrap();
+        }
+
+        let next = MockClient::default();
+        let mut smaller = Batcher::new(None);
+        smaller
+            .set_batch_limits(BatchLimits {
+                max_messages: Some(2),
+                ..Default::default()
+            })
+            .unwrap();
+        let mut batcher = batcher
+            .replace(AutoBatcher::new(next.clone(), smaller, "key".to_owned()))
+            .await;
+        // two full batches were sent, the last message is still buffered
+        assert_eq!(batcher.len(), 1);
+        batcher.flush().await.unwrap();
+        assert_eq!(next.sent_count(), 5);
+        assert_eq!(client.sent_count(), 0);
+
+        // the upload still running fails after the replace
+        let mut batcher = AutoBatcher::new(client.clone(), Batcher::new(None), "key".to_owned());
+        batcher.set_retry_policy(RetryPolicy::never());
+        client.failures.store(1, Ordering::SeqCst);
+        for i in 0..20 {
+            batcher.push(large_track(i)).await.unwrap();
+        }
+        let next = AutoBatcher::new(client.clone(), Batcher::new(None), "key".to_owned());
+        let mut batcher = batcher.replace(next).await;
+        assert!(matches!(
+            batcher.flush().await,
+            Err(Error::Undelivered { batches }) if batches.len() == 1
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_shutdown() {
+        let client = MockClient::default();
+        let mut batcher = AutoBatcher::new(client.clone(), Batcher::new(None), "key".to_owned());
+        batcher.set_retry_policy(RetryPolicy::never());
+
+        // the first full batch fails in the background
+        client.failures.store(1, Ordering::SeqCst);
+        for i in 0..20 {
+            batcher.push(large_track(i)).await.unwrap();
+        }
+        let undelivered = batcher.shutdown(Duration::from_secs(1)).await;
+        assert!(undelivered.is_empty());
+        assert_eq!(client.sent_count(), 20);
+
+        let mut batcher = AutoBatcher::new(client.clone(), Batcher::new(None), "key".to_owned());
+        client.failures.store(usize::MAX, Ordering::SeqCst);
+        for i in 0..20 {
+            batcher.push(large_track(i)).await.unwrap();
+        }
+        let start = tokio::time::Instant::now();
+        let undelivered = batcher.shutdown(Duration::from_secs(1)).await;
+        assert_eq!(undelivered.len(), 20);
+        assert!(start.elapsed() <= Duration::from_secs(1));
+    }
+
+    #[cfg(feature = "flush-on-drop")]
+    #[tokio::test(start_paused = true)]
+    async fn test_flush_on_drop() {
+        let client = MockClient::default();
+        let mut batcher = AutoBatcher::new(client.clone(), Batcher::new(None), "key".to_owned());
+
+        batcher.push(Track::default()).await.unwrap();
+        drop(batcher);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(client.sent_count(), 1);
+
+        // every clone sends its own messages, once
+        let mut batcher = AutoBatcher::new(client.clone(), Batcher::new(None), "key".to_owned());
+        batcher.push(Track::default()).await.unwrap();
+        let mut clone = batcher.clone();
+        assert_eq!(clone.batcher.len(), 0);
+        clone.push(Track::default()).await.unwrap();
+        drop(batcher);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(client.sent_count(), 2);
+        drop(clone);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(client.sent_count(), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_delivery_observer() {
+        #[derive(Default)]
+        struct Recorder {
+            delivered: Mutex<Vec<String>>,
+            failed: Mutex<Vec<String>>,
+        }
+
+        impl DeliveryObserver for Arc<Recorder> {
+            fn delivered(&self, message_ids: &[String]) {
+                self.delivered
+                    .lock()
+                    .unwrap()
+                    .extend_from_slice(message_ids);
+            }
+
+            fn failed(&self, message_ids: &[String], _error: &Error) {
+                self.failed.lock().unwrap().extend_from_slice(message_ids);
+            }
+        }
+
+        let client = MockClient::default();
+        let mut batcher = AutoBatcher::new(client.clone(), Batcher::new(None), "key".to_owned());
+        batcher.set_retry_policy(RetryPolicy::never());
+        let recorder = Arc::new(Recorder::default());
+        batcher.set_delivery_observer(recorder.clone());
+
+        batcher.push(Track::default()).await.unwrap();
+        batcher.push(Track::default()).await.unwrap();
+        batcher.flush().await.unwrap();
+        client.failures.store(1, Ordering::SeqCst);
+        batcher.push(Track::default()).await.unwrap();
+        assert!(batcher.flush().await.is_err());
+
+        // the retries are not reported, only the final outcome
+        assert_eq!(recorder.delivered.lock().unwrap().len(), 2);
+        assert_eq!(recorder.failed.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_delivery_event_on_failure() {
+        // nothing listens on the port 1, the connection is refused right away
+        let client = HttpClient::new(reqwest::Client::new(), "http://127.0.0.1:1".to_owned());
+        let mut batcher = Batcher::new(None);
+        batcher.without_message_ids();
+        let mut batcher = AutoBatcher::new(client, batcher, "key".to_owned());
+        batcher.set_retry_policy(RetryPolicy::never());
+        let mut deliveries = batcher.subscribe();
+
+        for id in ["a", "b"] {
+            let msg = Track {
+                user: User::UserId {
+                    user_id: "user".to_owned(),
+                },
+                extra: [("messageId".to_owned(), json!(id))].into_iter().collect(),
+                ..Default::default()
+            };
+            batcher.push(msg).await.unwrap();
+        }
+        batcher.push(Track::default()).await.unwrap();
+
+        assert!(batcher.flush().await.is_err());
+
+        let event = deliveries.try_recv().unwrap();
+        assert_eq!(event.message_ids, vec!["a".to_owned(), "b".to_owned()]);
+        assert!(matches!(event.outcome, DeliveryOutcome::Failed { .. }));
+        assert_eq!(event.attempt, 1);
+        assert!(deliveries.try_recv().is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_delivery_event_per_attempt() {
+        let client = MockClient::default();
+        let mut batcher = AutoBatcher::new(client.clone(), Batcher::new(None), "key".to_owned());
+        batcher.set_retry_policy(RetryPolicy {
+            max_attempts: 3,
+            ..RetryPolicy::default()
+        });
+        let mut deliveries = batcher.subscribe();
+
+        client.failures.store(1, Ordering::SeqCst);
+        batcher.push(Track::default()).await.unwrap();
+        batcher.flush().await.unwrap();
+
+        let event = deliveries.try_recv().unwrap();
+        assert!(matches!(event.outcome, DeliveryOutcome::Retrying { .. }));
+        assert_eq!(event.attempt, 1);
+        let event = deliveries.try_recv().unwrap();
+        assert_eq!(event.outcome, DeliveryOutcome::Delivered);
+        assert_eq!(event.attempt, 2);
+        assert!(deliveries.try_recv().is_err());
+    }
 }
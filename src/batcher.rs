@@ -1,13 +1,96 @@
 //! Utilities for batching up messages.
 
-use crate::message::{Batch, BatchMessage, Message};
-use crate::{Error, Result};
-use serde_json::{Map, Value};
+use crate::context::MergeStrategy;
+use crate::dedup::Deduplicator;
+use crate::event_limit::{EventLimiter, WindowKey};
+use crate::hooks::{self, catch_panic, Hook};
+use crate::id::SharedIdGenerator;
+use crate::message::{BatchMessage, Message, SerializedBatch};
+use crate::pipeline::{Entry, Pipeline, Step};
+use crate::rules::Rule;
+use crate::schema::EventShape;
+use crate::template;
+use crate::warning::{Warning, Warnings};
+use crate::{
+    ConsentFilter, Deduplication, Error, EventFilter, EventLimit, FlattenOptions, IdGenerator,
+    KeyNormalization, Result, Rules, Sampling, SchemaTracker, TrackingPlan, UserIdHashing, UuidV4,
+    Validation,
+};
+use serde_json::value::RawValue;
+use serde_json::{json, Value};
+use std::sync::Arc;
 use time::OffsetDateTime;
 
-const MAX_MESSAGE_SIZE: usize = 1024 * 32;
+pub(crate) const MAX_MESSAGE_SIZE: usize = 1024 * 32;
 const MAX_BATCH_SIZE: usize = 1024 * 512;
 
+/// The size limits of the batches built by a [`Batcher`].
+///
+/// Defaults to the limits of Segment's API, which are also the maximum
+/// values: 500KB per batch and 32KB per message, with no limit on the number
+/// of messages.
+///
+/// ```
+/// use segment::{BatchLimits, Batcher};
+///
+/// let mut batcher = Batcher::new(None);
+/// batcher.set_batch_limits(BatchLimits {
+///     max_messages: Some(100),
+///     ..Default::default()
+/// }).unwrap();
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize)]
+pub struct BatchLimits {
+    /// The maximum number of messages in a batch, `None` for no limit.
+    pub max_messages: Option<usize>,
+
+    /// The maximum size of a batch, in bytes.
+    pub max_bytes: usize,
+
+    /// The maximum size of a message, in bytes. Larger messages are rejected
+    /// with [`Error::MessageTooLarge`].
+    pub max_message_bytes: usize,
+}
+
+impl Default for BatchLimits {
+    fn default() -> Self {
+        Self {
+            max_messages: None,
+            max_bytes: MAX_BATCH_SIZE,
+            max_message_bytes: MAX_MESSAGE_SIZE,
+        }
+    }
+}
+
+impl BatchLimits {
+    /// Check that the limits are consistent and allowed by Segment's API.
+    pub fn validate(&self) -> Result<()> {
+        let invalid = |reason: String| Err(Error::InvalidConfiguration(reason));
+        if self.max_messages == Some(0) {
+            return invalid("a batch must accept at least one message".to_owned());
+        }
+        if self.max_bytes > MAX_BATCH_SIZE {
+            return invalid(format!(
+                "batches can't be larger than {MAX_BATCH_SIZE} bytes, got {}",
+                self.max_bytes
+            ));
+        }
+        if self.max_message_bytes > MAX_MESSAGE_SIZE {
+            return invalid(format!(
+                "messages can't be larger than {MAX_MESSAGE_SIZE} bytes, got {}",
+                self.max_message_bytes
+            ));
+        }
+        if self.max_message_bytes == 0 || self.max_message_bytes > self.max_bytes {
+            return invalid(format!(
+                "the message size limit ({}) must be between 1 and the batch size limit ({})",
+                self.max_message_bytes, self.max_bytes
+            ));
+        }
+        Ok(())
+    }
+}
+
 /// A batcher can accept messages into an internal buffer, and report when
 /// messages must be flushed.
 ///
@@ -35,7 +118,7 @@ const MAX_BATCH_SIZE: usize = 1024 * 512;
 ///     // When this occurs, we flush the batcher, create a new batcher, and add
 ///     // the message into the new batcher.
 ///     if let Some(msg) = batcher.push(msg).unwrap() {
-///         client.send("your_write_key".to_string(), batcher.into_message());
+///         client.send("your_write_key".to_string(), batcher.into_message().unwrap());
 ///         batcher = Batcher::new(None);
 ///         batcher.push(msg).unwrap();
 ///     }
@@ -55,25 +138,89 @@ const MAX_BATCH_SIZE: usize = 1024 * 512;
 /// added to your message.
 /// You can disable this behaviour with the [without_auto_timestamp] method
 /// though.
+///
+/// Likewise, a `messageId` is generated for the messages without one, see
+/// [Self::set_id_generator].
 #[derive(Clone, Debug)]
 pub struct Batcher {
-    pub(crate) buf: Vec<BatchMessage>,
+    /// The messages, serialized once when they are pushed.
+    pub(crate) buf: Vec<Box<RawValue>>,
+    /// The exact serialized size of the batch, envelope included.
     pub(crate) byte_count: usize,
+    /// The serialized size of an empty batch.
+    pub(crate) envelope_size: usize,
     pub(crate) context: Option<Value>,
+    pub(crate) message_context: Option<(Value, MergeStrategy)>,
+    pub(crate) message_integrations: Option<(Value, MergeStrategy)>,
+    pub(crate) integrations: Option<Value>,
+    pub(crate) limits: BatchLimits,
     pub(crate) auto_timestamp: bool,
+    pub(crate) flatten: Option<FlattenOptions>,
+    pub(crate) normalization: Option<KeyNormalization>,
+    pub(crate) schemas: Option<SchemaTracker>,
+    pub(crate) event_limiter: Option<EventLimiter>,
+    pub(crate) event_filter: Option<EventFilter>,
+    pub(crate) sampling: Option<Sampling>,
+    pub(crate) rules: Option<Rules>,
+    pub(crate) deduplicator: Option<Deduplicator>,
+    pub(crate) validation: Option<Validation>,
+    pub(crate) tracking_plan: Option<TrackingPlan>,
+    pub(crate) pipeline: Pipeline,
+    pub(crate) id_generator: Option<SharedIdGenerator>,
+    pub(crate) user_id_hashing: Option<UserIdHashing>,
+    pub(crate) consent_filter: Option<ConsentFilter>,
+    pub(crate) warnings: Warnings,
+    pub(crate) templates: bool,
+}
+
+/// The outcome of [`Batcher::extend`].
+#[derive(Debug, Default)]
+#[non_exhaustive]
+pub struct Extended {
+    /// The messages which didn't fit: the one which would have oversized the
+    /// batch, followed by the remaining ones.
+    pub remaining: Vec<BatchMessage>,
+
+    /// The messages which were rejected, by their position in the extending
+    /// messages, and why.
+    pub errors: Vec<(usize, Error)>,
 }
 
 impl Batcher {
     /// Construct a new, empty batcher.
     ///
     /// Optionally, you may specify a `context` that should be set on every
-    /// batch returned by `into_message`.
+    /// batch returned by `into_message`. Its `library` is set to this crate,
+    /// unless it already has one, see [Self::without_library_context].
     pub fn new(context: Option<Value>) -> Self {
+        let context = with_library(context);
+        let envelope_size = envelope_size(&context, &None);
         Self {
             buf: Vec::new(),
-            byte_count: 0,
+            byte_count: envelope_size,
+            envelope_size,
             context,
+            message_context: None,
+            message_integrations: None,
+            integrations: None,
+            limits: BatchLimits::default(),
             auto_timestamp: true,
+            flatten: None,
+            normalization: None,
+            schemas: None,
+            event_limiter: None,
+            event_filter: None,
+            sampling: None,
+            rules: None,
+            deduplicator: None,
+            validation: None,
+            tracking_plan: None,
+            pipeline: Pipeline::default(),
+            id_generator: Some(SharedIdGenerator::new(UuidV4)),
+            user_id_hashing: None,
+            consent_filter: None,
+            warnings: Warnings::default(),
+            templates: false,
         }
     }
 
@@ -81,10 +228,308 @@ impl Batcher {
         self.auto_timestamp = false;
     }
 
+    /// Don't set the `library` of the context to this crate, see
+    /// [`LIBRARY_NAME`].
+    pub fn without_library_context(&mut self) {
+        let mut context = self.context.take();
+        if let Some(Value::Object(map)) = &mut context {
+            if map.get("library") == Some(&library()) {
+                map.remove("library");
+            }
+            if map.is_empty() {
+                context = None;
+            }
+        }
+        let envelope_size = envelope_size(&context, &self.integrations);
+        self.byte_count = self.byte_count - self.envelope_size + envelope_size;
+        self.envelope_size = envelope_size;
+        self.context = context;
+    }
+
+    /// Stop setting a `messageId` on the pushed messages without one, see
+    /// [Self::set_id_generator].
+    pub fn without_message_ids(&mut self) {
+        self.id_generator = None;
+    }
+
+    /// Resolve the placeholders of the properties when the batches are sent
+    /// by an [`AutoBatcher`](crate::AutoBatcher), so that the events carry
+    /// delivery-time measurements: a property with the value
+    /// [`"{sent_at}"`](crate::template::SENT_AT) or
+    /// [`"{queue_latency_ms}"`](crate::template::QUEUE_LATENCY_MS) is replaced
+    /// when each attempt of a batch is sent.
+    ///
+    /// The placeholders are also resolved when a batch is sent with
+    /// [`HttpClient`](crate::HttpClient), and when the batcher is converted
+    /// with [Self::into_message].
+    ///
+    /// The messages with placeholders are deserialized again when sent. The
+    /// size of the batches accounts for the longest resolved `{sent_at}`.
+    ///
+    /// ```
+    /// use segment::Batcher;
+    /// use segment::message::Track;
+    /// use segment::template::QUEUE_LATENCY_MS;
+    /// use serde_json::json;
+    ///
+    /// let mut batcher = Batcher::new(None);
+    /// batcher.set_send_time_templates(true);
+    /// batcher.push(Track {
+    ///     event: "Job Done".to_owned(),
+    ///     properties: json!({ "queueLatencyMs": QUEUE_LATENCY_MS }),
+    ///     ..Default::default()
+    /// }).unwrap();
+    /// ```
+    pub fn set_send_time_templates(&mut self, enabled: bool) {
+        self.templates = enabled;
+    }
+
+    /// Change the size limits of the batches, see [`BatchLimits`].
+    ///
+    /// Returns an error if the limits are invalid, see
+    /// [`BatchLimits::validate`].
+    pub fn set_batch_limits(&mut self, limits: BatchLimits) -> Result<()> {
+        limits.validate()?;
+        self.limits = limits;
+        Ok(())
+    }
+
+    /// Set the integrations to route every batch to.
+    ///
+    /// Returns an error if the messages already pushed would not fit in the
+    /// batch anymore.
+    pub fn set_integrations(&mut self, integrations: Option<Value>) -> Result<()> {
+        let envelope_size = envelope_size(&self.context, &integrations);
+        let byte_count = self.byte_count - self.envelope_size + envelope_size;
+        if byte_count > self.limits.max_bytes {
+            return Err(Error::InvalidConfiguration(
+                "the integrations don't fit in the current batch".to_owned(),
+            ));
+        }
+        self.integrations = integrations;
+        self.envelope_size = envelope_size;
+        self.byte_count = byte_count;
+        Ok(())
+    }
+
+    /// Merge `context` into the context of every message pushed from now on,
+    /// the values of the messages taking precedence, see [`MergeStrategy`].
+    ///
+    /// Unlike the context given to [Self::new], which is only set on the
+    /// batch, the messages carry this context themselves.
+    pub fn set_message_context(&mut self, context: impl Into<Value>, strategy: MergeStrategy) {
+        self.message_context = Some((context.into(), strategy));
+    }
+
+    /// Merge `integrations` into the integrations of every message pushed
+    /// from now on, like `{"All": false, "Amplitude": true}`, the values of
+    /// the messages taking precedence, see [`MergeStrategy`].
+    ///
+    /// Unlike [Self::set_integrations], which only routes the batch, the
+    /// messages carry these integrations themselves.
+    pub fn set_message_integrations(
+        &mut self,
+        integrations: impl Into<Value>,
+        strategy: MergeStrategy,
+    ) {
+        self.message_integrations = Some((integrations.into(), strategy));
+    }
+
+    /// Flatten the nested objects of the properties (and traits) of every
+    /// message pushed from now on, see [`FlattenOptions`].
+    pub fn set_property_flattening(&mut self, options: FlattenOptions) {
+        self.flatten = Some(options);
+    }
+
+    /// Normalize the keys of the properties (and traits) of every message
+    /// pushed from now on into warehouse column names, after the flattening,
+    /// see [`KeyNormalization`].
+    pub fn set_key_normalization(&mut self, options: KeyNormalization) {
+        self.normalization = Some(options);
+    }
+
+    /// Record the schema of the pushed events in `tracker`, reporting the
+    /// drift as [`Warning`]s.
+    pub fn set_schema_tracker(&mut self, tracker: SchemaTracker) {
+        self.schemas = Some(tracker);
+    }
+
+    /// Drop the `track` events exceeding `limit`, see [`EventLimit`].
+    ///
+    /// Dropped events are reported as accepted by [Self::push].
+    pub fn set_event_limit(&mut self, limit: EventLimit) {
+        self.event_limiter = Some(EventLimiter::new(limit));
+    }
+
+    /// Drop the `track` events whose name is not allowed by `filter`, once
+    /// the rules renamed them, see [`EventFilter`].
+    ///
+    /// Dropped events are reported as accepted by [Self::push].
+    pub fn set_event_filter(&mut self, filter: EventFilter) {
+        self.event_filter = Some(filter);
+    }
+
+    /// Only keep a fraction of the events of some names, consistently for
+    /// each user, see [`Sampling`].
+    ///
+    /// Dropped events are reported as accepted by [Self::push].
+    pub fn set_sampling(&mut self, sampling: Sampling) {
+        self.sampling = Some(sampling);
+    }
+
+    /// Drop the messages whose `messageId` was already pushed recently, see
+    /// [`Deduplication`].
+    ///
+    /// Dropped messages are reported as accepted by [Self::push].
+    pub fn set_deduplication(&mut self, deduplication: Deduplication) {
+        self.deduplicator = Some(Deduplicator::new(deduplication));
+    }
+
+    /// Reject the invalid messages with [`Error::InvalidMessage`], once they
+    /// are processed, see [`Validation`].
+    pub fn set_validation(&mut self, validation: Validation) {
+        self.validation = Some(validation);
+    }
+
+    /// Check the `track` events against `plan`, reporting or rejecting the
+    /// violations depending on its [`Enforcement`](crate::Enforcement).
+    pub fn set_tracking_plan(&mut self, plan: TrackingPlan) {
+        self.tracking_plan = Some(plan);
+    }
+
+    /// Process every message pushed from now on in the order of the steps of
+    /// `pipeline`, running its stages between them, see [`Step`] and
+    /// [`Position`](crate::Position). This replaces the previous pipeline, but
+    /// keeps the enrichers added with [Self::add_enricher].
+    ///
+    /// Dropped messages are reported as accepted by [Self::push].
+    pub fn set_pipeline(&mut self, pipeline: Pipeline) {
+        self.pipeline = pipeline.with_enrichers_of(&self.pipeline);
+    }
+
+    /// Apply `rules` to every message pushed from now on, before any other
+    /// processing, see [`Rules`].
+    ///
+    /// Dropped messages are reported as accepted by [Self::push].
+    pub fn set_rules(&mut self, rules: Rules) {
+        self.rules = Some(rules);
+    }
+
+    /// Run `enricher` on every message pushed from now on, after the
+    /// previously added ones.
+    ///
+    /// An enricher panicking or corrupting the reserved fields of a message
+    /// (its type, its `messageId` or a `writeKey`) is skipped: its changes are
+    /// reverted, and a [`Warning::HookSkipped`] is reported. The next
+    /// enrichers run on the message as left by the previous ones.
+    ///
+    /// ```
+    /// use segment::Batcher;
+    /// use serde_json::json;
+    ///
+    /// let mut batcher = Batcher::new(None);
+    /// batcher.add_enricher("app version", |msg| {
+    ///     if let Some(properties) = msg.properties_mut() {
+    ///         properties["app_version"] = json!(env!("CARGO_PKG_VERSION"));
+    ///     }
+    /// });
+    /// ```
+    pub fn add_enricher(
+        &mut self,
+        name: impl Into<String>,
+        enricher: impl Fn(&mut BatchMessage) + Send + Sync + 'static,
+    ) {
+        self.pipeline
+            .add_enricher(Hook::new(name.into(), move |msg| {
+                enricher(msg);
+                true
+            }));
+    }
+
+    /// Set a `messageId` generated by `generator` on every message pushed from
+    /// now on without one, after the enrichers ran, and by
+    /// [Self::generate_id]. By default they are [`UuidV4`]s.
+    ///
+    /// Segment deduplicates the messages on their `messageId`, so that a batch
+    /// retried after a timeout doesn't create duplicate events.
+    ///
+    /// ```
+    /// use segment::{Batcher, UuidV7};
+    ///
+    /// let mut batcher = Batcher::new(None);
+    /// // sortable IDs
+    /// batcher.set_id_generator(UuidV7);
+    /// ```
+    pub fn set_id_generator(&mut self, generator: impl IdGenerator + 'static) {
+        self.id_generator = Some(SharedIdGenerator::new(generator));
+    }
+
+    /// Returns a new ID from the generator of [Self::set_id_generator], or a
+    /// [`UuidV4`] after [Self::without_message_ids], for instance for the
+    /// anonymous IDs or the session IDs of the messages, which are then as
+    /// deterministic as their `messageId` in tests.
+    ///
+    /// ```
+    /// use segment::Batcher;
+    /// use segment::message::User;
+    ///
+    /// let mut batcher = Batcher::new(None);
+    /// batcher.set_id_generator(|| "f00".to_owned());
+    /// let user = User::AnonymousId { anonymous_id: batcher.generate_id() };
+    /// assert_eq!(user, User::AnonymousId { anonymous_id: "f00".to_owned() });
+    /// ```
+    pub fn generate_id(&self) -> String {
+        match &self.id_generator {
+            Some(generator) => generator.generate(),
+            None => UuidV4.generate(),
+        }
+    }
+
+    /// Replace the user IDs of every message pushed from now on with their
+    /// salted hash, once the enrichers ran, see [`UserIdHashing`].
+    pub fn set_user_id_hashing(&mut self, hashing: UserIdHashing) {
+        self.user_id_hashing = Some(hashing);
+    }
+
+    /// Drop or strip the messages of the users who did not grant the consent
+    /// categories they require, once they are enriched, see
+    /// [`ConsentFilter`].
+    ///
+    /// Dropped messages are reported as accepted by [Self::push].
+    pub fn set_consent_filter(&mut self, filter: ConsentFilter) {
+        self.consent_filter = Some(filter);
+    }
+
+    /// Call `handler` with every [`Warning`] reported while processing the
+    /// pushed messages. The warnings are logged in any case, and so are the
+    /// panics of `handler`.
+    pub fn set_warning_handler(&mut self, handler: impl Fn(&Warning) + Send + Sync + 'static) {
+        self.warnings.set_handler(Arc::new(handler));
+    }
+
     pub fn is_empty(&self) -> bool {
         self.buf.is_empty()
     }
 
+    /// Iterate over the buffered messages, oldest first, as the JSON which
+    /// will be sent to Segment, after the processing of the batcher.
+    ///
+    /// ```
+    /// use segment::Batcher;
+    /// use segment::message::{BatchMessage, Track};
+    ///
+    /// let mut batcher = Batcher::new(None);
+    /// batcher.push(Track::default()).unwrap();
+    ///
+    /// for raw in batcher.iter() {
+    ///     let msg: BatchMessage = serde_json::from_str(raw.get()).unwrap();
+    ///     println!("buffered: {msg:?}");
+    /// }
+    /// ```
+    pub fn iter(&self) -> impl ExactSizeIterator<Item = &RawValue> + '_ {
+        self.buf.iter().map(AsRef::as_ref)
+    }
+
     /// Push a message into the batcher.
     ///
     /// Returns `Ok(None)` if the message was accepted and is now owned by the
@@ -92,34 +537,445 @@ impl Batcher {
     ///
     /// Returns `Ok(Some(msg))` if the message was rejected because the current
     /// batch would be oversized if this message were accepted. The given
-    /// message is returned back, and it is recommended that you flush the
-    /// current batch before attempting to push `msg` in again.
+    /// message is returned back as it was pushed, none of the processing of
+    /// the batcher applied, and it is recommended that you flush the current
+    /// batch before attempting to push `msg` in again.
     ///
     /// Returns an error if the message is too large to be sent to Segment's
     /// API.
     pub fn push(&mut self, msg: impl Into<BatchMessage>) -> Result<Option<BatchMessage>> {
-        let mut msg: BatchMessage = msg.into();
-        let timestamp = msg.timestamp_mut();
-        if self.auto_timestamp && timestamp.is_none() {
-            *timestamp = Some(OffsetDateTime::now_utc());
+        let msg = msg.into();
+        // only copied to be returned unprocessed when it may not fit
+        let original = (!self.has_room()).then(|| msg.clone());
+        let Some((processed, admission)) = self.process(msg) else {
+            return Ok(None);
+        };
+        self.push_processed(original, processed, admission)
+    }
+
+    /// Push a message into the batcher, with its properties (or traits)
+    /// computed by `properties` only if the message is not dropped by the
+    /// [deduplication](Self::set_deduplication), the [event
+    /// limit](Self::set_event_limit), the [event filter](Self::set_event_filter),
+    /// the [sampling](Self::set_sampling) or the [rules](Self::set_rules), see
+    /// [Self::push].
+    ///
+    /// The properties of `msg` are replaced. The properties are computed at
+    /// [`Step::Properties`], after the event filter, the sampling, the
+    /// deduplication and the event limit unless the [`Pipeline`] reorders
+    /// them, and the rules matching properties or redacting them are applied
+    /// then, the filtering steps therefore see the message before these rules. A
+    /// message returned because the batch is full holds the computed
+    /// properties, it is pushed again with [Self::push].
+    ///
+    /// If `properties` panics, the message keeps the properties it was pushed
+    /// with, and a [`Warning::HookSkipped`] is reported.
+    ///
+    /// ```
+    /// use segment::Batcher;
+    /// use segment::message::Track;
+    /// use serde_json::json;
+    ///
+    /// # fn expensive_cart_summary() -> serde_json::Value { json!({}) }
+    /// let mut batcher = Batcher::new(None);
+    /// batcher.push_with(
+    ///     Track { event: "Cart Viewed".to_owned(), ..Default::default() },
+    ///     || expensive_cart_summary(),
+    /// ).unwrap();
+    /// ```
+    pub fn push_with(
+        &mut self,
+        msg: impl Into<BatchMessage>,
+        properties: impl FnOnce() -> Value,
+    ) -> Result<Option<BatchMessage>> {
+        let msg = msg.into();
+        // only copied to be returned unprocessed when it may not fit
+        let mut original = (!self.has_room()).then(|| msg.clone());
+        let mut computed = None;
+        let properties = || {
+            let properties = properties();
+            if original.is_some() {
+                computed = Some(properties.clone());
+            }
+            properties
+        };
+        let Some((processed, admission)) = self.process_with(msg, Some(properties)) else {
+            return Ok(None);
+        };
+        if let (Some(computed), Some(slot)) = (
+            computed,
+            original.as_mut().and_then(BatchMessage::properties_mut),
+        ) {
+            *slot = computed;
+        }
+        self.push_processed(original, processed, admission)
+    }
+
+    /// Enqueue `processed`, returns its `original` message if the batch is
+    /// full, which is copied when the batch has no room for every message,
+    /// see [Self::has_room].
+    fn push_processed(
+        &mut self,
+        original: Option<BatchMessage>,
+        processed: BatchMessage,
+        admission: Admission,
+    ) -> Result<Option<BatchMessage>> {
+        let raw = self.serialize(&processed)?;
+        if self.enqueue(raw).is_some() {
+            let original = original.expect("a message is only refused by a batch without room");
+            return Ok(Some(original));
+        }
+        self.admit(admission);
+        Ok(None)
+    }
+
+    /// Push several messages into the batcher, until it is full.
+    ///
+    /// Returns the messages which didn't fit, it is recommended that you
+    /// flush the current batch before extending a new batcher with them.
+    ///
+    /// The messages which are rejected, for instance as too large to be sent
+    /// to Segment's API, are returned with their error, the following
+    /// messages are still pushed.
+    ///
+    /// ```
+    /// use segment::{Batcher, Client, HttpClient};
+    /// use segment::message::{BatchMessage, Track, User};
+    ///
+    /// let client = HttpClient::default();
+    /// let mut msgs: Vec<BatchMessage> = (0..1000)
+    ///     .map(|i| Track {
+    ///         user: User::UserId { user_id: format!("user-{i}") },
+    ///         event: "Example".to_owned(),
+    ///         ..Default::default()
+    ///     }.into())
+    ///     .collect();
+    ///
+    /// while !msgs.is_empty() {
+    ///     let mut batcher = Batcher::new(None);
+    ///     let extended = batcher.extend(msgs);
+    ///     assert!(extended.errors.is_empty());
+    ///     msgs = extended.remaining;
+    ///     client.send("your_write_key".to_string(), batcher.into_message().unwrap()); // .await
+    /// }
+    /// ```
+    pub fn extend<I>(&mut self, msgs: I) -> Extended
+    where
+        I: IntoIterator,
+        I::Item: Into<BatchMessage>,
+    {
+        let mut extended = Extended::default();
+        let mut msgs = msgs.into_iter().enumerate();
+        while let Some((i, msg)) = msgs.next() {
+            match self.push(msg) {
+                Ok(None) => {}
+                Ok(Some(rejected)) => {
+                    extended.remaining = std::iter::once(rejected)
+                        .chain(msgs.map(|(_, msg)| msg.into()))
+                        .collect();
+                    break;
+                }
+                Err(err) => extended.errors.push((i, err)),
+            }
+        }
+        extended
+    }
+
+    /// Apply the configured processing to a new message, returns `None` if it
+    /// must be dropped.
+    ///
+    /// This must only be applied once to a message, a message returned by
+    /// [Self::enqueue] is pushed back with [Self::enqueue] directly. Once the
+    /// message is enqueued, its [`Admission`] is passed to [Self::admit].
+    pub(crate) fn process(&mut self, msg: BatchMessage) -> Option<(BatchMessage, Admission)> {
+        self.process_with(msg, None::<fn() -> Value>)
+    }
+
+    /// Like [Self::process], computing the properties of the message with
+    /// `properties` once it passed the filters, see [Self::push_with].
+    pub(crate) fn process_with(
+        &mut self,
+        mut msg: BatchMessage,
+        mut properties: Option<impl FnOnce() -> Value>,
+    ) -> Option<(BatchMessage, Admission)> {
+        let mut admission = Admission::default();
+        let mut rules_applied = false;
+        for entry in self.pipeline.entries().iter() {
+            let kept = match entry {
+                Entry::Hooks(hooks) => hooks::run(hooks, &mut msg, &self.warnings),
+                Entry::Step(step) => self.run_step(
+                    *step,
+                    &mut msg,
+                    &mut properties,
+                    &mut rules_applied,
+                    &mut admission,
+                ),
+            };
+            if !kept {
+                return None;
+            }
+        }
+        Some((msg, admission))
+    }
+
+    /// Run a built-in step of the processing on `msg`, returns whether the
+    /// message is kept.
+    ///
+    /// The rules reading the properties wait for the lazy `properties` to be
+    /// computed.
+    fn run_step(
+        &mut self,
+        step: Step,
+        msg: &mut BatchMessage,
+        properties: &mut Option<impl FnOnce() -> Value>,
+        rules_applied: &mut bool,
+        admission: &mut Admission,
+    ) -> bool {
+        match step {
+            Step::Rules => {
+                *rules_applied = true;
+                let lazy = properties.is_some();
+                self.rules
+                    .as_ref()
+                    .is_none_or(|rules| rules.apply(msg, |rule| !lazy || !rule.reads_properties()))
+            }
+            Step::EventFilter => self
+                .event_filter
+                .as_ref()
+                .is_none_or(|filter| filter.allow_message(msg)),
+            Step::Sampling => self
+                .sampling
+                .as_ref()
+                .is_none_or(|sampling| sampling.allow(msg)),
+            Step::Deduplication => self
+                .deduplicator
+                .as_mut()
+                .is_none_or(|deduplicator| deduplicator.allow(msg, admission)),
+            Step::EventLimit => self
+                .event_limiter
+                .as_mut()
+                .is_none_or(|event_limiter| event_limiter.allow(msg, admission)),
+            Step::Properties => {
+                let Some(properties) = properties.take() else {
+                    return true;
+                };
+                match catch_panic(properties) {
+                    Ok(properties) => {
+                        if let Some(slot) = msg.properties_mut() {
+                            *slot = properties;
+                        }
+                    }
+                    // the message is kept with the properties it was pushed with
+                    Err(reason) => self.warnings.emit(Warning::HookSkipped {
+                        hook: "properties".to_owned(),
+                        reason,
+                    }),
+                }
+                match &self.rules {
+                    Some(rules) if *rules_applied => rules.apply(msg, Rule::reads_properties),
+                    _ => true,
+                }
+            }
+            Step::Defaults => {
+                if let Some((context, strategy)) = &self.message_context {
+                    strategy.merge(msg.context_mut(), context);
+                }
+                if let Some((integrations, strategy)) = &self.message_integrations {
+                    strategy.merge(msg.integrations_mut(), integrations);
+                }
+                if self.auto_timestamp {
+                    msg.stamp();
+                }
+                true
+            }
+            // the enrichers are hooks of the pipeline
+            Step::Enrichers => true,
+            Step::MessageId => {
+                if let (Some(generator), None) = (&self.id_generator, msg.message_id()) {
+                    match catch_panic(|| generator.generate()) {
+                        Ok(id) => msg.set_message_id(id),
+                        Err(reason) => self.warnings.emit(Warning::HookSkipped {
+                            hook: "id generator".to_owned(),
+                            reason,
+                        }),
+                    }
+                }
+                true
+            }
+            Step::UserIdHashing => {
+                if let Some(hashing) = &self.user_id_hashing {
+                    hashing.apply(msg);
+                }
+                true
+            }
+            Step::Consent => self
+                .consent_filter
+                .as_ref()
+                .is_none_or(|filter| filter.apply(msg)),
+            Step::Flatten => {
+                if let (Some(options), Some(properties)) = (&self.flatten, msg.properties_mut()) {
+                    options.flatten_reporting(properties, &self.warnings);
+                }
+                true
+            }
+            Step::Normalization => {
+                if let (Some(options), Some(properties)) =
+                    (&self.normalization, msg.properties_mut())
+                {
+                    options.normalize(properties);
+                }
+                true
+            }
+            Step::Schema => {
+                if self.schemas.is_some() {
+                    admission.shape = SchemaTracker::shape(msg);
+                }
+                true
+            }
         }
-        let size = serde_json::to_vec(&msg)?.len();
-        if size > MAX_MESSAGE_SIZE {
+    }
+
+    /// Update the state of the stages counting the enqueued messages, once
+    /// a processed message is enqueued.
+    pub(crate) fn admit(&mut self, admission: Admission) {
+        if let (Some(deduplicator), Some(id)) = (&mut self.deduplicator, admission.message_id) {
+            deduplicator.record(id);
+        }
+        if let (Some(event_limiter), Some(key)) = (&mut self.event_limiter, admission.event) {
+            event_limiter.record(key);
+        }
+        if let (Some(schemas), Some(shape)) = (&self.schemas, admission.shape) {
+            schemas.record(shape, &self.warnings);
+        }
+    }
+
+    /// Serialize an already processed message, checking that it is not too
+    /// large to ever fit in a batch.
+    pub(crate) fn serialize(&self, msg: &BatchMessage) -> Result<Box<RawValue>> {
+        if let Some(validation) = &self.validation {
+            validation.check(msg, &self.warnings)?;
+        }
+        if let Some(plan) = &self.tracking_plan {
+            plan.check(msg, &self.warnings)?;
+        }
+        let raw = serde_json::value::to_raw_value(msg)?;
+        if let Some(validation) = &self.validation {
+            validation.check_size(msg, raw.get().len())?;
+        }
+        self.check_size(&raw)?;
+        Ok(raw)
+    }
+
+    /// Check that a serialized message is not too large to ever fit in a
+    /// batch.
+    pub(crate) fn check_size(&self, msg: &RawValue) -> Result<()> {
+        let size = self.resolved_len(msg);
+        if size > self.limits.max_message_bytes || self.envelope_size + size > self.limits.max_bytes
+        {
             return Err(Error::MessageTooLarge);
         }
+        Ok(())
+    }
+
+    /// Returns whether any message not too large to ever fit in a batch still
+    /// fits in this one.
+    fn has_room(&self) -> bool {
+        self.remaining_bytes() >= self.limits.max_message_bytes
+    }
 
-        self.byte_count += size + 1; // +1 to account for Serialized data's extra commas
-        if self.byte_count > MAX_BATCH_SIZE {
-            return Ok(Some(msg));
+    /// Add a serialized message to the batch, or return it back if the batch
+    /// is full, see [Self::push].
+    pub(crate) fn enqueue(&mut self, msg: Box<RawValue>) -> Option<Box<RawValue>> {
+        // the messages are separated by commas
+        let added = self.resolved_len(&msg) + usize::from(!self.buf.is_empty());
+        let full = self
+            .limits
+            .max_messages
+            .is_some_and(|max| self.buf.len() >= max);
+        if full || self.byte_count + added > self.limits.max_bytes {
+            return Some(msg);
         }
 
+        self.byte_count += added;
         self.buf.push(msg);
-        Ok(None)
+        None
+    }
+
+    /// Remove the oldest message from the batch.
+    pub(crate) fn pop_oldest(&mut self) -> Option<Box<RawValue>> {
+        if self.buf.is_empty() {
+            return None;
+        }
+        let msg = self.buf.remove(0);
+        self.byte_count -= self.resolved_len(&msg) + usize::from(!self.buf.is_empty());
+        Some(msg)
+    }
+
+    /// Returns the size of a serialized message once sent, its placeholders
+    /// resolved, at most.
+    fn resolved_len(&self, msg: &RawValue) -> usize {
+        let json = msg.get();
+        match self.templates {
+            true => json.len() + template::growth(json),
+            false => json.len(),
+        }
     }
 
-    pub(crate) fn take(&mut self) -> Vec<BatchMessage> {
-        self.byte_count = 0;
-        std::mem::take(&mut self.buf)
+    /// Returns a batcher with the configuration and the state of this one and
+    /// an empty buffer, without copying the buffered messages.
+    pub(crate) fn empty_clone(&self) -> Batcher {
+        Batcher {
+            buf: Vec::new(),
+            byte_count: self.envelope_size,
+            envelope_size: self.envelope_size,
+            context: self.context.clone(),
+            message_context: self.message_context.clone(),
+            message_integrations: self.message_integrations.clone(),
+            integrations: self.integrations.clone(),
+            limits: self.limits,
+            auto_timestamp: self.auto_timestamp,
+            flatten: self.flatten.clone(),
+            normalization: self.normalization.clone(),
+            schemas: self.schemas.clone(),
+            event_limiter: self.event_limiter.clone(),
+            event_filter: self.event_filter.clone(),
+            sampling: self.sampling.clone(),
+            rules: self.rules.clone(),
+            deduplicator: self.deduplicator.clone(),
+            validation: self.validation.clone(),
+            tracking_plan: self.tracking_plan.clone(),
+            pipeline: self.pipeline.clone(),
+            id_generator: self.id_generator.clone(),
+            user_id_hashing: self.user_id_hashing.clone(),
+            consent_filter: self.consent_filter.clone(),
+            warnings: self.warnings.clone(),
+            templates: self.templates,
+        }
+    }
+
+    /// Returns a batcher like [`Self::empty_clone`], whose schemas, counters
+    /// and warnings are kept apart from the ones of this batcher.
+    pub(crate) fn detached(&self) -> Batcher {
+        Batcher {
+            schemas: self.schemas.as_ref().map(SchemaTracker::detached),
+            event_filter: self.event_filter.as_ref().map(EventFilter::detached),
+            sampling: self.sampling.as_ref().map(Sampling::detached),
+            consent_filter: self.consent_filter.as_ref().map(ConsentFilter::detached),
+            warnings: Warnings::default(),
+            ..self.empty_clone()
+        }
+    }
+
+    /// Take the messages of the batch, leaving it empty.
+    pub(crate) fn take(&mut self) -> SerializedBatch {
+        self.byte_count = self.envelope_size;
+        SerializedBatch {
+            batch: std::mem::take(&mut self.buf).into(),
+            context: self.context.clone(),
+            integrations: self.integrations.clone(),
+            sent_at: None,
+            templates: self.templates,
+        }
     }
 
     /// Returns the length of the buffer as the number of messages in the batch buffer.
@@ -128,18 +984,132 @@ impl Batcher {
         self.buf.len()
     }
 
+    /// Returns the maximum number of messages in a batch, `None` if only the
+    /// size of the batch is limited, see [`BatchLimits`].
+    #[inline]
+    pub fn max_len(&self) -> Option<usize> {
+        self.limits.max_messages
+    }
+
+    /// Returns the size of an empty batch, in bytes: the envelope of the
+    /// messages, with the context, the integrations and the `sentAt`
+    /// timestamp set by the [`HttpClient`](crate::HttpClient).
+    #[inline]
+    pub fn envelope_size(&self) -> usize {
+        self.envelope_size
+    }
+
+    /// Returns the exact size of the serialized batch, in bytes.
+    #[inline]
+    pub fn size_bytes(&self) -> usize {
+        self.byte_count
+    }
+
+    /// Returns the size of the largest serialized message which still fits in
+    /// the batch, in bytes. This is `0` once the batch holds [Self::max_len]
+    /// messages.
+    ///
+    /// ```
+    /// use segment::Batcher;
+    /// use segment::message::Track;
+    ///
+    /// let mut batcher = Batcher::new(None);
+    /// batcher.push(Track::default()).unwrap();
+    ///
+    /// // flush when the batch is 80% full
+    /// let used = batcher.size_bytes() as f64 / (batcher.size_bytes() + batcher.remaining_bytes()) as f64;
+    /// if used > 0.8 {
+    ///     // send batcher.into_message()
+    /// }
+    /// ```
+    pub fn remaining_bytes(&self) -> usize {
+        if self.max_len().is_some_and(|max| self.buf.len() >= max) {
+            return 0;
+        }
+        // the next message is preceded by a comma
+        let separator = usize::from(!self.buf.is_empty());
+        (self
+            .limits
+            .max_bytes
+            .saturating_sub(self.byte_count + separator))
+        .min(self.limits.max_message_bytes)
+    }
+
     /// Consumes this batcher and converts it into a message that can be sent to
     /// Segment.
-    pub fn into_message(self) -> Message {
-        Message::Batch(Batch {
-            batch: self.buf,
-            context: self.context,
-            integrations: None,
-            extra: Map::default(),
-        })
+    ///
+    /// The messages are deserialized back, [Self::into_serialized] is cheaper
+    /// when the batch is only sent. The placeholders of the
+    /// [send time templates](Self::set_send_time_templates) are resolved now.
+    ///
+    /// Returns [`Error::DeserializeError`] if a message can't be deserialized
+    /// back, for instance when its `extra` fields repeat one of its fields.
+    pub fn into_message(mut self) -> Result<Message> {
+        let mut batch = self.take();
+        if batch.templates {
+            batch = template::resolve(&batch, OffsetDateTime::now_utc());
+        }
+        Ok(batch.to_message()?)
     }
+
+    /// Consumes this batcher and returns its serialized batch, which can be
+    /// sent with [`Client::send_batch`](crate::Client::send_batch).
+    pub fn into_serialized(mut self) -> SerializedBatch {
+        self.take()
+    }
+}
+
+/// What a processed message changes in the state of the [`Batcher`] once it
+/// is enqueued, see [`Batcher::admit`]. A message which is not enqueued, for
+/// instance because the batch is full, doesn't count as pushed.
+#[derive(Debug, Default)]
+#[must_use]
+pub(crate) struct Admission {
+    /// The `messageId` to remember for the deduplication.
+    pub(crate) message_id: Option<String>,
+    /// The window of the event limit to count the message in.
+    pub(crate) event: Option<WindowKey>,
+    /// The shape of the event to record in the schema tracker.
+    pub(crate) shape: Option<EventShape>,
 }
 
+/// The name of this library in the `context.library` of the batches.
+pub const LIBRARY_NAME: &str = "segment-rust";
+
+/// Returns the `context.library` identifying this crate.
+fn library() -> Value {
+    json!({ "name": LIBRARY_NAME, "version": env!("CARGO_PKG_VERSION") })
+}
+
+/// Set the `library` of `context` to this crate, unless it already has one.
+fn with_library(context: Option<Value>) -> Option<Value> {
+    match context {
+        None => Some(json!({ "library": library() })),
+        Some(Value::Object(mut map)) => {
+            map.entry("library").or_insert_with(library);
+            Some(Value::Object(map))
+        }
+        other => other,
+    }
+}
+
+/// Returns the size of an empty batch, including the longest `sentAt` the
+/// client may set.
+fn envelope_size(context: &Option<Value>, integrations: &Option<Value>) -> usize {
+    let envelope = SerializedBatch {
+        batch: Arc::new([]),
+        context: context.clone(),
+        integrations: integrations.clone(),
+        sent_at: Some(LONGEST_SENT_AT),
+        templates: false,
+    };
+    serde_json::to_vec(&envelope).map_or(0, |json| json.len())
+}
+
+/// A timestamp with nanoseconds, the longest one in RFC 3339.
+const LONGEST_SENT_AT: OffsetDateTime =
+    OffsetDateTime::UNIX_EPOCH.saturating_add(time::Duration::nanoseconds(999_999_999));
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -158,15 +1128,19 @@ mod tests {
 
         let mut batcher = Batcher::new(Some(context.clone()));
         batcher.without_auto_timestamp();
+        batcher.without_message_ids();
         let result = batcher.push(batch_msg.clone());
         assert_eq!(None, result.ok().unwrap());
 
-        let batch = batcher.into_message();
+        let batch = batcher.into_message().unwrap();
         let inner_batch = match batch {
             Message::Batch(b) => b,
             _ => panic!("invalid message type"),
         };
-        assert_eq!(context, inner_batch.context.unwrap());
+        assert_eq!(
+            inner_batch.context.unwrap(),
+            json!({ "foo": "bar", "library": library() })
+        );
         assert_eq!(1, inner_batch.batch.len());
 
         assert_eq!(inner_batch.batch, vec![batch_msg]);
@@ -199,6 +1173,7 @@ mod tests {
 
         let mut batcher = Batcher::new(None);
         batcher.without_auto_timestamp();
+        batcher.without_message_ids();
         let mut result = Ok(None);
         for _i in 0..20 {
             result = batcher.push(batch_msg.clone());
@@ -211,4 +1186,474 @@ mod tests {
         let msg = result.ok().unwrap();
         assert_eq!(BatchMessage::from(batch_msg), msg.unwrap());
     }
+
+    /// The size of the batch sent by the client.
+    fn sent_size(batcher: &Batcher) -> usize {
+        let mut batch = batcher.clone().into_serialized();
+        batch.set_sent_at(Some(LONGEST_SENT_AT));
+        batch.to_json().unwrap().len()
+    }
+
+    #[test]
+    fn test_envelope_size() {
+        let mut batcher = Batcher::new(Some(json!({ "library": "segment" })));
+        assert_eq!(batcher.envelope_size(), sent_size(&batcher));
+
+        batcher.push(Track::default()).unwrap();
+        batcher
+            .set_integrations(Some(json!({ "Amplitude": false })))
+            .unwrap();
+        assert_eq!(batcher.size_bytes(), sent_size(&batcher));
+
+        let size = batcher.size_bytes();
+        let mut batch = batcher.into_serialized();
+        batch.set_sent_at(Some(OffsetDateTime::now_utc()));
+        assert!(batch.to_json().unwrap().len() <= size);
+    }
+
+    #[test]
+    fn test_message_context() {
+        let mut batcher = Batcher::new(None);
+        let defaults = crate::Context::builder()
+            .locale("en-US")
+            .ip("0.0.0.0")
+            .build();
+        batcher.set_message_context(defaults, MergeStrategy::Deep);
+        batcher
+            .push(Track {
+                context: Some(json!({ "ip": "203.0.113.7" })),
+                ..Default::default()
+            })
+            .unwrap();
+
+        let Message::Batch(mut batch) = batcher.into_message().unwrap() else {
+            panic!("invalid message type")
+        };
+        assert_eq!(
+            *batch.batch[0].context_mut(),
+            Some(json!({ "ip": "203.0.113.7", "locale": "en-US" }))
+        );
+    }
+
+    #[test]
+    fn test_message_integrations() {
+        let mut batcher = Batcher::new(None);
+        batcher.set_message_integrations(
+            json!({ "All": false, "Amplitude": true }),
+            MergeStrategy::Shallow,
+        );
+        batcher.push(Track::default()).unwrap();
+        batcher
+            .push(Track {
+                integrations: Some(json!({ "Amplitude": false })),
+                ..Default::default()
+            })
+            .unwrap();
+
+        let Message::Batch(mut batch) = batcher.into_message().unwrap() else {
+            panic!("invalid message type")
+        };
+        assert_eq!(
+            *batch.batch[0].integrations_mut(),
+            Some(json!({ "All": false, "Amplitude": true }))
+        );
+        assert_eq!(
+            *batch.batch[1].integrations_mut(),
+            Some(json!({ "All": false, "Amplitude": false }))
+        );
+    }
+
+    #[test]
+    fn test_library_context() {
+        let context = |batcher: Batcher| batcher.into_serialized().context;
+        let library = library();
+        assert_eq!(library["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(
+            context(Batcher::new(None)),
+            Some(json!({ "library": library }))
+        );
+
+        let overridden = json!({ "library": { "name": "wrapper" } });
+        assert_eq!(
+            context(Batcher::new(Some(overridden.clone()))),
+            Some(overridden.clone())
+        );
+
+        let mut batcher = Batcher::new(Some(json!({ "ip": "0.0.0.0" })));
+        batcher.push(Track::default()).unwrap();
+        batcher.without_library_context();
+        assert_eq!(batcher.size_bytes(), sent_size(&batcher));
+        assert_eq!(context(batcher), Some(json!({ "ip": "0.0.0.0" })));
+
+        let mut batcher = Batcher::new(Some(overridden.clone()));
+        batcher.without_library_context();
+        assert_eq!(context(batcher), Some(overridden));
+
+        let mut batcher = Batcher::new(None);
+        batcher.without_library_context();
+        assert_eq!(context(batcher), None);
+    }
+
+    #[test]
+    fn test_exact_byte_count() {
+        let mut batcher = Batcher::new(Some(json!({ "library": "segment" })));
+        let limits = BatchLimits {
+            max_messages: None,
+            max_bytes: 2048,
+            max_message_bytes: 512,
+        };
+        batcher.set_batch_limits(limits).unwrap();
+
+        let mut i = 0;
+        while batcher
+            .push(Track {
+                event: "a".repeat(i % 50),
+                ..Default::default()
+            })
+            .unwrap()
+            .is_none()
+        {
+            i += 1;
+            assert_eq!(batcher.byte_count, sent_size(&batcher));
+        }
+        assert!(batcher.byte_count <= 2048);
+
+        batcher.pop_oldest();
+        assert_eq!(batcher.byte_count, sent_size(&batcher));
+    }
+
+    #[test]
+    fn test_batch_limits() {
+        let mut batcher = Batcher::new(None);
+        let limits = BatchLimits {
+            max_messages: Some(2),
+            max_bytes: 1024,
+            max_message_bytes: 512,
+        };
+        batcher.set_batch_limits(limits).unwrap();
+
+        assert!(batcher.push(Track::default()).unwrap().is_none());
+        assert!(batcher.push(Track::default()).unwrap().is_none());
+        assert!(batcher.push(Track::default()).unwrap().is_some());
+
+        let large = Track {
+            event: "a".repeat(600),
+            ..Default::default()
+        };
+        assert!(matches!(batcher.push(large), Err(Error::MessageTooLarge)));
+
+        for invalid in [
+            BatchLimits {
+                max_messages: Some(0),
+                ..limits
+            },
+            BatchLimits {
+                max_bytes: MAX_BATCH_SIZE + 1,
+                ..limits
+            },
+            BatchLimits {
+                max_message_bytes: 2048,
+                ..limits
+            },
+        ] {
+            assert!(matches!(
+                batcher.set_batch_limits(invalid),
+                Err(Error::InvalidConfiguration(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_iter() {
+        let mut batcher = Batcher::new(None);
+        batcher.without_auto_timestamp();
+        batcher.without_message_ids();
+        let extended = batcher.extend((0..3).map(|i| Track {
+            event: format!("event-{i}"),
+            ..Default::default()
+        }));
+        assert!(extended.errors.is_empty());
+
+        let events: Vec<Value> = batcher
+            .iter()
+            .map(|raw| serde_json::from_str::<Value>(raw.get()).unwrap()["event"].clone())
+            .collect();
+        assert_eq!(
+            events,
+            [json!("event-0"), json!("event-1"), json!("event-2")]
+        );
+    }
+
+    #[test]
+    fn test_size_introspection() {
+        let mut batcher = Batcher::new(None);
+        batcher
+            .set_batch_limits(BatchLimits {
+                max_messages: Some(2),
+                max_bytes: 512,
+                max_message_bytes: 512,
+            })
+            .unwrap();
+        batcher.without_library_context();
+        assert_eq!(batcher.max_len(), Some(2));
+        assert_eq!(batcher.size_bytes(), batcher.envelope_size);
+        assert_eq!(batcher.remaining_bytes(), 512 - batcher.envelope_size);
+
+        let msg = Track {
+            event: "a".repeat(50),
+            ..Default::default()
+        };
+        batcher.push(msg.clone()).unwrap();
+        let size = batcher.size_bytes();
+        let remaining = batcher.remaining_bytes();
+        assert_eq!(size + remaining + 1, 512);
+        assert_eq!(size, sent_size(&batcher));
+
+        batcher.push(msg).unwrap();
+        assert_eq!(batcher.remaining_bytes(), 0);
+    }
+
+    #[test]
+    fn test_default_message_ids() {
+        let mut batcher = Batcher::new(None);
+        batcher.push(Track::default()).unwrap();
+        batcher.push(Track::default()).unwrap();
+
+        let ids = batcher.into_serialized().message_ids();
+        assert_eq!(ids.len(), 2);
+        assert_ne!(ids[0], ids[1]);
+    }
+
+    #[test]
+    fn test_id_generator() {
+        let mut batcher = Batcher::new(None);
+        let counter = std::sync::atomic::AtomicUsize::new(0);
+        batcher.set_id_generator(move || {
+            let id = counter.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            format!("id-{id}")
+        });
+
+        batcher.push(Track::default()).unwrap();
+        batcher
+            .push(Track {
+                extra: [("messageId".to_owned(), json!("custom"))]
+                    .into_iter()
+                    .collect(),
+                ..Default::default()
+            })
+            .unwrap();
+        batcher.push(Track::default()).unwrap();
+
+        assert_eq!(batcher.generate_id(), "id-2");
+        assert_eq!(
+            batcher.into_serialized().message_ids(),
+            ["id-0", "custom", "id-1"]
+        );
+
+        let mut batcher = Batcher::new(None);
+        batcher.push(Track::default()).unwrap();
+        let ids = batcher.into_serialized().message_ids();
+        assert_eq!(uuid::Uuid::parse_str(&ids[0]).unwrap().get_version_num(), 4);
+    }
+
+    #[test]
+    fn test_push_with() {
+        let mut batcher = Batcher::new(None);
+        batcher.without_auto_timestamp();
+        batcher.without_message_ids();
+        batcher.set_rules(
+            Rules::from_json(
+                r#"[
+                    { "match": { "event": "dropped" }, "action": "drop" },
+                    { "action": "redact", "properties": ["email"] }
+                ]"#,
+            )
+            .unwrap(),
+        );
+
+        let dropped = Track {
+            event: "dropped".to_owned(),
+            ..Default::default()
+        };
+        batcher
+            .push_with(dropped, || {
+                panic!("computed the properties of a dropped event")
+            })
+            .unwrap();
+        assert!(batcher.is_empty());
+
+        let kept = Track {
+            event: "kept".to_owned(),
+            ..Default::default()
+        };
+        batcher
+            .push_with(kept, || json!({ "email": "a@b.c", "plan": "pro" }))
+            .unwrap();
+        let expected = BatchMessage::Track(Track {
+            event: "kept".to_owned(),
+            properties: json!({ "email": crate::REDACTED, "plan": "pro" }),
+            ..Default::default()
+        });
+        assert_eq!(batcher.take().messages().unwrap(), vec![expected]);
+
+        let buggy = Track {
+            event: "buggy".to_owned(),
+            properties: json!({ "plan": "free" }),
+            ..Default::default()
+        };
+        batcher
+            .push_with(buggy.clone(), || panic!("buggy properties"))
+            .unwrap();
+        assert_eq!(
+            batcher.take().messages().unwrap(),
+            vec![BatchMessage::Track(buggy)]
+        );
+
+        // returned unprocessed, with the computed properties, once full
+        batcher
+            .set_batch_limits(BatchLimits {
+                max_messages: Some(1),
+                ..Default::default()
+            })
+            .unwrap();
+        batcher.push(Track::default()).unwrap();
+        let full = Track {
+            event: "full".to_owned(),
+            ..Default::default()
+        };
+        let returned = batcher
+            .push_with(full.clone(), || json!({ "email": "a@b.c" }))
+            .unwrap();
+        let expected = Track {
+            properties: json!({ "email": "a@b.c" }),
+            ..full
+        };
+        assert_eq!(returned, Some(BatchMessage::Track(expected)));
+    }
+
+    #[test]
+    fn test_extend() {
+        let mut batcher = Batcher::new(None);
+        let msgs: Vec<_> = (0..20)
+            .map(|i| Track {
+                user: User::UserId {
+                    user_id: format!("{i}-{}", "a".repeat(1024 * 30)),
+                },
+                ..Default::default()
+            })
+            .collect();
+
+        let extended = batcher.extend(msgs.clone());
+        assert!(extended.errors.is_empty());
+        let rejected = extended.remaining;
+        assert_eq!(batcher.len() + rejected.len(), 20);
+        assert!(rejected.len() > 1);
+        let BatchMessage::Track(first) = &rejected[0] else {
+            panic!("expected a track");
+        };
+        assert_eq!(first.user, msgs[batcher.len()].user);
+    }
+
+    #[test]
+    fn test_extend_past_errors() {
+        let mut batcher = Batcher::new(None);
+        let msgs: Vec<_> = (0..4)
+            .map(|i| Track {
+                event: if i == 1 {
+                    "a".repeat(MAX_MESSAGE_SIZE)
+                } else {
+                    format!("event-{i}")
+                },
+                ..Default::default()
+            })
+            .collect();
+
+        let extended = batcher.extend(msgs);
+        assert_eq!(batcher.len(), 3);
+        assert!(extended.remaining.is_empty());
+        assert_eq!(extended.errors.len(), 1);
+        assert!(matches!(extended.errors[0], (1, Error::MessageTooLarge)));
+    }
+
+    #[test]
+    fn test_deduplication_of_returned_messages() {
+        let mut batcher = Batcher::new(None);
+        batcher.set_deduplication(Deduplication::default());
+        batcher
+            .set_batch_limits(BatchLimits {
+                max_messages: Some(1),
+                ..Default::default()
+            })
+            .unwrap();
+
+        let track = |id: &str| {
+            let mut track = Track::default();
+            track.set_message_id(id);
+            track
+        };
+        assert!(batcher.push(track("a")).unwrap().is_none());
+        let returned = batcher.push(track("b")).unwrap().unwrap();
+
+        let mut next = batcher.clone();
+        next.take();
+        assert!(next.push(returned).unwrap().is_none());
+        assert_eq!(next.len(), 1);
+        next.take();
+        assert!(next.push(track("b")).unwrap().is_none());
+        assert!(next.is_empty());
+    }
+
+    #[test]
+    fn test_returned_messages_are_unprocessed() {
+        let schemas = SchemaTracker::default();
+        let mut batcher = Batcher::new(None);
+        batcher.set_schema_tracker(schemas.clone());
+        batcher
+            .set_batch_limits(BatchLimits {
+                max_messages: Some(1),
+                ..Default::default()
+            })
+            .unwrap();
+
+        let track = || Track {
+            event: "Signed Up".to_owned(),
+            ..Default::default()
+        };
+        assert!(batcher.push(track()).unwrap().is_none());
+        let returned = batcher.push(track()).unwrap().unwrap();
+        assert_eq!(returned, BatchMessage::Track(track()));
+        let returned = batcher
+            .push_with(track(), || json!({ "plan": "pro" }))
+            .unwrap()
+            .unwrap();
+        assert_eq!(returned.properties(), Some(&json!({ "plan": "pro" })));
+        assert_eq!(returned.message_id(), None);
+        assert_eq!(schemas.event("Signed Up").unwrap().count, 1);
+
+        batcher.take();
+        assert!(batcher.push(returned).unwrap().is_none());
+        let schema = schemas.event("Signed Up").unwrap();
+        assert_eq!(schema.count, 2);
+        assert!(schema.is_optional("plan"));
+    }
+
+    #[test]
+    fn test_property_flattening() {
+        let mut batcher = Batcher::new(None);
+        batcher.without_auto_timestamp();
+        batcher.without_message_ids();
+        batcher.set_property_flattening(FlattenOptions::default());
+        batcher
+            .push(Track {
+                properties: json!({ "address": { "city": "Paris" } }),
+                ..Default::default()
+            })
+            .unwrap();
+
+        let expected = BatchMessage::Track(Track {
+            properties: json!({ "address_city": "Paris" }),
+            ..Default::default()
+        });
+        assert_eq!(batcher.take().messages().unwrap(), vec![expected]);
+    }
 }
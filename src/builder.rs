@@ -0,0 +1,235 @@
+//! A builder checking at compile time that an [`AutoBatcher`] is complete.
+
+use std::num::NonZeroUsize;
+
+use crate::{
+    auto_batcher::AutoBatcher,
+    batcher::Batcher,
+    client::Client,
+    errors::{Error, Result},
+    queue::OverflowPolicy,
+    rate_limit::RateLimiter,
+    retry::{RetryBudget, RetryPolicy},
+    runtime::{Runtime, SharedRuntime},
+    worker::{Worker, WorkerConfig},
+};
+
+/// Marks a required part of an [`AutoBatcherBuilder`] that was not provided
+/// yet.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Missing;
+
+/// A builder of [`AutoBatcher`] and [`Worker`].
+///
+/// The client, the write key and the [`Batcher`] are tracked in the type of
+/// the builder: `build` and `spawn` only exist once all of them were
+/// provided, so forgetting one is a compile error rather than silent `401`s.
+/// An empty write key is rejected when building.
+///
+/// ```
+/// use segment::{AutoBatcher, Batcher, HttpClient, OverflowPolicy};
+///
+/// let batcher = AutoBatcher::builder()
+///     .client(HttpClient::default())
+///     .write_key("your_write_key")
+///     .batcher(Batcher::new(None))
+///     .overflow_policy(OverflowPolicy::DropNewest)
+///     .build()
+///     .unwrap();
+/// ```
+///
+/// ```compile_fail
+/// use segment::{AutoBatcher, Batcher, HttpClient};
+///
+/// let batcher = AutoBatcher::builder()
+///     .client(HttpClient::default())
+///     .batcher(Batcher::new(None))
+///     .build(); // the write key is missing
+/// ```
+#[derive(Clone, Debug)]
+pub struct AutoBatcherBuilder<C = Missing, K = Missing, B = Missing> {
+    client: C,
+    write_key: K,
+    batcher: B,
+    rate_limiter: Option<RateLimiter>,
+    max_concurrent_flushes: Option<NonZeroUsize>,
+    overflow_policy: OverflowPolicy,
+    retry_policy: RetryPolicy,
+    retry_budget: Option<RetryBudget>,
+    runtime: Option<SharedRuntime>,
+}
+
+impl AutoBatcherBuilder {
+    /// Construct a new, empty builder.
+    pub fn new() -> Self {
+        Self {
+            client: Missing,
+            write_key: Missing,
+            batcher: Missing,
+            rate_limiter: None,
+            max_concurrent_flushes: None,
+            overflow_policy: OverflowPolicy::default(),
+            retry_policy: RetryPolicy::default(),
+            retry_budget: None,
+            runtime: None,
+        }
+    }
+}
+
+impl Default for AutoBatcherBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<C, K, B> AutoBatcherBuilder<C, K, B> {
+    /// Set the client sending the batches.
+    pub fn client<T: Client>(self, client: T) -> AutoBatcherBuilder<T, K, B> {
+        AutoBatcherBuilder {
+            client,
+            write_key: self.write_key,
+            batcher: self.batcher,
+            rate_limiter: self.rate_limiter,
+            max_concurrent_flushes: self.max_concurrent_flushes,
+            overflow_policy: self.overflow_policy,
+            retry_policy: self.retry_policy,
+            retry_budget: self.retry_budget,
+            runtime: self.runtime,
+        }
+    }
+
+    /// Set the write key of the Segment source.
+    pub fn write_key(self, write_key: impl Into<String>) -> AutoBatcherBuilder<C, String, B> {
+        AutoBatcherBuilder {
+            client: self.client,
+            write_key: write_key.into(),
+            batcher: self.batcher,
+            rate_limiter: self.rate_limiter,
+            max_concurrent_flushes: self.max_concurrent_flushes,
+            overflow_policy: self.overflow_policy,
+            retry_policy: self.retry_policy,
+            retry_budget: self.retry_budget,
+            runtime: self.runtime,
+        }
+    }
+
+    /// Set the [`Batcher`] buffering the messages, which defines how they are
+    /// processed and flushed.
+    pub fn batcher(self, batcher: Batcher) -> AutoBatcherBuilder<C, K, Batcher> {
+        AutoBatcherBuilder {
+            client: self.client,
+            write_key: self.write_key,
+            batcher,
+            rate_limiter: self.rate_limiter,
+            max_concurrent_flushes: self.max_concurrent_flushes,
+            overflow_policy: self.overflow_policy,
+            retry_policy: self.retry_policy,
+            retry_budget: self.retry_budget,
+            runtime: self.runtime,
+        }
+    }
+
+    /// See [`AutoBatcher::set_rate_limiter`].
+    pub fn rate_limiter(mut self, rate_limiter: RateLimiter) -> Self {
+        self.rate_limiter = Some(rate_limiter);
+        self
+    }
+
+    /// See [`AutoBatcher::set_max_concurrent_flushes`].
+    pub fn max_concurrent_flushes(mut self, max: NonZeroUsize) -> Self {
+        self.max_concurrent_flushes = Some(max);
+        self
+    }
+
+    /// See [`AutoBatcher::set_overflow_policy`].
+    pub fn overflow_policy(mut self, policy: OverflowPolicy) -> Self {
+        self.overflow_policy = policy;
+        self
+    }
+
+    /// See [`AutoBatcher::set_retry_policy`].
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = policy;
+        self
+    }
+
+    /// See [`AutoBatcher::set_retry_budget`].
+    pub fn retry_budget(mut self, budget: RetryBudget) -> Self {
+        self.retry_budget = Some(budget);
+        self
+    }
+
+    /// See [`AutoBatcher::set_runtime`].
+    pub fn runtime(mut self, runtime: impl Runtime + 'static) -> Self {
+        self.runtime = Some(SharedRuntime::new(runtime));
+        self
+    }
+}
+
+impl<C> AutoBatcherBuilder<C, String, Batcher>
+where
+    C: Client + Clone + Send + Sync + 'static,
+{
+    /// Build the [`AutoBatcher`].
+    ///
+    /// Returns [`Error::InvalidConfiguration`] if the write key is empty or
+    /// only whitespace, which Segment would reject.
+    pub fn build(self) -> Result<AutoBatcher<C>> {
+        if self.write_key.trim().is_empty() {
+            return Err(Error::InvalidConfiguration(
+                "the write key is empty".to_owned(),
+            ));
+        }
+        let mut batcher = AutoBatcher::new(self.client, self.batcher, self.write_key);
+        if let Some(rate_limiter) = self.rate_limiter {
+            batcher.set_rate_limiter(rate_limiter);
+        }
+        if let Some(max) = self.max_concurrent_flushes {
+            batcher.set_max_concurrent_flushes(max);
+        }
+        batcher.set_overflow_policy(self.overflow_policy);
+        batcher.set_retry_policy(self.retry_policy);
+        if let Some(budget) = self.retry_budget {
+            batcher.set_retry_budget(budget);
+        }
+        if let Some(runtime) = self.runtime {
+            batcher.runtime = runtime;
+        }
+        Ok(batcher)
+    }
+
+    /// Build the [`AutoBatcher`] and spawn a [`Worker`] around it, on its
+    /// [`Runtime`].
+    ///
+    /// Returns an error if the [`AutoBatcher`] cannot be built, see
+    /// [Self::build].
+    pub fn spawn(self, config: WorkerConfig) -> Result<Worker> {
+        Ok(Worker::spawn(self.build()?, config))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MockClient;
+
+    #[test]
+    fn test_empty_write_key() {
+        for key in ["", " \t"] {
+            let result = AutoBatcherBuilder::new()
+                .client(MockClient::default())
+                .write_key(key)
+                .batcher(Batcher::new(None))
+                .build();
+            assert!(matches!(result, Err(Error::InvalidConfiguration(_))));
+        }
+
+        let batcher = AutoBatcherBuilder::new()
+            .client(MockClient::default())
+            .write_key("key")
+            .batcher(Batcher::new(None))
+            .build()
+            .unwrap();
+        assert_eq!(batcher.key, "key");
+    }
+}
@@ -0,0 +1,212 @@
+//! A client isolating the failures of each write key.
+
+use std::collections::HashMap;
+use std::num::NonZeroU32;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::{
+    client::Client,
+    errors::{Error, Result},
+    http::HttpClient,
+    logging,
+    message::{Message, SerializedBatch},
+    runtime::Instant,
+};
+
+/// A [`Client`] keeping a circuit breaker per write key, so that in a
+/// multi-tenant pipeline a tenant whose requests keep failing, for instance
+/// with an invalid write key answered by `401`s, doesn't delay the other
+/// tenants.
+///
+/// After `failure_threshold` consecutive failed requests for a write key, its
+/// circuit opens: its requests fail right away with [`Error::CircuitOpen`],
+/// which is not retried, for `open_interval`. The next request after that
+/// probes the endpoint again, alone: the other requests still fail right away
+/// until it completes, and the circuit closes if it succeeds.
+///
+/// Only the network errors count as failures. The circuits are shared by the
+/// clones of the client, and independent of the retries and backoffs of each
+/// batcher. Give a [`RetryBudget`](crate::RetryBudget) to each tenant to also
+/// isolate their retries.
+///
+/// ```
+/// use segment::{AutoBatcher, Batcher, CircuitBreaker, HttpClient};
+///
+/// let client = CircuitBreaker::new(HttpClient::default());
+/// let tenants = ["write_key_a", "write_key_b"]
+///     .map(|key| AutoBatcher::new(client.clone(), Batcher::new(None), key.to_owned()));
+/// ```
+#[derive(Clone, Debug)]
+pub struct CircuitBreaker<C = HttpClient> {
+    client: C,
+    failure_threshold: NonZeroU32,
+    open_interval: Duration,
+    circuits: Arc<Mutex<HashMap<String, Circuit>>>,
+}
+
+/// The circuit of a write key whose last request failed, the write keys
+/// without failures are not tracked.
+#[derive(Debug, Default)]
+struct Circuit {
+    consecutive_failures: u32,
+    open_until: Option<Instant>,
+    /// While a request probes the endpoint, until when the other requests
+    /// still fail right away, in case the probe is cancelled.
+    probing_until: Option<Instant>,
+}
+
+impl Circuit {
+    /// Returns whether the requests fail right away at `now`.
+    fn is_open(&self, now: Instant) -> bool {
+        self.open_until.is_some_and(|until| now < until)
+            || self.probing_until.is_some_and(|until| now < until)
+    }
+}
+
+impl<C> CircuitBreaker<C> {
+    /// Wrap `client`.
+    ///
+    /// The circuit of a write key opens after 5 consecutive failures, for 60
+    /// seconds.
+    pub fn new(client: C) -> Self {
+        Self {
+            client,
+            failure_threshold: NonZeroU32::new(5).unwrap(),
+            open_interval: Duration::from_secs(60),
+            circuits: Arc::default(),
+        }
+    }
+
+    /// Set after how many consecutive failures the circuit of a write key
+    /// opens.
+    pub fn set_failure_threshold(&mut self, threshold: NonZeroU32) {
+        self.failure_threshold = threshold;
+    }
+
+    /// Set how long the requests of a write key fail right away once its
+    /// circuit opened, before probing the endpoint again.
+    pub fn set_open_interval(&mut self, interval: Duration) {
+        self.open_interval = interval;
+    }
+
+    /// Returns whether the requests of `write_key` currently fail right away.
+    pub fn is_open(&self, write_key: &str) -> bool {
+        let circuits = self.circuits.lock().unwrap();
+        circuits
+            .get(write_key)
+            .is_some_and(|circuit| circuit.is_open(Instant::now()))
+    }
+
+    fn check(&self, write_key: &str) -> Result<()> {
+        let mut circuits = self.circuits.lock().unwrap();
+        let Some(circuit) = circuits.get_mut(write_key) else {
+            return Ok(());
+        };
+        let now = Instant::now();
+        if circuit.is_open(now) {
+            return Err(Error::CircuitOpen);
+        }
+        if circuit.open_until.is_some() {
+            // half-open, this request is the probe
+            circuit.probing_until = Some(now + self.open_interval);
+        }
+        Ok(())
+    }
+
+    fn record(&self, write_key: &str, result: &Result<()>) {
+        let mut circuits = self.circuits.lock().unwrap();
+        match result {
+            Err(err @ Error::NetworkError(_)) => {
+                let circuit = circuits.entry(write_key.to_owned()).or_default();
+                circuit.consecutive_failures += 1;
+                circuit.probing_until = None;
+                // a failed probe reopens the circuit right away
+                if circuit.consecutive_failures >= self.failure_threshold.get() {
+                    if circuit.open_until.is_none() {
+                        logging::warn!(
+                            err = err as &(dyn std::error::Error + 'static),
+                            "requests failing for a write key, opening its circuit"
+                        );
+                    }
+                    circuit.open_until = Some(Instant::now() + self.open_interval);
+                }
+            }
+            Err(_) => {
+                if let Some(circuit) = circuits.get_mut(write_key) {
+                    circuit.probing_until = None;
+                }
+            }
+            Ok(()) => {
+                let closed = circuits.remove(write_key);
+                if closed.is_some_and(|circuit| circuit.open_until.is_some()) {
+                    logging::info!(
+                        "requests succeeding again for a write key, closing its circuit"
+                    );
+                }
+            }
+        }
+    }
+}
+
+impl<C> Client for CircuitBreaker<C>
+where
+    C: Client + Send + Sync,
+{
+    async fn send(&self, write_key: String, msg: Message) -> Result<()> {
+        self.check(&write_key)?;
+        let result = self.client.send(write_key.clone(), msg).await;
+        self.record(&write_key, &result);
+        result
+    }
+
+    async fn send_batch(&self, write_key: String, batch: &SerializedBatch) -> Result<()> {
+        self.check(&write_key)?;
+        let result = self.client.send_batch(write_key.clone(), batch).await;
+        self.record(&write_key, &result);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MockClient;
+    use crate::Batcher;
+    use std::sync::atomic::Ordering;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_isolated_write_keys() {
+        let inner = MockClient::default();
+        let mut client = CircuitBreaker::new(inner.clone());
+        client.set_failure_threshold(NonZeroU32::new(2).unwrap());
+        client.set_open_interval(Duration::from_secs(10));
+        let batch = Batcher::new(None).into_serialized();
+
+        inner.failures.store(2, Ordering::SeqCst);
+        for _ in 0..2 {
+            let result = client.send_batch("invalid".to_owned(), &batch).await;
+            assert!(matches!(result, Err(Error::NetworkError(_))));
+        }
+        assert!(client.is_open("invalid"));
+        let result = client.send_batch("invalid".to_owned(), &batch).await;
+        assert!(matches!(result, Err(Error::CircuitOpen)));
+        assert!(!result.unwrap_err().is_retryable());
+
+        // the other write keys are not affected
+        assert!(!client.is_open("valid"));
+        client.send_batch("valid".to_owned(), &batch).await.unwrap();
+        assert_eq!(inner.sent.lock().unwrap().len(), 1);
+
+        tokio::time::advance(Duration::from_secs(10)).await;
+        // a single probe
+        let (probe, other) = tokio::join!(
+            client.send_batch("invalid".to_owned(), &batch),
+            client.send_batch("invalid".to_owned(), &batch)
+        );
+        probe.unwrap();
+        assert!(matches!(other, Err(Error::CircuitOpen)));
+        assert!(!client.is_open("invalid"));
+        assert!(client.circuits.lock().unwrap().is_empty());
+    }
+}
@@ -1,14 +1,139 @@
 //! Interfaces to the Segment tracking API.
 
+use std::future::Future;
+#[cfg(feature = "async-trait")]
+use std::sync::Arc;
+
+use crate::message::SerializedBatch;
 use crate::{Message, Result};
 
 /// `Client` is a trait representing the HTTP transport layer of the analytics library.
-#[async_trait::async_trait]
+///
+/// Its methods return `Send` futures without boxing them, implement them with
+/// `async fn`:
+///
+/// ```
+/// use segment::message::{Message, SerializedBatch};
+/// use segment::{Client, Result};
+///
+/// struct NullClient;
+///
+/// impl Client for NullClient {
+///     async fn send(&self, _write_key: String, _msg: Message) -> Result<()> {
+///         Ok(())
+///     }
+///
+///     async fn send_batch(&self, _write_key: String, _batch: &SerializedBatch) -> Result<()> {
+///         Ok(())
+///     }
+/// }
+/// ```
+///
+/// The futures must be `Send`: an `async fn` implementing them must not hold
+/// a value which is not `Send` across an `.await`.
+///
+/// Returning `impl Future` makes `Client` unusable as a trait object, there
+/// is no `dyn Client`. Take the client as a generic parameter, or implement
+/// [`BoxedClient`](crate::BoxedClient) instead, with the `async-trait`
+/// feature: it is written with `#[async_trait]`, and an `Arc<dyn
+/// BoxedClient>` is a `Client`.
 pub trait Client {
     /// Send a single message to Segment using the given write key.
     ///
     /// A `write_key` is an API key for Segment's tracking API. See [Segment's
     /// documentation](https://segment.com/docs/guides/setup/how-do-i-find-my-write-key/)
     /// for how to find this value.
+    fn send(&self, write_key: String, msg: Message) -> impl Future<Output = Result<()>> + Send;
+
+    /// Send a batch of already serialized messages to Segment using the given
+    /// write key.
+    ///
+    /// The default implementation deserializes the batch and calls
+    /// [Self::send], clients should override it to send the serialized batch
+    /// as is.
+    fn send_batch(
+        &self,
+        write_key: String,
+        batch: &SerializedBatch,
+    ) -> impl Future<Output = Result<()>> + Send
+    where
+        Self: Sync,
+    {
+        let msg = batch.to_message();
+        async move { self.send(write_key, msg?).await }
+    }
+}
+
+/// The [`Client`] trait as it was written with `#[async_trait]`, boxing the
+/// futures, for the clients which are not ported to `async fn` yet or which
+/// are used as trait objects.
+///
+/// Every `BoxedClient` is a `Client`, and so are a `Box<dyn BoxedClient>` and
+/// an `Arc<dyn BoxedClient>`.
+///
+/// ```
+/// use segment::message::Message;
+/// use segment::{AutoBatcher, Batcher, BoxedClient, Result};
+/// use std::sync::Arc;
+///
+/// struct NullClient;
+///
+/// #[async_trait::async_trait]
+/// impl BoxedClient for NullClient {
+///     async fn send(&self, _write_key: String, _msg: Message) -> Result<()> {
+///         Ok(())
+///     }
+/// }
+///
+/// let client: Arc<dyn BoxedClient> = Arc::new(NullClient);
+/// let batcher = AutoBatcher::new(client, Batcher::new(None), "your_write_key".to_string());
+/// ```
+#[cfg(feature = "async-trait")]
+#[async_trait::async_trait]
+pub trait BoxedClient: Send + Sync {
+    /// See [`Client::send`].
     async fn send(&self, write_key: String, msg: Message) -> Result<()>;
+
+    /// See [`Client::send_batch`].
+    async fn send_batch(&self, write_key: String, batch: &SerializedBatch) -> Result<()> {
+        self.send(write_key, batch.to_message()?).await
+    }
+}
+
+#[cfg(feature = "async-trait")]
+macro_rules! forward_boxed_client {
+    ($($pointer:ident),+) => {
+        $(
+            #[async_trait::async_trait]
+            impl<T> BoxedClient for $pointer<T>
+            where
+                T: BoxedClient + ?Sized,
+            {
+                async fn send(&self, write_key: String, msg: Message) -> Result<()> {
+                    (**self).send(write_key, msg).await
+                }
+
+                async fn send_batch(&self, write_key: String, batch: &SerializedBatch) -> Result<()> {
+                    (**self).send_batch(write_key, batch).await
+                }
+            }
+        )+
+    };
+}
+
+#[cfg(feature = "async-trait")]
+forward_boxed_client!(Box, Arc);
+
+#[cfg(feature = "async-trait")]
+impl<T> Client for T
+where
+    T: BoxedClient + ?Sized,
+{
+    async fn send(&self, write_key: String, msg: Message) -> Result<()> {
+        BoxedClient::send(self, write_key, msg).await
+    }
+
+    async fn send_batch(&self, write_key: String, batch: &SerializedBatch) -> Result<()> {
+        BoxedClient::send_batch(self, write_key, batch).await
+    }
 }
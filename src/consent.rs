@@ -0,0 +1,281 @@
+//! Enforcement of the consent of the users recorded in `context.consent`.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::context::Consent;
+use crate::message::BatchMessage;
+
+/// The fields of the context identifying the user, removed by
+/// [`ConsentAction::Strip`].
+const IDENTIFYING_CONTEXT: [&str; 5] = ["ip", "location", "traits", "userAgent", "device"];
+
+/// What a [`ConsentFilter`] does with the messages of the users who did not
+/// grant the required categories.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConsentAction {
+    /// Drop the message.
+    Drop,
+
+    /// Keep the message without its properties (or traits), and without the
+    /// fields of its context identifying the user: `ip`, `location`,
+    /// `traits`, `userAgent` and `device`.
+    ///
+    /// The rest of the message is kept as is, including its `userId` and
+    /// `anonymousId`, and the `page` (with its URL and referrer), `campaign`
+    /// and `os` fields of its context. Use [`ConsentAction::Drop`] if they
+    /// must not be sent either.
+    Strip,
+}
+
+/// Drops or strips the messages of the users who did not grant the
+/// [`Consent`] categories required by each event.
+///
+/// The consent is read from the `context.consent` of each message, see
+/// [`Context::consent`](crate::context::Context::consent). The messages
+/// without one are handled as if nothing was granted, unless
+/// [Self::allow_missing] is set, and so are the messages whose consent is
+/// malformed. The `track` events are matched by their name, and so are the
+/// `page` and `screen` messages; the other messages, and the events without
+/// their own requirement, get the default requirement.
+///
+/// A `ConsentFilter` is cheap to clone, all the clones share the same counts
+/// of dropped and stripped messages.
+///
+/// ```
+/// use segment::{Batcher, ConsentAction, ConsentFilter};
+/// use segment::context::{Consent, Context};
+/// use segment::message::Track;
+///
+/// let filter = ConsentFilter::new()
+///     .default_requirement(["Analytics"], ConsentAction::Drop)
+///     .event("Ad Clicked", ["Advertising"], ConsentAction::Strip);
+/// let mut batcher = Batcher::new(None);
+/// batcher.set_consent_filter(filter.clone());
+///
+/// let context = Context::builder()
+///     .consent(Consent::new([("Analytics", true), ("Advertising", false)]))
+///     .build();
+/// batcher.push(Track {
+///     event: "Ad Clicked".to_owned(),
+///     context: Some(context.into()),
+///     ..Default::default()
+/// }).unwrap();
+/// assert_eq!(filter.stripped(), 1);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct ConsentFilter {
+    default: Option<Requirement>,
+    events: HashMap<String, Requirement>,
+    allow_missing: bool,
+    counts: Arc<Counts>,
+}
+
+/// The categories a message requires, and what to do when one is missing.
+#[derive(Clone, Debug)]
+struct Requirement {
+    categories: Vec<String>,
+    action: ConsentAction,
+}
+
+#[derive(Debug, Default)]
+struct Counts {
+    dropped: AtomicU64,
+    stripped: AtomicU64,
+}
+
+impl ConsentFilter {
+    /// Construct a filter requiring no category.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Require `categories` for the messages without a requirement of their
+    /// own, applying `action` to those of the users who did not grant all of
+    /// them.
+    pub fn default_requirement<I>(mut self, categories: I, action: ConsentAction) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        self.default = Some(Requirement::new(categories, action));
+        self
+    }
+
+    /// Require `categories` for the events named `event`, applying `action`
+    /// to those of the users who did not grant all of them.
+    pub fn event<I>(
+        mut self,
+        event: impl Into<String>,
+        categories: I,
+        action: ConsentAction,
+    ) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        self.events
+            .insert(event.into(), Requirement::new(categories, action));
+        self
+    }
+
+    /// Keep the messages without a `context.consent` as is, for instance
+    /// those of the regions where no consent is collected.
+    pub fn allow_missing(mut self) -> Self {
+        self.allow_missing = true;
+        self
+    }
+
+    /// Returns the number of messages dropped so far.
+    pub fn dropped(&self) -> u64 {
+        self.counts.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of messages stripped so far.
+    pub fn stripped(&self) -> u64 {
+        self.counts.stripped.load(Ordering::Relaxed)
+    }
+
+    /// Apply the requirement of `msg`, returns `false` if it must be dropped.
+    pub(crate) fn apply(&self, msg: &mut BatchMessage) -> bool {
+        let requirement = msg
+            .event_name()
+            .and_then(|event| self.events.get(event))
+            .or(self.default.as_ref());
+        let Some(requirement) = requirement else {
+            return true;
+        };
+        let consent = match msg.context_mut() {
+            Some(Value::Object(context)) => context.get("consent"),
+            _ => None,
+        };
+        let granted = match consent {
+            // a malformed consent grants nothing
+            Some(consent) => Consent::deserialize(consent).is_ok_and(|consent| {
+                requirement
+                    .categories
+                    .iter()
+                    .all(|category| consent.grants(category))
+            }),
+            None => self.allow_missing,
+        };
+        if granted {
+            return true;
+        }
+
+        match requirement.action {
+            ConsentAction::Drop => {
+                self.counts.dropped.fetch_add(1, Ordering::Relaxed);
+                false
+            }
+            ConsentAction::Strip => {
+                if let Some(properties) = msg.properties_mut() {
+                    *properties = Value::Object(Map::new());
+                }
+                if let Some(Value::Object(context)) = msg.context_mut() {
+                    for field in IDENTIFYING_CONTEXT {
+                        context.remove(field);
+                    }
+                }
+                self.counts.stripped.fetch_add(1, Ordering::Relaxed);
+                true
+            }
+        }
+    }
+
+    /// Returns a copy of this filter counting its drops apart.
+    pub(crate) fn detached(&self) -> Self {
+        Self {
+            counts: Arc::default(),
+            ..self.clone()
+        }
+    }
+}
+
+impl Requirement {
+    fn new<I>(categories: I, action: ConsentAction) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        Self {
+            categories: categories.into_iter().map(Into::into).collect(),
+            action,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::{Identify, Track};
+    use serde_json::json;
+
+    fn track(event: &str, context: Option<Value>) -> BatchMessage {
+        BatchMessage::Track(Track {
+            event: event.to_owned(),
+            properties: json!({ "plan": "pro" }),
+            context,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_requirements() {
+        let filter = ConsentFilter::new()
+            .default_requirement(["Analytics"], ConsentAction::Drop)
+            .event("Ad Clicked", ["Advertising"], ConsentAction::Strip);
+        let context = json!({
+            "consent": { "categoryPreferences": { "Analytics": true, "Advertising": false } },
+            "ip": "203.0.113.7",
+            "locale": "fr-FR",
+        });
+
+        let mut msg = track("Signed Up", Some(context.clone()));
+        assert!(filter.apply(&mut msg));
+        assert_eq!(msg, track("Signed Up", Some(context.clone())));
+
+        let mut msg = track("Ad Clicked", Some(context));
+        assert!(filter.apply(&mut msg));
+        let BatchMessage::Track(stripped) = msg else {
+            unreachable!()
+        };
+        assert_eq!(stripped.properties, json!({}));
+        assert_eq!(
+            stripped.context,
+            Some(json!({
+                "consent": { "categoryPreferences": { "Analytics": true, "Advertising": false } },
+                "locale": "fr-FR",
+            }))
+        );
+
+        let mut msg = BatchMessage::Identify(Identify {
+            context: Some(json!({ "consent": { "categoryPreferences": {} } })),
+            ..Default::default()
+        });
+        assert!(!filter.apply(&mut msg));
+        assert_eq!((filter.dropped(), filter.stripped()), (1, 1));
+    }
+
+    #[test]
+    fn test_missing_consent() {
+        let filter = ConsentFilter::new().event("Ad Clicked", ["Advertising"], ConsentAction::Drop);
+        assert!(!filter.apply(&mut track("Ad Clicked", None)));
+        assert!(filter.apply(&mut track("Signed Up", None)));
+
+        let filter = filter.allow_missing();
+        assert!(filter.apply(&mut track("Ad Clicked", None)));
+        assert!(!filter.apply(&mut track(
+            "Ad Clicked",
+            Some(json!({ "consent": { "categoryPreferences": { "Advertising": false } } }))
+        )));
+        assert!(!filter.apply(&mut track(
+            "Ad Clicked",
+            Some(json!({ "consent": { "categoryPreferences": "all" } }))
+        )));
+    }
+}
@@ -0,0 +1,505 @@
+//! A typed `context`, following [Segment's
+//! spec](https://segment.com/docs/spec/common/#context).
+//!
+//! The messages hold their context as raw JSON, a [`Context`] converts to and
+//! from it:
+//!
+//! ```
+//! use segment::context::{App, Context};
+//! use segment::message::Track;
+//! use serde_json::{json, Value};
+//!
+//! let context = Context::builder()
+//!     .app(App {
+//!         name: Some("Shop".to_owned()),
+//!         version: Some("1.2.0".to_owned()),
+//!         ..Default::default()
+//!     })
+//!     .ip("203.0.113.7")
+//!     .user_agent("Mozilla/5.0")
+//!     .build();
+//!
+//! let track = Track {
+//!     event: "Signed Up".to_owned(),
+//!     context: Some(context.into()),
+//!     ..Default::default()
+//! };
+//! assert_eq!(
+//!     track.context,
+//!     Some(json!({
+//!         "app": { "name": "Shop", "version": "1.2.0" },
+//!         "ip": "203.0.113.7",
+//!         "userAgent": "Mozilla/5.0",
+//!     }))
+//! );
+//!
+//! let context = Context::try_from(track.context.unwrap()).unwrap();
+//! assert_eq!(context.ip.as_deref(), Some("203.0.113.7"));
+//! ```
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// The context of a message.
+///
+/// The fields which are not covered are kept in `extra`.
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct Context {
+    /// The application sending the message.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub app: Option<App>,
+
+    /// The campaign the user came from.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub campaign: Option<Campaign>,
+
+    /// The consent of the user to each category of data collection.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub consent: Option<Consent>,
+
+    /// The device of the user.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device: Option<Device>,
+
+    /// The IP address of the user.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ip: Option<String>,
+
+    /// The library sending the message.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub library: Option<Library>,
+
+    /// The locale of the user, like `en-US`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub locale: Option<String>,
+
+    /// The operating system of the user.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub os: Option<Os>,
+
+    /// The web page the message was sent from.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub page: Option<Page>,
+
+    /// The referrer of the user, like a mobile deep link.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub referrer: Option<Referrer>,
+
+    /// The timezone of the user, like `Europe/Paris`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+
+    /// The user agent of the user.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+
+    /// The other fields of the context.
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// The application sending the message.
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize, Default)]
+pub struct App {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub build: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
+    /// The other fields of the application.
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// The campaign the user came from, usually the UTM parameters.
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize, Default)]
+pub struct Campaign {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub medium: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub term: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+    /// The other fields of the campaign.
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// The consent of the user to each category of data collection, as recorded
+/// by a consent management platform, see [Segment's consent
+/// documentation](https://segment.com/docs/privacy/consent-management/).
+///
+/// See [`ConsentFilter`](crate::ConsentFilter) to enforce it.
+///
+/// ```
+/// use segment::context::Consent;
+///
+/// let consent = Consent::new([("Analytics", true), ("Advertising", false)]);
+/// assert!(consent.grants("Analytics"));
+/// assert!(!consent.grants("Advertising"));
+/// assert!(!consent.grants("Functional"));
+/// ```
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct Consent {
+    /// Whether the user granted each category.
+    #[serde(default)]
+    pub category_preferences: BTreeMap<String, bool>,
+    /// The other fields of the consent.
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl Consent {
+    /// Construct a consent from the preferences of the user for each
+    /// category.
+    pub fn new<I, K>(preferences: I) -> Self
+    where
+        I: IntoIterator<Item = (K, bool)>,
+        K: Into<String>,
+    {
+        Self {
+            category_preferences: preferences
+                .into_iter()
+                .map(|(category, granted)| (category.into(), granted))
+                .collect(),
+            extra: Map::new(),
+        }
+    }
+
+    /// Returns whether the user granted `category`, a category missing from
+    /// the preferences is not granted.
+    pub fn grants(&self, category: &str) -> bool {
+        self.category_preferences
+            .get(category)
+            .copied()
+            .unwrap_or(false)
+    }
+}
+
+/// The device of the user.
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct Device {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub advertising_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ad_tracking_enabled: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub manufacturer: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// The type of the device, like `ios` or `android`.
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    /// The push notification token of the device.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    /// The other fields of the device.
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// The library sending the message.
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize, Default)]
+pub struct Library {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// The other fields of the library.
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// The operating system of the user.
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize, Default)]
+pub struct Os {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// The other fields of the operating system.
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// The web page the message was sent from.
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize, Default)]
+pub struct Page {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub referrer: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub search: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// The other fields of the page.
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// The referrer of the user.
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize, Default)]
+pub struct Referrer {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// The type of the referrer, like `dataxu`.
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    /// The other fields of the referrer.
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// How a default context (or integrations) is merged into the one of a
+/// message, see
+/// [`Batcher::set_message_context`](crate::Batcher::set_message_context).
+///
+/// The values of the message always take precedence.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MergeStrategy {
+    /// Only add the top-level fields missing from the message, a `device`
+    /// of the message replaces the whole default `device`.
+    Shallow,
+    /// Merge the nested objects recursively, a `device.id` of the message
+    /// is completed by the rest of the default `device`.
+    #[default]
+    Deep,
+}
+
+impl MergeStrategy {
+    /// Merge `defaults` into `context`. A context which is not an object is
+    /// left untouched.
+    pub(crate) fn merge(self, context: &mut Option<Value>, defaults: &Value) {
+        match context {
+            None => *context = Some(defaults.clone()),
+            Some(context) => self.merge_value(context, defaults),
+        }
+    }
+
+    fn merge_value(self, value: &mut Value, defaults: &Value) {
+        let (Value::Object(map), Value::Object(defaults)) = (value, defaults) else {
+            return;
+        };
+        for (key, default) in defaults {
+            match map.get_mut(key) {
+                None => {
+                    map.insert(key.clone(), default.clone());
+                }
+                Some(value) if self == MergeStrategy::Deep => self.merge_value(value, default),
+                Some(_) => {}
+            }
+        }
+    }
+}
+
+impl Context {
+    /// Construct a new builder of [`Context`].
+    pub fn builder() -> ContextBuilder {
+        ContextBuilder::default()
+    }
+}
+
+impl From<Context> for Value {
+    fn from(context: Context) -> Self {
+        // the context only holds strings, booleans and JSON values
+        serde_json::to_value(context).expect("a context always serializes")
+    }
+}
+
+impl TryFrom<Value> for Context {
+    type Error = serde_json::Error;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        serde_json::from_value(value)
+    }
+}
+
+/// A builder of [`Context`], see [`Context::builder`].
+#[derive(Debug, Clone, Default)]
+pub struct ContextBuilder {
+    context: Context,
+}
+
+impl ContextBuilder {
+    /// Set the application sending the message.
+    pub fn app(mut self, app: App) -> Self {
+        self.context.app = Some(app);
+        self
+    }
+
+    /// Set the campaign the user came from.
+    pub fn campaign(mut self, campaign: Campaign) -> Self {
+        self.context.campaign = Some(campaign);
+        self
+    }
+
+    /// Set the consent of the user to each category of data collection.
+    pub fn consent(mut self, consent: Consent) -> Self {
+        self.context.consent = Some(consent);
+        self
+    }
+
+    /// Set the device of the user.
+    pub fn device(mut self, device: Device) -> Self {
+        self.context.device = Some(device);
+        self
+    }
+
+    /// Set the IP address of the user.
+    pub fn ip(mut self, ip: impl Into<String>) -> Self {
+        self.context.ip = Some(ip.into());
+        self
+    }
+
+    /// Set the library sending the message.
+    pub fn library(mut self, library: Library) -> Self {
+        self.context.library = Some(library);
+        self
+    }
+
+    /// Set the locale of the user, like `en-US`.
+    pub fn locale(mut self, locale: impl Into<String>) -> Self {
+        self.context.locale = Some(locale.into());
+        self
+    }
+
+    /// Set the operating system of the user.
+    pub fn os(mut self, os: Os) -> Self {
+        self.context.os = Some(os);
+        self
+    }
+
+    /// Set the web page the message was sent from.
+    pub fn page(mut self, page: Page) -> Self {
+        self.context.page = Some(page);
+        self
+    }
+
+    /// Set the referrer of the user.
+    pub fn referrer(mut self, referrer: Referrer) -> Self {
+        self.context.referrer = Some(referrer);
+        self
+    }
+
+    /// Set the timezone of the user, like `Europe/Paris`.
+    pub fn timezone(mut self, timezone: impl Into<String>) -> Self {
+        self.context.timezone = Some(timezone.into());
+        self
+    }
+
+    /// Set the user agent of the user.
+    pub fn user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.context.user_agent = Some(user_agent.into());
+        self
+    }
+
+    /// Set a field which is not covered by [`Context`].
+    pub fn extra(mut self, key: impl Into<String>, value: Value) -> Self {
+        self.context.extra.insert(key.into(), value);
+        self
+    }
+
+    /// Build the [`Context`].
+    pub fn build(self) -> Context {
+        self.context
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_round_trip() {
+        let value = json!({
+            "active": true,
+            "app": { "name": "Shop", "build": "42" },
+            "campaign": { "source": "newsletter", "medium": "email" },
+            "consent": { "categoryPreferences": { "Advertising": false, "Analytics": true } },
+            "device": { "id": "d1", "adTrackingEnabled": false, "type": "ios", "carrier": "Orange" },
+            "library": { "name": "segment-rust", "version": "1.0.0" },
+            "locale": "fr-FR",
+            "os": { "name": "iOS", "version": "17.1" },
+            "page": { "path": "/", "url": "https://example.com/" },
+            "referrer": { "id": "r1", "type": "dataxu" },
+            "timezone": "Europe/Paris",
+            "location": { "city": "Paris" },
+        });
+        let context = Context::try_from(value.clone()).unwrap();
+        assert_eq!(
+            context.device.as_ref().unwrap().kind.as_deref(),
+            Some("ios")
+        );
+        assert!(context.consent.as_ref().unwrap().grants("Analytics"));
+        assert_eq!(context.extra["location"], json!({ "city": "Paris" }));
+        assert_eq!(context.device.as_ref().unwrap().extra["carrier"], "Orange");
+        assert_eq!(Value::from(context), value);
+
+        assert!(Context::try_from(json!({ "ip": 3 })).is_err());
+    }
+
+    #[test]
+    fn test_merge() {
+        let defaults = json!({ "app": { "name": "Shop", "version": "1.0" }, "locale": "en-US" });
+        let message = || Some(json!({ "app": { "version": "2.0" }, "ip": "0.0.0.0" }));
+
+        let mut context = message();
+        MergeStrategy::Shallow.merge(&mut context, &defaults);
+        assert_eq!(
+            context,
+            Some(json!({ "app": { "version": "2.0" }, "ip": "0.0.0.0", "locale": "en-US" }))
+        );
+
+        let mut context = message();
+        MergeStrategy::Deep.merge(&mut context, &defaults);
+        assert_eq!(
+            context,
+            Some(json!({
+                "app": { "name": "Shop", "version": "2.0" },
+                "ip": "0.0.0.0",
+                "locale": "en-US",
+            }))
+        );
+
+        let mut context = None;
+        MergeStrategy::Deep.merge(&mut context, &defaults);
+        assert_eq!(context, Some(defaults));
+    }
+
+    #[test]
+    fn test_builder() {
+        let context = Context::builder()
+            .os(Os {
+                name: Some("Linux".to_owned()),
+                ..Default::default()
+            })
+            .locale("en-US")
+            .extra("active", json!(false))
+            .build();
+        assert_eq!(
+            Value::from(context),
+            json!({ "os": { "name": "Linux" }, "locale": "en-US", "active": false })
+        );
+        assert_eq!(Value::from(Context::default()), json!({}));
+    }
+}
@@ -0,0 +1,154 @@
+//! Deduplication of the messages pushed several times.
+
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+
+use crate::batcher::Admission;
+use crate::logging;
+use crate::message::BatchMessage;
+use crate::runtime::Instant;
+
+/// Drops the messages whose `messageId` was already pushed within a time
+/// window, to protect against upstream producers delivering their events at
+/// least once.
+///
+/// The most recent `capacity` IDs are remembered, older ones are forgotten
+/// even if their window didn't elapse. The messages without a `messageId`
+/// are never dropped.
+///
+/// See [`Batcher::set_deduplication`](crate::Batcher::set_deduplication).
+///
+/// ```
+/// use std::time::Duration;
+/// use segment::{Batcher, Deduplication};
+///
+/// let mut batcher = Batcher::new(None);
+/// batcher.set_deduplication(Deduplication {
+///     window: Duration::from_secs(60),
+///     capacity: 100_000,
+/// });
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Deduplication {
+    /// How long a `messageId` is remembered.
+    pub window: Duration,
+
+    /// How many `messageId`s are remembered at most.
+    pub capacity: usize,
+}
+
+impl Default for Deduplication {
+    fn default() -> Self {
+        Self {
+            window: Duration::from_secs(10 * 60),
+            capacity: 10_000,
+        }
+    }
+}
+
+/// Applies a [`Deduplication`], remembering the recent `messageId`s.
+#[derive(Clone, Debug)]
+pub(crate) struct Deduplicator {
+    config: Deduplication,
+    /// When each remembered ID was seen.
+    seen: HashMap<String, Instant>,
+    /// The remembered IDs, oldest first.
+    order: VecDeque<(String, Instant)>,
+}
+
+impl Deduplicator {
+    pub(crate) fn new(config: Deduplication) -> Self {
+        Self {
+            config,
+            seen: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    /// Returns whether `msg` should be kept, storing its `messageId` in
+    /// `admission` to be remembered by [Self::record] once it is enqueued: a
+    /// message returned by [`Batcher::push`](crate::Batcher::push) because
+    /// the batch is full is not a duplicate when pushed again.
+    pub(crate) fn allow(&mut self, msg: &BatchMessage, admission: &mut Admission) -> bool {
+        let Some(id) = msg.message_id() else {
+            return true;
+        };
+
+        let now = Instant::now();
+        while let Some((oldest, seen_at)) = self.order.front() {
+            let expired = now.duration_since(*seen_at) >= self.config.window;
+            if !expired && self.order.len() < self.config.capacity.max(1) {
+                break;
+            }
+            // an ID seen again after it expired was pushed back more recently
+            if self.seen.get(oldest) == Some(seen_at) {
+                self.seen.remove(oldest);
+            }
+            self.order.pop_front();
+        }
+
+        if self.seen.contains_key(id) {
+            logging::debug!(message_id = id, "duplicate message, dropping it");
+            return false;
+        }
+        admission.message_id = Some(id.to_owned());
+        true
+    }
+
+    /// Remember the `messageId` of an enqueued message.
+    pub(crate) fn record(&mut self, id: String) {
+        let now = Instant::now();
+        self.seen.insert(id.clone(), now);
+        self.order.push_back((id, now));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::Track;
+
+    fn track(id: &str) -> BatchMessage {
+        let mut track = Track::default();
+        track.set_message_id(id);
+        track.into()
+    }
+
+    /// Returns whether `msg` is kept, remembering it as if enqueued.
+    fn push(dedup: &mut Deduplicator, msg: &BatchMessage) -> bool {
+        let mut admission = Admission::default();
+        let allowed = dedup.allow(msg, &mut admission);
+        if let Some(id) = admission.message_id {
+            dedup.record(id);
+        }
+        allowed
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_deduplication() {
+        let mut dedup = Deduplicator::new(Deduplication {
+            window: Duration::from_secs(10),
+            capacity: 2,
+        });
+
+        assert!(push(&mut dedup, &track("a")));
+        assert!(!push(&mut dedup, &track("a")));
+        assert!(push(&mut dedup, &BatchMessage::Track(Track::default())));
+        assert!(push(&mut dedup, &BatchMessage::Track(Track::default())));
+
+        // "a" expires
+        tokio::time::advance(Duration::from_secs(10)).await;
+        assert!(push(&mut dedup, &track("a")));
+
+        // "a" is evicted by "b" and "c"
+        assert!(push(&mut dedup, &track("b")));
+        assert!(push(&mut dedup, &track("c")));
+        assert!(!push(&mut dedup, &track("c")));
+        assert!(push(&mut dedup, &track("a")));
+
+        // a message which was not enqueued is not remembered
+        let mut admission = Admission::default();
+        assert!(dedup.allow(&track("d"), &mut admission));
+        assert!(push(&mut dedup, &track("d")));
+    }
+}
@@ -0,0 +1,128 @@
+//! Notifications about the outcome of each upload attempt.
+
+use std::fmt;
+use std::sync::Arc;
+
+use time::OffsetDateTime;
+
+use crate::errors::Error;
+use crate::hooks::catch_panic;
+use crate::logging;
+
+/// Number of events a subscriber may lag behind before missing some.
+pub(crate) const DELIVERY_CHANNEL_CAPACITY: usize = 1024;
+
+/// An event emitted by the [`AutoBatcher`](crate::AutoBatcher) after each
+/// attempt to upload a batch.
+///
+/// A batch which is retried emits an event with
+/// [`DeliveryOutcome::Retrying`] for each failed attempt, then a last one
+/// with [`DeliveryOutcome::Delivered`] or [`DeliveryOutcome::Failed`].
+///
+/// See [`AutoBatcher::subscribe`](crate::AutoBatcher::subscribe) to receive
+/// them.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct DeliveryEvent {
+    /// The `messageId` of every message of the batch that had one.
+    pub message_ids: Vec<String>,
+
+    /// Whether the batch was accepted by Segment.
+    pub outcome: DeliveryOutcome,
+
+    /// The number of the attempt, starting at 1.
+    pub attempt: u32,
+
+    /// When the attempt completed.
+    pub timestamp: OffsetDateTime,
+}
+
+/// The outcome of an upload attempt.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum DeliveryOutcome {
+    /// The batch was accepted by Segment.
+    Delivered,
+
+    /// The batch could not be sent, and is retried after a backoff.
+    Retrying {
+        /// A description of the error that occurred.
+        error: String,
+    },
+
+    /// The batch could not be sent, and is not retried anymore.
+    Failed {
+        /// A description of the error that occurred.
+        error: String,
+    },
+}
+
+impl DeliveryOutcome {
+    /// Returns whether the batch was accepted by Segment.
+    pub fn is_delivered(&self) -> bool {
+        matches!(self, Self::Delivered)
+    }
+}
+
+/// Called with the `messageId`s of each batch once it is delivered, or once
+/// it could not be delivered even after the retries, for instance to keep
+/// track of the events sent exactly once or to alert on failing uploads.
+///
+/// Unlike the [`DeliveryEvent`]s of
+/// [`AutoBatcher::subscribe`](crate::AutoBatcher::subscribe), which a lagging
+/// subscriber misses, the observer is called for every batch.
+///
+/// Set it with
+/// [`AutoBatcher::set_delivery_observer`](crate::AutoBatcher::set_delivery_observer).
+/// It is called from the background uploads, and must therefore return
+/// quickly. A panicking observer is logged and ignored.
+///
+/// ```
+/// use segment::{DeliveryObserver, Error};
+///
+/// struct Alert;
+///
+/// impl DeliveryObserver for Alert {
+///     fn failed(&self, message_ids: &[String], error: &Error) {
+///         eprintln!("lost {} events: {error}", message_ids.len());
+///     }
+/// }
+/// ```
+pub trait DeliveryObserver: Send + Sync {
+    /// The messages were accepted by Segment.
+    fn delivered(&self, message_ids: &[String]) {
+        let _ = message_ids;
+    }
+
+    /// The messages could not be delivered, even after the retries.
+    ///
+    /// They are not lost yet: the failed batches are sent again at
+    /// [shutdown](crate::AutoBatcher::shutdown), or returned in
+    /// [`Error::Undelivered`] to be [requeued](crate::AutoBatcher::requeue),
+    /// which the [`Worker`](crate::Worker) does after its interval flushes.
+    /// The same messages may then later be reported as
+    /// [delivered](Self::delivered).
+    fn failed(&self, message_ids: &[String], error: &Error) {
+        let _ = (message_ids, error);
+    }
+}
+
+/// Notifies a [`DeliveryObserver`], shielding the uploads from its panics.
+#[derive(Clone)]
+pub(crate) struct Observer(pub(crate) Arc<dyn DeliveryObserver>);
+
+impl Observer {
+    pub(crate) fn notify(&self, message_ids: &[String], result: &Result<(), Error>) {
+        let notified = catch_panic(|| match result {
+            Ok(()) => self.0.delivered(message_ids),
+            Err(err) => self.0.failed(message_ids, err),
+        });
+        if let Err(reason) = notified {
+            logging::error!(reason, "delivery observer failed");
+        }
+    }
+}
+
+impl fmt::Debug for Observer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Observer").finish_non_exhaustive()
+    }
+}
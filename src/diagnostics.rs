@@ -0,0 +1,123 @@
+//! Support bundles describing the state of an [`AutoBatcher`].
+
+use serde::{Deserialize, Serialize};
+use serde_json::value::RawValue;
+use time::OffsetDateTime;
+
+use crate::batcher::BatchLimits;
+use crate::queue::OverflowPolicy;
+use crate::retry::RetryPolicy;
+use crate::stats::Stats;
+
+#[cfg(doc)]
+use crate::AutoBatcher;
+
+/// How many buffered messages are described in the [`Diagnostics`].
+pub(crate) const SAMPLE_SIZE: usize = 20;
+
+/// A snapshot of the configuration and state of an [`AutoBatcher`], to attach
+/// to a bug report, see [`AutoBatcher::dump_diagnostics`].
+///
+/// It is redacted: the write key is truncated, and only the metadata of the
+/// buffered messages is kept, never their users, properties or context.
+#[derive(Clone, Debug, Serialize)]
+#[non_exhaustive]
+pub struct Diagnostics {
+    #[serde(with = "time::serde::rfc3339")]
+    pub generated_at: OffsetDateTime,
+    /// The version of this crate.
+    pub version: &'static str,
+    /// The last 4 characters of the write key.
+    pub write_key: String,
+
+    pub batch_limits: BatchLimits,
+    pub overflow_policy: OverflowPolicy,
+    pub retry_policy: RetryPolicy,
+    /// The retries left in the [`RetryBudget`](crate::RetryBudget), if any.
+    pub retry_budget_balance: Option<f64>,
+    pub max_concurrent_flushes: u32,
+
+    pub stats: Stats,
+    /// The uploads running in the background.
+    pub uploads_in_flight: u32,
+    /// The batches which failed since the last flush.
+    pub undelivered_batches: usize,
+    /// The error of the last of these batches.
+    pub last_error: Option<String>,
+
+    /// The number of buffered messages.
+    pub buffered: usize,
+    /// The size of the buffered batch, in bytes.
+    pub buffered_bytes: usize,
+    /// The oldest buffered messages.
+    pub sample: Vec<MessageMetadata>,
+}
+
+/// The metadata of a buffered message.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[non_exhaustive]
+pub struct MessageMetadata {
+    /// The type of the message, like `track`.
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// The event name of a `track`, or the name of a `page` or `screen`.
+    #[serde(skip_serializing_if = "Option::is_none", alias = "name")]
+    pub event: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<String>,
+    /// The size of the serialized message, in bytes.
+    #[serde(default)]
+    pub size: usize,
+}
+
+impl MessageMetadata {
+    pub(crate) fn of(msg: &RawValue) -> Option<Self> {
+        let mut metadata: Self = serde_json::from_str(msg.get()).ok()?;
+        metadata.size = msg.get().len();
+        Some(metadata)
+    }
+}
+
+/// Keep the last 4 characters of `write_key`, if it is long enough for them
+/// not to give it away.
+pub(crate) fn redact(write_key: &str) -> String {
+    let chars: Vec<char> = write_key.chars().collect();
+    match chars.len() {
+        len if len > 8 => format!("****{}", chars[len - 4..].iter().collect::<String>()),
+        _ => "****".to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_metadata() {
+        let msg = json!({
+            "type": "page",
+            "name": "Home",
+            "userId": "secret",
+            "properties": { "email": "secret" },
+            "messageId": "id",
+        });
+        let raw = serde_json::value::to_raw_value(&msg).unwrap();
+        let metadata = MessageMetadata::of(&raw).unwrap();
+        assert_eq!(metadata.kind, "page");
+        assert_eq!(metadata.event.as_deref(), Some("Home"));
+        assert_eq!(metadata.size, raw.get().len());
+
+        let dumped = serde_json::to_string(&metadata).unwrap();
+        assert!(!dumped.contains("secret"), "{dumped}");
+    }
+
+    #[test]
+    fn test_redact() {
+        assert_eq!(redact("abcdefghijkl"), "****ijkl");
+        assert_eq!(redact("short"), "****");
+    }
+}
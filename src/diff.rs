@@ -0,0 +1,307 @@
+//! Comparison of the properties of `track` events, to catch the breaking
+//! changes of the analytics of an application in its CI.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::errors::Result;
+use crate::message::Track;
+use crate::schema::JsonType;
+use crate::tracking_plan::PlannedEvent;
+
+/// How a property differs, see [`PayloadDiff`].
+#[derive(PartialEq, Eq, Debug, Clone, Serialize)]
+#[serde(tag = "change", rename_all = "camelCase")]
+pub enum PropertyDiff {
+    /// The property is new.
+    Added { found: JsonType },
+
+    /// The property is missing.
+    Removed { expected: BTreeSet<JsonType> },
+
+    /// The property has another type.
+    Retyped {
+        expected: BTreeSet<JsonType>,
+        found: JsonType,
+    },
+}
+
+impl PropertyDiff {
+    /// Returns whether the change may break the consumers of the event, that
+    /// is anything but a new property.
+    pub fn is_breaking(&self) -> bool {
+        !matches!(self, PropertyDiff::Added { .. })
+    }
+}
+
+impl fmt::Display for PropertyDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let types = |types: &BTreeSet<JsonType>| {
+            let types: Vec<_> = types.iter().map(JsonType::to_string).collect();
+            types.join(" or ")
+        };
+        match self {
+            PropertyDiff::Added { found } => write!(f, "added, of type {found}"),
+            PropertyDiff::Removed { expected } => write!(f, "removed, was {}", types(expected)),
+            PropertyDiff::Retyped { expected, found } => {
+                write!(f, "expected {}, found {found}", types(expected))
+            }
+        }
+    }
+}
+
+/// The properties added, removed or retyped between two `track` payloads, or
+/// between a [`PlannedEvent`] of a tracking plan and a payload.
+///
+/// The nested properties of two payloads are compared too, their paths are
+/// joined with dots.
+///
+/// ```
+/// use segment::PayloadDiff;
+///
+/// let before = r#"{
+///     "userId": "user",
+///     "event": "Signed Up",
+///     "properties": { "plan": "pro", "seats": 3 }
+/// }"#;
+/// let after = r#"{
+///     "userId": "user",
+///     "event": "Signed Up",
+///     "properties": { "plan": 2, "trial": true }
+/// }"#;
+///
+/// let diff = PayloadDiff::from_json(before, after).unwrap();
+/// assert!(diff.is_breaking());
+/// for (path, change) in &diff.properties {
+///     println!("{path}: {change}");
+/// }
+/// // plan: expected string, found number
+/// // seats: removed, was number
+/// // trial: added, of type bool
+/// ```
+#[derive(PartialEq, Eq, Debug, Clone, Default, Serialize)]
+pub struct PayloadDiff {
+    /// The changes, by path of the property.
+    pub properties: BTreeMap<String, PropertyDiff>,
+}
+
+impl PayloadDiff {
+    /// Compare the properties of the `before` and `after` payloads.
+    pub fn new(before: &Track, after: &Track) -> Self {
+        let mut diff = Self::default();
+        diff.compare("", &before.properties, &after.properties);
+        diff
+    }
+
+    /// Compare two serialized `track` payloads, see [Self::new].
+    pub fn from_json(before: &str, after: &str) -> Result<Self> {
+        let before: Track = serde_json::from_str(before)?;
+        let after: Track = serde_json::from_str(after)?;
+        Ok(Self::new(&before, &after))
+    }
+
+    /// Compare the properties of `track` with the ones of the `planned`
+    /// event.
+    ///
+    /// Only the required properties are reported as removed, and the
+    /// properties without a type in the plan are never retyped.
+    pub fn against_plan(planned: &PlannedEvent, track: &Track) -> Self {
+        let empty = Map::new();
+        let properties = track.properties.as_object().unwrap_or(&empty);
+
+        let mut diff = Self::default();
+        for (name, expected) in &planned.properties {
+            match properties.get(name) {
+                None if planned.required.contains(name) => {
+                    diff.properties.insert(
+                        name.clone(),
+                        PropertyDiff::Removed {
+                            expected: expected.clone(),
+                        },
+                    );
+                }
+                Some(value) if !JsonType::matches(expected, value) => {
+                    diff.properties.insert(
+                        name.clone(),
+                        PropertyDiff::Retyped {
+                            expected: expected.clone(),
+                            found: JsonType::of(value),
+                        },
+                    );
+                }
+                None | Some(_) => {}
+            }
+        }
+        for name in &planned.required {
+            if !planned.properties.contains_key(name) && !properties.contains_key(name) {
+                diff.properties.insert(
+                    name.clone(),
+                    PropertyDiff::Removed {
+                        expected: BTreeSet::new(),
+                    },
+                );
+            }
+        }
+        for (name, value) in properties {
+            if !planned.properties.contains_key(name) && !planned.required.contains(name) {
+                diff.properties.insert(
+                    name.clone(),
+                    PropertyDiff::Added {
+                        found: JsonType::of(value),
+                    },
+                );
+            }
+        }
+        diff
+    }
+
+    /// Returns whether no property changed.
+    pub fn is_empty(&self) -> bool {
+        self.properties.is_empty()
+    }
+
+    /// Returns whether a change may break the consumers of the event, see
+    /// [`PropertyDiff::is_breaking`].
+    pub fn is_breaking(&self) -> bool {
+        self.properties.values().any(PropertyDiff::is_breaking)
+    }
+
+    fn compare(&mut self, prefix: &str, before: &Value, after: &Value) {
+        let empty = Map::new();
+        let before = before.as_object().unwrap_or(&empty);
+        let after = after.as_object().unwrap_or(&empty);
+
+        for (name, old) in before {
+            let path = format!("{prefix}{name}");
+            match after.get(name) {
+                None => {
+                    self.properties.insert(
+                        path,
+                        PropertyDiff::Removed {
+                            expected: BTreeSet::from([JsonType::of(old)]),
+                        },
+                    );
+                }
+                Some(new) if old.is_object() && new.is_object() => {
+                    self.compare(&format!("{path}."), old, new);
+                }
+                Some(new) => {
+                    let (expected, found) = (JsonType::of(old), JsonType::of(new));
+                    if expected != found {
+                        self.properties.insert(
+                            path,
+                            PropertyDiff::Retyped {
+                                expected: BTreeSet::from([expected]),
+                                found,
+                            },
+                        );
+                    }
+                }
+            }
+        }
+        for (name, new) in after {
+            if !before.contains_key(name) {
+                self.properties.insert(
+                    format!("{prefix}{name}"),
+                    PropertyDiff::Added {
+                        found: JsonType::of(new),
+                    },
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn track(properties: Value) -> Track {
+        Track {
+            event: "Signed Up".to_owned(),
+            properties,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_payloads() {
+        let before = track(json!({
+            "plan": "pro",
+            "seats": 3,
+            "address": { "city": "Paris", "zip": "75001" },
+        }));
+        let after = track(json!({
+            "plan": "pro",
+            "seats": "3",
+            "address": { "city": "Paris", "country": "FR" },
+        }));
+        let diff = PayloadDiff::new(&before, &after);
+        assert_eq!(
+            serde_json::to_value(&diff).unwrap(),
+            json!({
+                "properties": {
+                    "address.country": { "change": "added", "found": "string" },
+                    "address.zip": { "change": "removed", "expected": ["string"] },
+                    "seats": { "change": "retyped", "expected": ["number"], "found": "string" },
+                }
+            })
+        );
+        assert!(diff.is_breaking());
+        assert!(PayloadDiff::new(&before, &before).is_empty());
+
+        let diff = PayloadDiff::new(
+            &before,
+            &track(json!({ "plan": "pro", "seats": 3, "address": {}, "trial": true })),
+        );
+        assert!(!diff.is_empty());
+        assert!(diff.is_breaking());
+        let diff = PayloadDiff::new(&track(json!({})), &track(json!({ "trial": true })));
+        assert!(!diff.is_breaking());
+    }
+
+    #[test]
+    fn test_against_plan() {
+        let planned = PlannedEvent {
+            properties: BTreeMap::from([
+                ("plan".to_owned(), BTreeSet::from([JsonType::String])),
+                ("seats".to_owned(), BTreeSet::from([JsonType::Number])),
+                ("coupon".to_owned(), BTreeSet::from([JsonType::String])),
+                ("anything".to_owned(), BTreeSet::new()),
+            ]),
+            required: BTreeSet::from(["plan".to_owned(), "seats".to_owned()]),
+        };
+        let diff = PayloadDiff::against_plan(
+            &planned,
+            &track(json!({ "seats": "3", "anything": [], "trial": true })),
+        );
+        assert_eq!(
+            diff.properties,
+            BTreeMap::from([
+                (
+                    "plan".to_owned(),
+                    PropertyDiff::Removed {
+                        expected: BTreeSet::from([JsonType::String])
+                    }
+                ),
+                (
+                    "seats".to_owned(),
+                    PropertyDiff::Retyped {
+                        expected: BTreeSet::from([JsonType::Number]),
+                        found: JsonType::String,
+                    }
+                ),
+                (
+                    "trial".to_owned(),
+                    PropertyDiff::Added {
+                        found: JsonType::Bool
+                    }
+                ),
+            ])
+        );
+    }
+}
@@ -0,0 +1,182 @@
+//! Dry runs of the whole pipeline, recording what would be sent instead of
+//! sending it.
+
+use std::sync::{Arc, Mutex};
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{
+    auto_batcher::AutoBatcher,
+    client::Client,
+    errors::Result,
+    message::{Message, SerializedBatch},
+};
+
+/// A [`Client`] recording the batches instead of sending them, see
+/// [`AutoBatcher::dry_run`].
+#[derive(Clone, Debug, Default)]
+pub struct Recorder {
+    batches: Arc<Mutex<Vec<RecordedBatch>>>,
+}
+
+/// A request which would have been sent to Segment.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[non_exhaustive]
+pub struct RecordedBatch {
+    /// The number of messages in the request.
+    pub messages: usize,
+    /// The size of the body, in bytes.
+    pub size: usize,
+    /// The body of the request.
+    pub body: Value,
+}
+
+/// What a dry run would have sent, see [`AutoBatcher::dry_run`].
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+#[non_exhaustive]
+pub struct DryRunReport {
+    /// The requests, in the order they would have been sent.
+    pub batches: Vec<RecordedBatch>,
+}
+
+impl DryRunReport {
+    /// Returns the number of messages which would have been sent.
+    pub fn messages(&self) -> usize {
+        self.batches.iter().map(|batch| batch.messages).sum()
+    }
+
+    /// Returns the number of bytes which would have been sent.
+    pub fn size(&self) -> usize {
+        self.batches.iter().map(|batch| batch.size).sum()
+    }
+}
+
+impl Recorder {
+    /// Returns the requests recorded so far.
+    pub fn report(&self) -> DryRunReport {
+        DryRunReport {
+            batches: self.batches.lock().unwrap().clone(),
+        }
+    }
+
+    fn record(&self, messages: usize, body: Vec<u8>) -> Result<()> {
+        let batch = RecordedBatch {
+            messages,
+            size: body.len(),
+            body: serde_json::from_slice(&body)?,
+        };
+        self.batches.lock().unwrap().push(batch);
+        Ok(())
+    }
+}
+
+impl Client for Recorder {
+    async fn send(&self, _write_key: String, msg: Message) -> Result<()> {
+        let messages = match &msg {
+            Message::Batch(batch) => batch.batch.len(),
+            _ => 1,
+        };
+        self.record(messages, serde_json::to_vec(&msg)?)
+    }
+
+    async fn send_batch(&self, _write_key: String, batch: &SerializedBatch) -> Result<()> {
+        self.record(batch.len(), batch.to_json()?)
+    }
+}
+
+impl<C> AutoBatcher<C>
+where
+    C: Client + Clone + Send + Sync + 'static,
+{
+    /// Returns a batcher with the configuration of this one, where every stage
+    /// runs (validation, enrichment, batching, serialization) but the batches
+    /// are recorded instead of sent, for instance to check a configuration
+    /// change in staging.
+    ///
+    /// The dry run starts with an empty buffer, and a copy of the state of
+    /// this batcher (deduplication, event limit, schemas...) which it doesn't
+    /// update, its warnings are only logged.
+    ///
+    /// To check a single message, [`Batcher::preview`](crate::Batcher::preview) is enough. To check
+    /// that Segment accepts the messages, see [`AutoBatcher::self_test`].
+    ///
+    /// ```
+    /// use segment::{AutoBatcher, Batcher, HttpClient};
+    /// use segment::message::{Track, User};
+    ///
+    /// # async fn run() -> segment::Result<()> {
+    /// let batcher = AutoBatcher::new(HttpClient::default(), Batcher::new(None), "your_write_key".to_string());
+    ///
+    /// let mut dry_run = batcher.dry_run();
+    /// dry_run.push(Track {
+    ///     user: User::UserId { user_id: "user".to_owned() },
+    ///     event: "Signed Up".to_owned(),
+    ///     ..Default::default()
+    /// }).await?;
+    /// let report = dry_run.report().await?;
+    /// assert_eq!(report.messages(), 1);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn dry_run(&self) -> AutoBatcher<Recorder> {
+        let mut dry_run = self.with_client(Recorder::default());
+        dry_run.batcher = self.batcher.detached();
+        dry_run
+    }
+}
+
+impl AutoBatcher<Recorder> {
+    /// Flush the buffered messages, and returns every request which would
+    /// have been sent so far.
+    pub async fn report(&mut self) -> Result<DryRunReport> {
+        self.flush().await?;
+        Ok(self.client.report())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::{Track, User};
+    use crate::testing::{large_track, MockClient};
+    use crate::{Batcher, Rules};
+
+    #[tokio::test(start_paused = true)]
+    async fn test_dry_run() {
+        let client = MockClient::default();
+        let mut batcher = Batcher::new(None);
+        batcher.set_rules(
+            Rules::from_json(r#"[{ "match": { "event": "Ignored" }, "action": "drop" }]"#).unwrap(),
+        );
+        let mut batcher = AutoBatcher::new(client.clone(), batcher, "key".to_owned());
+        batcher.push(large_track(0)).await.unwrap();
+
+        let mut dry_run = batcher.dry_run();
+        for i in 1..=20 {
+            dry_run.push(large_track(i)).await.unwrap();
+        }
+        let ignored = Track {
+            user: User::UserId {
+                user_id: "user".to_owned(),
+            },
+            event: "Ignored".to_owned(),
+            ..Default::default()
+        };
+        dry_run.push(ignored).await.unwrap();
+
+        let report = dry_run.report().await.unwrap();
+        assert_eq!(report.batches.len(), 2);
+        assert_eq!(report.messages(), 20);
+        assert_eq!(
+            report.batches[0].body["batch"].as_array().unwrap().len(),
+            17
+        );
+        assert!(report.size() > 20 * 30 * 1024);
+
+        // the original batcher is untouched
+        assert_eq!(batcher.len(), 1);
+        batcher.flush().await.unwrap();
+        assert_eq!(client.sent_count(), 1);
+    }
+}
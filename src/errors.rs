@@ -2,9 +2,14 @@
 
 use thiserror::Error;
 
+use crate::logging;
+use crate::message::{BatchMessage, PropertyError};
+use crate::validation::ValidationError;
+
 /// An enum of errors this crate may produce. These are compatible with
 /// `failure` errors.
 #[derive(Error, Debug)]
+#[non_exhaustive]
 pub enum Error {
     /// The given message is too large to be sent to Segment's API.
     #[error("message too large")]
@@ -13,6 +18,113 @@ pub enum Error {
     DeserializeError(#[from] serde_json::Error),
     #[error("Network error: {0}")]
     NetworkError(#[from] reqwest::Error),
+    /// The buffer or the queue is full, see
+    /// [`OverflowPolicy`](crate::OverflowPolicy).
+    #[error("queue full")]
+    QueueFull,
+    /// The [`Worker`](crate::Worker) is stopped and doesn't accept messages
+    /// anymore.
+    #[error("worker closed")]
+    Closed,
+    /// Reading or writing a file failed.
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    /// A hook modified a reserved field of a message, which would produce an
+    /// invalid payload.
+    #[error("reserved field `{0}` was modified")]
+    ReservedFieldModified(&'static str),
+    /// The configuration is invalid.
+    #[error("invalid configuration: {0}")]
+    InvalidConfiguration(String),
+    /// The message is invalid, see [`Validation`](crate::Validation).
+    #[error("invalid message: {0}")]
+    InvalidMessage(#[from] ValidationError),
+    /// The properties (or traits) could not be serialized into JSON.
+    #[error(transparent)]
+    InvalidProperty(#[from] PropertyError),
+    /// The requests of the write key keep failing, see
+    /// [`CircuitBreaker`](crate::CircuitBreaker).
+    #[error("circuit open for the write key")]
+    CircuitOpen,
+    /// The source of an import doesn't match its
+    /// [`Checkpoint`](crate::Checkpoint).
+    #[error("checkpoint mismatch: {0}")]
+    CheckpointMismatch(String),
+    /// Sending batches failed, their messages were not delivered and may be
+    /// persisted, or enqueued again with
+    /// [`AutoBatcher::requeue`](crate::AutoBatcher::requeue).
+    #[error("{}", describe_undelivered(batches))]
+    Undelivered {
+        /// The batches which could not be delivered, each with its own error.
+        batches: Vec<UndeliveredBatch>,
+    },
+}
+
+/// A batch which could not be delivered, see [`Error::Undelivered`].
+#[derive(Debug)]
+pub struct UndeliveredBatch {
+    /// The last error returned while sending the batch.
+    pub error: Error,
+    /// The messages of the batch, as they were enqueued: already processed by
+    /// the batcher.
+    pub messages: Vec<BatchMessage>,
+}
+
+fn describe_undelivered(batches: &[UndeliveredBatch]) -> String {
+    let messages: usize = batches.iter().map(|batch| batch.messages.len()).sum();
+    match batches {
+        [] => format!("{messages} messages were not delivered"),
+        [batch] => format!("{messages} messages were not delivered: {}", batch.error),
+        [batch, ..] => format!(
+            "{messages} messages of {} batches were not delivered, the first one failed with: {}",
+            batches.len(),
+            batch.error
+        ),
+    }
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
+
+/// Collects the errors of several operations which all run even if some of
+/// them fail: the batches of the [`Error::Undelivered`] ones are merged, so
+/// that none of their messages is lost, and the first other error is kept.
+#[derive(Debug, Default)]
+pub(crate) struct Errors {
+    first: Option<Error>,
+    undelivered: Vec<UndeliveredBatch>,
+}
+
+impl Errors {
+    pub(crate) fn push(&mut self, err: Error) {
+        match err {
+            Error::Undelivered { batches } => self.undelivered.extend(batches),
+            err if self.first.is_none() => self.first = Some(err),
+            _ => {}
+        }
+    }
+
+    /// Returns the first error which is not [`Error::Undelivered`] if any,
+    /// otherwise the merged undelivered batches if any.
+    pub(crate) fn into_result(self) -> Result<()> {
+        match self.first {
+            Some(err) => {
+                if !self.undelivered.is_empty() {
+                    let lost: usize = self
+                        .undelivered
+                        .iter()
+                        .map(|batch| batch.messages.len())
+                        .sum();
+                    logging::error!(
+                        lost,
+                        "dropping undelivered messages, returning another error"
+                    );
+                }
+                Err(err)
+            }
+            None if self.undelivered.is_empty() => Ok(()),
+            None => Err(Error::Undelivered {
+                batches: self.undelivered,
+            }),
+        }
+    }
+}
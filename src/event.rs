@@ -0,0 +1,84 @@
+//! Typed `track` events.
+
+use serde::Serialize;
+
+use crate::message::{Track, User};
+
+/// An event with a fixed name and typed properties, converting into a
+/// [`Track`], like the events of the [Segment specs](crate::spec).
+///
+/// With the `derive` feature, `#[derive(TrackEvent)]` implements it for a
+/// struct, named after its `#[track(event = "...")]` attribute, with its
+/// fields as properties:
+///
+/// ```
+/// # #[cfg(feature = "derive")]
+/// # {
+/// use segment::TrackEvent;
+/// use segment::message::User;
+/// use serde::Serialize;
+/// use serde_json::json;
+///
+/// #[derive(Serialize, TrackEvent)]
+/// #[track(event = "Signed Up")]
+/// struct SignedUp {
+///     plan: String,
+///     seats: u32,
+/// }
+///
+/// let signed_up = SignedUp { plan: "pro".to_owned(), seats: 3 };
+/// let track = signed_up.into_track(User::UserId { user_id: "user".to_owned() });
+/// assert_eq!(track.event, "Signed Up");
+/// assert_eq!(track.properties, json!({ "plan": "pro", "seats": 3 }));
+/// # }
+/// ```
+///
+/// The properties are named by the `Serialize` implementation, so the serde
+/// attributes like `#[serde(rename = "...")]` apply.
+pub trait TrackEvent: Serialize {
+    /// The name of the event, like `Order Completed`.
+    const EVENT: &'static str;
+
+    /// Returns the `track` event of `user`, with `self` as properties.
+    ///
+    /// # Panics
+    ///
+    /// If `self` doesn't serialize into an object, which never happens for
+    /// a struct with named fields deriving `Serialize`.
+    fn into_track(self, user: User) -> Track
+    where
+        Self: Sized,
+    {
+        Track::from_props(user, Self::EVENT, &self)
+            .expect("a track event serializes into an object")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use segment_derive::TrackEvent;
+    use serde_json::json;
+
+    #[derive(Serialize, TrackEvent)]
+    #[track(event = "Report Exported")]
+    struct ReportExported<F: Serialize> {
+        format: F,
+        #[serde(rename = "rowCount")]
+        rows: u64,
+    }
+
+    #[test]
+    fn test_derive() {
+        assert_eq!(ReportExported::<&str>::EVENT, "Report Exported");
+        let exported = ReportExported {
+            format: "csv",
+            rows: 42,
+        };
+        let track = exported.into_track(User::UserId {
+            user_id: "user".to_owned(),
+        });
+        assert_eq!(track.event, "Report Exported");
+        assert_eq!(track.properties, json!({ "format": "csv", "rowCount": 42 }));
+    }
+}
@@ -0,0 +1,218 @@
+//! Filtering of the `track` events against a catalog of approved names.
+
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::message::BatchMessage;
+
+/// Drops the `track` events whose name is not allowed, to enforce an
+/// approved event catalog client-side.
+///
+/// The names are exact, or globs where `*` matches any sequence of
+/// characters and `?` any single character. The other messages are always
+/// kept.
+///
+/// An `EventFilter` is cheap to clone, all the clones share the same count of
+/// dropped events. Keep one around to monitor it:
+///
+/// ```
+/// use segment::{Batcher, EventFilter};
+/// use segment::message::Track;
+///
+/// let filter = EventFilter::allow(["Signed Up", "Order *"]);
+/// let mut batcher = Batcher::new(None);
+/// batcher.set_event_filter(filter.clone());
+///
+/// batcher.push(Track { event: "Order Completed".to_owned(), ..Default::default() }).unwrap();
+/// batcher.push(Track { event: "Button Clicked".to_owned(), ..Default::default() }).unwrap();
+/// assert_eq!(batcher.len(), 1);
+/// assert_eq!(filter.dropped(), 1);
+/// ```
+#[derive(Clone)]
+pub struct EventFilter {
+    rule: FilterRule,
+    dropped: Arc<AtomicU64>,
+}
+
+#[derive(Clone)]
+enum FilterRule {
+    Allow(Vec<String>),
+    Deny(Vec<String>),
+    Predicate(Arc<dyn Fn(&str) -> bool + Send + Sync>),
+}
+
+impl EventFilter {
+    /// Only keep the events matching one of `patterns`.
+    pub fn allow<I>(patterns: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        Self::new(FilterRule::Allow(
+            patterns.into_iter().map(Into::into).collect(),
+        ))
+    }
+
+    /// Drop the events matching one of `patterns`.
+    pub fn deny<I>(patterns: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        Self::new(FilterRule::Deny(
+            patterns.into_iter().map(Into::into).collect(),
+        ))
+    }
+
+    /// Only keep the events whose name `predicate` returns `true` for.
+    pub fn predicate(predicate: impl Fn(&str) -> bool + Send + Sync + 'static) -> Self {
+        Self::new(FilterRule::Predicate(Arc::new(predicate)))
+    }
+
+    fn new(rule: FilterRule) -> Self {
+        Self {
+            rule,
+            dropped: Arc::default(),
+        }
+    }
+
+    /// Returns the number of events dropped so far.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Returns whether an event named `event` is allowed.
+    pub fn allows(&self, event: &str) -> bool {
+        match &self.rule {
+            FilterRule::Allow(patterns) => patterns.iter().any(|pattern| glob(pattern, event)),
+            FilterRule::Deny(patterns) => !patterns.iter().any(|pattern| glob(pattern, event)),
+            FilterRule::Predicate(predicate) => predicate(event),
+        }
+    }
+
+    /// Returns whether `msg` should be kept, counting it otherwise.
+    pub(crate) fn allow_message(&self, msg: &BatchMessage) -> bool {
+        let BatchMessage::Track(track) = msg else {
+            return true;
+        };
+        let allowed = self.allows(&track.event);
+        if !allowed {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        allowed
+    }
+
+    /// Returns a copy of this filter counting its drops apart.
+    pub(crate) fn detached(&self) -> Self {
+        Self {
+            rule: self.rule.clone(),
+            dropped: Arc::default(),
+        }
+    }
+}
+
+impl fmt::Debug for EventFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = f.debug_struct("EventFilter");
+        match &self.rule {
+            FilterRule::Allow(patterns) => debug.field("allow", patterns),
+            FilterRule::Deny(patterns) => debug.field("deny", patterns),
+            FilterRule::Predicate(_) => debug.field("predicate", &format_args!("..")),
+        };
+        debug.field("dropped", &self.dropped()).finish()
+    }
+}
+
+/// Returns whether `name` matches `pattern`, where `*` matches any sequence
+/// of characters and `?` any single character.
+fn glob(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // where to resume after the last `*`: its position, and the name position
+    let mut backtrack = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                // let the last `*` swallow one more character
+                Some((star, matched)) => {
+                    backtrack = Some((star, matched + 1));
+                    p = star + 1;
+                    n = matched + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::{Identify, Track};
+    use crate::Batcher;
+
+    #[test]
+    fn test_glob() {
+        assert!(glob("Signed Up", "Signed Up"));
+        assert!(!glob("Signed Up", "Signed Up Again"));
+        assert!(glob("Order *", "Order Completed"));
+        assert!(glob("Order *", "Order "));
+        assert!(!glob("Order *", "Order"));
+        assert!(glob("*Viewed", "Product Viewed"));
+        assert!(glob("*a*b*", "xxaxxbxx"));
+        assert!(!glob("*a*b", "xxaxxbxx"));
+        assert!(glob("Step ?", "Step 1"));
+        assert!(!glob("Step ?", "Step 10"));
+        assert!(glob("*", ""));
+    }
+
+    #[test]
+    fn test_filters() {
+        let allow = EventFilter::allow(["Signed Up", "Order *"]);
+        assert!(allow.allows("Order Completed"));
+        assert!(!allow.allows("Debug"));
+
+        let deny = EventFilter::deny(["Debug *"]);
+        assert!(deny.allows("Order Completed"));
+        assert!(!deny.allows("Debug Clicked"));
+
+        let predicate = EventFilter::predicate(|event| !event.starts_with("internal."));
+        assert!(!predicate.allows("internal.ping"));
+    }
+
+    #[test]
+    fn test_batcher() {
+        let filter = EventFilter::deny(["Debug *"]);
+        let mut batcher = Batcher::new(None);
+        batcher.set_event_filter(filter.clone());
+
+        for event in ["Debug Clicked", "Signed Up", "Debug Opened"] {
+            batcher
+                .push(Track {
+                    event: event.to_owned(),
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+        // only the `track` events are filtered
+        batcher.push(Identify::default()).unwrap();
+
+        assert_eq!(batcher.len(), 2);
+        assert_eq!(filter.dropped(), 2);
+        assert_eq!(
+            format!("{filter:?}"),
+            r#"EventFilter { deny: ["Debug *"], dropped: 2 }"#
+        );
+    }
+}
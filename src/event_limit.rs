@@ -0,0 +1,217 @@
+//! Limitation of the number of identical events sent per time window.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::batcher::Admission;
+use crate::logging;
+use crate::message::BatchMessage;
+use crate::runtime::Instant;
+
+/// Above this number of tracked keys, the expired windows are pruned.
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// Caps how many `track` events of a given name are sent per time window,
+/// to protect against runaway loops emitting millions of identical events.
+///
+/// See [`Batcher::set_event_limit`](crate::Batcher::set_event_limit).
+///
+/// ```
+/// use std::time::Duration;
+/// use segment::{Batcher, EventLimit, Excess};
+///
+/// let mut batcher = Batcher::new(None);
+/// batcher.set_event_limit(EventLimit {
+///     max_events: 100,
+///     window: Duration::from_secs(60),
+///     per_user: true,
+///     excess: Excess::Sample { one_in: 100 },
+/// });
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EventLimit {
+    /// How many events of the same name may be sent per window.
+    pub max_events: u64,
+
+    /// The duration of a window.
+    pub window: Duration,
+
+    /// Whether the limit applies to each user separately, instead of to all
+    /// the events of the same name.
+    pub per_user: bool,
+
+    /// What to do with the events exceeding the limit.
+    pub excess: Excess,
+}
+
+/// What to do with the events exceeding an [`EventLimit`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Excess {
+    /// Drop all of them.
+    Drop,
+
+    /// Only keep one excess event out of `one_in`.
+    Sample { one_in: u64 },
+}
+
+/// Applies an [`EventLimit`], counting the events of each window.
+#[derive(Clone, Debug)]
+pub(crate) struct EventLimiter {
+    limit: EventLimit,
+    windows: HashMap<WindowKey, Window>,
+}
+
+/// The event name, and the user with [`EventLimit::per_user`], of a window.
+pub(crate) type WindowKey = (String, Option<String>);
+
+#[derive(Clone, Debug)]
+struct Window {
+    start: Instant,
+    count: u64,
+}
+
+impl EventLimiter {
+    pub(crate) fn new(limit: EventLimit) -> Self {
+        Self {
+            limit,
+            windows: HashMap::new(),
+        }
+    }
+
+    /// Returns whether `msg` should be kept. A dropped event is counted in its
+    /// window right away, a kept one once it is enqueued, see [Self::record]:
+    /// an event returned by [`Batcher::push`](crate::Batcher::push) because
+    /// the batch is full is only counted once when pushed again.
+    pub(crate) fn allow(&mut self, msg: &BatchMessage, admission: &mut Admission) -> bool {
+        let BatchMessage::Track(track) = msg else {
+            return true;
+        };
+
+        let now = Instant::now();
+        let window_duration = self.limit.window;
+        if self.windows.len() > PRUNE_THRESHOLD {
+            self.windows
+                .retain(|_, window| now.duration_since(window.start) < window_duration);
+        }
+
+        let user = self.limit.per_user.then(|| track.user.to_string());
+        let key = (track.event.clone(), user);
+        let window = self.windows.entry(key.clone()).or_insert(Window {
+            start: now,
+            count: 0,
+        });
+        if now.duration_since(window.start) >= window_duration {
+            *window = Window {
+                start: now,
+                count: 0,
+            };
+        }
+        let count = window.count + 1;
+
+        let keep = match count.checked_sub(self.limit.max_events) {
+            None | Some(0) => true,
+            Some(excess) => match self.limit.excess {
+                Excess::Drop => false,
+                Excess::Sample { one_in } => excess % one_in.max(1) == 0,
+            },
+        };
+        if keep {
+            admission.event = Some(key);
+        } else {
+            window.count = count;
+            logging::debug!(event = %track.event, "event limit exceeded, dropping event");
+        }
+        keep
+    }
+
+    /// Count an enqueued event in its window.
+    pub(crate) fn record(&mut self, key: WindowKey) {
+        self.windows
+            .entry(key)
+            .or_insert(Window {
+                start: Instant::now(),
+                count: 0,
+            })
+            .count += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::{Track, User};
+
+    fn track(event: &str, user_id: &str) -> BatchMessage {
+        BatchMessage::Track(Track {
+            user: User::UserId {
+                user_id: user_id.to_owned(),
+            },
+            event: event.to_owned(),
+            ..Default::default()
+        })
+    }
+
+    /// Returns whether `msg` is kept, counting it as if enqueued.
+    fn allow(limiter: &mut EventLimiter, msg: &BatchMessage) -> bool {
+        let mut admission = Admission::default();
+        let allowed = limiter.allow(msg, &mut admission);
+        if let Some(key) = admission.event {
+            limiter.record(key);
+        }
+        allowed
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_drop_excess() {
+        let mut limiter = EventLimiter::new(EventLimit {
+            max_events: 2,
+            window: Duration::from_secs(1),
+            per_user: false,
+            excess: Excess::Drop,
+        });
+
+        assert!(allow(&mut limiter, &track("a", "1")));
+        assert!(allow(&mut limiter, &track("a", "2")));
+        assert!(!allow(&mut limiter, &track("a", "3")));
+        assert!(allow(&mut limiter, &track("b", "1")));
+        assert!(allow(
+            &mut limiter,
+            &BatchMessage::Identify(Default::default())
+        ));
+
+        tokio::time::advance(Duration::from_secs(1)).await;
+        assert!(allow(&mut limiter, &track("a", "1")));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_per_user_sampling() {
+        let mut limiter = EventLimiter::new(EventLimit {
+            max_events: 1,
+            window: Duration::from_secs(1),
+            per_user: true,
+            excess: Excess::Sample { one_in: 3 },
+        });
+
+        let kept: Vec<_> = (0..7)
+            .map(|_| allow(&mut limiter, &track("a", "1")))
+            .collect();
+        assert_eq!(kept, [true, false, false, true, false, false, true]);
+        assert!(allow(&mut limiter, &track("a", "2")));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_count_enqueued_events() {
+        let mut limiter = EventLimiter::new(EventLimit {
+            max_events: 1,
+            window: Duration::from_secs(1),
+            per_user: false,
+            excess: Excess::Drop,
+        });
+
+        // not enqueued, for instance because the batch was full
+        let mut admission = Admission::default();
+        assert!(limiter.allow(&track("a", "1"), &mut admission));
+        assert!(allow(&mut limiter, &track("a", "1")));
+        assert!(!allow(&mut limiter, &track("a", "1")));
+    }
+}
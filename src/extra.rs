@@ -0,0 +1,164 @@
+//! A schema of the custom top-level fields of the messages.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::message::BatchMessage;
+use crate::schema::JsonType;
+use crate::validation::ValidationError;
+
+/// The top-level fields of the Segment spec which are kept in the `extra`
+/// fields of the messages, always allowed.
+const SPEC_FIELDS: [&str; 6] = [
+    "channel",
+    "messageId",
+    "originalTimestamp",
+    "receivedAt",
+    "sentAt",
+    "version",
+];
+
+/// The custom top-level fields allowed in the `extra` fields of the
+/// messages, like the `tenantId` expected by a gateway, see
+/// [`Validation::extra_fields`](crate::Validation::extra_fields).
+///
+/// ```
+/// use segment::{Batcher, Error, ExtraSchema, JsonType, Validation, ValidationError};
+/// use segment::message::{BatchMessage, Track, User};
+/// use serde_json::json;
+///
+/// let mut batcher = Batcher::new(None);
+/// batcher.set_validation(Validation {
+///     extra_fields: Some(ExtraSchema::default().field("tenantId", [JsonType::String])),
+///     ..Default::default()
+/// });
+///
+/// let mut msg = BatchMessage::from(Track {
+///     user: User::UserId { user_id: "user".to_owned() },
+///     event: "Signed Up".to_owned(),
+///     ..Default::default()
+/// });
+/// msg.extra_mut().insert("tenantID".to_owned(), json!("acme"));
+/// let result = batcher.push(msg);
+/// assert!(matches!(result, Err(Error::InvalidMessage(ValidationError::UnknownField { .. }))));
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ExtraSchema {
+    /// The types allowed for each field, the fields without a type accept
+    /// any value.
+    pub fields: BTreeMap<String, BTreeSet<JsonType>>,
+
+    /// The fields which must be present.
+    pub required: BTreeSet<String>,
+
+    /// Allow the fields which are not in the schema.
+    pub allow_unknown: bool,
+}
+
+impl ExtraSchema {
+    /// Allow the field `name`, with one of the `types`.
+    pub fn field(
+        mut self,
+        name: impl Into<String>,
+        types: impl IntoIterator<Item = JsonType>,
+    ) -> Self {
+        self.fields.insert(name.into(), types.into_iter().collect());
+        self
+    }
+
+    /// Require the field `name`, with one of the `types`.
+    pub fn required(
+        mut self,
+        name: impl Into<String>,
+        types: impl IntoIterator<Item = JsonType>,
+    ) -> Self {
+        let name = name.into();
+        self.required.insert(name.clone());
+        self.field(name, types)
+    }
+
+    /// Check the extra fields of `msg`, returns the first violation.
+    pub(crate) fn check(&self, msg: &BatchMessage) -> Result<(), ValidationError> {
+        let extra = msg.extra();
+        if let Some(missing) = self.required.iter().find(|name| !extra.contains_key(*name)) {
+            return Err(ValidationError::MissingProperty {
+                field: missing.clone(),
+            });
+        }
+        for (name, value) in extra {
+            match self.fields.get(name) {
+                Some(expected) => {
+                    if !JsonType::matches(expected, value) {
+                        let found = JsonType::of(value);
+                        return Err(ValidationError::InvalidType {
+                            field: name.clone(),
+                            expected: expected.clone(),
+                            found,
+                        });
+                    }
+                }
+                None if self.allow_unknown || SPEC_FIELDS.contains(&name.as_str()) => {}
+                None => {
+                    return Err(ValidationError::UnknownField {
+                        field: name.clone(),
+                    })
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::Identify;
+    use serde_json::{json, Value};
+
+    fn identify(extra: Value) -> BatchMessage {
+        let Value::Object(extra) = extra else {
+            unreachable!()
+        };
+        BatchMessage::Identify(Identify {
+            extra,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_check() {
+        let schema = ExtraSchema::default()
+            .required("tenantId", [JsonType::String])
+            .field("region", []);
+
+        let msg = identify(json!({ "tenantId": "acme", "region": 1, "messageId": "id" }));
+        assert_eq!(schema.check(&msg), Ok(()));
+
+        assert_eq!(
+            schema.check(&identify(json!({}))),
+            Err(ValidationError::MissingProperty {
+                field: "tenantId".to_owned()
+            })
+        );
+        assert_eq!(
+            schema.check(&identify(json!({ "tenantId": 1 }))),
+            Err(ValidationError::InvalidType {
+                field: "tenantId".to_owned(),
+                expected: [JsonType::String].into(),
+                found: JsonType::Number,
+            })
+        );
+
+        let msg = identify(json!({ "tenantId": "acme", "tenant": "acme" }));
+        assert_eq!(
+            schema.check(&msg),
+            Err(ValidationError::UnknownField {
+                field: "tenant".to_owned()
+            })
+        );
+        let schema = ExtraSchema {
+            allow_unknown: true,
+            ..schema
+        };
+        assert_eq!(schema.check(&msg), Ok(()));
+    }
+}
@@ -0,0 +1,176 @@
+//! A client failing over from a primary to a secondary endpoint.
+
+use std::num::NonZeroU32;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::{
+    client::Client,
+    errors::Result,
+    http::HttpClient,
+    logging,
+    message::{Message, SerializedBatch},
+    runtime::Instant,
+};
+
+/// A [`Client`] sending to a primary endpoint, and failing over to a
+/// secondary one while the primary is unhealthy, for instance with Segment
+/// fronted by proxies in several regions.
+///
+/// A request failing on the primary with a transient error (see
+/// [`Error::is_retryable`](crate::Error::is_retryable)) is sent again to the
+/// secondary. After `failure_threshold` consecutive failures the primary is
+/// considered unhealthy, and the requests go straight to the secondary for
+/// `recovery_interval`. The next request after that probes the primary again,
+/// switching back to it if it succeeds.
+///
+/// The health is shared by the clones of the client.
+///
+/// ```
+/// use segment::{AutoBatcher, Batcher, FailoverClient, HttpClient};
+///
+/// let primary = HttpClient::new(reqwest::Client::new(), "https://eu.proxy.example".to_owned());
+/// let secondary = HttpClient::new(reqwest::Client::new(), "https://us.proxy.example".to_owned());
+/// let client = FailoverClient::new(primary, secondary);
+///
+/// let batcher = AutoBatcher::new(client, Batcher::new(None), "your_write_key".to_string());
+/// ```
+#[derive(Clone, Debug)]
+pub struct FailoverClient<P = HttpClient, S = HttpClient> {
+    primary: P,
+    secondary: S,
+    failure_threshold: NonZeroU32,
+    recovery_interval: Duration,
+    health: Arc<Mutex<Health>>,
+}
+
+#[derive(Debug, Default)]
+struct Health {
+    consecutive_failures: u32,
+    unhealthy_until: Option<Instant>,
+}
+
+impl<P, S> FailoverClient<P, S> {
+    /// Construct a client preferring `primary` over `secondary`.
+    ///
+    /// The primary is considered unhealthy after 3 consecutive failures, for
+    /// 30 seconds.
+    pub fn new(primary: P, secondary: S) -> Self {
+        Self {
+            primary,
+            secondary,
+            failure_threshold: NonZeroU32::new(3).unwrap(),
+            recovery_interval: Duration::from_secs(30),
+            health: Arc::default(),
+        }
+    }
+
+    /// Set after how many consecutive failures the primary is considered
+    /// unhealthy.
+    pub fn set_failure_threshold(&mut self, threshold: NonZeroU32) {
+        self.failure_threshold = threshold;
+    }
+
+    /// Set how long the requests skip an unhealthy primary before probing it
+    /// again.
+    pub fn set_recovery_interval(&mut self, interval: Duration) {
+        self.recovery_interval = interval;
+    }
+
+    /// Returns whether the requests are currently sent to the primary.
+    pub fn is_primary_healthy(&self) -> bool {
+        let health = self.health.lock().unwrap();
+        health
+            .unhealthy_until
+            .is_none_or(|until| Instant::now() >= until)
+    }
+
+    fn record(&self, result: &Result<()>) {
+        let mut health = self.health.lock().unwrap();
+        match result {
+            Ok(()) => {
+                if health.unhealthy_until.take().is_some() {
+                    logging::info!("primary endpoint recovered");
+                }
+                health.consecutive_failures = 0;
+            }
+            Err(err) if err.is_retryable() => {
+                health.consecutive_failures += 1;
+                if health.consecutive_failures >= self.failure_threshold.get() {
+                    logging::warn!(
+                        err = err as &(dyn std::error::Error + 'static),
+                        "primary endpoint unhealthy, failing over to the secondary"
+                    );
+                    health.unhealthy_until = Some(Instant::now() + self.recovery_interval);
+                }
+            }
+            // the request itself is invalid, the secondary would reject it too
+            Err(_) => {}
+        }
+    }
+}
+
+impl<P, S> Client for FailoverClient<P, S>
+where
+    P: Client + Send + Sync,
+    S: Client + Send + Sync,
+{
+    async fn send(&self, write_key: String, msg: Message) -> Result<()> {
+        if !self.is_primary_healthy() {
+            return self.secondary.send(write_key, msg).await;
+        }
+
+        let result = self.primary.send(write_key.clone(), msg.clone()).await;
+        self.record(&result);
+        match result {
+            Err(err) if err.is_retryable() => self.secondary.send(write_key, msg).await,
+            result => result,
+        }
+    }
+
+    async fn send_batch(&self, write_key: String, batch: &SerializedBatch) -> Result<()> {
+        if !self.is_primary_healthy() {
+            return self.secondary.send_batch(write_key, batch).await;
+        }
+
+        let result = self.primary.send_batch(write_key.clone(), batch).await;
+        self.record(&result);
+        match result {
+            Err(err) if err.is_retryable() => self.secondary.send_batch(write_key, batch).await,
+            result => result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MockClient;
+    use crate::Batcher;
+    use std::sync::atomic::Ordering;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_failover_and_recovery() {
+        let primary = MockClient::default();
+        let secondary = MockClient::default();
+        let mut client = FailoverClient::new(primary.clone(), secondary.clone());
+        client.set_failure_threshold(NonZeroU32::new(2).unwrap());
+        client.set_recovery_interval(Duration::from_secs(10));
+        let batch = Batcher::new(None).into_serialized();
+
+        primary.failures.store(usize::MAX, Ordering::SeqCst);
+        for _ in 0..3 {
+            client.send_batch("key".to_owned(), &batch).await.unwrap();
+        }
+        // the third request skipped the unhealthy primary
+        assert!(!client.is_primary_healthy());
+        assert_eq!(secondary.sent.lock().unwrap().len(), 3);
+
+        primary.failures.store(0, Ordering::SeqCst);
+        tokio::time::advance(Duration::from_secs(10)).await;
+        client.send_batch("key".to_owned(), &batch).await.unwrap();
+        assert!(client.is_primary_healthy());
+        assert_eq!(primary.sent.lock().unwrap().len(), 1);
+        assert_eq!(secondary.sent.lock().unwrap().len(), 3);
+    }
+}
@@ -0,0 +1,195 @@
+//! Flattening of nested properties.
+
+use std::collections::VecDeque;
+
+use serde_json::{Map, Value};
+
+use crate::logging;
+use crate::warning::{Warning, Warnings};
+
+/// Options to flatten nested property objects into top-level keys.
+///
+/// Some destinations (Mixpanel for instance) handle nested objects poorly.
+/// Flattening turns `{"address": {"city": "Paris"}}` into
+/// `{"address_city": "Paris"}`. Arrays are left untouched, and empty objects
+/// are kept as `{}`.
+///
+/// When a flattened key collides with another key, like `address_city` with
+/// `{"address": {"city": ..}}`, the value of the least nested key is kept,
+/// or the first one in the order of the keys, and the collision is reported
+/// as a [`Warning::FlattenCollision`].
+///
+/// Flattening is opt-in, see
+/// [`Batcher::set_property_flattening`](crate::Batcher::set_property_flattening).
+///
+/// ```
+/// use segment::FlattenOptions;
+/// use serde_json::json;
+///
+/// let mut properties = json!({ "address": { "city": "Paris", "zip": "75001" } });
+/// FlattenOptions::default().flatten(&mut properties);
+/// assert_eq!(properties, json!({ "address_city": "Paris", "address_zip": "75001" }));
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FlattenOptions {
+    /// The string used to join the keys of nested objects.
+    pub separator: String,
+
+    /// Objects nested deeper than this are serialized into a JSON string
+    /// instead of being flattened.
+    pub max_depth: usize,
+
+    /// The maximum number of keys the flattened object may contain. Keys
+    /// beyond this limit are dropped.
+    pub max_keys: usize,
+}
+
+impl Default for FlattenOptions {
+    fn default() -> Self {
+        Self {
+            separator: "_".to_owned(),
+            max_depth: 5,
+            max_keys: 255,
+        }
+    }
+}
+
+impl FlattenOptions {
+    /// Flatten `value` in place. Does nothing if `value` is not an object.
+    ///
+    /// The collisions are only logged, the [`Batcher`](crate::Batcher) also
+    /// forwards them to its warning handler.
+    pub fn flatten(&self, value: &mut Value) {
+        self.flatten_reporting(value, &Warnings::default());
+    }
+
+    /// Flatten `value` in place, reporting the collisions to `warnings`.
+    pub(crate) fn flatten_reporting(&self, value: &mut Value, warnings: &Warnings) {
+        let Value::Object(object) = value else {
+            return;
+        };
+
+        // breadth first, so that the least nested keys are inserted first
+        let mut flattened = Map::new();
+        let mut dropped = 0;
+        let mut nested = VecDeque::from([(String::new(), std::mem::take(object), 0)]);
+        while let Some((prefix, object, depth)) = nested.pop_front() {
+            for (key, value) in object {
+                let key = if prefix.is_empty() {
+                    key
+                } else {
+                    format!("{prefix}{}{key}", self.separator)
+                };
+
+                let value = match value {
+                    Value::Object(object) if !object.is_empty() && depth < self.max_depth => {
+                        nested.push_back((key, object, depth + 1));
+                        continue;
+                    }
+                    Value::Object(object) if !object.is_empty() => {
+                        Value::String(Value::Object(object).to_string())
+                    }
+                    value => value,
+                };
+                if flattened.contains_key(&key) {
+                    warnings.emit(Warning::FlattenCollision { property: key });
+                } else if flattened.len() < self.max_keys {
+                    flattened.insert(key, value);
+                } else {
+                    dropped += 1;
+                }
+            }
+        }
+        if dropped > 0 {
+            logging::warn!(
+                dropped,
+                "too many properties after flattening, some were dropped"
+            );
+        }
+        *object = flattened;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_flatten() {
+        let mut value = json!({
+            "plan": "pro",
+            "address": { "city": "Paris", "geo": { "lat": 48.8, "lon": 2.3 } },
+            "tags": [{ "a": 1 }],
+            "empty": {},
+        });
+        FlattenOptions::default().flatten(&mut value);
+
+        assert_eq!(
+            value,
+            json!({
+                "plan": "pro",
+                "address_city": "Paris",
+                "address_geo_lat": 48.8,
+                "address_geo_lon": 2.3,
+                "tags": [{ "a": 1 }],
+                "empty": {},
+            })
+        );
+    }
+
+    #[test]
+    fn test_collisions() {
+        let collisions = Arc::new(Mutex::new(Vec::new()));
+        let mut warnings = Warnings::default();
+        warnings.set_handler({
+            let collisions = collisions.clone();
+            Arc::new(move |warning: &Warning| collisions.lock().unwrap().push(warning.clone()))
+        });
+
+        // the least nested key is kept, whatever the order of the keys
+        let mut value = json!({
+            "a": { "b": 1, "c": { "d": 2 } },
+            "a_b": 3,
+            "a_c": { "d": 4 },
+        });
+        FlattenOptions::default().flatten_reporting(&mut value, &warnings);
+        assert_eq!(value, json!({ "a_b": 3, "a_c_d": 4 }));
+        assert_eq!(
+            *collisions.lock().unwrap(),
+            [
+                Warning::FlattenCollision {
+                    property: "a_b".to_owned()
+                },
+                Warning::FlattenCollision {
+                    property: "a_c_d".to_owned()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_limits() {
+        let options = FlattenOptions {
+            separator: ".".to_owned(),
+            max_depth: 1,
+            max_keys: 2,
+        };
+
+        let mut value = json!({ "a": { "b": { "c": 1 } } });
+        options.flatten(&mut value);
+        assert_eq!(value, json!({ "a.b": r#"{"c":1}"# }));
+
+        let mut value = json!({ "a": 1, "b": { "c": 2, "d": 3 } });
+        options.flatten(&mut value);
+        assert_eq!(value, json!({ "a": 1, "b.c": 2 }));
+    }
+
+    #[test]
+    fn test_not_an_object() {
+        let mut value = json!([1, 2]);
+        FlattenOptions::default().flatten(&mut value);
+        assert_eq!(value, json!([1, 2]));
+    }
+}
@@ -0,0 +1,206 @@
+//! Enrichment of the messages with the location of their IP address.
+
+use std::fmt;
+use std::future::Future;
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::hooks::catch_async_panic;
+use crate::message::BatchMessage;
+use crate::warning::{Warning, Warnings};
+
+/// A location, as stored in `context.location`.
+///
+/// See [Segment's context
+/// documentation](https://segment.com/docs/spec/common/#context).
+#[derive(PartialEq, Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GeoLocation {
+    /// The country name.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub country: Option<String>,
+
+    /// The region, or state, name.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+
+    /// The city name.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub city: Option<String>,
+
+    /// The latitude, in degrees.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latitude: Option<f64>,
+
+    /// The longitude, in degrees.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub longitude: Option<f64>,
+}
+
+/// Resolves an IP address into a [`GeoLocation`], for instance with a MaxMind
+/// database or an internal service.
+///
+/// When a resolver is set with
+/// [`AutoBatcher::set_geo_resolver`](crate::AutoBatcher::set_geo_resolver),
+/// every pushed message with a `context.ip` is enriched with its
+/// `context.location` before being buffered, once processed by the
+/// [`Batcher`](crate::Batcher): the messages dropped by the filters are not
+/// resolved, the `ip` of the
+/// [message context](crate::Batcher::set_message_context) is, and the `ip`
+/// removed by the
+/// [`ConsentFilter`](crate::ConsentFilter) is not. The location is then not
+/// visible to the enrichers and the stages of the
+/// [`Pipeline`](crate::Pipeline). The fields already present in
+/// `context.location` are kept. A resolver which panics leaves the message
+/// as is, and reports a [`Warning::HookSkipped`](crate::Warning::HookSkipped).
+///
+/// ```
+/// use std::net::IpAddr;
+/// use segment::{GeoLocation, GeoResolver};
+///
+/// struct Resolver;
+///
+/// impl GeoResolver for Resolver {
+///     async fn resolve(&self, ip: IpAddr) -> Option<GeoLocation> {
+///         ip.is_loopback().then(|| GeoLocation {
+///             country: Some("France".to_owned()),
+///             ..Default::default()
+///         })
+///     }
+/// }
+/// ```
+pub trait GeoResolver: Send + Sync {
+    /// Returns the location of `ip`, or `None` if it is unknown.
+    fn resolve(&self, ip: IpAddr) -> impl Future<Output = Option<GeoLocation>> + Send;
+}
+
+/// [`GeoResolver`] as a trait object, boxing the futures.
+pub(crate) trait DynGeoResolver: Send + Sync {
+    fn resolve(&self, ip: IpAddr)
+        -> Pin<Box<dyn Future<Output = Option<GeoLocation>> + Send + '_>>;
+}
+
+impl<T: GeoResolver> DynGeoResolver for T {
+    fn resolve(
+        &self,
+        ip: IpAddr,
+    ) -> Pin<Box<dyn Future<Output = Option<GeoLocation>> + Send + '_>> {
+        Box::pin(GeoResolver::resolve(self, ip))
+    }
+}
+
+/// Applies a [`GeoResolver`] to the messages.
+#[derive(Clone)]
+pub(crate) struct GeoEnricher(pub(crate) Arc<dyn DynGeoResolver>);
+
+impl GeoEnricher {
+    /// Add the location of the IP address of `msg`. A resolver which
+    /// panicked is skipped, with a [`Warning::HookSkipped`].
+    pub(crate) async fn enrich(&self, msg: &mut BatchMessage, warnings: &Warnings) {
+        let Some(Value::Object(context)) = msg.context_mut() else {
+            return;
+        };
+        let Some(ip) = context
+            .get("ip")
+            .and_then(Value::as_str)
+            .and_then(|ip| ip.parse().ok())
+        else {
+            return;
+        };
+        let location = match catch_async_panic(self.0.resolve(ip)).await {
+            Ok(Some(location)) => location,
+            Ok(None) => return,
+            Err(reason) => {
+                warnings.emit(Warning::HookSkipped {
+                    hook: "geo resolver".to_owned(),
+                    reason,
+                });
+                return;
+            }
+        };
+        let Ok(Value::Object(resolved)) = serde_json::to_value(location) else {
+            return;
+        };
+
+        let location = context
+            .entry("location")
+            .or_insert_with(|| Value::Object(Map::new()));
+        if let Value::Object(location) = location {
+            for (key, value) in resolved {
+                location.entry(key).or_insert(value);
+            }
+        }
+    }
+}
+
+impl fmt::Debug for GeoEnricher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GeoEnricher").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::Track;
+    use serde_json::json;
+
+    struct StaticResolver;
+
+    impl GeoResolver for StaticResolver {
+        async fn resolve(&self, ip: IpAddr) -> Option<GeoLocation> {
+            assert!(!ip.is_unspecified(), "unspecified address");
+            ip.is_loopback().then(|| GeoLocation {
+                country: Some("France".to_owned()),
+                city: Some("Paris".to_owned()),
+                ..Default::default()
+            })
+        }
+    }
+
+    async fn enrich(context: Option<Value>) -> Option<Value> {
+        let mut msg = BatchMessage::Track(Track {
+            context,
+            ..Default::default()
+        });
+        GeoEnricher(Arc::new(StaticResolver))
+            .enrich(&mut msg, &Warnings::default())
+            .await;
+        msg.context_mut().clone()
+    }
+
+    #[tokio::test]
+    async fn test_enrich() {
+        assert_eq!(
+            enrich(Some(json!({ "ip": "127.0.0.1" }))).await,
+            Some(
+                json!({ "ip": "127.0.0.1", "location": { "country": "France", "city": "Paris" } })
+            )
+        );
+        assert_eq!(
+            enrich(Some(json!({ "ip": "::1", "location": { "city": "Lyon" } }))).await,
+            Some(json!({ "ip": "::1", "location": { "country": "France", "city": "Lyon" } }))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_nothing_to_enrich() {
+        assert_eq!(enrich(None).await, None);
+        assert_eq!(
+            enrich(Some(json!({ "ip": "8.8.8.8" }))).await,
+            Some(json!({ "ip": "8.8.8.8" }))
+        );
+        assert_eq!(
+            enrich(Some(json!({ "ip": "not an ip" }))).await,
+            Some(json!({ "ip": "not an ip" }))
+        );
+        // the resolver panics
+        assert_eq!(
+            enrich(Some(json!({ "ip": "0.0.0.0" }))).await,
+            Some(json!({ "ip": "0.0.0.0" }))
+        );
+    }
+}
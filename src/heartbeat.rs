@@ -0,0 +1,154 @@
+//! Heartbeat events probing the health of each destination.
+
+use std::time::Duration;
+
+use serde_json::json;
+use time::OffsetDateTime;
+
+use crate::{
+    auto_batcher::AutoBatcher,
+    batcher::Batcher,
+    client::Client,
+    integrations::Integrations,
+    logging,
+    message::{BatchMessage, Track, User},
+};
+
+/// The name of the heartbeat events sent by the destination probes.
+pub const HEARTBEAT_EVENT: &str = "Segment Destination Heartbeat";
+
+/// Periodic heartbeat events, one per enabled destination of
+/// `integrations`, sent by a [`Worker`](crate::Worker), see
+/// [`WorkerConfig::health_probes`](crate::WorkerConfig::health_probes).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HealthProbes {
+    /// The destinations to probe: the ones listed and enabled.
+    pub integrations: Integrations,
+
+    /// How often the destinations are probed, the first time right away.
+    pub interval: Duration,
+}
+
+impl<C> AutoBatcher<C>
+where
+    C: Client + Clone + Send + Sync + 'static,
+{
+    /// Send a [`HEARTBEAT_EVENT`] `track` event to each destination listed
+    /// and enabled in `integrations`, routed to this destination only, and
+    /// record the time at which Segment accepted it in
+    /// [`Stats::heartbeats`](crate::Stats::heartbeats).
+    ///
+    /// A stale heartbeat means that Segment rejects the events, while a
+    /// missing heartbeat in a single destination, alerted on by a check of
+    /// the destination, means that this one doesn't receive them.
+    ///
+    /// The heartbeats are neither buffered nor processed by the batcher, and
+    /// are sent without retries nor rate limiting.
+    ///
+    /// ```
+    /// use segment::{AutoBatcher, Batcher, HttpClient, Integrations};
+    ///
+    /// # async fn run() {
+    /// let client = HttpClient::default();
+    /// let batcher = AutoBatcher::new(client, Batcher::new(None), "your_write_key".to_string());
+    ///
+    /// let destinations = Integrations::new().enable("Amplitude").enable("BigQuery");
+    /// batcher.probe_destinations(&destinations).await;
+    /// println!("{:?}", batcher.stats().heartbeats);
+    /// # }
+    /// ```
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    pub async fn probe_destinations(&self, integrations: &Integrations) {
+        let destinations = integrations
+            .destinations
+            .keys()
+            .filter(|name| integrations.is_enabled(name));
+        for destination in destinations {
+            let mut batcher = Batcher::new(self.batcher.context.clone());
+            let route = json!({ "All": false, destination.as_str(): true });
+            let result = batcher
+                .set_integrations(Some(route))
+                .and_then(|()| batcher.serialize(&heartbeat(destination)));
+            let msg = match result {
+                Ok(msg) => msg,
+                Err(err) => {
+                    logging::error!(
+                        err = &err as &(dyn std::error::Error + 'static),
+                        destination,
+                        "failed to build the heartbeat"
+                    );
+                    continue;
+                }
+            };
+            batcher.enqueue(msg);
+
+            let batch = batcher.into_serialized();
+            match self.client.send_batch(self.key.clone(), &batch).await {
+                Ok(()) => {
+                    self.counters()
+                        .heartbeats
+                        .lock()
+                        .unwrap()
+                        .insert(destination.clone(), OffsetDateTime::now_utc());
+                }
+                Err(err) => logging::warn!(
+                    err = &err as &(dyn std::error::Error + 'static),
+                    destination,
+                    "failed to send the heartbeat"
+                ),
+            }
+        }
+    }
+}
+
+fn heartbeat(destination: &str) -> BatchMessage {
+    BatchMessage::Track(Track {
+        user: User::AnonymousId {
+            anonymous_id: "segment-heartbeat".to_owned(),
+        },
+        event: HEARTBEAT_EVENT.to_owned(),
+        properties: json!({ "destination": destination }),
+        timestamp: Some(OffsetDateTime::now_utc()),
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MockClient;
+    use crate::Message;
+    use std::sync::atomic::Ordering;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_probe_destinations() {
+        let client = MockClient::default();
+        let batcher = AutoBatcher::new(client.clone(), Batcher::new(None), "key".to_owned());
+        let destinations = Integrations::new()
+            .enable("Amplitude")
+            .enable("BigQuery")
+            .disable("Mixpanel");
+
+        // the first heartbeat fails
+        client.failures.store(1, Ordering::SeqCst);
+        batcher.probe_destinations(&destinations).await;
+        let heartbeats = batcher.stats().heartbeats;
+        assert_eq!(heartbeats.keys().collect::<Vec<_>>(), ["BigQuery"]);
+
+        let Message::Batch(batch) = client.sent.lock().unwrap()[0].clone() else {
+            panic!("expected a batch");
+        };
+        assert_eq!(
+            batch.integrations,
+            Some(json!({ "All": false, "BigQuery": true }))
+        );
+        let BatchMessage::Track(track) = &batch.batch[0] else {
+            panic!("expected a track");
+        };
+        assert_eq!(track.event, HEARTBEAT_EVENT);
+
+        batcher.probe_destinations(&destinations).await;
+        assert_eq!(batcher.stats().heartbeats.len(), 2);
+        assert_eq!(client.sent_count(), 3);
+    }
+}
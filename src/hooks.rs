@@ -0,0 +1,229 @@
+//! User-provided hooks transforming the pushed messages.
+
+use std::any::Any;
+use std::fmt;
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+
+use futures_util::FutureExt;
+
+use crate::errors::Error;
+use crate::message::BatchMessage;
+use crate::warning::{Warning, Warnings};
+
+/// The top-level fields which the hooks must not corrupt. `type` is the tag
+/// of the message and must never be present in the extra fields.
+const RESERVED_FIELDS: [&str; 3] = ["type", "messageId", "writeKey"];
+
+/// A named user-provided function transforming the pushed messages, returns
+/// whether the message is kept: an enricher, or a stage of a
+/// [`Pipeline`](crate::Pipeline).
+#[derive(Clone)]
+pub(crate) struct Hook {
+    pub(crate) name: String,
+    run: Arc<dyn Fn(&mut BatchMessage) -> bool + Send + Sync>,
+}
+
+impl Hook {
+    pub(crate) fn new(
+        name: String,
+        run: impl Fn(&mut BatchMessage) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            name,
+            run: Arc::new(run),
+        }
+    }
+}
+
+/// Run `hooks` on `msg` in order, returns whether the message is kept,
+/// skipping the hooks which panicked or corrupted a reserved field.
+///
+/// The message is copied before each hook: when a hook fails, its changes are
+/// discarded and the next hooks run on the message left by the previous ones,
+/// which don't run again.
+pub(crate) fn run(hooks: &[Hook], msg: &mut BatchMessage, warnings: &Warnings) -> bool {
+    for hook in hooks {
+        let before = msg.clone();
+        // a dropped message is not checked
+        let result = catch_panic(|| (hook.run)(msg)).and_then(|keep| match keep {
+            true => check_reserved_fields(&before, msg)
+                .map(|()| true)
+                .map_err(|err| err.to_string()),
+            false => Ok(false),
+        });
+        match result {
+            Ok(true) => {}
+            Ok(false) => return false,
+            Err(reason) => {
+                warnings.emit(Warning::HookSkipped {
+                    hook: hook.name.clone(),
+                    reason,
+                });
+                *msg = before;
+            }
+        }
+    }
+    true
+}
+
+impl fmt::Debug for Hook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Hook")
+            .field("name", &self.name)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Run a user-provided function, converting its panic into an error
+/// message, so that a buggy hook doesn't take the pipeline down.
+pub(crate) fn catch_panic<R>(f: impl FnOnce() -> R) -> Result<R, String> {
+    panic::catch_unwind(AssertUnwindSafe(f)).map_err(|payload| panic_message(&*payload))
+}
+
+/// Like [catch_panic], for a user-provided future.
+pub(crate) async fn catch_async_panic<F: Future>(future: F) -> Result<F::Output, String> {
+    AssertUnwindSafe(future)
+        .catch_unwind()
+        .await
+        .map_err(|payload| panic_message(&*payload))
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    let message = payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown payload");
+    format!("panicked: {message}")
+}
+
+/// Check that the reserved fields of `original` were left untouched in
+/// `modified`, and that they still produce a parseable payload.
+///
+/// Returns [`Error::ReservedFieldModified`] otherwise.
+pub(crate) fn check_reserved_fields(
+    original: &BatchMessage,
+    modified: &BatchMessage,
+) -> Result<(), Error> {
+    let (before, after) = (original.extra(), modified.extra());
+    for field in RESERVED_FIELDS {
+        let valid = match (field, before.get(field), after.get(field)) {
+            ("type", _, after) => after.is_none(),
+            // a hook may add a message ID, but not change an existing one
+            ("messageId", None, Some(after)) => after.is_string(),
+            (_, before, after) => before == after,
+        };
+        if !valid {
+            return Err(Error::ReservedFieldModified(field));
+        }
+    }
+    if std::mem::discriminant(original) != std::mem::discriminant(modified) {
+        return Err(Error::ReservedFieldModified("type"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::{Identify, Track};
+    use serde_json::{json, Value};
+    use std::sync::Mutex;
+
+    fn apply(hook: impl Fn(&mut BatchMessage) + Send + Sync + 'static) -> Vec<Warning> {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let mut warnings = Warnings::default();
+        let handler = received.clone();
+        warnings.set_handler(Arc::new(move |warning| {
+            handler.lock().unwrap().push(warning.clone());
+        }));
+
+        let mut msg = BatchMessage::Track(Track {
+            extra: [("messageId".to_owned(), json!("id"))]
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        });
+        let original = msg.clone();
+        let hook = Hook::new("test".to_owned(), move |msg| {
+            hook(msg);
+            true
+        });
+        assert!(run(&[hook], &mut msg, &warnings));
+
+        let received = received.lock().unwrap().clone();
+        if !received.is_empty() {
+            assert_eq!(msg, original);
+        }
+        received
+    }
+
+    #[test]
+    fn test_valid_hook() {
+        let warnings = apply(|msg| {
+            if let Some(properties) = msg.properties_mut() {
+                *properties = json!({ "enriched": true });
+            }
+        });
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn test_panicking_hook() {
+        let warnings = apply(|msg| {
+            msg.extra_mut().insert("half".to_owned(), json!("done"));
+            panic!("buggy hook");
+        });
+        assert!(
+            matches!(&warnings[..], [Warning::HookSkipped { reason, .. }] if reason == "panicked: buggy hook"),
+            "{warnings:?}"
+        );
+    }
+
+    #[test]
+    fn test_corrupting_hooks() {
+        let hooks: [fn(&mut BatchMessage); 3] = [
+            |msg| {
+                msg.extra_mut().insert("type".to_owned(), json!("page"));
+            },
+            |msg| {
+                msg.extra_mut().insert("messageId".to_owned(), Value::Null);
+            },
+            |msg| *msg = BatchMessage::Identify(Identify::default()),
+        ];
+        for hook in hooks {
+            let warnings = apply(hook);
+            assert!(
+                matches!(&warnings[..], [Warning::HookSkipped { hook, .. }] if hook == "test"),
+                "{warnings:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_hooks_before_a_failing_one() {
+        let calls = Arc::new(Mutex::new(0));
+        let counter = calls.clone();
+        let hooks = [
+            Hook::new("first".to_owned(), move |msg| {
+                *counter.lock().unwrap() += 1;
+                msg.extra_mut().insert("first".to_owned(), json!(true));
+                true
+            }),
+            Hook::new("failing".to_owned(), |_| panic!("buggy hook")),
+            Hook::new("last".to_owned(), |msg| {
+                msg.extra_mut().insert("last".to_owned(), json!(true));
+                true
+            }),
+            Hook::new("dropping".to_owned(), |msg| msg.extra().len() < 2),
+        ];
+        let mut msg = BatchMessage::Track(Track::default());
+        assert!(!run(&hooks, &mut msg, &Warnings::default()));
+        assert_eq!(msg.extra().get("first"), Some(&json!(true)));
+        assert_eq!(msg.extra().get("last"), Some(&json!(true)));
+        // not replayed after the failing hook
+        assert_eq!(*calls.lock().unwrap(), 1);
+    }
+}
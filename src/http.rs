@@ -1,30 +1,303 @@
 //! Low-level HTTP bindings to the Segment tracking API.
 
+use crate::logging;
+use crate::message::SerializedBatch;
+#[cfg(feature = "opentelemetry")]
+use crate::trace_context;
+use crate::validation::ValidationError;
 use crate::Client;
 use crate::Message;
-use crate::Result;
+use crate::{Error, Result};
+#[cfg(not(target_arch = "wasm32"))]
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::header::CONTENT_TYPE;
+use reqwest::{Body, RequestBuilder};
+#[cfg(not(target_arch = "wasm32"))]
+use std::convert::Infallible;
+use std::fmt;
+use std::future::Future;
+#[cfg(not(target_arch = "wasm32"))]
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+#[cfg(target_arch = "wasm32")]
+use std::task::{Context, Poll};
 use std::time::Duration;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+#[cfg(feature = "tracing")]
+use tracing::field::Empty;
+#[cfg(target_arch = "wasm32")]
+use web_time::Instant;
 
 /// A client which synchronously sends single messages to the Segment tracking
 /// API.
 ///
 /// `HttpClient` implements [`Client`](../client/trait.Client.html); see the
 /// documentation for `Client` for more on how to send events to Segment.
+///
+/// With the `opentelemetry` feature, the requests carry the `traceparent` of
+/// the current span, so that the deliveries show up in distributed traces.
+///
+/// On wasm32, with the `wasm` feature, the requests go through the `fetch` of
+/// the JavaScript host, which manages the connections and the redirects:
+/// only [`HttpClientConfig::allow_http`] applies, and the batches are not
+/// streamed. Only the `HttpClient` and the [`Batcher`](crate::Batcher) are
+/// supported there: without a [`Runtime`](crate::Runtime) of its own, the
+/// [`AutoBatcher`](crate::AutoBatcher) runs its timers on threads, which
+/// `wasm32-unknown-unknown` doesn't have.
 #[derive(Clone, Debug)]
 pub struct HttpClient {
     client: reqwest::Client,
     host: String,
+    streaming: bool,
+    auth: Auth,
+    allow_http: bool,
+    import: bool,
 }
 
-impl Default for HttpClient {
+/// How an [`HttpClient`] authenticates its requests.
+#[derive(Clone, Debug, Default)]
+pub enum Auth {
+    /// Basic authentication, with the write key as the username and an empty
+    /// password, as expected by Segment.
+    #[default]
+    WriteKeyBasic,
+
+    /// An `Authorization: Bearer <write key>` header, as expected by some
+    /// Segment-compatible gateways.
+    WriteKeyBearer,
+
+    /// The write key as the value of the given header, like `X-Api-Key`.
+    WriteKeyHeader(String),
+
+    /// An `Authorization: Bearer <token>` header, with a token of a Segment
+    /// OAuth app, instead of the write key, see [`Auth::bearer`].
+    ///
+    /// The token is requested from the provider before every request, so
+    /// that it can refresh the token before it expires.
+    Bearer(BearerTokens),
+}
+
+impl Auth {
+    /// Authenticate with the tokens of `provider`, see [`Auth::Bearer`].
+    pub fn bearer(provider: impl TokenProvider + 'static) -> Self {
+        Auth::Bearer(BearerTokens(Arc::new(provider)))
+    }
+}
+
+/// The provider of the tokens of [`Auth::Bearer`].
+#[derive(Clone)]
+pub struct BearerTokens(Arc<dyn DynTokenProvider>);
+
+impl fmt::Debug for BearerTokens {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("BearerTokens(..)")
+    }
+}
+
+/// Provides the access tokens of [`Auth::Bearer`].
+///
+/// ```
+/// use segment::{Auth, HttpClient, TokenProvider};
+///
+/// struct Vault;
+///
+/// impl TokenProvider for Vault {
+///     async fn token(&self) -> segment::Result<String> {
+///         // fetch, cache and refresh the token of the OAuth app
+///         Ok("token".to_owned())
+///     }
+/// }
+///
+/// let mut client = HttpClient::default();
+/// client.set_auth(Auth::bearer(Vault));
+/// ```
+pub trait TokenProvider: Send + Sync {
+    /// Returns a valid access token.
+    fn token(&self) -> impl Future<Output = Result<String>> + Send;
+}
+
+/// A token which never expires.
+impl TokenProvider for String {
+    async fn token(&self) -> Result<String> {
+        Ok(self.clone())
+    }
+}
+
+/// [`TokenProvider`] as a trait object, boxing the futures.
+trait DynTokenProvider: Send + Sync {
+    fn token(&self) -> Pin<Box<dyn Future<Output = Result<String>> + Send + '_>>;
+}
+
+impl<T: TokenProvider> DynTokenProvider for T {
+    fn token(&self) -> Pin<Box<dyn Future<Output = Result<String>> + Send + '_>> {
+        Box::pin(TokenProvider::token(self))
+    }
+}
+
+/// The connection settings of an [`HttpClient`], see
+/// [`HttpClient::with_config`].
+///
+/// On wasm32, the connections are managed by the JavaScript host, and only
+/// [`Self::allow_http`] applies.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HttpClientConfig {
+    /// Which addresses of the host are connected to.
+    pub address_family: AddressFamily,
+
+    /// How long to wait for a connection to an address before trying the
+    /// next one.
+    pub connect_timeout: Duration,
+
+    /// Which redirects are followed.
+    pub redirects: RedirectPolicy,
+
+    /// Allow a plain-HTTP host, which sends the write key in clear, see
+    /// [`HttpClient::set_allow_http`].
+    pub allow_http: bool,
+}
+
+impl Default for HttpClientConfig {
     fn default() -> Self {
-        HttpClient {
-            client: reqwest::Client::builder()
-                .connect_timeout(Duration::new(10, 0))
-                .build()
-                .unwrap(),
-            host: "https://api.segment.io".to_owned(),
+        Self {
+            address_family: AddressFamily::default(),
+            connect_timeout: Duration::from_secs(10),
+            redirects: RedirectPolicy::default(),
+            allow_http: false,
+        }
+    }
+}
+
+/// Which redirects an [`HttpClient`] follows.
+///
+/// A redirect which is not followed fails the request, so that the batch is
+/// not mistaken for delivered. Restricting the redirects prevents a
+/// misconfigured proxy from replaying the write key to an unexpected
+/// endpoint: the `Authorization` header is dropped on a redirect to another
+/// host, but not the header of [`Auth::WriteKeyHeader`].
+///
+/// A redirect from HTTPS to plain HTTP is never followed, unless
+/// [`HttpClientConfig::allow_http`] is set.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RedirectPolicy {
+    /// Follow up to 10 redirects to any host.
+    Follow,
+    /// Only follow up to 10 redirects to the same host and port.
+    #[default]
+    SameHost,
+    /// Never follow a redirect.
+    Deny,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl RedirectPolicy {
+    /// The maximum number of redirects followed.
+    const MAX_REDIRECTS: usize = 10;
+
+    /// Returns the `reqwest` policy following the same redirects, for the
+    /// `reqwest::Client` given to [`HttpClient::new`].
+    pub fn to_reqwest(self, allow_http: bool) -> reqwest::redirect::Policy {
+        if self == RedirectPolicy::Deny {
+            return reqwest::redirect::Policy::custom(|attempt| {
+                attempt.error("redirects are denied")
+            });
+        }
+        reqwest::redirect::Policy::custom(move |attempt| {
+            let url = attempt.url();
+            let (Some(first), Some(last)) = (attempt.previous().first(), attempt.previous().last())
+            else {
+                return attempt.follow();
+            };
+            let same_host = first.host_str() == url.host_str()
+                && first.port_or_known_default() == url.port_or_known_default();
+            let downgrade = last.scheme() == "https" && url.scheme() != "https";
+            if attempt.previous().len() > Self::MAX_REDIRECTS {
+                attempt.error("too many redirects")
+            } else if (downgrade && !allow_http) || (self == RedirectPolicy::SameHost && !same_host)
+            {
+                let url = attempt.url().to_string();
+                attempt.error(format!("refusing to follow the redirect to {url}"))
+            } else {
+                attempt.follow()
+            }
+        })
+    }
+}
+
+/// Which IP addresses of the host an [`HttpClient`] connects to.
+///
+/// When the host has both IPv4 and IPv6 addresses, the connection to the
+/// addresses of the first family is raced against the other family after a
+/// short delay ("happy eyeballs"). On a network where IPv6 is broken,
+/// [`AddressFamily::Ipv4`] avoids this delay altogether.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AddressFamily {
+    /// Connect to the addresses in the order returned by the system.
+    #[default]
+    Any,
+    /// Try the IPv4 addresses first, falling back to IPv6.
+    PreferIpv4,
+    /// Try the IPv6 addresses first, falling back to IPv4.
+    PreferIpv6,
+    /// Only connect to the IPv4 addresses.
+    Ipv4,
+    /// Only connect to the IPv6 addresses.
+    Ipv6,
+}
+
+impl AddressFamily {
+    /// Filter and order the resolved `addrs`.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn apply(self, addrs: impl Iterator<Item = SocketAddr>) -> Vec<SocketAddr> {
+        let mut addrs: Vec<_> = match self {
+            AddressFamily::Ipv4 => addrs.filter(SocketAddr::is_ipv4).collect(),
+            AddressFamily::Ipv6 => addrs.filter(SocketAddr::is_ipv6).collect(),
+            _ => addrs.collect(),
+        };
+        match self {
+            // the sort is stable, the order of each family is kept
+            AddressFamily::PreferIpv4 => addrs.sort_by_key(SocketAddr::is_ipv6),
+            AddressFamily::PreferIpv6 => addrs.sort_by_key(SocketAddr::is_ipv4),
+            _ => {}
         }
+        addrs
+    }
+}
+
+/// A resolver applying an [`AddressFamily`] to the system resolver.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug)]
+struct FamilyResolver(AddressFamily);
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Resolve for FamilyResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let family = self.0;
+        Box::pin(async move {
+            // the port is set by the connector
+            let addrs = tokio::net::lookup_host((name.as_str(), 0)).await?;
+            let addrs = family.apply(addrs);
+            if addrs.is_empty() {
+                return Err(format!("no {family:?} address for {}", name.as_str()).into());
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+impl Default for HttpClient {
+    fn default() -> Self {
+        // follows the redirects like `HttpClient::new`, the stricter
+        // policies are opted into with `HttpClient::with_config`
+        let config = HttpClientConfig {
+            redirects: RedirectPolicy::Follow,
+            allow_http: true,
+            ..Default::default()
+        };
+        HttpClient::with_config("https://api.segment.io".to_owned(), config).unwrap()
     }
 }
 
@@ -35,43 +308,196 @@ impl HttpClient {
     /// If you don't care to re-use an existing `reqwest::Client`, you can use
     /// the `Default::default` value, which will send events to
     /// `https://api.segment.io`.
+    ///
+    /// The requests are sent to `host` whatever its scheme, plain HTTP
+    /// included, unless refused with [Self::set_allow_http]. The redirects
+    /// are followed as configured on `client`, by default `reqwest` follows
+    /// up to 10 redirects to any host. Build it with
+    /// [`RedirectPolicy::to_reqwest`] to only follow the redirects of
+    /// [`HttpClient::with_config`]:
+    ///
+    /// ```
+    /// use segment::{HttpClient, RedirectPolicy};
+    ///
+    /// let client = reqwest::Client::builder()
+    ///     .redirect(RedirectPolicy::SameHost.to_reqwest(false))
+    ///     .build()
+    ///     .unwrap();
+    /// let client = HttpClient::new(client, "https://api.segment.io".to_owned());
+    /// ```
     pub fn new(client: reqwest::Client, host: String) -> HttpClient {
-        HttpClient { client, host }
+        HttpClient {
+            client,
+            host,
+            streaming: false,
+            auth: Auth::default(),
+            allow_http: true,
+            import: false,
+        }
     }
-}
 
-#[async_trait::async_trait]
-impl Client for HttpClient {
-    #[tracing::instrument(skip_all, fields(http.url = tracing::field::Empty, http.status_code = tracing::field::Empty))]
-    async fn send(&self, write_key: String, msg: Message) -> Result<()> {
-        let path = match msg {
-            Message::Identify(_) => "/v1/identify",
-            Message::Track(_) => "/v1/track",
-            Message::Page(_) => "/v1/page",
-            Message::Screen(_) => "/v1/screen",
-            Message::Group(_) => "/v1/group",
-            Message::Alias(_) => "/v1/alias",
-            Message::Batch(_) => "/v1/batch",
+    /// Construct a new `HttpClient` sending to the Segment API at `host`,
+    /// connecting with `config`.
+    ///
+    /// ```
+    /// use segment::{AddressFamily, HttpClient, HttpClientConfig};
+    ///
+    /// // IPv6 is broken on this network
+    /// let config = HttpClientConfig {
+    ///     address_family: AddressFamily::Ipv4,
+    ///     ..Default::default()
+    /// };
+    /// let client = HttpClient::with_config("https://api.segment.io".to_owned(), config).unwrap();
+    /// ```
+    ///
+    /// Returns an error if `host` is a plain-HTTP URL and
+    /// [`HttpClientConfig::allow_http`] is not set, or if the TLS backend
+    /// cannot be initialized.
+    pub fn with_config(host: String, config: HttpClientConfig) -> Result<HttpClient> {
+        #[cfg(not(target_arch = "wasm32"))]
+        let builder = {
+            let mut builder = reqwest::Client::builder()
+                .connect_timeout(config.connect_timeout)
+                .redirect(config.redirects.to_reqwest(config.allow_http));
+            if config.address_family != AddressFamily::Any {
+                builder = builder.dns_resolver(Arc::new(FamilyResolver(config.address_family)));
+            }
+            builder
         };
+        // the JavaScript host connects and follows the redirects
+        #[cfg(target_arch = "wasm32")]
+        let builder = reqwest::Client::builder();
+        let mut client = HttpClient::new(builder.build()?, host);
+        client.set_allow_http(config.allow_http);
+        client.check_host()?;
+        Ok(client)
+    }
 
+    /// Allow sending to a plain-HTTP host, like a proxy on the same machine.
+    ///
+    /// Otherwise the requests to such a host fail without being sent, so
+    /// that the write key is never sent in clear. It is allowed by
+    /// [Self::new], and refused by [Self::with_config] unless
+    /// [`HttpClientConfig::allow_http`] is set.
+    pub fn set_allow_http(&mut self, allow_http: bool) {
+        self.allow_http = allow_http;
+    }
+
+    fn check_host(&self) -> Result<()> {
+        let is_http = self
+            .host
+            .get(..7)
+            .is_some_and(|scheme| scheme.eq_ignore_ascii_case("http://"));
+        if is_http && !self.allow_http {
+            return Err(Error::InvalidConfiguration(format!(
+                "refusing to send the write key to the plain-HTTP host {}, see `HttpClient::set_allow_http`",
+                self.host
+            )));
+        }
+        Ok(())
+    }
+
+    /// Stream the body of the batches into the requests, one message at a
+    /// time, instead of building the whole body in memory first.
+    ///
+    /// This reduces the peak memory of large flushes, at the cost of sending
+    /// the requests with a chunked transfer encoding.
+    pub fn set_streaming(&mut self, streaming: bool) {
+        self.streaming = streaming;
+    }
+
+    /// Send the batches to the historical import endpoint, see [Self::import].
+    pub fn set_import(&mut self, import: bool) {
+        self.import = import;
+    }
+
+    /// Send a batch of historical messages, for instance to backfill the
+    /// events of an outage, to Segment's `/v1/import` endpoint.
+    ///
+    /// Every message must have a `timestamp`, so that Segment records it at
+    /// the time it happened rather than when it is received, otherwise
+    /// nothing is sent and a [`ValidationError::MissingProperty`] is
+    /// returned.
+    ///
+    /// [`AutoBatcher::import`](crate::AutoBatcher::import) and the
+    /// [`replay`](crate::replay) go through this endpoint with a client
+    /// configured by [Self::set_import].
+    pub async fn import(&self, write_key: String, batch: &SerializedBatch) -> Result<()> {
+        #[derive(serde::Deserialize)]
+        struct Timestamp {
+            timestamp: Option<serde::de::IgnoredAny>,
+        }
+
+        for msg in batch.batch.iter() {
+            let Timestamp { timestamp } = serde_json::from_str(msg.get())?;
+            if timestamp.is_none() {
+                return Err(Error::InvalidMessage(ValidationError::MissingProperty {
+                    field: "timestamp".to_owned(),
+                }));
+            }
+        }
+        self.post_batch(write_key, "/v1/import", batch).await
+    }
+
+    /// Change how the requests are authenticated, see [`Auth`].
+    pub fn set_auth(&mut self, auth: Auth) {
+        self.auth = auth;
+    }
+
+    async fn post(&self, write_key: String, path: &str) -> Result<RequestBuilder> {
         let url = format!("{}{}", self.host, path);
-        let span = tracing::Span::current();
-        span.record("http.url", url.as_str());
-
-        let response = self
-            .client
-            .post(&url)
-            .basic_auth(write_key, Some(""))
-            .json(&msg)
-            .send()
-            .await;
+        logging::record!("http.url", url.as_str());
+        let request = self.client.post(url);
+        #[cfg(feature = "opentelemetry")]
+        let request = trace_context::inject(request);
+        Ok(match &self.auth {
+            Auth::WriteKeyBasic => request.basic_auth(write_key, Some("")),
+            Auth::WriteKeyBearer => request.bearer_auth(write_key),
+            Auth::WriteKeyHeader(name) => request.header(name.as_str(), write_key),
+            Auth::Bearer(BearerTokens(provider)) => request.bearer_auth(provider.token().await?),
+        })
+    }
 
+    async fn post_batch(
+        &self,
+        write_key: String,
+        path: &str,
+        batch: &SerializedBatch,
+    ) -> Result<()> {
+        logging::record!(
+            "batch.bytes",
+            batch.batch.iter().map(|msg| msg.get().len()).sum::<usize>()
+        );
+        #[cfg(not(target_arch = "wasm32"))]
+        let body = if self.streaming {
+            let chunks = batch.to_json_chunks().map(Ok::<_, Infallible>);
+            Body::wrap_stream(futures_util::stream::iter(chunks))
+        } else {
+            Body::from(batch.to_json()?)
+        };
+        // the fetch of the JavaScript host doesn't stream the request bodies
+        #[cfg(target_arch = "wasm32")]
+        let body = Body::from(batch.to_json()?);
+        let request = self
+            .post(write_key, path)
+            .await?
+            .header(CONTENT_TYPE, "application/json")
+            .body(body);
+        self.execute(request).await
+    }
+
+    async fn execute(&self, request: RequestBuilder) -> Result<()> {
+        self.check_host()?;
+        let start = Instant::now();
+        let response = request.send().await;
+
+        logging::record!("elapsed_ms", start.elapsed().as_millis() as u64);
         if let Ok(response) = &response {
-            span.record("http.status_code", response.status().as_u16());
+            logging::record!("http.status_code", response.status().as_u16());
         }
 
         if let Err(err) = response.and_then(|rsp| rsp.error_for_status()) {
-            tracing::error!(
+            logging::error!(
                 err = &err as &(dyn std::error::Error + 'static),
                 "segment http request failed"
             );
@@ -80,4 +506,294 @@ impl Client for HttpClient {
             Ok(())
         }
     }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            skip_all,
+            fields(http.url = Empty, http.status_code = Empty, elapsed_ms = Empty)
+        )
+    )]
+    async fn send_message(&self, write_key: String, mut msg: Message) -> Result<()> {
+        let path = match msg {
+            Message::Identify(_) => "/v1/identify",
+            Message::Track(_) => "/v1/track",
+            Message::Page(_) => "/v1/page",
+            Message::Screen(_) => "/v1/screen",
+            Message::Group(_) => "/v1/group",
+            Message::Alias(_) => "/v1/alias",
+            Message::Batch(_) => "/v1/batch",
+        };
+
+        if let Message::Batch(batch) = &mut msg {
+            if let Ok(sent_at) = OffsetDateTime::now_utc().format(&Rfc3339) {
+                batch
+                    .extra
+                    .entry("sentAt")
+                    .or_insert(serde_json::Value::String(sent_at));
+            }
+        }
+        let request = self.post(write_key, path).await?.json(&msg);
+        self.execute(request).await
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            skip_all,
+            fields(
+                http.url = Empty,
+                http.status_code = Empty,
+                batch.messages = batch.len(),
+                batch.bytes = Empty,
+                elapsed_ms = Empty,
+            )
+        )
+    )]
+    async fn send_serialized(&self, write_key: String, batch: &SerializedBatch) -> Result<()> {
+        // stamped here for the batches not sent by an AutoBatcher
+        let batch = batch.at_send_time();
+        match self.import {
+            true => self.import(write_key, &batch).await,
+            false => self.post_batch(write_key, "/v1/batch", &batch).await,
+        }
+    }
+}
+
+impl Client for HttpClient {
+    fn send(&self, write_key: String, msg: Message) -> impl Future<Output = Result<()>> + Send {
+        assume_send(self.send_message(write_key, msg))
+    }
+
+    fn send_batch(
+        &self,
+        write_key: String,
+        batch: &SerializedBatch,
+    ) -> impl Future<Output = Result<()>> + Send {
+        assume_send(self.send_serialized(write_key, batch))
+    }
+}
+
+/// Returns the URL of `host` followed by the path `segments`, each of them
+/// percent-encoded, so that an ID holding a `/`, a `?` or a `#` stays a
+/// single segment.
+pub(crate) fn endpoint<'a>(
+    host: &str,
+    segments: impl IntoIterator<Item = &'a str>,
+) -> Result<reqwest::Url> {
+    let invalid = || Error::InvalidConfiguration(format!("invalid host `{host}`"));
+    let mut url = reqwest::Url::parse(host).map_err(|_| invalid())?;
+    url.path_segments_mut()
+        .map_err(|()| invalid())?
+        .pop_if_empty()
+        .extend(segments);
+    Ok(url)
+}
+
+/// The requests of reqwest are `Send`, except on wasm32.
+#[cfg(not(target_arch = "wasm32"))]
+fn assume_send<F: Future + Send>(future: F) -> F {
+    future
+}
+
+/// Returns the requests of reqwest's wasm backend, which hold JavaScript
+/// values and are not `Send`, as `Send` futures, so that the [`HttpClient`]
+/// implements the same [`Client`] as on the other targets.
+#[cfg(target_arch = "wasm32")]
+fn assume_send<F: Future>(future: F) -> SingleThreaded<F> {
+    SingleThreaded(Box::pin(future))
+}
+
+#[cfg(target_arch = "wasm32")]
+struct SingleThreaded<F>(Pin<Box<F>>);
+
+// SAFETY: without the `atomics` target feature, wasm32 has a single thread,
+// the future never moves to another one.
+#[cfg(all(target_arch = "wasm32", not(target_feature = "atomics")))]
+unsafe impl<F> Send for SingleThreaded<F> {}
+
+#[cfg(target_arch = "wasm32")]
+impl<F: Future> Future for SingleThreaded<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.0.as_mut().poll(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::AUTHORIZATION;
+
+    use crate::testing::serve;
+
+    #[test]
+    fn test_address_family() {
+        let addrs = || {
+            ["[::1]:0", "127.0.0.1:0", "[::2]:0", "127.0.0.2:0"]
+                .map(|addr| addr.parse::<SocketAddr>().unwrap())
+                .into_iter()
+        };
+        let apply = |family: AddressFamily| -> Vec<String> {
+            family
+                .apply(addrs())
+                .iter()
+                .map(|addr| addr.ip().to_string())
+                .collect()
+        };
+        assert_eq!(
+            apply(AddressFamily::Any),
+            ["::1", "127.0.0.1", "::2", "127.0.0.2"]
+        );
+        assert_eq!(apply(AddressFamily::Ipv4), ["127.0.0.1", "127.0.0.2"]);
+        assert_eq!(apply(AddressFamily::Ipv6), ["::1", "::2"]);
+        assert_eq!(
+            apply(AddressFamily::PreferIpv4),
+            ["127.0.0.1", "127.0.0.2", "::1", "::2"]
+        );
+        assert_eq!(
+            apply(AddressFamily::PreferIpv6),
+            ["::1", "::2", "127.0.0.1", "127.0.0.2"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_family_resolver() {
+        let name: Name = "localhost".parse().unwrap();
+        let addrs: Vec<_> = FamilyResolver(AddressFamily::Ipv4)
+            .resolve(name)
+            .await
+            .unwrap()
+            .collect();
+        assert!(!addrs.is_empty());
+        assert!(addrs.iter().all(SocketAddr::is_ipv4));
+    }
+
+    async fn header(client: &HttpClient, name: &str) -> String {
+        let request = client.post("key".to_owned(), "/v1/batch").await.unwrap();
+        let request = request.build().unwrap();
+        request.headers()[name].to_str().unwrap().to_owned()
+    }
+
+    #[tokio::test]
+    async fn test_auth_schemes() {
+        let mut client = HttpClient::default();
+
+        // base64("key:")
+        assert_eq!(
+            header(&client, AUTHORIZATION.as_str()).await,
+            "Basic a2V5Og=="
+        );
+
+        client.set_auth(Auth::WriteKeyBearer);
+        assert_eq!(header(&client, AUTHORIZATION.as_str()).await, "Bearer key");
+
+        client.set_auth(Auth::WriteKeyHeader("X-Api-Key".to_owned()));
+        assert_eq!(header(&client, "x-api-key").await, "key");
+    }
+
+    #[tokio::test]
+    async fn test_bearer_tokens() {
+        struct Refreshing(std::sync::atomic::AtomicUsize);
+
+        impl TokenProvider for Refreshing {
+            async fn token(&self) -> Result<String> {
+                let n = self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                Ok(format!("token-{n}"))
+            }
+        }
+
+        let mut client = HttpClient::default();
+        client.set_auth(Auth::bearer(Refreshing(Default::default())));
+        assert_eq!(
+            header(&client, AUTHORIZATION.as_str()).await,
+            "Bearer token-0"
+        );
+        assert_eq!(
+            header(&client, AUTHORIZATION.as_str()).await,
+            "Bearer token-1"
+        );
+
+        client.set_auth(Auth::bearer("static".to_owned()));
+        assert_eq!(
+            header(&client, AUTHORIZATION.as_str()).await,
+            "Bearer static"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_redirect_policies() {
+        let (ok, _) = serve(vec![
+            "HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n".to_owned()
+        ])
+        .await;
+        let (redirect, _) = serve(vec![format!(
+            "HTTP/1.1 307 Temporary Redirect\r\nlocation: {ok}/v1/batch\r\ncontent-length: 0\r\n\r\n"
+        )])
+        .await;
+        let batch = crate::Batcher::new(None).into_serialized();
+
+        for (redirects, delivered) in [
+            (RedirectPolicy::Follow, true),
+            // to another port
+            (RedirectPolicy::SameHost, false),
+            (RedirectPolicy::Deny, false),
+        ] {
+            let config = HttpClientConfig {
+                redirects,
+                allow_http: true,
+                ..Default::default()
+            };
+            let client = HttpClient::with_config(redirect.clone(), config).unwrap();
+            let result = client.send_batch("key".to_owned(), &batch).await;
+            assert_eq!(result.is_ok(), delivered, "{redirects:?}: {result:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_plain_http() {
+        let host = "http://127.0.0.1:1".to_owned();
+        let result = HttpClient::with_config(host.clone(), HttpClientConfig::default());
+        assert!(matches!(result, Err(Error::InvalidConfiguration(_))));
+
+        // the connection is refused
+        let mut client = HttpClient::new(reqwest::Client::new(), host);
+        let batch = crate::Batcher::new(None).into_serialized();
+        let result = client.send_batch("key".to_owned(), &batch).await;
+        assert!(matches!(result, Err(Error::NetworkError(_))));
+
+        client.set_allow_http(false);
+        let result = client.send_batch("key".to_owned(), &batch).await;
+        assert!(matches!(result, Err(Error::InvalidConfiguration(_))));
+    }
+
+    #[tokio::test]
+    async fn test_import() {
+        let (ok, _) = serve(vec![
+            "HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n".to_owned()
+        ])
+        .await;
+        let mut client = HttpClient::new(reqwest::Client::new(), ok);
+        client.set_import(true);
+
+        let mut batcher = crate::Batcher::new(None);
+        batcher.without_auto_timestamp();
+        batcher.push(crate::message::Track::default()).unwrap();
+        let result = client
+            .send_batch("key".to_owned(), &batcher.into_serialized())
+            .await;
+        assert!(matches!(
+            result,
+            Err(Error::InvalidMessage(
+                ValidationError::MissingProperty { .. }
+            ))
+        ));
+
+        let mut batcher = crate::Batcher::new(None);
+        batcher.push(crate::message::Track::default()).unwrap();
+        let batch = batcher.into_serialized();
+        client.send_batch("key".to_owned(), &batch).await.unwrap();
+        client.import("key".to_owned(), &batch).await.unwrap();
+    }
 }
@@ -0,0 +1,92 @@
+//! Generation of the random IDs attached to the messages.
+
+use std::fmt;
+use std::sync::Arc;
+
+use uuid::Uuid;
+
+/// Generates unique IDs, such as the `messageId` of the messages (see
+/// [`Batcher::set_id_generator`](crate::Batcher::set_id_generator)), random
+/// anonymous IDs or session IDs (see
+/// [`Batcher::generate_id`](crate::Batcher::generate_id)).
+///
+/// It is implemented for closures, which allows injecting deterministic IDs
+/// in tests.
+///
+/// ```
+/// use std::sync::atomic::{AtomicUsize, Ordering};
+/// use segment::IdGenerator;
+///
+/// let counter = AtomicUsize::new(0);
+/// let generator = move || format!("id-{}", counter.fetch_add(1, Ordering::Relaxed));
+/// assert_eq!(generator.generate(), "id-0");
+/// assert_eq!(generator.generate(), "id-1");
+/// ```
+pub trait IdGenerator: Send + Sync {
+    /// Returns a new unique ID.
+    fn generate(&self) -> String;
+}
+
+impl<F> IdGenerator for F
+where
+    F: Fn() -> String + Send + Sync,
+{
+    fn generate(&self) -> String {
+        self()
+    }
+}
+
+/// Random UUIDv4s, the default.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct UuidV4;
+
+impl IdGenerator for UuidV4 {
+    fn generate(&self) -> String {
+        Uuid::new_v4().to_string()
+    }
+}
+
+/// Time-ordered UUIDv7s, which sort in the order they were generated.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct UuidV7;
+
+impl IdGenerator for UuidV7 {
+    fn generate(&self) -> String {
+        Uuid::now_v7().to_string()
+    }
+}
+
+/// An [`IdGenerator`] shared by the clones of a batcher.
+#[derive(Clone)]
+pub(crate) struct SharedIdGenerator(Arc<dyn IdGenerator>);
+
+impl SharedIdGenerator {
+    pub(crate) fn new(generator: impl IdGenerator + 'static) -> Self {
+        Self(Arc::new(generator))
+    }
+
+    pub(crate) fn generate(&self) -> String {
+        self.0.generate()
+    }
+}
+
+impl fmt::Debug for SharedIdGenerator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SharedIdGenerator").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_uuids() {
+        let v4 = UuidV4.generate();
+        assert_eq!(Uuid::parse_str(&v4).unwrap().get_version_num(), 4);
+        assert_ne!(v4, UuidV4.generate());
+
+        let ids: Vec<_> = (0..100).map(|_| UuidV7.generate()).collect();
+        assert!(ids.windows(2).all(|ids| ids[0] < ids[1]));
+    }
+}
@@ -0,0 +1,293 @@
+//! Bulk imports which resume where they stopped.
+
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    auto_batcher::{undelivered, AutoBatcher},
+    client::Client,
+    errors::{Error, Result},
+    logging,
+    message::BatchMessage,
+};
+
+/// The progress of an [import](AutoBatcher::import), persisted after each
+/// delivered batch.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[non_exhaustive]
+pub struct Checkpoint {
+    /// The number of messages of the source which were delivered, or
+    /// dropped by the batcher.
+    pub offset: u64,
+
+    /// The `messageId` in the source of the message before the offset, if it
+    /// had one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_message_id: Option<String>,
+}
+
+impl Checkpoint {
+    /// Read the checkpoint at `path`, `None` if the file doesn't exist.
+    pub fn load(path: impl AsRef<Path>) -> Result<Option<Self>> {
+        match fs::read(path) {
+            Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Write the checkpoint at `path`, atomically replacing the previous one
+    /// so that a crash never leaves a truncated checkpoint.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        fs::write(&tmp, serde_json::to_vec(self)?)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
+
+impl<C> AutoBatcher<C>
+where
+    C: Client + Clone + Send + Sync + 'static,
+{
+    /// Send every message of `source`, batch after batch, persisting a
+    /// [`Checkpoint`] at `checkpoint` after each delivered batch, and returns
+    /// the final one.
+    ///
+    /// When the checkpoint file exists, the import resumes after the last
+    /// delivered batch: the messages up to its offset are skipped, and the
+    /// last of them must have the `messageId` recorded in the checkpoint, if
+    /// it had one in the source, otherwise [`Error::CheckpointMismatch`] is
+    /// returned as the source changed. Give the messages a `messageId` in the
+    /// source for this check, and for Segment to deduplicate them.
+    ///
+    /// The messages are processed and counted like by [Self::push]. The
+    /// import stops at the first error reading the source or delivering a
+    /// batch, the next import resumes from the last checkpoint. The messages
+    /// the batcher rejects are logged and skipped.
+    ///
+    /// ```no_run
+    /// use segment::{AutoBatcher, Batcher, HttpClient, SpillReader};
+    ///
+    /// # async fn run() -> segment::Result<()> {
+    /// let client = HttpClient::default();
+    /// let mut batcher = AutoBatcher::new(client, Batcher::new(None), "your_write_key".to_string());
+    ///
+    /// let source = SpillReader::open("events.ndjson")?;
+    /// let checkpoint = batcher.import(source, "events.checkpoint").await?;
+    /// println!("{} messages imported", checkpoint.offset);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn import<I>(&mut self, source: I, checkpoint: impl AsRef<Path>) -> Result<Checkpoint>
+    where
+        I: IntoIterator<Item = Result<BatchMessage>>,
+    {
+        let path = checkpoint.as_ref();
+        let mut checkpoint = Checkpoint::load(path)?.unwrap_or_default();
+        // the buffered messages would be delivered with the first batch
+        self.flush().await?;
+
+        let mut source = source.into_iter();
+        let mut last_skipped = None;
+        for _ in 0..checkpoint.offset {
+            let Some(msg) = source.next() else {
+                return Err(Error::CheckpointMismatch(format!(
+                    "the source has less than {} messages",
+                    checkpoint.offset
+                )));
+            };
+            last_skipped = Some(msg?);
+        }
+        if let Some(expected) = &checkpoint.last_message_id {
+            let found = last_skipped.as_ref().and_then(BatchMessage::message_id);
+            if found != Some(expected.as_str()) {
+                return Err(Error::CheckpointMismatch(format!(
+                    "expected the message `{expected}` at offset {}, found {found:?}",
+                    checkpoint.offset
+                )));
+            }
+        }
+
+        let mut offset = checkpoint.offset;
+        let mut last_id = checkpoint.last_message_id.clone();
+        for msg in source {
+            let msg = msg?;
+            // the ID of the source, before the batcher generates or rewrites it
+            let id = msg.message_id().map(str::to_owned);
+            match self.prepare(msg, None::<fn() -> Value>).await {
+                Ok(Some((msg, admission))) => {
+                    // every message before this one is delivered or dropped
+                    let full = async |batcher: &mut Self| {
+                        batcher
+                            .commit_checkpoint(&mut checkpoint, offset, last_id.clone(), path)
+                            .await
+                            .map(|()| true)
+                    };
+                    if self.enqueue(msg, admission, full).await? {
+                        self.count_pushed();
+                    }
+                }
+                Ok(None) => {}
+                Err(err) => logging::error!(
+                    err = &err as &(dyn std::error::Error + 'static),
+                    offset,
+                    "skipping message"
+                ),
+            }
+            last_id = id;
+            offset += 1;
+        }
+        self.commit_checkpoint(&mut checkpoint, offset, last_id, path)
+            .await?;
+        Ok(checkpoint)
+    }
+
+    /// Deliver the buffered messages, then persist `offset` and `last_id`,
+    /// the source `messageId` of the message before it.
+    async fn commit_checkpoint(
+        &mut self,
+        checkpoint: &mut Checkpoint,
+        offset: u64,
+        last_id: Option<String>,
+        path: &Path,
+    ) -> Result<()> {
+        let batch = self.batcher.take();
+        if !batch.is_empty() {
+            self.send_now(batch)
+                .await
+                .map_err(|failed| undelivered(vec![failed]))?;
+        }
+        checkpoint.offset = offset;
+        checkpoint.last_message_id = last_id;
+        checkpoint.save(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::Track;
+    use crate::testing::{large_track, MockClient};
+    use crate::Batcher;
+    use std::sync::atomic::Ordering;
+
+    fn source(len: usize) -> Vec<BatchMessage> {
+        (0..len)
+            .map(|i| {
+                let mut msg: BatchMessage = large_track(i).into();
+                msg.set_message_id(format!("id-{i}"));
+                msg
+            })
+            .collect()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_resume() {
+        let dir = std::env::temp_dir().join(format!("segment-import-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("checkpoint.json");
+        let _ = fs::remove_file(&path);
+
+        // the second batch fails, after the 17 messages of the first one
+        let client = MockClient::default();
+        let mut batcher = AutoBatcher::new(client.clone(), Batcher::new(None), "key".to_owned());
+        batcher.set_retry_policy(crate::RetryPolicy::never());
+        let mut source = source(40);
+        source.insert(
+            20,
+            Track {
+                event: "a".repeat(1024 * 33),
+                ..Default::default()
+            }
+            .into(),
+        );
+        let fail_second = async {
+            while client.sent_count() < 17 {
+                tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+            }
+            client.failures.store(1, Ordering::SeqCst);
+        };
+        let (result, ()) = tokio::join!(
+            batcher.import(source.iter().cloned().map(Ok), &path),
+            fail_second
+        );
+        assert!(matches!(result, Err(Error::Undelivered { .. })));
+        let checkpoint = Checkpoint::load(&path).unwrap().unwrap();
+        assert_eq!(checkpoint.offset, 17);
+        assert_eq!(checkpoint.last_message_id.as_deref(), Some("id-16"));
+
+        let pushed = batcher.stats().pushed;
+        let checkpoint = batcher
+            .import(source.iter().cloned().map(Ok), &path)
+            .await
+            .unwrap();
+        assert_eq!(checkpoint.offset, 41);
+        assert_eq!(checkpoint.last_message_id.as_deref(), Some("id-39"));
+        // delivered exactly once, the too large message is skipped
+        assert_eq!(client.sent_count(), 40);
+        assert_eq!(batcher.stats().pushed - pushed, 41 - 17 - 1);
+
+        // nothing is left to import
+        batcher
+            .import(source.into_iter().map(Ok), &path)
+            .await
+            .unwrap();
+        assert_eq!(client.sent_count(), 40);
+
+        // another source doesn't match the checkpoint
+        let result = batcher
+            .import(self::source(50).into_iter().map(Ok), &path)
+            .await;
+        assert!(matches!(result, Err(Error::CheckpointMismatch(_))));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_resume_without_ids() {
+        let dir = std::env::temp_dir().join(format!("segment-import-ids-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("checkpoint.json");
+        let _ = fs::remove_file(&path);
+
+        let client = MockClient::default();
+        let mut batcher = AutoBatcher::new(client.clone(), Batcher::new(None), "key".to_owned());
+        batcher.set_retry_policy(crate::RetryPolicy::never());
+        let mut source = source(40);
+        for msg in &mut source[..30] {
+            msg.extra_mut().remove("messageId");
+        }
+        let fail_second = async {
+            while client.sent_count() < 17 {
+                tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+            }
+            client.failures.store(1, Ordering::SeqCst);
+        };
+        let (result, ()) = tokio::join!(
+            batcher.import(source.iter().cloned().map(Ok), &path),
+            fail_second
+        );
+        assert!(matches!(result, Err(Error::Undelivered { .. })));
+        let checkpoint = Checkpoint::load(&path).unwrap().unwrap();
+        assert_eq!(checkpoint.offset, 17);
+        // not the ID generated by the batcher
+        assert_eq!(checkpoint.last_message_id, None);
+
+        let checkpoint = batcher
+            .import(source.into_iter().map(Ok), &path)
+            .await
+            .unwrap();
+        assert_eq!(checkpoint.offset, 40);
+        assert_eq!(checkpoint.last_message_id.as_deref(), Some("id-39"));
+        assert_eq!(client.sent_count(), 40);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
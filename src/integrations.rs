@@ -0,0 +1,149 @@
+//! A typed `integrations` object, routing the messages to the destinations.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// The destinations a message (or a batch) is sent to, see [Segment's
+/// docs](https://segment.com/docs/guides/filtering-data/#filtering-with-the-integrations-object).
+///
+/// It serializes to the `integrations` object expected by Segment, like
+/// `{"All": false, "Amplitude": true}`:
+///
+/// ```
+/// use segment::Integrations;
+/// use segment::message::Track;
+/// use serde_json::json;
+///
+/// let integrations = Integrations::new()
+///     .disable_all()
+///     .enable("Amplitude")
+///     .options("Google Analytics", json!({ "clientId": "42" }).as_object().unwrap().clone());
+///
+/// let track = Track {
+///     event: "Signed Up".to_owned(),
+///     integrations: Some(integrations.into()),
+///     ..Default::default()
+/// };
+/// assert_eq!(
+///     track.integrations,
+///     Some(json!({
+///         "All": false,
+///         "Amplitude": true,
+///         "Google Analytics": { "clientId": "42" },
+///     }))
+/// );
+/// ```
+#[derive(PartialEq, Eq, Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Integrations {
+    /// Whether the destinations which are not listed are enabled, `true`
+    /// when unset.
+    #[serde(rename = "All", default, skip_serializing_if = "Option::is_none")]
+    pub all: Option<bool>,
+
+    /// The settings of each destination.
+    #[serde(flatten)]
+    pub destinations: BTreeMap<String, Integration>,
+}
+
+/// The setting of a destination in [`Integrations`].
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Integration {
+    /// The destination is enabled, or disabled.
+    Enabled(bool),
+    /// The destination is enabled, with these options.
+    Options(Map<String, Value>),
+}
+
+impl Integrations {
+    /// Construct an empty `Integrations`, enabling every destination.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Enable the destination `name`.
+    pub fn enable(mut self, name: impl Into<String>) -> Self {
+        self.destinations
+            .insert(name.into(), Integration::Enabled(true));
+        self
+    }
+
+    /// Disable the destination `name`.
+    pub fn disable(mut self, name: impl Into<String>) -> Self {
+        self.destinations
+            .insert(name.into(), Integration::Enabled(false));
+        self
+    }
+
+    /// Enable the destination `name` with destination-specific `options`.
+    pub fn options(mut self, name: impl Into<String>, options: Map<String, Value>) -> Self {
+        self.destinations
+            .insert(name.into(), Integration::Options(options));
+        self
+    }
+
+    /// Enable the destinations which are not listed.
+    pub fn enable_all(mut self) -> Self {
+        self.all = Some(true);
+        self
+    }
+
+    /// Disable the destinations which are not listed.
+    pub fn disable_all(mut self) -> Self {
+        self.all = Some(false);
+        self
+    }
+
+    /// Returns whether the destination `name` is enabled.
+    pub fn is_enabled(&self, name: &str) -> bool {
+        match self.destinations.get(name) {
+            Some(Integration::Enabled(enabled)) => *enabled,
+            Some(Integration::Options(_)) => true,
+            None => self.all.unwrap_or(true),
+        }
+    }
+}
+
+impl From<Integrations> for Value {
+    fn from(integrations: Integrations) -> Self {
+        // the integrations only hold booleans and JSON objects
+        serde_json::to_value(integrations).expect("integrations always serialize")
+    }
+}
+
+impl TryFrom<Value> for Integrations {
+    type Error = serde_json::Error;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        serde_json::from_value(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_wire_format() {
+        let value = json!({
+            "All": false,
+            "Amplitude": true,
+            "Mixpanel": false,
+            "Google Analytics": { "clientId": "42" },
+        });
+        let integrations = Integrations::try_from(value.clone()).unwrap();
+        assert_eq!(integrations.all, Some(false));
+        assert!(integrations.is_enabled("Amplitude"));
+        assert!(integrations.is_enabled("Google Analytics"));
+        assert!(!integrations.is_enabled("Mixpanel"));
+        assert!(!integrations.is_enabled("Intercom"));
+        assert_eq!(Value::from(integrations), value);
+
+        assert!(Integrations::try_from(json!({ "Amplitude": "yes" })).is_err());
+        assert_eq!(Value::from(Integrations::new()), json!({}));
+        assert!(Integrations::new().is_enabled("Amplitude"));
+    }
+}
@@ -1,15 +1,169 @@
 #![doc = include_str!("../README.md")]
 
+// lets the derive macros name `::segment` from within this crate
+extern crate self as segment;
+
+#[cfg(all(target_arch = "wasm32", not(feature = "wasm")))]
+compile_error!("the `wasm` feature is required to build for wasm32");
+
+// the requests of the `HttpClient` are assumed to stay on their thread
+#[cfg(all(target_arch = "wasm32", target_feature = "atomics"))]
+compile_error!("wasm32 with the `atomics` target feature is not supported");
+
+mod anonymous;
 mod auto_batcher;
 mod batcher;
+mod builder;
+mod circuit;
 mod client;
+mod consent;
+pub mod context;
+mod dedup;
+mod delivery;
+mod diagnostics;
+mod diff;
+mod dry_run;
 mod errors;
+mod event;
+mod event_filter;
+mod event_limit;
+mod extra;
+mod failover;
+mod flatten;
+mod geoip;
+mod heartbeat;
+mod hooks;
 mod http;
+mod id;
+mod import;
+mod integrations;
+mod logging;
+mod macros;
 pub mod message;
+mod multi_source;
+mod normalize;
+#[cfg(feature = "pii")]
+pub mod pii;
+mod pipeline;
+mod prepared;
+mod preview;
+#[cfg(feature = "profiles")]
+pub mod profiles;
+#[cfg(feature = "profiling")]
+pub mod profiling;
+mod pseudonym;
+mod queue;
+mod rate_limit;
+pub mod regulations;
+pub mod replay;
+#[cfg(feature = "http")]
+mod request;
+mod retry;
+mod rules;
+mod runtime;
+mod sampling;
+mod schema;
+mod self_test;
+mod sharded;
+mod shared;
+pub mod spec;
+mod spill;
+mod stats;
+#[cfg(feature = "metrics")]
+pub mod telemetry;
+pub mod template;
+#[cfg(test)]
+mod testing;
+#[cfg(feature = "opentelemetry")]
+mod trace_context;
+mod tracking_plan;
+pub mod traits;
+mod validation;
+mod warning;
+mod worker;
 
+pub use anonymous::AnonymousIds;
 pub use auto_batcher::AutoBatcher;
-pub use batcher::Batcher;
+pub use batcher::{BatchLimits, Batcher, Extended, LIBRARY_NAME};
+pub use builder::{AutoBatcherBuilder, Missing};
+pub use circuit::CircuitBreaker;
+#[cfg(feature = "async-trait")]
+pub use client::BoxedClient;
 pub use client::Client;
-pub use errors::{Error, Result};
-pub use http::HttpClient;
-pub use message::Message;
+pub use consent::{ConsentAction, ConsentFilter};
+pub use context::{Context, MergeStrategy};
+pub use dedup::Deduplication;
+pub use delivery::{DeliveryEvent, DeliveryObserver, DeliveryOutcome};
+pub use diagnostics::{Diagnostics, MessageMetadata};
+pub use diff::{PayloadDiff, PropertyDiff};
+pub use dry_run::{DryRunReport, RecordedBatch, Recorder};
+pub use errors::{Error, Result, UndeliveredBatch};
+pub use event::TrackEvent;
+pub use event_filter::EventFilter;
+pub use event_limit::{EventLimit, Excess};
+pub use extra::ExtraSchema;
+pub use failover::FailoverClient;
+pub use flatten::FlattenOptions;
+pub use geoip::{GeoLocation, GeoResolver};
+pub use heartbeat::{HealthProbes, HEARTBEAT_EVENT};
+pub use http::{
+    AddressFamily, Auth, BearerTokens, HttpClient, HttpClientConfig, RedirectPolicy, TokenProvider,
+};
+pub use id::{IdGenerator, UuidV4, UuidV7};
+pub use import::Checkpoint;
+pub use integrations::{Integration, Integrations};
+pub use message::{Message, PropertyError};
+pub use multi_source::MultiSourceBatcher;
+pub use normalize::KeyNormalization;
+#[cfg(feature = "pii")]
+pub use pii::PiiScrubber;
+pub use pipeline::{Middleware, Pipeline, PipelineBuilder, Position, Stage, Step};
+pub use prepared::PreparedBatch;
+pub use preview::Preview;
+pub use pseudonym::UserIdHashing;
+pub use queue::OverflowPolicy;
+pub use rate_limit::RateLimiter;
+#[cfg(feature = "http")]
+pub use request::RequestContext;
+pub use retry::{RetryBudget, RetryPolicy};
+pub use rules::{Action, Matcher, Rule, Rules, REDACTED};
+pub use runtime::Runtime;
+#[cfg(feature = "tokio")]
+pub use runtime::TokioRuntime;
+pub use sampling::Sampling;
+pub use schema::{EventSchema, JsonType, PropertySchema, SchemaChange, SchemaTracker};
+/// Derive [`TrackEvent`](trait@TrackEvent) for a struct, see the trait.
+#[cfg(feature = "derive")]
+pub use segment_derive::TrackEvent;
+pub use self_test::{SelfTestMode, SelfTestReport, SELF_TEST_EVENT};
+pub use sharded::ShardedClient;
+pub use shared::SharedAutoBatcher;
+pub use spill::{Compression, Encoding, SpillReader, SpillWriter};
+pub use stats::Stats;
+pub use tracking_plan::{Enforcement, PlannedEvent, TrackingPlan};
+pub use traits::Traits;
+pub use validation::{Validation, ValidationError, RESERVED_KEYS};
+pub use warning::Warning;
+pub use worker::{Worker, WorkerConfig, WorkerHandle};
+
+#[doc(hidden)]
+pub mod __private {
+    pub use serde_json::{Map, Value};
+
+    pub fn to_property<T>(value: &T) -> Result<Value, crate::message::PropertyError>
+    where
+        T: serde::Serialize + ?Sized,
+    {
+        crate::message::to_property(value)
+    }
+
+    pub fn properties<const N: usize>(
+        entries: [(String, Result<Value, crate::message::PropertyError>); N],
+    ) -> Result<Value, crate::message::PropertyError> {
+        let mut properties = Map::new();
+        for (key, value) in entries {
+            properties.insert(key, value?);
+        }
+        Ok(Value::Object(properties))
+    }
+}
@@ -0,0 +1,93 @@
+//! The events and spans logged by the crate, through `tracing` with the
+//! `tracing` feature, or through the `log` facade otherwise.
+//!
+//! The macros take the subset of the `tracing` syntax used by the crate: the
+//! fields, given as `name = value`, `name = %value`, `name = ?value`, `name`,
+//! `%name` or `?name`, followed by a literal message. With `log`, the fields
+//! are appended to the message as `name=value`, formatted with `Display`
+//! unless prefixed with `?`.
+
+#[cfg(feature = "tracing")]
+macro_rules! event {
+    ($level:ident, $($fields:tt)*) => {
+        ::tracing::$level!($($fields)*)
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! event {
+    ($level:ident, $($fields:tt)*) => {
+        $crate::logging::log_event!($level, [] $($fields)*)
+    };
+}
+
+/// Collects the fields of an event into `name format value` triples, until
+/// its message.
+#[cfg(not(feature = "tracing"))]
+macro_rules! log_event {
+    ($level:ident, [$($name:ident $format:literal $value:expr,)*] $message:literal $(,)?) => {
+        ::log::$level!(
+            concat!($message $(, " ", stringify!($name), "=", $format)*)
+            $(, $value)*
+        )
+    };
+    ($level:ident, [$($fields:tt)*] $name:ident = %$value:expr, $($rest:tt)*) => {
+        $crate::logging::log_event!($level, [$($fields)* $name "{}" $value,] $($rest)*)
+    };
+    ($level:ident, [$($fields:tt)*] $name:ident = ?$value:expr, $($rest:tt)*) => {
+        $crate::logging::log_event!($level, [$($fields)* $name "{:?}" $value,] $($rest)*)
+    };
+    ($level:ident, [$($fields:tt)*] $name:ident = $value:expr, $($rest:tt)*) => {
+        $crate::logging::log_event!($level, [$($fields)* $name "{}" $value,] $($rest)*)
+    };
+    ($level:ident, [$($fields:tt)*] $name:ident, $($rest:tt)*) => {
+        $crate::logging::log_event!($level, [$($fields)* $name "{}" $name,] $($rest)*)
+    };
+    ($level:ident, [$($fields:tt)*] %$name:ident, $($rest:tt)*) => {
+        $crate::logging::log_event!($level, [$($fields)* $name "{}" $name,] $($rest)*)
+    };
+    ($level:ident, [$($fields:tt)*] ?$name:ident, $($rest:tt)*) => {
+        $crate::logging::log_event!($level, [$($fields)* $name "{:?}" $name,] $($rest)*)
+    };
+}
+
+macro_rules! error {
+    ($($fields:tt)*) => {
+        $crate::logging::event!(error, $($fields)*)
+    };
+}
+
+macro_rules! warn_ {
+    ($($fields:tt)*) => {
+        $crate::logging::event!(warn, $($fields)*)
+    };
+}
+
+macro_rules! info {
+    ($($fields:tt)*) => {
+        $crate::logging::event!(info, $($fields)*)
+    };
+}
+
+macro_rules! debug {
+    ($($fields:tt)*) => {
+        $crate::logging::event!(debug, $($fields)*)
+    };
+}
+
+/// Record `value` in the `field` of the current span, declared as `Empty` by
+/// its `#[tracing::instrument]`. Without the `tracing` feature, there are no
+/// spans and the value is discarded.
+macro_rules! record {
+    ($field:literal, $value:expr) => {{
+        let _value = $value;
+        #[cfg(feature = "tracing")]
+        ::tracing::Span::current().record($field, _value);
+    }};
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) use log_event;
+pub(crate) use {debug, error, event, info, record};
+// renamed, `warn` alone is ambiguous with the built-in attribute
+pub(crate) use warn_ as warn;
@@ -0,0 +1,203 @@
+//! Declarative macros building and pushing the messages in one go.
+
+use crate::client::Client;
+use crate::errors::Result;
+use crate::message::{BatchMessage, PropertyError};
+use crate::{AutoBatcher, Batcher};
+
+/// Push a `track` message into a [`Batcher`](crate::Batcher) or an
+/// [`AutoBatcher`](crate::AutoBatcher), returns what its `push` returns, or
+/// [`Error::InvalidProperty`](crate::Error::InvalidProperty) if a property
+/// can't be serialized.
+///
+/// The user is given as `user_id = ...` or `anonymous_id = ...`, followed by
+/// the event name and optionally the properties, keyed by identifiers or
+/// string literals, with any serializable value:
+///
+/// ```
+/// use segment::{track, Batcher};
+///
+/// # fn main() -> segment::Result<()> {
+/// let mut batcher = Batcher::new(None);
+/// let seats = 3;
+/// if let Some(msg) = track!(batcher, user_id = "u1", "Signed Up", { plan: "pro", "seat count": seats })? {
+///     // the batch is full: send it, then push `msg` into the next one
+/// }
+/// # Ok(())
+/// # }
+/// ```
+///
+/// With an [`AutoBatcher`](crate::AutoBatcher), await the push:
+/// `track!(batcher, user_id = "u1", "Signed Up").await?`.
+#[macro_export]
+macro_rules! track {
+    ($batcher:expr, $kind:ident = $id:expr, $event:expr $(, { $($key:tt : $value:expr),* $(,)? })? $(,)?) => {
+        $batcher.__push($crate::__properties!($($($key : $value),*)?).map(|properties| {
+            $crate::message::Track {
+                user: $crate::__user!($kind = $id),
+                event: ::std::convert::Into::into($event),
+                properties,
+                ..::std::default::Default::default()
+            }
+        }))
+    };
+}
+
+/// Push an `identify` message into a [`Batcher`](crate::Batcher) or an
+/// [`AutoBatcher`](crate::AutoBatcher), like [`track!`](crate::track!) with
+/// traits instead of an event and properties.
+///
+/// ```
+/// use segment::{identify, Batcher};
+///
+/// # fn main() -> segment::Result<()> {
+/// let mut batcher = Batcher::new(None);
+/// if let Some(msg) = identify!(batcher, user_id = "u1", { email: "ada@example.com", plan: "pro" })? {
+///     // the batch is full: send it, then push `msg` into the next one
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[macro_export]
+macro_rules! identify {
+    ($batcher:expr, $kind:ident = $id:expr $(, { $($key:tt : $value:expr),* $(,)? })? $(,)?) => {
+        $batcher.__push($crate::__properties!($($($key : $value),*)?).map(|traits| {
+            $crate::message::Identify {
+                user: $crate::__user!($kind = $id),
+                traits,
+                ..::std::default::Default::default()
+            }
+        }))
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __user {
+    (user_id = $id:expr) => {
+        $crate::message::User::UserId {
+            user_id: ::std::convert::Into::into($id),
+        }
+    };
+    (anonymous_id = $id:expr) => {
+        $crate::message::User::AnonymousId {
+            anonymous_id: ::std::convert::Into::into($id),
+        }
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __properties {
+    ($($key:tt : $value:expr),*) => {
+        $crate::__private::properties([$(
+            ($crate::__property_key!($key), $crate::__private::to_property(&$value)),
+        )*])
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __property_key {
+    ($key:ident) => {
+        ::std::string::ToString::to_string(::std::stringify!($key))
+    };
+    ($key:literal) => {
+        ::std::string::ToString::to_string(&$key)
+    };
+}
+
+impl Batcher {
+    /// Push the message built by a macro, see [`track!`](crate::track!).
+    #[doc(hidden)]
+    pub fn __push(
+        &mut self,
+        msg: std::result::Result<impl Into<BatchMessage>, PropertyError>,
+    ) -> Result<Option<BatchMessage>> {
+        self.push(msg?)
+    }
+}
+
+impl<C> AutoBatcher<C>
+where
+    C: Client + Clone + Send + Sync + 'static,
+{
+    /// Push the message built by a macro, see [`track!`](crate::track!).
+    #[doc(hidden)]
+    pub async fn __push(
+        &mut self,
+        msg: std::result::Result<impl Into<BatchMessage>, PropertyError>,
+    ) -> Result<()> {
+        self.push(msg?).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::message::{BatchMessage, User};
+    use crate::testing::MockClient;
+    use crate::{AutoBatcher, Batcher};
+    use serde_json::json;
+
+    #[test]
+    fn test_track() {
+        let mut batcher = Batcher::new(None);
+        batcher.without_auto_timestamp();
+        batcher.without_message_ids();
+        let plan = String::from("pro");
+        track!(batcher, user_id = "u1", "Signed Up", { plan: plan, "seat count": 3, tags: ["a"] })
+            .unwrap();
+        track!(
+            &mut batcher,
+            anonymous_id = String::from("a1"),
+            "Pricing Viewed",
+        )
+        .unwrap();
+
+        let batch = batcher.into_serialized().messages().unwrap();
+        let BatchMessage::Track(track) = &batch[0] else {
+            panic!("unexpected {:?}", batch[0]);
+        };
+        assert_eq!(
+            track.user,
+            User::UserId {
+                user_id: "u1".to_owned()
+            }
+        );
+        assert_eq!(track.event, "Signed Up");
+        assert_eq!(
+            track.properties,
+            json!({ "plan": "pro", "seat count": 3, "tags": ["a"] })
+        );
+        let BatchMessage::Track(track) = &batch[1] else {
+            panic!("unexpected {:?}", batch[1]);
+        };
+        assert_eq!(
+            track.user,
+            User::AnonymousId {
+                anonymous_id: "a1".to_owned()
+            }
+        );
+        assert_eq!(track.properties, json!({}));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_identify() {
+        let client = MockClient::default();
+        let mut batcher = AutoBatcher::new(client.clone(), Batcher::new(None), "key".to_owned());
+        identify!(batcher, user_id = "u1", { email: "ada@example.com" })
+            .await
+            .unwrap();
+        batcher.flush().await.unwrap();
+        assert_eq!(client.sent_count(), 1);
+    }
+
+    #[test]
+    fn test_invalid_property() {
+        let mut batcher = Batcher::new(None);
+        let quantities: std::collections::BTreeMap<(u32, u32), u32> = [((1, 2), 3)].into();
+        let result = track!(batcher, user_id = "u1", "Cart Viewed", { quantities: quantities });
+        assert!(matches!(result, Err(crate::Error::InvalidProperty(_))));
+        assert!(batcher.is_empty());
+    }
+}
@@ -26,14 +26,27 @@
 //!   docs](https://segment.com/docs/spec/common/#integrations) for how to use
 //!   this field.
 
+use std::borrow::Cow;
 use std::fmt::Display;
+use std::sync::Arc;
 
+use bytes::Bytes;
 use serde::{Deserialize, Serialize};
+use serde_json::value::RawValue;
 use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use time::format_description::well_known::Rfc3339;
 use time::OffsetDateTime;
 
+use crate::template;
+
 /// An enum containing all values which may be sent to Segment's tracking API.
-#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+///
+/// It deserializes from the recorded payloads of any message: the `type`
+/// field picks the kind of the message if present, otherwise it is guessed
+/// from the required fields, a `screen` being then indistinguishable from a
+/// `page`.
+#[derive(PartialEq, Eq, Debug, Clone, Serialize)]
 #[serde(untagged)]
 pub enum Message {
     Identify(Identify),
@@ -45,6 +58,43 @@ pub enum Message {
     Batch(Batch),
 }
 
+impl<'de> Deserialize<'de> for Message {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        // most specific first: a group also has traits, like an identify
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Untagged {
+            Group(Group),
+            Identify(Identify),
+            Track(Track),
+            Alias(Alias),
+            Page(Page),
+            Screen(Screen),
+            Batch(Batch),
+        }
+
+        let value = Value::deserialize(deserializer)?;
+        if value.get("type").is_some() {
+            let msg = BatchMessage::deserialize(value).map_err(serde::de::Error::custom)?;
+            return Ok(msg.into());
+        }
+        let msg = Untagged::deserialize(value)
+            .map_err(|_| serde::de::Error::custom("data did not match any kind of message"))?;
+        Ok(match msg {
+            Untagged::Group(msg) => Message::Group(msg),
+            Untagged::Identify(msg) => Message::Identify(msg),
+            Untagged::Track(msg) => Message::Track(msg),
+            Untagged::Alias(msg) => Message::Alias(msg),
+            Untagged::Page(msg) => Message::Page(msg),
+            Untagged::Screen(msg) => Message::Screen(msg),
+            Untagged::Batch(msg) => Message::Batch(msg),
+        })
+    }
+}
+
 /// An identify event.
 ///
 /// See [Segment's documentation](https://segment.com/docs/spec/identify/) for
@@ -60,6 +110,7 @@ pub struct Identify {
 
     /// The timestamp associated with this message.
     #[serde(
+        default,
         skip_serializing_if = "Option::is_none",
         with = "time::serde::rfc3339::option"
     )]
@@ -74,7 +125,7 @@ pub struct Identify {
     pub integrations: Option<Value>,
 
     /// Extra fields to put at the top level of this message.
-    #[serde(flatten)]
+    #[serde(flatten, deserialize_with = "deserialize_extra")]
     pub extra: Map<String, Value>,
 }
 
@@ -96,6 +147,7 @@ pub struct Track {
 
     /// The timestamp associated with this message.
     #[serde(
+        default,
         skip_serializing_if = "Option::is_none",
         with = "time::serde::rfc3339::option"
     )]
@@ -110,7 +162,7 @@ pub struct Track {
     pub integrations: Option<Value>,
 
     /// Extra fields to put at the top level of this message.
-    #[serde(flatten)]
+    #[serde(flatten, deserialize_with = "deserialize_extra")]
     pub extra: Map<String, Value>,
 }
 
@@ -132,6 +184,7 @@ pub struct Page {
 
     /// The timestamp associated with this message.
     #[serde(
+        default,
         skip_serializing_if = "Option::is_none",
         with = "time::serde::rfc3339::option"
     )]
@@ -146,7 +199,7 @@ pub struct Page {
     pub integrations: Option<Value>,
 
     /// Extra fields to put at the top level of this message.
-    #[serde(flatten)]
+    #[serde(flatten, deserialize_with = "deserialize_extra")]
     pub extra: Map<String, Value>,
 }
 
@@ -168,6 +221,7 @@ pub struct Screen {
 
     /// The timestamp associated with this message.
     #[serde(
+        default,
         skip_serializing_if = "Option::is_none",
         with = "time::serde::rfc3339::option"
     )]
@@ -182,7 +236,7 @@ pub struct Screen {
     pub integrations: Option<Value>,
 
     /// Extra fields to put at the top level of this message.
-    #[serde(flatten)]
+    #[serde(flatten, deserialize_with = "deserialize_extra")]
     pub extra: Map<String, Value>,
 }
 
@@ -205,6 +259,7 @@ pub struct Group {
 
     /// The timestamp associated with this message.
     #[serde(
+        default,
         skip_serializing_if = "Option::is_none",
         with = "time::serde::rfc3339::option"
     )]
@@ -219,7 +274,7 @@ pub struct Group {
     pub integrations: Option<Value>,
 
     /// Extra fields to put at the top level of this message.
-    #[serde(flatten)]
+    #[serde(flatten, deserialize_with = "deserialize_extra")]
     pub extra: Map<String, Value>,
 }
 
@@ -239,6 +294,7 @@ pub struct Alias {
 
     /// The timestamp associated with this message.
     #[serde(
+        default,
         skip_serializing_if = "Option::is_none",
         with = "time::serde::rfc3339::option"
     )]
@@ -253,7 +309,7 @@ pub struct Alias {
     pub integrations: Option<Value>,
 
     /// Extra fields to put at the top level of this message.
-    #[serde(flatten)]
+    #[serde(flatten, deserialize_with = "deserialize_extra")]
     pub extra: Map<String, Value>,
 }
 
@@ -280,6 +336,172 @@ pub struct Batch {
     pub extra: Map<String, Value>,
 }
 
+/// A batch of messages which were serialized when they were pushed into a
+/// [`Batcher`](crate::Batcher), so that sending the batch doesn't serialize
+/// them again.
+///
+/// It serializes to the same JSON as the equivalent [`Batch`], and
+/// deserializes from it with [`serde_json`], keeping the messages as is.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SerializedBatch {
+    // shared, so that the batch can be streamed into a request body
+    pub(crate) batch: Arc<[Box<RawValue>]>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) context: Option<Value>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) integrations: Option<Value>,
+
+    #[serde(
+        rename = "sentAt",
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "time::serde::rfc3339::option"
+    )]
+    pub(crate) sent_at: Option<OffsetDateTime>,
+
+    /// Whether the properties have placeholders to resolve when the batch is
+    /// sent, see [`Batcher::set_send_time_templates`](crate::Batcher::set_send_time_templates).
+    #[serde(skip)]
+    pub(crate) templates: bool,
+}
+
+impl SerializedBatch {
+    /// Returns the number of messages in the batch.
+    pub fn len(&self) -> usize {
+        self.batch.len()
+    }
+
+    /// Returns whether the batch is empty.
+    pub fn is_empty(&self) -> bool {
+        self.batch.is_empty()
+    }
+
+    /// Set the integrations to route the batch to.
+    ///
+    /// They are not accounted for in the size of the batch, prefer
+    /// [`Batcher::set_integrations`](crate::Batcher::set_integrations).
+    pub fn set_integrations(&mut self, integrations: Option<Value>) {
+        self.integrations = integrations;
+    }
+
+    /// Set the time at which the batch is sent, which Segment uses to correct
+    /// the clock skew of the timestamps of the messages.
+    ///
+    /// Unless set, the batch is stamped with the time of each attempt to send
+    /// it.
+    pub fn set_sent_at(&mut self, sent_at: Option<OffsetDateTime>) {
+        self.sent_at = sent_at;
+    }
+
+    /// Returns the JSON body of the batch.
+    pub fn to_json(&self) -> Result<Vec<u8>, serde_json::Error> {
+        serde_json::to_vec(self)
+    }
+
+    /// Returns the JSON body of the batch split in chunks, one per message,
+    /// which are only built when iterated.
+    pub fn to_json_chunks(&self) -> impl Iterator<Item = Bytes> + Send + 'static {
+        let head = Bytes::from_static(b"{\"batch\":[");
+        let mut tail = String::from("]");
+        for (key, value) in [
+            ("context", &self.context),
+            ("integrations", &self.integrations),
+        ] {
+            if let Some(value) = value {
+                tail.push_str(&format!(",\"{key}\":{value}"));
+            }
+        }
+        if let Some(sent_at) = self.sent_at.and_then(|at| at.format(&Rfc3339).ok()) {
+            tail.push_str(&format!(",\"sentAt\":\"{sent_at}\""));
+        }
+        tail.push('}');
+
+        let batch = self.batch.clone();
+        let messages = (0..batch.len()).map(move |i| {
+            let json = batch[i].get();
+            match i {
+                0 => Bytes::copy_from_slice(json.as_bytes()),
+                _ => Bytes::from(format!(",{json}")),
+            }
+        });
+        std::iter::once(head)
+            .chain(messages)
+            .chain(std::iter::once(Bytes::from(tail)))
+    }
+
+    /// Deserialize the messages of the batch.
+    pub fn messages(&self) -> Result<Vec<BatchMessage>, serde_json::Error> {
+        self.batch
+            .iter()
+            .map(|raw| serde_json::from_str(raw.get()))
+            .collect()
+    }
+
+    /// Deserialize the batch into a [`Message`].
+    pub fn to_message(&self) -> Result<Message, serde_json::Error> {
+        Ok(Message::Batch(Batch {
+            batch: self.messages()?,
+            context: self.context.clone(),
+            integrations: self.integrations.clone(),
+            extra: self
+                .sent_at
+                .and_then(|at| at.format(&Rfc3339).ok())
+                .map(|at| ("sentAt".to_owned(), Value::String(at)))
+                .into_iter()
+                .collect(),
+        }))
+    }
+
+    /// Returns the batch as sent now: with its `sentAt`, the current time
+    /// unless it has one, and with the placeholders of its properties
+    /// resolved, see [`template`](crate::template).
+    pub(crate) fn at_send_time(&self) -> Cow<'_, SerializedBatch> {
+        if self.sent_at.is_some() && !self.templates {
+            return Cow::Borrowed(self);
+        }
+        let sent_at = self.sent_at.unwrap_or_else(OffsetDateTime::now_utc);
+        let mut batch = match self.templates {
+            true => template::resolve(self, sent_at),
+            false => self.clone(),
+        };
+        batch.sent_at = Some(sent_at);
+        Cow::Owned(batch)
+    }
+
+    /// Returns the `messageId`s of the messages having one.
+    pub(crate) fn message_ids(&self) -> Vec<String> {
+        self.batch
+            .iter()
+            .filter_map(|raw| message_id(raw))
+            .collect()
+    }
+}
+
+/// Returns the `messageId` of a serialized message, if it has one.
+pub(crate) fn message_id(raw: &RawValue) -> Option<String> {
+    #[derive(Deserialize)]
+    struct Id {
+        #[serde(rename = "messageId")]
+        message_id: Option<String>,
+    }
+
+    serde_json::from_str::<Id>(raw.get()).ok()?.message_id
+}
+
+/// Deserialize the extra fields of a message, which also capture the fields
+/// of the flattened [`User`].
+fn deserialize_extra<'de, D>(deserializer: D) -> Result<Map<String, Value>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let mut extra = Map::deserialize(deserializer)?;
+    extra.remove("userId");
+    extra.remove("anonymousId");
+    Ok(extra)
+}
+
 /// An enum containing all messages which may be placed inside a batch.
 #[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
@@ -299,6 +521,140 @@ pub enum BatchMessage {
 }
 
 impl BatchMessage {
+    /// Returns the `messageId` of this message, if it has one.
+    pub fn message_id(&self) -> Option<&str> {
+        self.extra().get("messageId").and_then(Value::as_str)
+    }
+
+    /// Set the `messageId` of this message, which Segment uses to deduplicate
+    /// the messages.
+    pub fn set_message_id(&mut self, id: impl Into<String>) {
+        self.extra_mut()
+            .insert("messageId".to_owned(), Value::String(id.into()));
+    }
+
+    pub(crate) fn extra(&self) -> &Map<String, Value> {
+        match self {
+            Self::Identify(identify) => &identify.extra,
+            Self::Track(track) => &track.extra,
+            Self::Page(page) => &page.extra,
+            Self::Screen(screen) => &screen.extra,
+            Self::Group(group) => &group.extra,
+            Self::Alias(alias) => &alias.extra,
+        }
+    }
+
+    /// The extra fields put at the top level of the message.
+    pub fn extra_mut(&mut self) -> &mut Map<String, Value> {
+        match self {
+            Self::Identify(identify) => &mut identify.extra,
+            Self::Track(track) => &mut track.extra,
+            Self::Page(page) => &mut page.extra,
+            Self::Screen(screen) => &mut screen.extra,
+            Self::Group(group) => &mut group.extra,
+            Self::Alias(alias) => &mut alias.extra,
+        }
+    }
+
+    pub(crate) fn properties(&self) -> Option<&Value> {
+        match self {
+            Self::Identify(identify) => Some(&identify.traits),
+            Self::Track(track) => Some(&track.properties),
+            Self::Page(page) => Some(&page.properties),
+            Self::Screen(screen) => Some(&screen.properties),
+            Self::Group(group) => Some(&group.traits),
+            Self::Alias(_) => None,
+        }
+    }
+
+    /// The name of the event of a `track` message, or the name of a `page` or
+    /// `screen` message.
+    pub(crate) fn event_name(&self) -> Option<&str> {
+        match self {
+            Self::Track(track) => Some(&track.event),
+            Self::Page(page) => Some(&page.name),
+            Self::Screen(screen) => Some(&screen.name),
+            Self::Identify(_) | Self::Group(_) | Self::Alias(_) => None,
+        }
+    }
+
+    pub(crate) fn event_name_mut(&mut self) -> Option<&mut String> {
+        match self {
+            Self::Track(track) => Some(&mut track.event),
+            Self::Page(page) => Some(&mut page.name),
+            Self::Screen(screen) => Some(&mut screen.name),
+            Self::Identify(_) | Self::Group(_) | Self::Alias(_) => None,
+        }
+    }
+
+    /// Returns the user of the message.
+    pub(crate) fn user(&self) -> &User {
+        match self {
+            Self::Identify(identify) => &identify.user,
+            Self::Track(track) => &track.user,
+            Self::Page(page) => &page.user,
+            Self::Screen(screen) => &screen.user,
+            Self::Group(group) => &group.user,
+            Self::Alias(alias) => &alias.user,
+        }
+    }
+
+    /// Returns a mutable reference to the user of the message, to replace
+    /// its IDs.
+    pub(crate) fn user_mut(&mut self) -> &mut User {
+        match self {
+            Self::Identify(identify) => &mut identify.user,
+            Self::Track(track) => &mut track.user,
+            Self::Page(page) => &mut page.user,
+            Self::Screen(screen) => &mut screen.user,
+            Self::Group(group) => &mut group.user,
+            Self::Alias(alias) => &mut alias.user,
+        }
+    }
+
+    /// The properties of the message, or its traits for `identify` and
+    /// `group` messages.
+    pub fn properties_mut(&mut self) -> Option<&mut Value> {
+        match self {
+            Self::Identify(identify) => Some(&mut identify.traits),
+            Self::Track(track) => Some(&mut track.properties),
+            Self::Page(page) => Some(&mut page.properties),
+            Self::Screen(screen) => Some(&mut screen.properties),
+            Self::Group(group) => Some(&mut group.traits),
+            Self::Alias(_) => None,
+        }
+    }
+
+    /// The context of the message.
+    pub fn context_mut(&mut self) -> &mut Option<Value> {
+        match self {
+            Self::Identify(identify) => &mut identify.context,
+            Self::Track(track) => &mut track.context,
+            Self::Page(page) => &mut page.context,
+            Self::Screen(screen) => &mut screen.context,
+            Self::Group(group) => &mut group.context,
+            Self::Alias(alias) => &mut alias.context,
+        }
+    }
+
+    /// The integrations of the message.
+    pub fn integrations_mut(&mut self) -> &mut Option<Value> {
+        match self {
+            Self::Identify(identify) => &mut identify.integrations,
+            Self::Track(track) => &mut track.integrations,
+            Self::Page(page) => &mut page.integrations,
+            Self::Screen(screen) => &mut screen.integrations,
+            Self::Group(group) => &mut group.integrations,
+            Self::Alias(alias) => &mut alias.integrations,
+        }
+    }
+
+    /// Set the timestamp of the message to now, unless it already has one.
+    pub(crate) fn stamp(&mut self) {
+        self.timestamp_mut()
+            .get_or_insert_with(OffsetDateTime::now_utc);
+    }
+
     pub(crate) fn timestamp_mut(&mut self) -> &mut Option<OffsetDateTime> {
         match self {
             Self::Identify(identify) => &mut identify.timestamp,
@@ -318,7 +674,7 @@ impl BatchMessage {
 /// documentation](https://segment.com/docs/spec/identify/#identities) for how
 /// user IDs and anonymous IDs should be used.
 #[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
-#[serde(untagged)]
+#[serde(untagged, try_from = "UserIds")]
 pub enum User {
     /// The user is identified only by a user ID.
     UserId {
@@ -342,6 +698,45 @@ pub enum User {
     },
 }
 
+/// The IDs of a deserialized [`User`], which doesn't depend on the order of
+/// its variants.
+#[derive(Deserialize)]
+struct UserIds {
+    #[serde(rename = "userId")]
+    user_id: Option<String>,
+
+    #[serde(rename = "anonymousId")]
+    anonymous_id: Option<String>,
+}
+
+impl TryFrom<UserIds> for User {
+    type Error = &'static str;
+
+    fn try_from(ids: UserIds) -> Result<Self, Self::Error> {
+        match ids {
+            UserIds {
+                user_id: Some(user_id),
+                anonymous_id: Some(anonymous_id),
+            } => Ok(User::Both {
+                user_id,
+                anonymous_id,
+            }),
+            UserIds {
+                user_id: Some(user_id),
+                anonymous_id: None,
+            } => Ok(User::UserId { user_id }),
+            UserIds {
+                user_id: None,
+                anonymous_id: Some(anonymous_id),
+            } => Ok(User::AnonymousId { anonymous_id }),
+            UserIds {
+                user_id: None,
+                anonymous_id: None,
+            } => Err("a userId or an anonymousId is required"),
+        }
+    }
+}
+
 impl Display for User {
     /// Display a `UserId`. If he has both an `anonymous_id` and a `user_id` we display the `user_id`
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -353,6 +748,43 @@ impl Display for User {
     }
 }
 
+impl User {
+    /// Construct a stable anonymous user from an identifying input, such as a
+    /// hashed cookie or a device fingerprint.
+    ///
+    /// The anonymous ID is derived from the SHA-256 of `input`, formatted as a
+    /// UUID: the same input always yields the same ID, so it doesn't need to
+    /// be stored to track a visitor across requests, before they sign up.
+    ///
+    /// ```
+    /// use segment::message::User;
+    ///
+    /// let user = User::anonymous_from_hash("cookie-value");
+    /// assert_eq!(user, User::anonymous_from_hash("cookie-value"));
+    /// assert_ne!(user, User::anonymous_from_hash("another-cookie"));
+    /// ```
+    pub fn anonymous_from_hash(input: impl AsRef<[u8]>) -> Self {
+        User::AnonymousId {
+            anonymous_id: uuid_from_hash(input),
+        }
+    }
+}
+
+/// Returns a UUID derived from the SHA-256 hash of `input`.
+pub(crate) fn uuid_from_hash(input: impl AsRef<[u8]>) -> String {
+    uuid_from_digest(&Sha256::digest(input.as_ref()))
+}
+
+/// Returns a version 8 (custom) UUID made of the first 16 bytes of `digest`.
+pub(crate) fn uuid_from_digest(digest: &[u8]) -> String {
+    let bytes = digest[..16]
+        .try_into()
+        .expect("the digests have at least 16 bytes");
+    uuid::Builder::from_custom_bytes(bytes)
+        .into_uuid()
+        .to_string()
+}
+
 impl Default for User {
     fn default() -> Self {
         User::AnonymousId {
@@ -361,6 +793,237 @@ impl Default for User {
     }
 }
 
+/// Typed properties (or traits), serialized flattened alongside free-form
+/// extra properties.
+///
+/// This allows migrating incrementally from `json!` blobs to typed structs:
+/// the fields moved to `T` and the ones left in `extra` produce the same
+/// payload. Deserializing fills `T` and collects the unknown keys in `extra`.
+///
+/// The keys of `extra` should not collide with the fields of `T`.
+///
+/// ```
+/// use segment::message::{Properties, Track};
+/// use serde::Serialize;
+/// use serde_json::json;
+///
+/// #[derive(Serialize)]
+/// struct Purchase {
+///     price: u32,
+///     currency: &'static str,
+/// }
+///
+/// let mut properties = Properties::new(Purchase { price: 42, currency: "EUR" });
+/// properties.extra.insert("coupon".to_owned(), json!("SUMMER"));
+///
+/// let track = Track {
+///     event: "Order Completed".to_owned(),
+///     properties: properties.to_value().unwrap(),
+///     ..Default::default()
+/// };
+/// assert_eq!(track.properties, json!({ "price": 42, "currency": "EUR", "coupon": "SUMMER" }));
+/// ```
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize, Default)]
+pub struct Properties<T> {
+    /// The typed properties.
+    #[serde(flatten)]
+    pub typed: T,
+
+    /// The free-form properties.
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl<T> Properties<T> {
+    /// Wrap typed properties, without any extra properties.
+    pub fn new(typed: T) -> Self {
+        Self {
+            typed,
+            extra: Map::new(),
+        }
+    }
+}
+
+impl<T: Serialize> Properties<T> {
+    /// Serialize the properties into the value expected by the `properties`
+    /// or `traits` field of a message.
+    ///
+    /// Returns an error if `T` doesn't serialize into an object.
+    pub fn to_value(&self) -> Result<Value, PropertyError> {
+        to_property(self)
+    }
+}
+
+/// A property (or trait) which could not be serialized into JSON, like a
+/// map with non-string keys, with the path to the offending value.
+///
+/// ```
+/// use std::collections::BTreeMap;
+/// use segment::message::{Track, User};
+/// use serde::Serialize;
+///
+/// #[derive(Serialize)]
+/// struct Cart {
+///     quantities: BTreeMap<(u32, u32), u32>,
+/// }
+///
+/// let cart = Cart { quantities: [((1, 2), 3)].into() };
+/// let user = User::UserId { user_id: "user".to_owned() };
+/// let err = Track::from_props(user, "Cart Viewed", &cart).unwrap_err();
+/// assert_eq!(err.path(), "quantities");
+/// ```
+#[derive(Debug, thiserror::Error)]
+#[error("invalid property at `{path}`: {source}")]
+pub struct PropertyError {
+    path: String,
+    #[source]
+    source: serde_json::Error,
+}
+
+impl PropertyError {
+    /// Returns the path to the offending value, like `items[2].price`, or
+    /// `.` for the properties themselves.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+}
+
+/// Serialize `value` into JSON, keeping track of the path to the offending
+/// value if it fails.
+pub(crate) fn to_property<T: Serialize + ?Sized>(value: &T) -> Result<Value, PropertyError> {
+    serde_path_to_error::serialize(value, serde_json::value::Serializer).map_err(|err| {
+        PropertyError {
+            path: err.path().to_string(),
+            source: err.into_inner(),
+        }
+    })
+}
+
+impl Identify {
+    /// Construct an `identify` of `user` with typed `traits`.
+    ///
+    /// Returns an error if `traits` doesn't serialize into an object.
+    pub fn from_traits<T: Serialize>(user: User, traits: &T) -> Result<Self, PropertyError> {
+        Ok(Self {
+            user,
+            traits: Properties::new(traits).to_value()?,
+            ..Default::default()
+        })
+    }
+}
+
+impl Track {
+    /// Construct the `track` of `event` by `user` with typed `properties`,
+    /// checked at compile time instead of a `json!` blob.
+    ///
+    /// Returns an error if `properties` doesn't serialize into an object.
+    ///
+    /// ```
+    /// use segment::message::{Track, User};
+    /// use serde::Serialize;
+    /// use serde_json::json;
+    ///
+    /// #[derive(Serialize)]
+    /// struct SignedUp {
+    ///     plan: &'static str,
+    ///     seats: u32,
+    /// }
+    ///
+    /// let user = User::UserId { user_id: "user".to_owned() };
+    /// let track = Track::from_props(user, "Signed Up", &SignedUp { plan: "pro", seats: 3 }).unwrap();
+    /// assert_eq!(track.properties, json!({ "plan": "pro", "seats": 3 }));
+    /// ```
+    pub fn from_props<T: Serialize>(
+        user: User,
+        event: impl Into<String>,
+        properties: &T,
+    ) -> Result<Self, PropertyError> {
+        Ok(Self {
+            user,
+            event: event.into(),
+            properties: Properties::new(properties).to_value()?,
+            ..Default::default()
+        })
+    }
+}
+
+impl Page {
+    /// Construct the `page` view of `name` by `user` with typed `properties`.
+    ///
+    /// Returns an error if `properties` doesn't serialize into an object.
+    pub fn from_props<T: Serialize>(
+        user: User,
+        name: impl Into<String>,
+        properties: &T,
+    ) -> Result<Self, PropertyError> {
+        Ok(Self {
+            user,
+            name: name.into(),
+            properties: Properties::new(properties).to_value()?,
+            ..Default::default()
+        })
+    }
+}
+
+impl Screen {
+    /// Construct the `screen` view of `name` by `user` with typed
+    /// `properties`.
+    ///
+    /// Returns an error if `properties` doesn't serialize into an object.
+    pub fn from_props<T: Serialize>(
+        user: User,
+        name: impl Into<String>,
+        properties: &T,
+    ) -> Result<Self, PropertyError> {
+        Ok(Self {
+            user,
+            name: name.into(),
+            properties: Properties::new(properties).to_value()?,
+            ..Default::default()
+        })
+    }
+}
+
+impl Group {
+    /// Construct the `group` of `user` into `group_id` with typed `traits`.
+    ///
+    /// Returns an error if `traits` doesn't serialize into an object.
+    pub fn from_traits<T: Serialize>(
+        user: User,
+        group_id: impl Into<String>,
+        traits: &T,
+    ) -> Result<Self, PropertyError> {
+        Ok(Self {
+            user,
+            group_id: group_id.into(),
+            traits: Properties::new(traits).to_value()?,
+            ..Default::default()
+        })
+    }
+}
+
+macro_rules! message_id {
+    ($($message:ident),+ $(,)?) => {
+        $(
+            impl $message {
+                /// Returns the `messageId` of this message, if it has one.
+                pub fn message_id(&self) -> Option<&str> {
+                    self.extra.get("messageId").and_then(Value::as_str)
+                }
+
+                /// Set the `messageId` of this message, which Segment uses to
+                /// deduplicate the messages.
+                pub fn set_message_id(&mut self, id: impl Into<String>) {
+                    self.extra
+                        .insert("messageId".to_owned(), Value::String(id.into()));
+                }
+            }
+        )+
+    };
+}
+
+message_id!(Identify, Track, Page, Screen, Group, Alias);
+
 macro_rules! into {
     (from $from:ident into $for:ident) => {
         impl From<$from> for $for {
@@ -393,11 +1056,100 @@ into! {
     from Alias into BatchMessage,
 }
 
+impl From<BatchMessage> for Message {
+    fn from(message: BatchMessage) -> Self {
+        match message {
+            BatchMessage::Identify(message) => Self::Identify(message),
+            BatchMessage::Track(message) => Self::Track(message),
+            BatchMessage::Page(message) => Self::Page(message),
+            BatchMessage::Screen(message) => Self::Screen(message),
+            BatchMessage::Group(message) => Self::Group(message),
+            BatchMessage::Alias(message) => Self::Alias(message),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use serde_json::json;
 
+    #[test]
+    fn properties() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Signup {
+            plan: String,
+            seats: u32,
+        }
+
+        let value = json!({ "plan": "pro", "seats": 3, "referrer": "ads" });
+        let properties: Properties<Signup> = serde_json::from_value(value.clone()).unwrap();
+        assert_eq!(
+            properties.typed,
+            Signup {
+                plan: "pro".to_owned(),
+                seats: 3
+            }
+        );
+        assert_eq!(properties.extra.len(), 1);
+        assert_eq!(properties.to_value().unwrap(), value);
+
+        assert!(Properties::new(42).to_value().is_err());
+    }
+
+    #[test]
+    fn typed_constructors() {
+        #[derive(Serialize)]
+        struct Plan {
+            plan: &'static str,
+        }
+
+        let user = || User::UserId {
+            user_id: "user".to_owned(),
+        };
+        let plan = Plan { plan: "pro" };
+        let track = Track::from_props(user(), "Upgraded", &plan).unwrap();
+        assert_eq!(track.event, "Upgraded");
+        assert_eq!(track.properties, json!({ "plan": "pro" }));
+        let page = Page::from_props(user(), "Pricing", &plan).unwrap();
+        assert_eq!(page.properties, json!({ "plan": "pro" }));
+        let screen = Screen::from_props(user(), "Pricing", &plan).unwrap();
+        assert_eq!(screen.name, "Pricing");
+        let identify = Identify::from_traits(user(), &plan).unwrap();
+        assert_eq!(identify.traits, json!({ "plan": "pro" }));
+        let group = Group::from_traits(user(), "acme", &plan).unwrap();
+        assert_eq!(group.group_id, "acme");
+
+        assert!(Track::from_props(user(), "Upgraded", &"pro").is_err());
+
+        #[derive(Serialize)]
+        struct Item {
+            tags: std::collections::HashMap<Vec<u8>, bool>,
+        }
+        #[derive(Serialize)]
+        struct Cart {
+            items: Vec<Item>,
+        }
+        let cart = Cart {
+            items: vec![
+                Item {
+                    tags: Default::default(),
+                },
+                Item {
+                    tags: [(vec![1], true)].into(),
+                },
+            ],
+        };
+        let err = Track::from_props(user(), "Cart Viewed", &cart).unwrap_err();
+        assert_eq!(err.path(), "items[1].tags");
+        assert!(err
+            .to_string()
+            .starts_with("invalid property at `items[1].tags`"));
+        let err = Track::from_props(user(), "Upgraded", &"pro").unwrap_err();
+        assert_eq!(err.path(), ".");
+        assert!(Identify::from_traits(user(), &[1, 2]).is_err());
+    }
+
     #[test]
     fn serialize() {
         assert_eq!(
@@ -538,4 +1290,141 @@ mod tests {
                 .to_owned(),
         );
     }
+
+    #[test]
+    fn deserialize_serialized() {
+        let track = BatchMessage::Track(Track {
+            user: User::Both {
+                user_id: "foo".to_owned(),
+                anonymous_id: "bar".to_owned(),
+            },
+            event: "Example".to_owned(),
+            properties: json!({ "a": 1 }),
+            extra: [("messageId".to_owned(), json!("id"))]
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        });
+        let json = serde_json::to_string(&track).unwrap();
+        assert_eq!(serde_json::from_str::<BatchMessage>(&json).unwrap(), track);
+    }
+
+    #[test]
+    fn round_trips() {
+        let user = || User::UserId {
+            user_id: "user".to_owned(),
+        };
+        let timestamp = Some(OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap());
+        let msgs = [
+            BatchMessage::Identify(Identify {
+                user: user(),
+                traits: json!({ "email": "ada@example.com" }),
+                timestamp,
+                ..Default::default()
+            }),
+            BatchMessage::Track(Track {
+                user: user(),
+                event: "Signed Up".to_owned(),
+                properties: json!({ "plan": "pro" }),
+                context: Some(json!({ "ip": "127.0.0.1" })),
+                ..Default::default()
+            }),
+            BatchMessage::Page(Page {
+                user: user(),
+                name: "Home".to_owned(),
+                properties: json!({}),
+                ..Default::default()
+            }),
+            BatchMessage::Screen(Screen {
+                user: user(),
+                name: "Home".to_owned(),
+                properties: json!({}),
+                integrations: Some(json!({ "All": false })),
+                ..Default::default()
+            }),
+            BatchMessage::Group(Group {
+                user: user(),
+                group_id: "acme".to_owned(),
+                traits: json!({ "name": "Acme" }),
+                ..Default::default()
+            }),
+            BatchMessage::Alias(Alias {
+                user: user(),
+                previous_id: "anonymous".to_owned(),
+                extra: [("messageId".to_owned(), json!("id"))]
+                    .into_iter()
+                    .collect(),
+                ..Default::default()
+            }),
+        ];
+
+        for msg in &msgs {
+            let json = serde_json::to_string(msg).unwrap();
+            assert_eq!(&serde_json::from_str::<BatchMessage>(&json).unwrap(), msg);
+            // the `type` picks the kind of the message, even a `screen`
+            let expected = Message::from(msg.clone());
+            assert_eq!(serde_json::from_str::<Message>(&json).unwrap(), expected);
+
+            if !matches!(msg, BatchMessage::Screen(_)) {
+                let json = serde_json::to_string(&expected).unwrap();
+                assert_eq!(serde_json::from_str::<Message>(&json).unwrap(), expected);
+            }
+        }
+
+        let batch = Message::Batch(Batch {
+            batch: msgs.to_vec(),
+            context: Some(json!({ "library": { "name": "segment" } })),
+            ..Default::default()
+        });
+        let json = serde_json::to_string(&batch).unwrap();
+        assert_eq!(serde_json::from_str::<Message>(&json).unwrap(), batch);
+
+        let serialized: SerializedBatch = serde_json::from_str(&json).unwrap();
+        assert_eq!(serialized.len(), msgs.len());
+        assert_eq!(serialized.to_json().unwrap(), json.as_bytes());
+        assert_eq!(serialized.to_message().unwrap(), batch);
+
+        assert!(serde_json::from_str::<Message>(r#"{ "userId": "user" }"#).is_err());
+    }
+
+    #[test]
+    fn serialized_batch_chunks() {
+        let mut batcher = crate::Batcher::new(Some(json!({ "library": "segment" })));
+        for event in ["a", "b", "c"] {
+            batcher
+                .push(Track {
+                    event: event.to_owned(),
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+        let mut batch = batcher.into_serialized();
+        batch.set_integrations(Some(json!({ "All": false })));
+        batch.set_sent_at(Some(OffsetDateTime::now_utc()));
+
+        let chunks: Vec<u8> = batch.to_json_chunks().flatten().collect();
+        assert_eq!(chunks, batch.to_json().unwrap());
+    }
+
+    #[test]
+    fn serialized_batch_at_send_time() {
+        let mut batch = crate::Batcher::new(None).into_serialized();
+        let before = OffsetDateTime::now_utc();
+        let sent = batch.at_send_time();
+        assert!(sent.sent_at.unwrap() >= before);
+        assert_eq!(batch.sent_at, None);
+
+        batch.set_sent_at(Some(before));
+        assert!(
+            matches!(batch.at_send_time(), Cow::Borrowed(sent) if sent.sent_at == Some(before))
+        );
+    }
+
+    #[test]
+    fn anonymous_from_hash() {
+        let User::AnonymousId { anonymous_id } = User::anonymous_from_hash("foo") else {
+            panic!("expected an anonymous user");
+        };
+        assert_eq!(anonymous_id, "2c26b46b-68ff-868f-b99b-453c1d304134");
+    }
 }
@@ -0,0 +1,211 @@
+//! A batcher sending to several Segment sources.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::{
+    auto_batcher::AutoBatcher,
+    client::Client,
+    errors::{Errors, Result},
+    http::HttpClient,
+    message::BatchMessage,
+    runtime::Instant,
+    stats::Stats,
+};
+
+/// Batches the messages of several Segment sources, with one
+/// [`AutoBatcher`] per write key, so that a single batcher routes each
+/// message to the batches of its source.
+///
+/// The batcher of each source is created on its first message, with the
+/// configuration of the batcher given to [Self::new], which is also the one
+/// of the default source. They all share the same client, upload slots (see
+/// [`AutoBatcher::set_max_concurrent_flushes`]), [`Stats`], deliveries and
+/// [`DeliveryObserver`](crate::DeliveryObserver): subscribe to the
+/// deliveries before constructing the `MultiSourceBatcher`.
+///
+/// The batchers of the other sources are dropped once flushed, and created
+/// again on their next message, so that only the sources used since the
+/// last flush are kept.
+///
+/// ```
+/// use segment::{AutoBatcher, Batcher, HttpClient, MultiSourceBatcher};
+/// use segment::message::{Track, User};
+///
+/// # async fn run() -> segment::Result<()> {
+/// let client = HttpClient::default();
+/// let batcher = AutoBatcher::new(client, Batcher::new(None), "web_write_key".to_string());
+/// let mut batcher = MultiSourceBatcher::new(batcher);
+///
+/// let msg = Track {
+///     user: User::UserId { user_id: String::from("user") },
+///     event: "Example".to_owned(),
+///     ..Default::default()
+/// };
+/// batcher.push(msg.clone()).await?;
+/// batcher.push_to("backend_write_key", msg).await?;
+/// batcher.flush().await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct MultiSourceBatcher<C = HttpClient>
+where
+    C: Client + Clone + Send + Sync + 'static,
+{
+    default: AutoBatcher<C>,
+    sources: HashMap<String, AutoBatcher<C>>,
+}
+
+impl<C> MultiSourceBatcher<C>
+where
+    C: Client + Clone + Send + Sync + 'static,
+{
+    /// Construct a batcher sending to the source of `batcher` by default,
+    /// and to the other sources with its configuration.
+    pub fn new(batcher: AutoBatcher<C>) -> Self {
+        Self {
+            default: batcher,
+            sources: HashMap::new(),
+        }
+    }
+
+    /// Push a message into the batch of the default source, see
+    /// [`AutoBatcher::push`].
+    pub async fn push(&mut self, msg: impl Into<BatchMessage>) -> Result<()> {
+        self.default.push(msg).await
+    }
+
+    /// Push a message into the batch of the source of `write_key`, see
+    /// [`AutoBatcher::push`].
+    pub async fn push_to(&mut self, write_key: &str, msg: impl Into<BatchMessage>) -> Result<()> {
+        self.source(write_key).push(msg).await
+    }
+
+    /// Returns the batcher of the source of `write_key`, creating it if
+    /// needed.
+    pub fn source(&mut self, write_key: &str) -> &mut AutoBatcher<C> {
+        if write_key == self.default.key {
+            return &mut self.default;
+        }
+        self.sources
+            .entry(write_key.to_owned())
+            .or_insert_with(|| self.default.for_key(write_key.to_owned()))
+    }
+
+    /// Returns the number of messages buffered for all the sources.
+    pub fn len(&self) -> usize {
+        self.batchers().map(AutoBatcher::len).sum()
+    }
+
+    /// Returns whether no message is buffered.
+    pub fn is_empty(&self) -> bool {
+        self.batchers().all(AutoBatcher::is_empty)
+    }
+
+    /// Returns the statistics of all the sources.
+    pub fn stats(&self) -> Stats {
+        self.default.stats()
+    }
+
+    /// Flush the batches of every source, see [`AutoBatcher::flush`].
+    ///
+    /// Every source is flushed even if some of them fail. The batches which
+    /// could not be delivered, whatever their source, are all returned in a
+    /// single [`Error::Undelivered`](crate::Error::Undelivered).
+    pub async fn flush(&mut self) -> Result<()> {
+        let mut errors = Errors::default();
+        if let Err(err) = self.default.flush().await {
+            errors.push(err);
+        }
+        for (_, mut batcher) in self.sources.drain() {
+            if let Err(err) = batcher.flush().await {
+                errors.push(err);
+            }
+        }
+        errors.into_result()
+    }
+
+    /// Stop the batchers of every source within the same `deadline`, see
+    /// [`AutoBatcher::shutdown`].
+    ///
+    /// Returns the messages that could not be delivered, by write key.
+    pub async fn shutdown(self, deadline: Duration) -> HashMap<String, Vec<BatchMessage>> {
+        let deadline = Instant::now() + deadline;
+        let mut undelivered = HashMap::new();
+        let batchers = std::iter::once(self.default).chain(self.sources.into_values());
+        for batcher in batchers {
+            let key = batcher.key.clone();
+            let messages = batcher.shutdown_at(Vec::new(), deadline).await;
+            if !messages.is_empty() {
+                undelivered.insert(key, messages);
+            }
+        }
+        undelivered
+    }
+
+    fn batchers(&self) -> impl Iterator<Item = &AutoBatcher<C>> {
+        std::iter::once(&self.default).chain(self.sources.values())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::Track;
+    use crate::testing::{large_track, MockClient};
+    use crate::{Batcher, Error};
+    use std::sync::atomic::Ordering;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_routing() {
+        let client = MockClient::default();
+        let batcher = AutoBatcher::new(client.clone(), Batcher::new(None), "web".to_owned());
+        let mut batcher = MultiSourceBatcher::new(batcher);
+
+        batcher.push(Track::default()).await.unwrap();
+        batcher.push_to("web", Track::default()).await.unwrap();
+        batcher.push_to("backend", Track::default()).await.unwrap();
+        assert_eq!(batcher.len(), 3);
+        assert_eq!(batcher.source("backend").len(), 1);
+
+        batcher.flush().await.unwrap();
+        assert!(batcher.is_empty());
+        assert!(batcher.sources.is_empty());
+        assert_eq!(client.sent_count(), 3);
+        assert_eq!(*client.keys.lock().unwrap(), ["web", "backend"]);
+
+        // both sources fail, all their messages come back
+        client.failures.store(2, Ordering::SeqCst);
+        batcher.push(Track::default()).await.unwrap();
+        batcher.push_to("backend", Track::default()).await.unwrap();
+        batcher.push_to("backend", Track::default()).await.unwrap();
+        let Err(Error::Undelivered { batches }) = batcher.flush().await else {
+            panic!("expected undelivered batches");
+        };
+        assert_eq!(batches.len(), 2);
+        let messages: usize = batches.iter().map(|batch| batch.messages.len()).sum();
+        assert_eq!(messages, 3);
+        assert_eq!(client.sent_count(), 3);
+
+        batcher.push_to("backend", Track::default()).await.unwrap();
+        assert!(batcher.shutdown(Duration::from_secs(1)).await.is_empty());
+        assert_eq!(client.sent_count(), 4);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_shared_upload_slots() {
+        let client = MockClient::default();
+        let batcher = AutoBatcher::new(client.clone(), Batcher::new(None), "web".to_owned());
+        let mut batcher = MultiSourceBatcher::new(batcher);
+
+        // a full batch for each source, uploaded one at a time
+        for i in 0..20 {
+            batcher.push(large_track(i)).await.unwrap();
+            batcher.push_to("backend", large_track(i)).await.unwrap();
+        }
+        batcher.flush().await.unwrap();
+        assert_eq!(client.sent_count(), 40);
+        assert_eq!(client.max_running.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,176 @@
+//! Normalization of the property keys into warehouse column names.
+
+use serde_json::{Map, Value};
+
+use crate::logging;
+
+/// Options to normalize the keys of the properties and traits into names
+/// that warehouses accept as column names, so that `Plan`, `plan` and
+/// `plan ` end up in a single `plan` column instead of three.
+///
+/// A key is converted to snake case, lowercased, and every run of characters
+/// other than letters and digits is replaced by an underscore. The letters
+/// and digits of every script are kept. The keys which collide once
+/// normalized are suffixed with `_2`, `_3`...: the key which was already
+/// normalized keeps its name, otherwise the first key in order.
+///
+/// Normalization is opt-in, see
+/// [`Batcher::set_key_normalization`](crate::Batcher::set_key_normalization).
+///
+/// ```
+/// use segment::KeyNormalization;
+/// use serde_json::json;
+///
+/// let mut properties = json!({ "Plan": "free", "plan": "pro", "firstName": "Ada", "2fa": true });
+/// KeyNormalization::default().normalize(&mut properties);
+/// assert_eq!(
+///     properties,
+///     json!({ "plan": "pro", "plan_2": "free", "first_name": "Ada", "_2fa": true })
+/// );
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyNormalization {
+    /// Also normalize the keys of the nested objects.
+    pub nested: bool,
+}
+
+impl Default for KeyNormalization {
+    fn default() -> Self {
+        Self { nested: true }
+    }
+}
+
+impl KeyNormalization {
+    /// Normalize the keys of `value` in place. Does nothing if `value` is not
+    /// an object.
+    pub fn normalize(&self, value: &mut Value) {
+        let Value::Object(object) = value else {
+            return;
+        };
+
+        // the keys already normalized keep their name
+        let mut normalized = Map::new();
+        let mut renamed = Vec::new();
+        for (key, mut value) in std::mem::take(object) {
+            if self.nested {
+                self.normalize(&mut value);
+            }
+            let name = column_name(&key);
+            if name == key {
+                normalized.insert(name, value);
+            } else {
+                renamed.push((name, value));
+            }
+        }
+        let mut suffixed = 0;
+        for (name, value) in renamed {
+            let name = match normalized.contains_key(&name) {
+                true => {
+                    suffixed += 1;
+                    (2..)
+                        .map(|n| format!("{name}_{n}"))
+                        .find(|name| !normalized.contains_key(name))
+                        .expect("a free suffix")
+                }
+                false => name,
+            };
+            normalized.insert(name, value);
+        }
+        if suffixed > 0 {
+            logging::warn!(
+                suffixed,
+                "properties collided once normalized, some were suffixed"
+            );
+        }
+        *object = normalized;
+    }
+}
+
+/// Returns `key` as a warehouse column name.
+fn column_name(key: &str) -> String {
+    let mut name = String::with_capacity(key.len());
+    let mut previous: Option<char> = None;
+    for c in key.chars() {
+        if c.is_alphanumeric() {
+            // a word starts at an uppercase letter after a lowercase one
+            let boundary =
+                c.is_uppercase() && previous.is_some_and(|p| p.is_lowercase() || p.is_numeric());
+            if boundary {
+                name.push('_');
+            }
+            name.extend(c.to_lowercase());
+        } else if !name.is_empty() && !name.ends_with('_') {
+            name.push('_');
+        }
+        previous = Some(c);
+    }
+    while name.ends_with('_') {
+        name.pop();
+    }
+    if name.starts_with(char::is_numeric) || name.is_empty() {
+        name.insert(0, '_');
+    }
+    name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_column_names() {
+        for (key, name) in [
+            ("plan", "plan"),
+            ("Plan", "plan"),
+            ("plan ", "plan"),
+            ("firstName", "first_name"),
+            ("HTTPStatus", "httpstatus"),
+            ("order id", "order_id"),
+            ("order--ID", "order_id"),
+            ("page2View", "page2_view"),
+            ("2fa", "_2fa"),
+            ("__private", "private"),
+            ("  ", "_"),
+            ("été", "été"),
+            ("Prénom", "prénom"),
+            ("dateÉté", "date_été"),
+            ("名前", "名前"),
+            ("٣days", "_٣days"),
+        ] {
+            assert_eq!(column_name(key), name, "{key:?}");
+        }
+    }
+
+    #[test]
+    fn test_normalize() {
+        let mut properties = json!({
+            "Plan": "free",
+            "plan ": "trial",
+            "Address": { "zipCode": "75001" },
+            "items": [{ "Name": "kept as is" }],
+        });
+        KeyNormalization::default().normalize(&mut properties);
+        assert_eq!(
+            properties,
+            json!({
+                "plan": "free",
+                "plan_2": "trial",
+                "address": { "zip_code": "75001" },
+                "items": [{ "Name": "kept as is" }],
+            })
+        );
+
+        let mut properties = json!({ "Address": { "zipCode": "75001" } });
+        KeyNormalization { nested: false }.normalize(&mut properties);
+        assert_eq!(properties, json!({ "address": { "zipCode": "75001" } }));
+
+        // the suffixes don't collide with the keys already normalized
+        let mut properties = json!({ "Plan": 1, "PLAN": 2, "plan": 3, "plan_2": 4 });
+        KeyNormalization::default().normalize(&mut properties);
+        assert_eq!(
+            properties,
+            json!({ "plan": 3, "plan_2": 4, "plan_3": 2, "plan_4": 1 })
+        );
+    }
+}
@@ -0,0 +1,245 @@
+//! Redaction of the personal data of the messages before they are buffered.
+
+use std::collections::BTreeSet;
+
+use regex::{Captures, Regex};
+use serde_json::Value;
+
+use crate::errors::{Error, Result};
+use crate::message::BatchMessage;
+use crate::pipeline::Middleware;
+use crate::rules::REDACTED;
+
+/// Matches the email addresses.
+pub const EMAIL: &str = r"[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}";
+
+/// Matches the international phone numbers starting with a `+`, and the
+/// North American ones with separators, like `(555) 123-4567`.
+pub const PHONE: &str =
+    r"\+\d{1,3}(?:[\s.-]?\d{1,4}){3,6}\b|(?:\(\d{3}\)\s?|\b\d{3}[\s.-])\d{3}[\s.-]\d{4}\b";
+
+/// Matches the credit card numbers, of 13 to 19 digits grouped by four.
+///
+/// [`PiiScrubber::with_defaults`] only redacts the matches passing the Luhn
+/// check, not any long number like a timestamp in milliseconds.
+pub const CREDIT_CARD: &str = r"\b\d{4}(?:[\s-]?\d{4}){2}[\s-]?\d{1,7}\b";
+
+/// A [`Middleware`] replacing the personal data of the properties (or
+/// traits), and of `context.traits`, with [`REDACTED`].
+///
+/// The values of the listed keys are replaced whole, at any depth, while the
+/// parts of the strings matching a pattern are replaced wherever they are.
+/// The patterns are heuristics: a string looking like a phone number is
+/// redacted even if it is not one.
+///
+/// ```
+/// use segment::{Batcher, Pipeline, PiiScrubber, Stage};
+/// use segment::message::Track;
+/// use serde_json::json;
+///
+/// let scrubber = PiiScrubber::with_defaults().key("password");
+/// let mut batcher = Batcher::new(None);
+/// batcher.set_pipeline(Pipeline::builder().middleware(Stage::Processed, scrubber).build());
+///
+/// batcher.push(Track {
+///     event: "Signed Up".to_owned(),
+///     properties: json!({ "note": "call me at +33 6 12 34 56 78", "password": "hunter2" }),
+///     ..Default::default()
+/// }).unwrap();
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct PiiScrubber {
+    /// The keys whose values are redacted, in lowercase.
+    keys: BTreeSet<String>,
+    /// The patterns, with the check their matches must pass to be redacted.
+    patterns: Vec<(Regex, Check)>,
+}
+
+/// Returns whether a match of a pattern is personal data.
+type Check = fn(&str) -> bool;
+
+impl PiiScrubber {
+    /// Construct a scrubber redacting nothing.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Construct a scrubber redacting the [`EMAIL`]s, the [`PHONE`] numbers
+    /// and the [`CREDIT_CARD`] numbers.
+    pub fn with_defaults() -> Self {
+        let any: Check = |_| true;
+        let patterns = [(EMAIL, any), (PHONE, any), (CREDIT_CARD, luhn)]
+            .into_iter()
+            .map(|(pattern, check)| {
+                let regex = Regex::new(pattern).expect("the default patterns are valid");
+                (regex, check)
+            })
+            .collect();
+        Self {
+            keys: BTreeSet::new(),
+            patterns,
+        }
+    }
+
+    /// Redact the values of `key`, compared case-insensitively.
+    pub fn key(mut self, key: impl AsRef<str>) -> Self {
+        self.keys.insert(key.as_ref().to_lowercase());
+        self
+    }
+
+    /// Redact the parts of the strings matching `pattern`.
+    ///
+    /// Returns [`Error::InvalidConfiguration`] if it is not a valid regular
+    /// expression.
+    pub fn pattern(mut self, pattern: &str) -> Result<Self> {
+        let regex = Regex::new(pattern)
+            .map_err(|err| Error::InvalidConfiguration(format!("invalid PII pattern: {err}")))?;
+        self.patterns.push((regex, |_| true));
+        Ok(self)
+    }
+
+    fn scrub(&self, value: &mut Value) {
+        match value {
+            Value::String(string) => {
+                for (pattern, check) in &self.patterns {
+                    let replaced = pattern.replace_all(string, |captures: &Captures| {
+                        if check(&captures[0]) {
+                            REDACTED.to_owned()
+                        } else {
+                            captures[0].to_owned()
+                        }
+                    });
+                    if let std::borrow::Cow::Owned(replaced) = replaced {
+                        *string = replaced;
+                    }
+                }
+            }
+            Value::Array(values) => values.iter_mut().for_each(|value| self.scrub(value)),
+            Value::Object(values) => {
+                for (key, value) in values {
+                    if self.keys.contains(&key.to_lowercase()) {
+                        *value = Value::String(REDACTED.to_owned());
+                    } else {
+                        self.scrub(value);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Returns whether the digits of `number` pass the Luhn check of the credit
+/// card numbers.
+fn luhn(number: &str) -> bool {
+    let sum: u32 = number
+        .chars()
+        .rev()
+        .filter_map(|c| c.to_digit(10))
+        .enumerate()
+        .map(|(i, digit)| match (i % 2, digit * 2) {
+            (0, _) => digit,
+            (_, double) if double > 9 => double - 9,
+            (_, double) => double,
+        })
+        .sum();
+    sum.is_multiple_of(10)
+}
+
+impl Middleware for PiiScrubber {
+    fn process(&self, mut msg: BatchMessage) -> Option<BatchMessage> {
+        if let Some(properties) = msg.properties_mut() {
+            self.scrub(properties);
+        }
+        if let Some(Value::Object(context)) = msg.context_mut() {
+            if let Some(traits) = context.get_mut("traits") {
+                self.scrub(traits);
+            }
+        }
+        Some(msg)
+    }
+
+    fn name(&self) -> &str {
+        "PII scrubber"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::{Identify, Track};
+    use serde_json::json;
+
+    fn scrub(scrubber: &PiiScrubber, properties: Value) -> Value {
+        let msg = BatchMessage::Track(Track {
+            properties,
+            ..Default::default()
+        });
+        let BatchMessage::Track(track) = scrubber.process(msg).unwrap() else {
+            unreachable!()
+        };
+        track.properties
+    }
+
+    #[test]
+    fn test_patterns() {
+        let scrubber = PiiScrubber::with_defaults();
+        assert_eq!(
+            scrub(
+                &scrubber,
+                json!({
+                    "contact": "write to jane.doe+news@example.co.uk today",
+                    "phones": ["+33 6 12 34 56 78", "(555) 123-4567", "555.123.4567"],
+                    "card": "4111 1111 1111 1111",
+                    "timestamp": "at 1714557600123",
+                    "order": "4111-1111-1111-1112",
+                    "date": "2024-01-31",
+                    "count": 4111111111111111u64,
+                })
+            ),
+            json!({
+                "contact": "write to [REDACTED] today",
+                "phones": [REDACTED, REDACTED, REDACTED],
+                "card": REDACTED,
+                "timestamp": "at 1714557600123",
+                "order": "4111-1111-1111-1112",
+                "date": "2024-01-31",
+                "count": 4111111111111111u64,
+            })
+        );
+    }
+
+    #[test]
+    fn test_keys() {
+        let scrubber = PiiScrubber::new().key("Email").key("ssn");
+        assert_eq!(
+            scrub(
+                &scrubber,
+                json!({ "email": "a@b.co", "user": { "SSN": 123, "name": "Jane" } })
+            ),
+            json!({ "email": REDACTED, "user": { "SSN": REDACTED, "name": "Jane" } })
+        );
+
+        let msg = BatchMessage::Identify(Identify {
+            traits: json!({ "email": "a@b.co" }),
+            context: Some(json!({ "traits": { "email": "a@b.co" }, "ip": "1.2.3.4" })),
+            ..Default::default()
+        });
+        let BatchMessage::Identify(identify) = scrubber.process(msg).unwrap() else {
+            unreachable!()
+        };
+        assert_eq!(identify.traits, json!({ "email": REDACTED }));
+        assert_eq!(
+            identify.context,
+            Some(json!({ "traits": { "email": REDACTED }, "ip": "1.2.3.4" }))
+        );
+    }
+
+    #[test]
+    fn test_invalid_pattern() {
+        assert!(matches!(
+            PiiScrubber::new().pattern("("),
+            Err(Error::InvalidConfiguration(_))
+        ));
+    }
+}
@@ -0,0 +1,531 @@
+//! The order of the processing of the pushed messages, and the user-provided
+//! stages inserted in it.
+
+use std::fmt;
+use std::sync::Arc;
+
+use crate::hooks::Hook;
+use crate::message::{BatchMessage, Track};
+
+/// A built-in step of the processing of the messages by a
+/// [`Batcher`](crate::Batcher), which a [`Pipeline`] can reorder, see
+/// [`PipelineBuilder::order`].
+///
+/// By default a pushed message goes through the steps in the order they are
+/// declared here. The [`Validation`](crate::Validation) and the
+/// [`TrackingPlan`](crate::TrackingPlan), which reject the messages with an
+/// error, always run last, once the message is processed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Step {
+    /// The [`Rules`](crate::Rules). With
+    /// [`push_with`](crate::Batcher::push_with), the rules which read the
+    /// properties run once they are computed, at [Step::Properties].
+    Rules,
+    /// The [`EventFilter`](crate::EventFilter).
+    EventFilter,
+    /// The [`Sampling`](crate::Sampling).
+    Sampling,
+    /// The [`Deduplication`](crate::Deduplication).
+    Deduplication,
+    /// The [`EventLimit`](crate::EventLimit).
+    EventLimit,
+    /// The properties of [`push_with`](crate::Batcher::push_with) are
+    /// computed.
+    Properties,
+    /// The context and the integrations of the messages, and their timestamp.
+    Defaults,
+    /// The enrichers, see [`Batcher::add_enricher`](crate::Batcher::add_enricher).
+    Enrichers,
+    /// The `messageId`, see
+    /// [`Batcher::set_id_generator`](crate::Batcher::set_id_generator).
+    MessageId,
+    /// The [`UserIdHashing`](crate::UserIdHashing).
+    UserIdHashing,
+    /// The [`ConsentFilter`](crate::ConsentFilter).
+    Consent,
+    /// The flattening of the properties, see
+    /// [`FlattenOptions`](crate::FlattenOptions).
+    Flatten,
+    /// The normalization of the keys of the properties, see
+    /// [`KeyNormalization`](crate::KeyNormalization).
+    Normalization,
+    /// The [`SchemaTracker`](crate::SchemaTracker).
+    Schema,
+}
+
+impl Step {
+    /// The steps, in their default order.
+    const ALL: [Step; 14] = [
+        Step::Rules,
+        Step::EventFilter,
+        Step::Sampling,
+        Step::Deduplication,
+        Step::EventLimit,
+        Step::Properties,
+        Step::Defaults,
+        Step::Enrichers,
+        Step::MessageId,
+        Step::UserIdHashing,
+        Step::Consent,
+        Step::Flatten,
+        Step::Normalization,
+        Step::Schema,
+    ];
+}
+
+/// A point of the processing of the messages where the stages of a
+/// [`Pipeline`] can be inserted, besides right before or after any [`Step`],
+/// see [`Position`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[non_exhaustive]
+pub enum Stage {
+    /// Before any processing. The properties of
+    /// [`push_with`](crate::Batcher::push_with) are not computed yet.
+    Received,
+    /// Once the message passed the filters: after [Step::Properties].
+    Filtered,
+    /// Once the message is enriched: after [Step::Consent].
+    Enriched,
+    /// Right before the message is validated and batched, after every step.
+    Processed,
+}
+
+/// Where a stage is inserted in a [`Pipeline`]: at a [`Stage`], or right
+/// before or after a built-in [`Step`]. The stages inserted at the same
+/// position run in the order they were inserted.
+///
+/// ```
+/// use segment::{Pipeline, Position, Stage, Step};
+///
+/// let pipeline = Pipeline::builder()
+///     .stage(Stage::Received, "first", |_| true)
+///     .stage(Position::before(Step::Sampling), "before the sampling", |_| true)
+///     .build();
+/// ```
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Position(Anchor);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Anchor {
+    Start,
+    Before(Step),
+    /// The enrichers of the batcher, at [Step::Enrichers].
+    Enrichers,
+    After(Step),
+    End,
+}
+
+impl Position {
+    /// Right before `step`.
+    pub fn before(step: Step) -> Self {
+        Self(Anchor::Before(step))
+    }
+
+    /// Right after `step`.
+    pub fn after(step: Step) -> Self {
+        Self(Anchor::After(step))
+    }
+}
+
+impl From<Stage> for Position {
+    fn from(stage: Stage) -> Self {
+        Self(match stage {
+            Stage::Received => Anchor::Start,
+            Stage::Filtered => Anchor::After(Step::Properties),
+            Stage::Enriched => Anchor::After(Step::Consent),
+            Stage::Processed => Anchor::End,
+        })
+    }
+}
+
+impl fmt::Debug for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// A transformation of the pushed messages, inserted in a [`Pipeline`] with
+/// [`PipelineBuilder::middleware`], for instance to enrich, redact or filter
+/// them without forking the push path.
+///
+/// ```
+/// use segment::{Batcher, Middleware, Pipeline, Stage};
+/// use segment::message::{BatchMessage, Track};
+///
+/// struct DropInternal;
+///
+/// impl Middleware for DropInternal {
+///     fn process(&self, msg: BatchMessage) -> Option<BatchMessage> {
+///         match &msg {
+///             BatchMessage::Track(track) if track.event.starts_with("internal.") => None,
+///             _ => Some(msg),
+///         }
+///     }
+/// }
+///
+/// let mut batcher = Batcher::new(None);
+/// batcher.set_pipeline(Pipeline::builder().middleware(Stage::Filtered, DropInternal).build());
+/// batcher.push(Track { event: "internal.ping".to_owned(), ..Default::default() }).unwrap();
+/// assert!(batcher.is_empty());
+/// ```
+pub trait Middleware: Send + Sync {
+    /// Returns the transformed message, or `None` to drop it.
+    fn process(&self, msg: BatchMessage) -> Option<BatchMessage>;
+
+    /// The name of the middleware, reported by the [`Warning`](crate::Warning)s about it.
+    fn name(&self) -> &str {
+        std::any::type_name::<Self>()
+    }
+}
+
+/// What a [`Batcher`](crate::Batcher) runs on the pushed messages.
+#[derive(Clone, Debug)]
+pub(crate) enum Entry {
+    Step(Step),
+    /// Consecutive stages, each skipped if it fails, see `hooks::run`.
+    Hooks(Vec<Hook>),
+}
+
+/// The order of the built-in [`Step`]s, and the stages inserted between
+/// them, see [`Batcher::set_pipeline`](crate::Batcher::set_pipeline).
+///
+/// ```
+/// use segment::{Batcher, Pipeline, Stage, Step};
+/// use segment::message::{BatchMessage, Track};
+///
+/// let pipeline = Pipeline::builder()
+///     // drop the internal events before they are enriched
+///     .stage(Stage::Filtered, "internal events", |msg| {
+///         !matches!(msg, BatchMessage::Track(track) if track.event.starts_with("internal."))
+///     })
+///     // drop the messages without consent before sampling them
+///     .order([Step::Consent])
+///     .build();
+///
+/// let mut batcher = Batcher::new(None);
+/// batcher.set_pipeline(pipeline);
+/// batcher.push(Track { event: "internal.ping".to_owned(), ..Default::default() }).unwrap();
+/// assert!(batcher.is_empty());
+/// ```
+#[derive(Clone)]
+pub struct Pipeline {
+    order: Vec<Step>,
+    hooks: Vec<(Anchor, Hook)>,
+    /// The steps and the hooks, in the order they run.
+    entries: Arc<[Entry]>,
+}
+
+impl Pipeline {
+    /// Construct a new builder of [`Pipeline`].
+    pub fn builder() -> PipelineBuilder {
+        PipelineBuilder::default()
+    }
+
+    /// Returns the number of stages.
+    pub fn len(&self) -> usize {
+        self.hooks
+            .iter()
+            .filter(|(anchor, _)| *anchor != Anchor::Enrichers)
+            .count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the steps and the hooks, in the order they run.
+    pub(crate) fn entries(&self) -> Arc<[Entry]> {
+        self.entries.clone()
+    }
+
+    /// Add an enricher, run at [Step::Enrichers] after the previous ones.
+    pub(crate) fn add_enricher(&mut self, enricher: Hook) {
+        self.hooks.push((Anchor::Enrichers, enricher));
+        self.compile();
+    }
+
+    /// Returns this pipeline with the enrichers of `previous`.
+    pub(crate) fn with_enrichers_of(mut self, previous: &Pipeline) -> Self {
+        let enrichers = previous
+            .hooks
+            .iter()
+            .filter(|(anchor, _)| *anchor == Anchor::Enrichers);
+        self.hooks.extend(enrichers.cloned());
+        self.compile();
+        self
+    }
+
+    /// Lay out the steps and the hooks in the order they run.
+    fn compile(&mut self) {
+        let at = |anchor| {
+            self.hooks
+                .iter()
+                .filter(move |(at, _)| *at == anchor)
+                .map(|(_, hook)| hook.clone())
+        };
+        let mut entries = Vec::new();
+        let mut pending: Vec<Hook> = at(Anchor::Start).collect();
+        for &step in &self.order {
+            pending.extend(at(Anchor::Before(step)));
+            if step == Step::Enrichers {
+                pending.extend(at(Anchor::Enrichers));
+            } else {
+                if !pending.is_empty() {
+                    entries.push(Entry::Hooks(std::mem::take(&mut pending)));
+                }
+                entries.push(Entry::Step(step));
+            }
+            pending.extend(at(Anchor::After(step)));
+        }
+        pending.extend(at(Anchor::End));
+        if !pending.is_empty() {
+            entries.push(Entry::Hooks(pending));
+        }
+        self.entries = entries.into();
+    }
+}
+
+impl Default for Pipeline {
+    fn default() -> Self {
+        Pipeline::builder().build()
+    }
+}
+
+impl fmt::Debug for Pipeline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let stages: Vec<_> = self
+            .hooks
+            .iter()
+            .map(|(anchor, hook)| (Position(*anchor), &hook.name))
+            .collect();
+        f.debug_struct("Pipeline")
+            .field("order", &self.order)
+            .field("stages", &stages)
+            .finish()
+    }
+}
+
+/// A builder of [`Pipeline`], see [`Pipeline::builder`].
+#[derive(Clone, Debug)]
+pub struct PipelineBuilder {
+    order: Vec<Step>,
+    hooks: Vec<(Anchor, Hook)>,
+}
+
+impl Default for PipelineBuilder {
+    fn default() -> Self {
+        Self {
+            order: Step::ALL.to_vec(),
+            hooks: Vec::new(),
+        }
+    }
+}
+
+impl PipelineBuilder {
+    /// Run the built-in steps of `order` first, in this order, then the
+    /// other ones in their default order.
+    ///
+    /// The stages inserted before or after a step, or at a [`Stage`] defined
+    /// by a step, move with it.
+    pub fn order(mut self, order: impl IntoIterator<Item = Step>) -> Self {
+        let mut steps = Vec::with_capacity(Step::ALL.len());
+        for step in order.into_iter().chain(Step::ALL) {
+            if !steps.contains(&step) {
+                steps.push(step);
+            }
+        }
+        self.order = steps;
+        self
+    }
+
+    /// Run `run` on every message reaching `position`, after the stages
+    /// previously inserted there. The message is dropped if it returns
+    /// `false`, and reported as accepted by [`Batcher::push`](crate::Batcher::push).
+    ///
+    /// A stage which panicked or corrupted a reserved field is skipped: its
+    /// changes are reverted, and a
+    /// [`Warning::HookSkipped`](crate::Warning::HookSkipped) is reported. The
+    /// next stages run on the message as left by the previous ones.
+    pub fn stage(
+        mut self,
+        position: impl Into<Position>,
+        name: impl Into<String>,
+        run: impl Fn(&mut BatchMessage) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        let Position(anchor) = position.into();
+        self.hooks.push((anchor, Hook::new(name.into(), run)));
+        self
+    }
+
+    /// Run `middleware` on every message reaching `position`, after the
+    /// stages previously inserted there, see [`Middleware`]. The message is
+    /// dropped if it returns `None`, and reported as accepted by
+    /// [`Batcher::push`](crate::Batcher::push).
+    pub fn middleware(
+        self,
+        position: impl Into<Position>,
+        middleware: impl Middleware + 'static,
+    ) -> Self {
+        let name = middleware.name().to_owned();
+        self.stage(position, name, move |msg| {
+            // the message is restored by the pipeline if the middleware panics
+            let owned = std::mem::replace(msg, BatchMessage::Track(Track::default()));
+            match middleware.process(owned) {
+                Some(processed) => {
+                    *msg = processed;
+                    true
+                }
+                None => false,
+            }
+        })
+    }
+
+    pub fn build(self) -> Pipeline {
+        let mut pipeline = Pipeline {
+            order: self.order,
+            hooks: self.hooks,
+            entries: Arc::new([]),
+        };
+        pipeline.compile();
+        pipeline
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Batcher, ConsentAction, ConsentFilter, Sampling, Warning};
+    use serde_json::json;
+    use serde_json::value::RawValue;
+    use std::sync::Mutex;
+
+    fn track(event: &str) -> Track {
+        Track {
+            event: event.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_order() {
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let record = |name: &'static str| {
+            let order = order.clone();
+            move |_: &mut BatchMessage| {
+                order.lock().unwrap().push(name);
+                true
+            }
+        };
+        let pipeline = Pipeline::builder()
+            .stage(Stage::Processed, "processed", record("processed"))
+            .stage(Stage::Received, "first", record("first"))
+            .stage(
+                Position::before(Step::Sampling),
+                "sampling",
+                record("sampling"),
+            )
+            .stage(Stage::Received, "second", record("second"))
+            .build();
+        assert_eq!(pipeline.len(), 4);
+
+        let mut batcher = Batcher::new(None);
+        let enrichers = order.clone();
+        batcher.add_enricher("enricher", move |_| {
+            enrichers.lock().unwrap().push("enricher");
+        });
+        batcher.set_pipeline(pipeline);
+        batcher.push(track("Signed Up")).unwrap();
+        assert_eq!(batcher.len(), 1);
+        assert_eq!(
+            *order.lock().unwrap(),
+            ["first", "second", "sampling", "enricher", "processed"]
+        );
+    }
+
+    #[test]
+    fn test_reorder() {
+        let sampling = Sampling::new()
+            .event("Ad Clicked", 0.0)
+            .unwrap()
+            .event("Ad Viewed", 0.0)
+            .unwrap();
+        let consent =
+            ConsentFilter::new().event("Ad Clicked", ["Advertising"], ConsentAction::Drop);
+        let mut batcher = Batcher::new(None);
+        batcher.set_sampling(sampling.clone());
+        batcher.set_consent_filter(consent.clone());
+        batcher.set_pipeline(Pipeline::builder().order([Step::Consent]).build());
+
+        batcher.push(track("Ad Clicked")).unwrap();
+        batcher.push(track("Ad Viewed")).unwrap();
+        assert!(batcher.is_empty());
+        // the consent is checked before the sampling
+        assert_eq!((consent.dropped(), sampling.dropped()), (1, 1));
+    }
+
+    #[test]
+    fn test_drop_and_skip() {
+        let mut batcher = Batcher::new(None);
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let sink = received.clone();
+        batcher.set_warning_handler(move |warning| sink.lock().unwrap().push(warning.clone()));
+        batcher.set_pipeline(
+            Pipeline::builder()
+                .stage(Stage::Received, "buggy", |msg| {
+                    msg.extra_mut().insert("half".to_owned(), json!("done"));
+                    panic!("buggy stage")
+                })
+                .stage(Stage::Received, "sampling", |msg| {
+                    msg.event_name() != Some("Dropped")
+                })
+                .build(),
+        );
+
+        batcher.push(track("Kept")).unwrap();
+        assert_eq!(batcher.len(), 1);
+        assert!(batcher.iter().all(|msg| !msg.get().contains("half")));
+        assert!(matches!(
+            &received.lock().unwrap()[..],
+            [Warning::HookSkipped { hook, .. }] if hook == "buggy"
+        ));
+
+        batcher.push(track("Dropped")).unwrap();
+        assert_eq!(batcher.len(), 1);
+    }
+
+    #[test]
+    fn test_middleware() {
+        struct Rename;
+
+        impl Middleware for Rename {
+            fn process(&self, msg: BatchMessage) -> Option<BatchMessage> {
+                match msg {
+                    BatchMessage::Track(track) if track.event == "Dropped" => None,
+                    BatchMessage::Track(track) => Some(BatchMessage::Track(Track {
+                        event: track.event.to_lowercase(),
+                        ..track
+                    })),
+                    msg => Some(msg),
+                }
+            }
+
+            fn name(&self) -> &str {
+                "rename"
+            }
+        }
+
+        let pipeline = Pipeline::builder()
+            .middleware(Stage::Enriched, Rename)
+            .build();
+        assert!(format!("{pipeline:?}").contains(r#"stages: [(After(Consent), "rename")]"#));
+
+        let mut batcher = Batcher::new(None);
+        batcher.set_pipeline(pipeline);
+        batcher.push(track("Signed Up")).unwrap();
+        batcher.push(track("Dropped")).unwrap();
+        let events: Vec<_> = batcher.iter().map(RawValue::get).collect();
+        assert_eq!(events.len(), 1);
+        assert!(events[0].contains(r#""event":"signed up""#));
+    }
+}
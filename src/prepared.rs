@@ -0,0 +1,95 @@
+//! Batches held by an [`AutoBatcher`] until the surrounding transaction
+//! commits, or rolls back.
+
+use serde_json::value::RawValue;
+
+use crate::batcher::Admission;
+use crate::logging;
+use crate::message::{self, SerializedBatch};
+use crate::Batcher;
+
+#[cfg(doc)]
+use crate::AutoBatcher;
+
+/// The messages of a transaction, returned empty by
+/// [`AutoBatcher::flush_prepared`] and filled by
+/// [`AutoBatcher::push_prepared`], held until they are sent by
+/// [`AutoBatcher::commit`] or discarded by [`AutoBatcher::rollback`].
+///
+/// The prepared messages are kept apart from the buffer of the batcher, they
+/// are never sent before the commit, however many they are.
+///
+/// Dropping a prepared batch discards its messages, with a warning.
+#[derive(Debug)]
+#[must_use = "a prepared batch must be committed or rolled back"]
+pub struct PreparedBatch {
+    /// The full batches.
+    batches: Vec<SerializedBatch>,
+    /// Batches the messages with the limits of the batcher.
+    batcher: Batcher,
+    /// What the messages change in the state of the batcher once committed.
+    admissions: Vec<Admission>,
+}
+
+impl PreparedBatch {
+    pub(crate) fn new(batcher: Batcher) -> Self {
+        Self {
+            batches: Vec::new(),
+            batcher,
+            admissions: Vec::new(),
+        }
+    }
+
+    /// Returns the number of prepared messages.
+    pub fn len(&self) -> usize {
+        self.batches.iter().map(SerializedBatch::len).sum::<usize>() + self.batcher.len()
+    }
+
+    /// Returns whether no message is prepared.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the `messageId` of every prepared message that has one, for
+    /// instance to record them in the transaction.
+    pub fn message_ids(&self) -> Vec<String> {
+        self.batches
+            .iter()
+            .flat_map(SerializedBatch::message_ids)
+            .chain(self.batcher.iter().filter_map(message::message_id))
+            .collect()
+    }
+
+    /// Add a processed message.
+    pub(crate) fn push(&mut self, msg: Box<RawValue>, admission: Admission) {
+        if let Some(msg) = self.batcher.enqueue(msg) {
+            self.batches.push(self.batcher.take());
+            // the batcher is empty, the message fits
+            self.batcher.enqueue(msg);
+        }
+        self.admissions.push(admission);
+    }
+
+    /// Take the batches, and the admissions of their messages, settling the
+    /// token.
+    pub(crate) fn take(&mut self) -> (Vec<SerializedBatch>, Vec<Admission>) {
+        let mut batches = std::mem::take(&mut self.batches);
+        if !self.batcher.is_empty() {
+            batches.push(self.batcher.take());
+        }
+        (batches, std::mem::take(&mut self.admissions))
+    }
+}
+
+impl Drop for PreparedBatch {
+    fn drop(&mut self) {
+        let (batches, _) = self.take();
+        let lost: usize = batches.iter().map(SerializedBatch::len).sum();
+        if lost > 0 {
+            logging::warn!(
+                lost,
+                "prepared batch dropped, call `commit` or `rollback` before dropping it"
+            );
+        }
+    }
+}
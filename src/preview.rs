@@ -0,0 +1,194 @@
+//! Previews of what Segment receives, to debug the tracking.
+
+use std::collections::BTreeMap;
+
+use serde_json::Value;
+
+use crate::{batcher::Batcher, errors::Result, message::BatchMessage};
+
+/// What would be sent for a message, see [`Batcher::preview`].
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub struct Preview {
+    /// The payload of the message once processed by the batcher (redacted,
+    /// flattened, enriched...), or `None` if it would be dropped.
+    pub payload: Option<Value>,
+
+    /// Whether each of the destinations would receive the message, according
+    /// to the `integrations` of the message and of the batch.
+    pub destinations: BTreeMap<String, bool>,
+}
+
+impl Batcher {
+    /// Returns what would be sent for `msg`, and which of the `destinations`
+    /// would receive it, without pushing it.
+    ///
+    /// The message goes through the whole processing of the batcher, on a
+    /// copy of its state: previewing a message doesn't count it in the
+    /// deduplication, the event limit, the schemas nor the counters of the
+    /// filters, and its warnings are only logged, not passed to the
+    /// [handler](Batcher::set_warning_handler).
+    ///
+    /// Returns an error if the message would be rejected.
+    ///
+    /// ```
+    /// use segment::Batcher;
+    /// use segment::message::Track;
+    /// use serde_json::json;
+    ///
+    /// let batcher = Batcher::new(None);
+    /// let msg = Track {
+    ///     event: "Signed Up".to_owned(),
+    ///     integrations: Some(json!({ "All": false, "Amplitude": true })),
+    ///     ..Default::default()
+    /// };
+    ///
+    /// let preview = batcher.preview(msg, ["Amplitude", "Mixpanel"]).unwrap();
+    /// assert_eq!(preview.destinations["Amplitude"], true);
+    /// assert_eq!(preview.destinations["Mixpanel"], false);
+    /// ```
+    pub fn preview<'a>(
+        &self,
+        msg: impl Into<BatchMessage>,
+        destinations: impl IntoIterator<Item = &'a str>,
+    ) -> Result<Preview> {
+        let mut batcher = self.detached();
+        let payload = match batcher.process(msg.into()) {
+            Some((msg, _)) => {
+                let raw = batcher.serialize(&msg)?;
+                Some(serde_json::from_str(raw.get())?)
+            }
+            None => None,
+        };
+
+        let integrations = payload
+            .as_ref()
+            .and_then(|payload: &Value| payload.get("integrations"));
+        let destinations = destinations
+            .into_iter()
+            .map(|name| {
+                let enabled = payload.is_some()
+                    && is_enabled(integrations, name)
+                        .or_else(|| is_enabled(self.integrations.as_ref(), name))
+                        .unwrap_or(true);
+                (name.to_owned(), enabled)
+            })
+            .collect();
+
+        Ok(Preview {
+            payload,
+            destinations,
+        })
+    }
+}
+
+/// Returns whether the `integrations` settings enable the destination, or
+/// `None` if they don't say.
+fn is_enabled(integrations: Option<&Value>, destination: &str) -> Option<bool> {
+    let integrations = integrations?;
+    let enabled = |setting: &Value| match setting {
+        Value::Bool(enabled) => Some(*enabled),
+        // the options of the destination
+        Value::Object(_) => Some(true),
+        _ => None,
+    };
+    integrations
+        .get(destination)
+        .and_then(enabled)
+        .or_else(|| integrations.get("All").and_then(enabled))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::Track;
+    use crate::Rules;
+    use serde_json::json;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_preview() {
+        let mut batcher = Batcher::new(None);
+        batcher
+            .set_integrations(Some(json!({ "Mixpanel": false })))
+            .unwrap();
+        batcher.set_rules(
+            Rules::from_json(
+                r#"[
+                    { "match": { "event": "debug" }, "action": "drop" },
+                    { "action": "redact", "properties": ["email"] }
+                ]"#,
+            )
+            .unwrap(),
+        );
+
+        let msg = Track {
+            event: "Signed Up".to_owned(),
+            properties: json!({ "email": "a@b.c" }),
+            integrations: Some(json!({ "Amplitude": { "session_id": 1 } })),
+            ..Default::default()
+        };
+        let preview = batcher
+            .preview(msg, ["Amplitude", "Mixpanel", "Intercom"])
+            .unwrap();
+        let payload = preview.payload.unwrap();
+        assert_eq!(payload["properties"], json!({ "email": crate::REDACTED }));
+        assert_eq!(
+            preview.destinations,
+            BTreeMap::from([
+                ("Amplitude".to_owned(), true),
+                ("Intercom".to_owned(), true),
+                ("Mixpanel".to_owned(), false),
+            ])
+        );
+        assert!(batcher.is_empty());
+
+        let msg = Track {
+            event: "debug".to_owned(),
+            ..Default::default()
+        };
+        let preview = batcher.preview(msg, ["Amplitude"]).unwrap();
+        assert!(preview.payload.is_none());
+        assert!(!preview.destinations["Amplitude"]);
+    }
+
+    #[test]
+    fn test_preview_is_detached() {
+        let schemas = crate::SchemaTracker::default();
+        let filter = crate::EventFilter::deny(["debug"]);
+        let warned = Arc::new(AtomicUsize::new(0));
+        let mut batcher = Batcher::new(None);
+        batcher.set_schema_tracker(schemas.clone());
+        batcher.set_event_filter(filter.clone());
+        let counter = warned.clone();
+        batcher.set_warning_handler(move |_| {
+            counter.fetch_add(1, Ordering::Relaxed);
+        });
+        batcher
+            .push(Track {
+                event: "Signed Up".to_owned(),
+                properties: json!({ "plan": "pro" }),
+                ..Default::default()
+            })
+            .unwrap();
+
+        // a drift of the type of `plan`
+        let msg = Track {
+            event: "Signed Up".to_owned(),
+            properties: json!({ "plan": 1, "seats": 2 }),
+            ..Default::default()
+        };
+        batcher.preview(msg, ["Amplitude"]).unwrap();
+        let msg = Track {
+            event: "debug".to_owned(),
+            ..Default::default()
+        };
+        batcher.preview(msg, ["Amplitude"]).unwrap();
+
+        assert_eq!(schemas.event("Signed Up").unwrap().count, 1);
+        assert_eq!(filter.dropped(), 0);
+        assert_eq!(warned.load(Ordering::Relaxed), 0);
+        assert_eq!(batcher.len(), 1);
+    }
+}
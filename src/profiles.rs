@@ -0,0 +1,300 @@
+//! A client of Segment's [Profile API](https://segment.com/docs/unify/profile-api/),
+//! reading back the traits, the external ids and the events of the unified
+//! profiles of a space.
+//!
+//! The profiles are identified by an external id, as `<type>:<value>`, like
+//! `user_id:ada` or `email:ada@example.com`.
+//!
+//! ```no_run
+//! use segment::profiles::ProfilesClient;
+//!
+//! # async fn run() -> segment::Result<()> {
+//! let client = ProfilesClient::new("your_space_id".to_owned(), "your_access_token".to_owned());
+//!
+//! let traits = client.traits("user_id:ada").await?;
+//! println!("plan: {:?}", traits.get("plan"));
+//! # Ok(())
+//! # }
+//! ```
+
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use time::OffsetDateTime;
+
+use crate::errors::Result;
+use crate::http;
+
+/// The host of the Profile API.
+const PROFILE_API: &str = "https://profiles.segment.com";
+
+/// The largest page the Profile API returns.
+const MAX_PAGE: usize = 200;
+
+/// A client of the profiles of a space, authenticated with an access token
+/// of the space.
+#[derive(Clone, Debug)]
+pub struct ProfilesClient {
+    client: reqwest::Client,
+    host: String,
+    space_id: String,
+    token: String,
+}
+
+/// An identifier of a profile, as returned by
+/// [`ProfilesClient::external_ids`].
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[non_exhaustive]
+pub struct ExternalId {
+    /// The value of the identifier, like `ada@example.com`.
+    pub id: String,
+
+    /// The type of the identifier, like `email`.
+    #[serde(rename = "type")]
+    pub id_type: String,
+
+    /// The source which first sent the identifier.
+    #[serde(default)]
+    pub source_id: Option<String>,
+
+    /// When the identifier was first seen.
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub created_at: Option<OffsetDateTime>,
+}
+
+/// A page of results, and the cursor of the next one.
+#[derive(Deserialize)]
+struct Page<T> {
+    #[serde(alias = "traits")]
+    data: T,
+    #[serde(default)]
+    cursor: Option<Cursor>,
+}
+
+#[derive(Deserialize)]
+struct Cursor {
+    #[serde(default)]
+    has_more: bool,
+    #[serde(default)]
+    next: Option<String>,
+}
+
+impl<T> Page<T> {
+    /// Returns the cursor of the next page, if there is one.
+    fn next(&self) -> Option<&str> {
+        self.cursor
+            .as_ref()
+            .filter(|cursor| cursor.has_more)
+            .and_then(|cursor| cursor.next.as_deref())
+            .filter(|next| !next.is_empty())
+    }
+}
+
+impl ProfilesClient {
+    /// Construct a client of the profiles of the space `space_id`,
+    /// authenticated with the access `token` of the space.
+    pub fn new(space_id: String, token: String) -> Self {
+        Self::with_host(
+            reqwest::Client::new(),
+            PROFILE_API.to_owned(),
+            space_id,
+            token,
+        )
+    }
+
+    /// Construct a client of the Profile API at `host`, like the one of the
+    /// EU region, `https://profiles.euw1.segment.com`.
+    pub fn with_host(
+        client: reqwest::Client,
+        host: String,
+        space_id: String,
+        token: String,
+    ) -> Self {
+        Self {
+            client,
+            host,
+            space_id,
+            token,
+        }
+    }
+
+    /// Returns all the traits of the profile `external_id`.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    pub async fn traits(&self, external_id: &str) -> Result<Map<String, Value>> {
+        let mut traits = Map::new();
+        let mut next = None;
+        loop {
+            let page: Page<Map<String, Value>> = self
+                .get(external_id, "traits", MAX_PAGE, next.as_deref())
+                .await?;
+            next = page.next().map(str::to_owned);
+            traits.extend(page.data);
+            if next.is_none() {
+                return Ok(traits);
+            }
+        }
+    }
+
+    /// Returns all the external ids of the profile `external_id`.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    pub async fn external_ids(&self, external_id: &str) -> Result<Vec<ExternalId>> {
+        self.list(external_id, "external_ids", usize::MAX).await
+    }
+
+    /// Returns up to `limit` of the latest events of the profile
+    /// `external_id`, as returned by the Profile API.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    pub async fn events(&self, external_id: &str, limit: usize) -> Result<Vec<Value>> {
+        self.list(external_id, "events", limit).await
+    }
+
+    /// Fetch the pages of `resource` until `limit` items are fetched, or
+    /// there are no more pages.
+    async fn list<T: DeserializeOwned>(
+        &self,
+        external_id: &str,
+        resource: &str,
+        limit: usize,
+    ) -> Result<Vec<T>> {
+        let mut items = Vec::new();
+        let mut next = None;
+        while items.len() < limit {
+            let page_size = (limit - items.len()).min(MAX_PAGE);
+            let page: Page<Vec<T>> = self
+                .get(external_id, resource, page_size, next.as_deref())
+                .await?;
+            next = page.next().map(str::to_owned);
+            items.extend(page.data);
+            if next.is_none() {
+                break;
+            }
+        }
+        items.truncate(limit);
+        Ok(items)
+    }
+
+    async fn get<T: DeserializeOwned>(
+        &self,
+        external_id: &str,
+        resource: &str,
+        limit: usize,
+        next: Option<&str>,
+    ) -> Result<T> {
+        let url = http::endpoint(
+            &self.host,
+            [
+                "v1",
+                "spaces",
+                &self.space_id,
+                "collections",
+                "users",
+                "profiles",
+                external_id,
+                resource,
+            ],
+        )?;
+        let mut request = self
+            .client
+            .get(url)
+            .basic_auth(&self.token, Some(""))
+            .query(&[("limit", limit.to_string())]);
+        if let Some(next) = next {
+            request = request.query(&[("next", next)]);
+        }
+        Ok(request.send().await?.error_for_status()?.json().await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tokio::sync::mpsc;
+
+    use crate::testing::{json_response, serve};
+
+    /// Serve the `bodies` in turn.
+    async fn serve_json(bodies: Vec<Value>) -> (String, mpsc::UnboundedReceiver<String>) {
+        serve(
+            bodies
+                .iter()
+                .map(|body| json_response(&body.to_string()))
+                .collect(),
+        )
+        .await
+    }
+
+    /// Returns the request line of the next request received.
+    async fn request_line(requests: &mut mpsc::UnboundedReceiver<String>) -> String {
+        let request = requests.recv().await.unwrap();
+        request.lines().next().unwrap().to_owned()
+    }
+
+    fn client(host: String) -> ProfilesClient {
+        ProfilesClient::with_host(
+            reqwest::Client::new(),
+            host,
+            "space".to_owned(),
+            "token".to_owned(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_traits() {
+        let (host, mut requests) = serve_json(vec![
+            json!({
+                "traits": { "plan": "pro" },
+                "cursor": { "has_more": true, "next": "seats" },
+            }),
+            json!({
+                "traits": { "seats": 3 },
+                "cursor": { "has_more": false, "next": "" },
+            }),
+        ])
+        .await;
+
+        let traits = client(host).traits("user_id:ada").await.unwrap();
+        assert_eq!(Value::from(traits), json!({ "plan": "pro", "seats": 3 }));
+        assert_eq!(
+            request_line(&mut requests).await,
+            "GET /v1/spaces/space/collections/users/profiles/user_id:ada/traits?limit=200 HTTP/1.1"
+        );
+        assert_eq!(
+            request_line(&mut requests).await,
+            "GET /v1/spaces/space/collections/users/profiles/user_id:ada/traits?limit=200&next=seats HTTP/1.1"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_lists() {
+        let (host, mut requests) = serve_json(vec![
+            json!({
+                "data": [{
+                    "id": "ada@example.com",
+                    "type": "email",
+                    "source_id": "source",
+                    "created_at": "2024-05-01T10:00:00Z",
+                }],
+            }),
+            json!({
+                "data": [{ "event": "Signed Up" }, { "event": "Logged In" }],
+                "cursor": { "has_more": true, "next": "more" },
+            }),
+        ])
+        .await;
+        let client = client(host);
+
+        let ids = client.external_ids("user_id:ada").await.unwrap();
+        assert_eq!(ids.len(), 1);
+        assert_eq!(ids[0].id_type, "email");
+        assert!(ids[0].created_at.is_some());
+        requests.recv().await.unwrap();
+
+        let events = client.events("email:a/b?c#d", 2).await.unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(
+            request_line(&mut requests).await,
+            "GET /v1/spaces/space/collections/users/profiles/email:a%2Fb%3Fc%23d/events?limit=2 HTTP/1.1"
+        );
+    }
+}
@@ -0,0 +1,113 @@
+//! Timing hooks around the phases of the pipeline, so that its performance
+//! can be monitored in production rather than only in benchmarks.
+//!
+//! A [`ProfileSink`] set with
+//! [`AutoBatcher::set_profiler`](crate::AutoBatcher::set_profiler) receives
+//! the duration of every phase, for instance to feed a histogram.
+
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// A phase of the pipeline timed by a [`ProfileSink`].
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+#[non_exhaustive]
+pub enum Phase {
+    /// Preparing a pushed message for the batch: the hooks, the rules and the
+    /// validation of the [`Batcher`](crate::Batcher).
+    Batch,
+
+    /// Serializing a pushed message into the batch.
+    Serialize,
+
+    /// Sending a batch to Segment, once per attempt.
+    Send,
+}
+
+impl Phase {
+    /// Returns the name of the phase, in snake case.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Batch => "batch",
+            Self::Serialize => "serialize",
+            Self::Send => "send",
+        }
+    }
+}
+
+/// Receives the duration of each [`Phase`] of the pipeline.
+///
+/// It is called inline, on the hot path, and must therefore return quickly.
+/// It is implemented for the closures taking a [`Phase`] and a [`Duration`].
+///
+/// ```
+/// use std::time::Duration;
+/// use segment::{AutoBatcher, Batcher, HttpClient};
+/// use segment::profiling::Phase;
+///
+/// let client = HttpClient::default();
+/// let mut batcher = AutoBatcher::new(client, Batcher::new(None), "your_write_key".to_string());
+/// batcher.set_profiler(|phase: Phase, elapsed: Duration| {
+///     println!("{} took {elapsed:?}", phase.as_str());
+/// });
+/// ```
+pub trait ProfileSink: Send + Sync {
+    /// Record that `phase` took `elapsed`.
+    fn record(&self, phase: Phase, elapsed: Duration);
+}
+
+impl<F> ProfileSink for F
+where
+    F: Fn(Phase, Duration) + Send + Sync,
+{
+    fn record(&self, phase: Phase, elapsed: Duration) {
+        self(phase, elapsed)
+    }
+}
+
+/// Times the phases into a [`ProfileSink`].
+#[derive(Clone)]
+pub(crate) struct Profiler(pub(crate) Arc<dyn ProfileSink>);
+
+/// Record the time elapsed since `start` as `phase`, if a profiler is set.
+pub(crate) fn record(profiler: &Option<Profiler>, phase: Phase, start: Instant) {
+    if let Some(profiler) = profiler {
+        profiler.0.record(phase, start.elapsed());
+    }
+}
+
+impl fmt::Debug for Profiler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Profiler").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::Track;
+    use crate::testing::MockClient;
+    use crate::{AutoBatcher, Batcher};
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_profiler() {
+        let phases = Arc::new(Mutex::new(HashMap::<Phase, usize>::new()));
+        let client = MockClient::default();
+        let mut batcher = AutoBatcher::new(client, Batcher::new(None), "key".to_owned());
+        let recorded = phases.clone();
+        batcher.set_profiler(move |phase, _| {
+            *recorded.lock().unwrap().entry(phase).or_default() += 1;
+        });
+
+        batcher.push(Track::default()).await.unwrap();
+        batcher.push(Track::default()).await.unwrap();
+        batcher.flush().await.unwrap();
+
+        let phases = phases.lock().unwrap();
+        assert_eq!(phases[&Phase::Batch], 2);
+        assert_eq!(phases[&Phase::Serialize], 2);
+        assert_eq!(phases[&Phase::Send], 1);
+    }
+}
@@ -0,0 +1,167 @@
+//! Pseudonymization of the user IDs before they are sent to Segment.
+
+use std::fmt;
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::message::{BatchMessage, User};
+
+/// Replaces the user IDs of the pushed messages with their keyed hash, for
+/// the products which must not send raw identifiers to Segment.
+///
+/// The hash is the lowercase hexadecimal HMAC-SHA256 of the user ID, keyed
+/// with the salt: the same user ID always yields the same pseudonym, so the
+/// events of a user are still linked together. The anonymous IDs are left as
+/// is, while the `previousId` of the `alias` messages is hashed too, as it
+/// usually is a user ID.
+///
+/// Every pushed message is hashed, whatever its content. The messages
+/// already processed, like the undelivered ones returned by
+/// [`AutoBatcher::shutdown`](crate::AutoBatcher::shutdown), are therefore
+/// sent again with [`AutoBatcher::requeue`](crate::AutoBatcher::requeue),
+/// which doesn't hash them twice. The message returned by a full
+/// [`Batcher::push`](crate::Batcher::push) is not processed yet, it is
+/// pushed again.
+///
+/// See [`Batcher::set_user_id_hashing`](crate::Batcher::set_user_id_hashing).
+///
+/// ```
+/// use segment::{Batcher, UserIdHashing};
+///
+/// let hashing = UserIdHashing::new("some secret salt");
+/// assert_eq!(hashing.hash("user-42"), hashing.hash("user-42"));
+///
+/// let mut batcher = Batcher::new(None);
+/// batcher.set_user_id_hashing(hashing);
+/// ```
+#[derive(Clone)]
+pub struct UserIdHashing {
+    salt: String,
+}
+
+impl UserIdHashing {
+    /// Construct a hashing of the user IDs with `salt`.
+    ///
+    /// The salt must stay the same across restarts and replicas for the
+    /// pseudonyms to be stable, and secret for them not to be reversed by
+    /// hashing candidate IDs.
+    pub fn new(salt: impl Into<String>) -> Self {
+        Self { salt: salt.into() }
+    }
+
+    /// Returns the pseudonym of `user_id`, for instance to look up the events
+    /// of a user in the warehouse.
+    pub fn hash(&self, user_id: &str) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.salt.as_bytes())
+            .expect("HMAC accepts keys of any size");
+        mac.update(user_id.as_bytes());
+        let digest = mac.finalize().into_bytes();
+        digest.iter().map(|byte| format!("{byte:02x}")).collect()
+    }
+
+    pub(crate) fn apply(&self, msg: &mut BatchMessage) {
+        match msg.user_mut() {
+            User::UserId { user_id } | User::Both { user_id, .. } => {
+                *user_id = self.hash(user_id);
+            }
+            User::AnonymousId { .. } => {}
+        }
+        if let BatchMessage::Alias(alias) = msg {
+            alias.previous_id = self.hash(&alias.previous_id);
+        }
+    }
+}
+
+impl fmt::Debug for UserIdHashing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UserIdHashing").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::{Alias, Track};
+    use crate::Batcher;
+
+    #[test]
+    fn test_hash() {
+        let hashing = UserIdHashing::new("salt");
+        let hash = hashing.hash("user");
+        assert_eq!(hash.len(), 64);
+        assert_eq!(hash, hashing.hash("user"));
+        assert_ne!(hash, hashing.hash("another user"));
+        assert_ne!(hash, UserIdHashing::new("pepper").hash("user"));
+        assert!(!format!("{hashing:?}").contains("salt"));
+    }
+
+    #[test]
+    fn test_push() {
+        let hashing = UserIdHashing::new("salt");
+        let mut batcher = Batcher::new(None);
+        batcher.set_user_id_hashing(hashing.clone());
+
+        for user in [
+            User::UserId {
+                user_id: "user".to_owned(),
+            },
+            User::Both {
+                user_id: "user".to_owned(),
+                anonymous_id: "anonymous".to_owned(),
+            },
+            User::AnonymousId {
+                anonymous_id: "anonymous".to_owned(),
+            },
+        ] {
+            batcher
+                .push(Track {
+                    user,
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+        batcher
+            .push(Alias {
+                user: User::UserId {
+                    user_id: "user".to_owned(),
+                },
+                previous_id: "previous".to_owned(),
+                ..Default::default()
+            })
+            .unwrap();
+
+        let pushed: Vec<BatchMessage> = batcher
+            .iter()
+            .map(|raw| serde_json::from_str(raw.get()).unwrap())
+            .collect();
+        let hashed = hashing.hash("user");
+        assert!(
+            matches!(&pushed[0], BatchMessage::Track(Track { user: User::UserId { user_id }, .. }) if *user_id == hashed)
+        );
+        assert!(
+            matches!(&pushed[1], BatchMessage::Track(Track { user: User::Both { user_id, anonymous_id }, .. }) if *user_id == hashed && anonymous_id == "anonymous")
+        );
+        assert!(
+            matches!(&pushed[2], BatchMessage::Track(Track { user: User::AnonymousId { anonymous_id }, .. }) if anonymous_id == "anonymous")
+        );
+        let BatchMessage::Alias(alias) = &pushed[3] else {
+            panic!("expected an alias");
+        };
+        assert_eq!(alias.previous_id, hashing.hash("previous"));
+
+        // no flag in the payload skips the hashing
+        batcher
+            .push(Track {
+                user: User::UserId {
+                    user_id: "user".to_owned(),
+                },
+                context: Some(serde_json::json!({ "pseudonymized": true })),
+                ..Default::default()
+            })
+            .unwrap();
+        let raw = batcher.iter().last().unwrap().get();
+        assert!(raw.contains(&hashed));
+        assert!(!raw.contains(r#""userId":"user""#));
+    }
+}
@@ -0,0 +1,423 @@
+//! A bounded queue of messages applying an [`OverflowPolicy`].
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::Notify;
+
+use crate::errors::{Error, Result};
+use crate::message::BatchMessage;
+use crate::stats::Counters;
+
+/// What to do with a new message when the buffer or queue is full.
+///
+/// Web servers usually prefer to drop messages rather than to slow down the
+/// requests, while batch jobs prefer to wait. The dropped messages are
+/// counted in [`Stats::dropped`](crate::Stats::dropped).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default, serde::Serialize)]
+pub enum OverflowPolicy {
+    /// Wait until there is some room.
+    #[default]
+    Block,
+
+    /// Drop the oldest buffered messages to make some room. With the
+    /// [`Worker`](crate::Worker), which message is the oldest is only exact
+    /// among the messages pushed from the same thread.
+    DropOldest,
+
+    /// Drop the new message.
+    DropNewest,
+
+    /// Return [`Error::QueueFull`].
+    Error,
+}
+
+/// A bounded multi-producer single-consumer queue.
+///
+/// The producers push into shards, picked per thread, so that they don't
+/// contend on a single lock. The consumer moves the shards one at a time to
+/// its own buffer, ordering the messages moved together by their sequence
+/// number. The order is thus only guaranteed for the messages pushed from
+/// the same thread: a task moving to another thread between two pushes may
+/// have its second message popped first, if the shard of the first one was
+/// already moved.
+#[derive(Debug)]
+pub(crate) struct Queue {
+    shards: Box<[Mutex<VecDeque<Queued>>]>,
+    /// The messages taken from the shards by the consumer, in order.
+    ready: Mutex<VecDeque<Queued>>,
+    /// The sequence number of the next pushed message.
+    seq: AtomicU64,
+    /// The number of messages in the queue, including the reserved slots of
+    /// the messages being pushed.
+    len: AtomicUsize,
+    closed: AtomicBool,
+    capacity: usize,
+    /// Wakes the consumer when a message is pushed or the queue is closed.
+    pushed: Notify,
+    /// Wakes the blocked producers when a message is popped or the queue is
+    /// closed.
+    popped: Notify,
+    /// Wakes the producers dropping the oldest message, waiting for a queue
+    /// full of reserved slots, when a message is pushed or the queue is
+    /// closed.
+    filled: Notify,
+    counters: Arc<Counters>,
+}
+
+/// A message, and its sequence number.
+type Queued = (u64, BatchMessage);
+
+enum Offer {
+    Done(Result<()>),
+    Full(BatchMessage),
+}
+
+impl Queue {
+    pub(crate) fn new(capacity: usize, counters: Arc<Counters>) -> Self {
+        let shards = std::thread::available_parallelism().map_or(4, usize::from);
+        Self {
+            shards: (0..shards).map(|_| Mutex::default()).collect(),
+            ready: Mutex::default(),
+            seq: AtomicU64::new(0),
+            len: AtomicUsize::new(0),
+            closed: AtomicBool::new(false),
+            capacity: capacity.max(1),
+            pushed: Notify::new(),
+            popped: Notify::new(),
+            filled: Notify::new(),
+            counters,
+        }
+    }
+
+    /// Returns the shard of the current thread.
+    fn shard(&self) -> &Mutex<VecDeque<Queued>> {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        thread_local! {
+            static INDEX: usize = NEXT.fetch_add(1, Ordering::Relaxed);
+        }
+        &self.shards[INDEX.with(|index| *index) % self.shards.len()]
+    }
+
+    /// Push a message, waiting for some room if the queue is full and the
+    /// policy is [`OverflowPolicy::Block`], or for a message to drop with
+    /// [`OverflowPolicy::DropOldest`].
+    pub(crate) async fn push(&self, mut msg: BatchMessage, policy: OverflowPolicy) -> Result<()> {
+        loop {
+            let room = match policy {
+                OverflowPolicy::DropOldest => self.filled.notified(),
+                _ => self.popped.notified(),
+            };
+            tokio::pin!(room);
+            room.as_mut().enable();
+
+            match self.offer(msg, policy) {
+                Offer::Done(result) => return result,
+                Offer::Full(rejected) => msg = rejected,
+            }
+            room.await;
+        }
+    }
+
+    /// Push a message without waiting, [`OverflowPolicy::Block`] behaves like
+    /// [`OverflowPolicy::Error`]. With [`OverflowPolicy::DropOldest`],
+    /// returns [`Error::QueueFull`] if the queue only holds the messages
+    /// still being pushed.
+    pub(crate) fn try_push(&self, msg: BatchMessage, policy: OverflowPolicy) -> Result<()> {
+        match self.offer(msg, policy) {
+            Offer::Done(result) => result,
+            Offer::Full(_) => Err(Error::QueueFull),
+        }
+    }
+
+    fn offer(&self, msg: BatchMessage, policy: OverflowPolicy) -> Offer {
+        if self.closed.load(Ordering::Acquire) {
+            return Offer::Done(Err(Error::Closed));
+        }
+
+        // reserve a slot
+        loop {
+            let len = self.len.load(Ordering::Acquire);
+            if len >= self.capacity {
+                match policy {
+                    OverflowPolicy::Block => return Offer::Full(msg),
+                    OverflowPolicy::Error => return Offer::Done(Err(Error::QueueFull)),
+                    OverflowPolicy::DropNewest => {
+                        self.counters.dropped.fetch_add(1, Ordering::Relaxed);
+                        return Offer::Done(Ok(()));
+                    }
+                    OverflowPolicy::DropOldest => {
+                        if !self.remove_oldest() {
+                            // the queue is full of the slots reserved by the
+                            // pushes in progress, wait for them to complete
+                            return Offer::Full(msg);
+                        }
+                        self.counters.dropped.fetch_add(1, Ordering::Relaxed);
+                        continue;
+                    }
+                }
+            }
+            if self
+                .len
+                .compare_exchange_weak(len, len + 1, Ordering::AcqRel, Ordering::Relaxed)
+                .is_ok()
+            {
+                break;
+            }
+        }
+
+        let mut shard = self.shard().lock().unwrap();
+        // checked under the lock of the shard, see `close`
+        if self.closed.load(Ordering::Acquire) {
+            drop(shard);
+            self.len.fetch_sub(1, Ordering::AcqRel);
+            return Offer::Done(Err(Error::Closed));
+        }
+        // numbered under the lock, so that each shard is ordered
+        let seq = self.seq.fetch_add(1, Ordering::Relaxed);
+        shard.push_back((seq, msg));
+        drop(shard);
+        self.pushed.notify_one();
+        self.filled.notify_waiters();
+        Offer::Done(Ok(()))
+    }
+
+    /// Remove the oldest message of the queue, returns whether one was
+    /// removed.
+    fn remove_oldest(&self) -> bool {
+        // the messages are only removed under the lock of `ready`, the fronts
+        // of the shards don't change until it is released
+        let mut ready = self.ready.lock().unwrap();
+        let mut oldest = ready.front().map(|(seq, _)| (*seq, None));
+        for (index, shard) in self.shards.iter().enumerate() {
+            if let Some((seq, _)) = shard.lock().unwrap().front() {
+                if oldest.is_none_or(|(oldest, _)| *seq < oldest) {
+                    oldest = Some((*seq, Some(index)));
+                }
+            }
+        }
+        let removed = match oldest {
+            None => false,
+            Some((_, None)) => ready.pop_front().is_some(),
+            Some((_, Some(index))) => self.shards[index].lock().unwrap().pop_front().is_some(),
+        };
+        if removed {
+            self.len.fetch_sub(1, Ordering::AcqRel);
+        }
+        removed
+    }
+
+    /// Pop the oldest message, waiting for one to be pushed. Returns `None`
+    /// once the queue is closed and empty.
+    pub(crate) async fn pop(&self) -> Option<BatchMessage> {
+        loop {
+            let pushed = self.pushed.notified();
+            tokio::pin!(pushed);
+            pushed.as_mut().enable();
+
+            let msg = {
+                let mut ready = self.ready.lock().unwrap();
+                if ready.is_empty() {
+                    for shard in self.shards.iter() {
+                        ready.append(&mut shard.lock().unwrap());
+                    }
+                    ready
+                        .make_contiguous()
+                        .sort_unstable_by_key(|(seq, _)| *seq);
+                }
+                ready.pop_front().map(|(_, msg)| msg)
+            };
+            if let Some(msg) = msg {
+                self.len.fetch_sub(1, Ordering::AcqRel);
+                self.popped.notify_one();
+                return Some(msg);
+            }
+            if self.closed.load(Ordering::Acquire) {
+                return None;
+            }
+            pushed.await;
+        }
+    }
+
+    /// Pop all the messages currently queued.
+    pub(crate) fn drain(&self) -> Vec<BatchMessage> {
+        let mut items: Vec<_> = self.ready.lock().unwrap().drain(..).collect();
+        for shard in self.shards.iter() {
+            items.extend(shard.lock().unwrap().drain(..));
+        }
+        items.sort_unstable_by_key(|(seq, _)| *seq);
+        self.len.fetch_sub(items.len(), Ordering::AcqRel);
+        self.popped.notify_waiters();
+        items.into_iter().map(|(_, msg)| msg).collect()
+    }
+
+    /// Stop accepting new messages. The queued ones can still be popped.
+    pub(crate) fn close(&self) {
+        self.closed.store(true, Ordering::Release);
+        // wait for the pushes which didn't see the flag yet, so that they are
+        // drained
+        for shard in self.shards.iter() {
+            drop(shard.lock().unwrap());
+        }
+        self.pushed.notify_one();
+        self.popped.notify_waiters();
+        self.filled.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::Track;
+
+    fn track(event: &str) -> BatchMessage {
+        BatchMessage::Track(Track {
+            event: event.to_owned(),
+            ..Default::default()
+        })
+    }
+
+    fn events(queue: &Queue) -> Vec<String> {
+        queue
+            .drain()
+            .into_iter()
+            .map(|msg| match msg {
+                BatchMessage::Track(track) => track.event,
+                _ => unreachable!(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_policies() {
+        let counters = Arc::new(Counters::default());
+        let queue = Queue::new(2, counters.clone());
+        queue.try_push(track("a"), OverflowPolicy::Block).unwrap();
+        queue.try_push(track("b"), OverflowPolicy::Block).unwrap();
+
+        assert!(matches!(
+            queue.try_push(track("c"), OverflowPolicy::Block),
+            Err(Error::QueueFull)
+        ));
+        assert!(matches!(
+            queue.try_push(track("c"), OverflowPolicy::Error),
+            Err(Error::QueueFull)
+        ));
+        queue
+            .try_push(track("c"), OverflowPolicy::DropNewest)
+            .unwrap();
+        assert_eq!(counters.snapshot().dropped, 1);
+        queue
+            .try_push(track("d"), OverflowPolicy::DropOldest)
+            .unwrap();
+        assert_eq!(counters.snapshot().dropped, 2);
+
+        assert_eq!(events(&queue), ["b", "d"]);
+    }
+
+    #[test]
+    fn test_drop_oldest_of_every_producer() {
+        let queue = Arc::new(Queue::new(3, Arc::default()));
+        queue.try_push(track("a"), OverflowPolicy::Error).unwrap();
+        std::thread::spawn({
+            let queue = queue.clone();
+            move || {
+                queue.try_push(track("b"), OverflowPolicy::Error).unwrap();
+                queue.try_push(track("c"), OverflowPolicy::Error).unwrap();
+            }
+        })
+        .join()
+        .unwrap();
+
+        queue
+            .try_push(track("d"), OverflowPolicy::DropOldest)
+            .unwrap();
+        queue
+            .try_push(track("e"), OverflowPolicy::DropOldest)
+            .unwrap();
+        assert_eq!(events(&queue), ["c", "d", "e"]);
+    }
+
+    #[tokio::test]
+    async fn test_block_until_popped() {
+        let queue = Arc::new(Queue::new(1, Arc::default()));
+        queue.push(track("a"), OverflowPolicy::Block).await.unwrap();
+
+        let producer = tokio::spawn({
+            let queue = queue.clone();
+            async move { queue.push(track("b"), OverflowPolicy::Block).await }
+        });
+        tokio::task::yield_now().await;
+        assert!(!producer.is_finished());
+
+        assert!(queue.pop().await.is_some());
+        producer.await.unwrap().unwrap();
+        assert_eq!(events(&queue), ["b"]);
+
+        queue.close();
+        assert!(queue.pop().await.is_none());
+        assert!(matches!(
+            queue.push(track("c"), OverflowPolicy::Block).await,
+            Err(Error::Closed)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_drop_oldest_waits_for_reserved_slots() {
+        let queue = Arc::new(Queue::new(1, Arc::default()));
+        // a push in progress, between the reservation of its slot and the
+        // push into a shard
+        queue.len.fetch_add(1, Ordering::AcqRel);
+        assert!(matches!(
+            queue.try_push(track("b"), OverflowPolicy::DropOldest),
+            Err(Error::QueueFull)
+        ));
+
+        let producer = tokio::spawn({
+            let queue = queue.clone();
+            async move { queue.push(track("b"), OverflowPolicy::DropOldest).await }
+        });
+        tokio::task::yield_now().await;
+        assert!(!producer.is_finished());
+
+        queue.len.fetch_sub(1, Ordering::AcqRel);
+        queue.try_push(track("a"), OverflowPolicy::Error).unwrap();
+        producer.await.unwrap().unwrap();
+        assert_eq!(events(&queue), ["b"]);
+    }
+
+    #[test]
+    fn test_concurrent_producers() {
+        let queue = Arc::new(Queue::new(10_000, Arc::default()));
+        let producers: Vec<_> = (0..4)
+            .map(|producer| {
+                let queue = queue.clone();
+                std::thread::spawn(move || {
+                    for i in 0..1000 {
+                        let event = format!("{producer}-{i}");
+                        queue
+                            .try_push(track(&event), OverflowPolicy::Error)
+                            .unwrap();
+                    }
+                })
+            })
+            .collect();
+        for producer in producers {
+            producer.join().unwrap();
+        }
+
+        let events = events(&queue);
+        assert_eq!(events.len(), 4000);
+        // the order is kept per producer
+        for producer in 0..4 {
+            let sent: Vec<_> = events
+                .iter()
+                .filter_map(|event| event.strip_prefix(&format!("{producer}-")))
+                .map(|i| i.parse::<usize>().unwrap())
+                .collect();
+            assert_eq!(sent, (0..1000).collect::<Vec<_>>());
+        }
+        assert_eq!(queue.len.load(Ordering::Relaxed), 0);
+    }
+}
@@ -0,0 +1,223 @@
+//! Client-side throttling of the traffic sent to Segment.
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::errors::{Error, Result};
+use crate::logging;
+use crate::runtime::{Instant, SharedRuntime};
+
+/// A token-bucket rate limiter consulted by the
+/// [`AutoBatcher`](crate::AutoBatcher) before every flush.
+///
+/// Segment enforces per-source throughput limits and answers with `429 Too
+/// Many Requests` once they are exceeded. A `RateLimiter` delays the sends
+/// instead, so that the traffic stays under the configured rates.
+///
+/// Two independent limits can be configured: a number of events per second
+/// and a number of HTTP requests per second. Each bucket holds up to one
+/// second worth of tokens, which allows short bursts.
+///
+/// The clones of a `RateLimiter` share its buckets, and so do the clones of
+/// the [`AutoBatcher`](crate::AutoBatcher) it is set on: together, they stay
+/// under the configured rates. The batchers of the other sources of a
+/// [`MultiSourceBatcher`](crate::MultiSourceBatcher) get their own buckets,
+/// with the same rates, since Segment limits each source apart.
+///
+/// ```
+/// use segment::{AutoBatcher, Batcher, HttpClient, RateLimiter};
+///
+/// let client = HttpClient::default();
+/// let batcher = Batcher::new(None);
+/// let mut batcher = AutoBatcher::new(client, batcher, "your_write_key".to_string());
+/// batcher.set_rate_limiter(RateLimiter::new(Some(500), Some(10)).unwrap());
+/// ```
+#[derive(Clone, Debug)]
+pub struct RateLimiter {
+    inner: Arc<Mutex<Buckets>>,
+}
+
+#[derive(Debug)]
+struct Buckets {
+    events: Option<TokenBucket>,
+    requests: Option<TokenBucket>,
+}
+
+impl RateLimiter {
+    /// Construct a new rate limiter allowing at most `events_per_second`
+    /// events and `requests_per_second` requests per second.
+    ///
+    /// `None` disables the corresponding limit. Returns
+    /// [`Error::InvalidConfiguration`] if a rate is 0, which would never let
+    /// anything through.
+    pub fn new(events_per_second: Option<u32>, requests_per_second: Option<u32>) -> Result<Self> {
+        let check = |rate: Option<u32>, unit: &str| match rate {
+            Some(0) => Err(Error::InvalidConfiguration(format!(
+                "the rate limit of {unit} per second must be at least 1"
+            ))),
+            rate => Ok(rate),
+        };
+        let events = check(events_per_second, "events")?;
+        let requests = check(requests_per_second, "requests")?;
+
+        let now = Instant::now();
+        Ok(Self {
+            inner: Arc::new(Mutex::new(Buckets {
+                events: events.map(|rate| TokenBucket::new(rate, now)),
+                requests: requests.map(|rate| TokenBucket::new(rate, now)),
+            })),
+        })
+    }
+
+    /// Returns a rate limiter with the rates of this one, and its own full
+    /// buckets.
+    pub(crate) fn unshared(&self) -> Self {
+        let buckets = self.inner.lock().unwrap();
+        let rate = |bucket: &Option<TokenBucket>| bucket.as_ref().map(|bucket| bucket.rate);
+        let now = Instant::now();
+        Self {
+            inner: Arc::new(Mutex::new(Buckets {
+                events: rate(&buckets.events).map(|rate| TokenBucket::with_rate(rate, now)),
+                requests: rate(&buckets.requests).map(|rate| TokenBucket::with_rate(rate, now)),
+            })),
+        }
+    }
+
+    /// Reserve the tokens required to send a batch of `events` events, and
+    /// return how long the caller must wait before sending it.
+    pub(crate) fn reserve(&self, events: usize) -> Duration {
+        let now = Instant::now();
+        let mut buckets = self.inner.lock().unwrap();
+        let events = buckets
+            .events
+            .as_mut()
+            .map_or(Duration::ZERO, |bucket| bucket.reserve(events as f64, now));
+        let requests = buckets
+            .requests
+            .as_mut()
+            .map_or(Duration::ZERO, |bucket| bucket.reserve(1.0, now));
+        events.max(requests)
+    }
+
+    /// Wait until a batch of `events` events can be sent, with the timers of
+    /// `runtime`.
+    pub(crate) async fn acquire(&self, events: usize, runtime: &SharedRuntime) {
+        let delay = self.reserve(events);
+        if !delay.is_zero() {
+            logging::debug!(
+                delay_ms = delay.as_millis() as u64,
+                "rate limited, delaying flush"
+            );
+            runtime.sleep(delay).await;
+        }
+    }
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    /// Tokens added per second, which is also the capacity of the bucket.
+    rate: f64,
+    /// Available tokens. Goes negative when a reservation is larger than what
+    /// is available, the debt is then paid back by waiting.
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: u32, now: Instant) -> Self {
+        Self::with_rate(f64::from(rate), now)
+    }
+
+    fn with_rate(rate: f64, now: Instant) -> Self {
+        Self {
+            rate,
+            tokens: rate,
+            last_refill: now,
+        }
+    }
+
+    fn reserve(&mut self, amount: f64, now: Instant) -> Duration {
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        self.last_refill = now;
+
+        self.tokens -= amount;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_burst_then_delay() {
+        let limiter = RateLimiter::new(Some(100), None).unwrap();
+
+        assert_eq!(limiter.reserve(100), Duration::ZERO);
+        assert_eq!(limiter.reserve(50), Duration::from_millis(500));
+
+        tokio::time::advance(Duration::from_millis(500)).await;
+        assert_eq!(limiter.reserve(10), Duration::from_millis(100));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_requests_limit() {
+        let limiter = RateLimiter::new(None, Some(2)).unwrap();
+
+        assert_eq!(limiter.reserve(1000), Duration::ZERO);
+        assert_eq!(limiter.reserve(1000), Duration::ZERO);
+        assert_eq!(limiter.reserve(1000), Duration::from_millis(500));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_acquire_waits() {
+        let limiter = RateLimiter::new(Some(10), Some(10)).unwrap();
+        let runtime = SharedRuntime::default();
+        let start = Instant::now();
+
+        limiter.acquire(10, &runtime).await;
+        assert_eq!(start.elapsed(), Duration::ZERO);
+
+        limiter.acquire(5, &runtime).await;
+        assert_eq!(start.elapsed(), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn test_unlimited() {
+        let limiter = RateLimiter::new(None, None).unwrap();
+        for _ in 0..100 {
+            assert_eq!(limiter.reserve(usize::MAX), Duration::ZERO);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_clones_share_buckets() {
+        let limiter = RateLimiter::new(Some(100), None).unwrap();
+        let clone = limiter.clone();
+
+        assert_eq!(limiter.reserve(100), Duration::ZERO);
+        assert_eq!(clone.reserve(50), Duration::from_millis(500));
+
+        let unshared = limiter.unshared();
+        assert_eq!(unshared.reserve(100), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_zero_rate() {
+        assert!(matches!(
+            RateLimiter::new(Some(0), None),
+            Err(Error::InvalidConfiguration(_))
+        ));
+        assert!(matches!(
+            RateLimiter::new(None, Some(0)),
+            Err(Error::InvalidConfiguration(_))
+        ));
+    }
+}
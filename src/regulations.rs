@@ -0,0 +1,220 @@
+//! A client of Segment's [User Deletion and Suppression
+//! API](https://docs.segmentapis.com/tag/Deletion-and-Suppression), to delete
+//! the data of users and stop collecting it, for instance to honour
+//! right-to-be-forgotten requests.
+//!
+//! ```no_run
+//! use segment::regulations::{RegulationStatus, RegulationsClient};
+//!
+//! # async fn run() -> segment::Result<()> {
+//! let client = RegulationsClient::new("your_workspace_token".to_owned());
+//!
+//! let id = client.delete_users(vec!["user".to_owned()]).await?;
+//! let regulation = client.regulation(&id).await?;
+//! if regulation.overall_status == RegulationStatus::Finished {
+//!     println!("the data of the user is deleted");
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use serde::{Deserialize, Serialize};
+
+use crate::errors::Result;
+use crate::http;
+
+/// The host of Segment's Public API.
+const PUBLIC_API: &str = "https://api.segmentapis.com";
+
+/// A client creating regulations in a workspace, authenticated with a token
+/// of the workspace.
+#[derive(Clone, Debug)]
+pub struct RegulationsClient {
+    client: reqwest::Client,
+    host: String,
+    token: String,
+}
+
+/// What a regulation does to the data of its subjects.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+#[non_exhaustive]
+pub enum RegulationType {
+    /// Delete the data already collected.
+    DeleteOnly,
+    /// Drop the messages collected from now on.
+    SuppressOnly,
+    /// Drop the messages collected from now on, and delete the data already
+    /// collected.
+    SuppressWithDelete,
+    /// Collect the messages of suppressed subjects again.
+    Unsuppress,
+}
+
+/// The progress of a regulation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+#[non_exhaustive]
+pub enum RegulationStatus {
+    Initialized,
+    Running,
+    Finished,
+    PartialSuccess,
+    Failed,
+    Invalid,
+    NotSupported,
+    /// A status unknown to this version of the crate.
+    #[serde(other)]
+    Unknown,
+}
+
+/// A regulation, as returned by [`RegulationsClient::regulation`].
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[non_exhaustive]
+pub struct Regulation {
+    pub id: String,
+    pub overall_status: RegulationStatus,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CreateRegulation {
+    regulation_type: RegulationType,
+    subject_type: &'static str,
+    subject_ids: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct Response<T> {
+    data: T,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Created {
+    regulate_id: String,
+}
+
+#[derive(Deserialize)]
+struct Fetched {
+    regulation: Regulation,
+}
+
+impl RegulationsClient {
+    /// Construct a client of Segment's Public API, authenticated with the
+    /// workspace `token`.
+    pub fn new(token: String) -> Self {
+        Self::with_host(reqwest::Client::new(), PUBLIC_API.to_owned(), token)
+    }
+
+    /// Construct a client of the Public API at `host`, like the one of the EU
+    /// region, `https://eu1.api.segmentapis.com`.
+    pub fn with_host(client: reqwest::Client, host: String, token: String) -> Self {
+        Self {
+            client,
+            host,
+            token,
+        }
+    }
+
+    /// Delete the data of the users with `user_ids`, and drop their messages
+    /// from now on.
+    ///
+    /// Returns the id of the regulation, to follow its progress with
+    /// [Self::regulation].
+    pub async fn delete_users(&self, user_ids: Vec<String>) -> Result<String> {
+        self.create(RegulationType::SuppressWithDelete, user_ids)
+            .await
+    }
+
+    /// Drop the messages of the users with `user_ids` from now on, without
+    /// deleting the data already collected.
+    pub async fn suppress_users(&self, user_ids: Vec<String>) -> Result<String> {
+        self.create(RegulationType::SuppressOnly, user_ids).await
+    }
+
+    /// Create a regulation of the users with `user_ids`, and returns its id.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    pub async fn create(
+        &self,
+        regulation_type: RegulationType,
+        user_ids: Vec<String>,
+    ) -> Result<String> {
+        let body = CreateRegulation {
+            regulation_type,
+            subject_type: "USER_ID",
+            subject_ids: user_ids,
+        };
+        let response: Response<Created> = self
+            .client
+            .post(format!("{}/regulations", self.host))
+            .bearer_auth(&self.token)
+            .json(&body)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(response.data.regulate_id)
+    }
+
+    /// Returns the regulation with the given `id`, to check its status.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    pub async fn regulation(&self, id: &str) -> Result<Regulation> {
+        let response: Response<Fetched> = self
+            .client
+            .get(http::endpoint(&self.host, ["regulations", id])?)
+            .bearer_auth(&self.token)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(response.data.regulation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{json_response, serve};
+
+    #[tokio::test]
+    async fn test_delete_users() {
+        let (host, mut requests) =
+            serve(vec![json_response(r#"{"data":{"regulateId":"reg-1"}}"#)]).await;
+        let client = RegulationsClient::with_host(reqwest::Client::new(), host, "token".to_owned());
+
+        let id = client.delete_users(vec!["user".to_owned()]).await.unwrap();
+        assert_eq!(id, "reg-1");
+        let request = requests.recv().await.unwrap();
+        assert!(request.starts_with("POST /regulations "));
+        assert!(request.contains("authorization: Bearer token"));
+        assert!(request.contains(
+            r#"{"regulationType":"SUPPRESS_WITH_DELETE","subjectType":"USER_ID","subjectIds":["user"]}"#
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_regulation() {
+        let (host, mut requests) = serve(vec![json_response(
+            r#"{"data":{"regulation":{"id":"reg-1","workspaceId":"ws","overallStatus":"RUNNING"}}}"#,
+        )])
+        .await;
+        let client = RegulationsClient::with_host(reqwest::Client::new(), host, "token".to_owned());
+
+        let regulation = client.regulation("reg-1").await.unwrap();
+        assert_eq!(regulation.id, "reg-1");
+        assert_eq!(regulation.overall_status, RegulationStatus::Running);
+        let request = requests.recv().await.unwrap();
+        assert!(request.starts_with("GET /regulations/reg-1 "));
+
+        client.regulation("reg/1?#").await.unwrap();
+        let request = requests.recv().await.unwrap();
+        assert!(
+            request.starts_with("GET /regulations/reg%2F1%3F%23 "),
+            "{request}"
+        );
+    }
+}
@@ -0,0 +1,235 @@
+//! Replays of stored messages, for instance to backfill the events captured
+//! during an outage.
+//!
+//! ```no_run
+//! use std::fs::File;
+//! use std::io::BufReader;
+//! use segment::{AutoBatcher, Batcher, HttpClient};
+//! use segment::replay::{self, ReplayOptions};
+//!
+//! # async fn run() -> segment::Result<()> {
+//! let client = HttpClient::default();
+//! let mut batcher = AutoBatcher::new(client, Batcher::new(None), "your_write_key".to_string());
+//!
+//! let reader = BufReader::new(File::open("outage.ndjson")?);
+//! let options = ReplayOptions {
+//!     events_per_second: Some(500),
+//!     ..Default::default()
+//! };
+//! let report = replay::from_reader(reader, &mut batcher, options).await?;
+//! println!("{} messages replayed", report.replayed);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::io::BufRead;
+
+use crate::{
+    auto_batcher::AutoBatcher,
+    client::Client,
+    context::MergeStrategy,
+    errors::{Error, Result},
+    message::{self, BatchMessage, Message},
+    rate_limit::RateLimiter,
+};
+
+/// How the messages are replayed.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ReplayOptions {
+    /// Push at most this many messages per second, `None` to push them as
+    /// fast as the batcher accepts them. The replay fails with
+    /// [`Error::InvalidConfiguration`] if it is 0.
+    pub events_per_second: Option<u32>,
+
+    /// Skip the lines which are not a valid message, or which the batcher
+    /// rejects, instead of stopping the replay.
+    pub skip_invalid: bool,
+}
+
+/// The outcome of a replay.
+#[derive(Debug, Default)]
+#[non_exhaustive]
+pub struct ReplayReport {
+    /// The number of messages pushed into the batcher.
+    pub replayed: u64,
+
+    /// The lines skipped, numbered from 1, and why, with
+    /// [`ReplayOptions::skip_invalid`].
+    pub skipped: Vec<(u64, Error)>,
+}
+
+/// Push the messages stored in `reader`, one JSON message per line, into
+/// `batcher`, then flush it.
+///
+/// The lines may hold any message, with or without a `type`, or a whole
+/// batch whose messages are replayed one by one. The empty lines are
+/// ignored. The `context` and the `integrations` of a batch are merged into
+/// its messages, the values of the messages taking precedence.
+///
+/// The messages keep their `timestamp` and `messageId`, so that Segment
+/// records them at the time they happened, and deduplicates the ones which
+/// were delivered already. The messages without a `messageId` get one
+/// derived from their line and their content, the same on every replay of
+/// the file.
+///
+/// Returns an error on the first invalid line or rejected message, unless
+/// [`ReplayOptions::skip_invalid`] is set, or if the final flush fails.
+pub async fn from_reader<C>(
+    reader: impl BufRead,
+    batcher: &mut AutoBatcher<C>,
+    options: ReplayOptions,
+) -> Result<ReplayReport>
+where
+    C: Client + Clone + Send + Sync + 'static,
+{
+    let rate_limiter = options
+        .events_per_second
+        .map(|rate| RateLimiter::new(Some(rate), None))
+        .transpose()?;
+    let mut report = ReplayReport::default();
+
+    for (line, content) in (1..).zip(reader.lines()) {
+        let content = content?;
+        if content.trim().is_empty() {
+            continue;
+        }
+        let msgs = match serde_json::from_str(&content) {
+            Ok(Message::Batch(batch)) => {
+                let mut msgs = batch.batch;
+                for msg in &mut msgs {
+                    if let Some(context) = &batch.context {
+                        MergeStrategy::Deep.merge(msg.context_mut(), context);
+                    }
+                    if let Some(integrations) = &batch.integrations {
+                        MergeStrategy::Deep.merge(msg.integrations_mut(), integrations);
+                    }
+                }
+                msgs
+            }
+            Ok(msg) => vec![into_batch_message(msg)],
+            Err(err) if options.skip_invalid => {
+                report.skipped.push((line, err.into()));
+                continue;
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        for (index, mut msg) in msgs.into_iter().enumerate() {
+            if msg.message_id().is_none() {
+                let content = serde_json::to_string(&msg)?;
+                msg.set_message_id(message::uuid_from_hash(format!("{line}:{index}:{content}")));
+            }
+            if let Some(rate_limiter) = &rate_limiter {
+                rate_limiter.acquire(1, &batcher.runtime).await;
+            }
+            match batcher.push(msg).await {
+                Ok(()) => report.replayed += 1,
+                Err(err) if options.skip_invalid => report.skipped.push((line, err)),
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    batcher.flush().await?;
+    Ok(report)
+}
+
+fn into_batch_message(msg: Message) -> BatchMessage {
+    match msg {
+        Message::Identify(msg) => msg.into(),
+        Message::Track(msg) => msg.into(),
+        Message::Page(msg) => msg.into(),
+        Message::Screen(msg) => msg.into(),
+        Message::Group(msg) => msg.into(),
+        Message::Alias(msg) => msg.into(),
+        Message::Batch(_) => unreachable!("the batches are replayed message by message"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MockClient;
+    use crate::Batcher;
+    use std::time::Duration;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_replay() {
+        let client = MockClient::default();
+        let mut batcher = AutoBatcher::new(client.clone(), Batcher::new(None), "key".to_owned());
+
+        let ndjson = r#"{"type":"track","userId":"a","event":"Signed Up","properties":{},"timestamp":"2024-01-01T00:00:00Z"}
+
+{"userId":"b","traits":{"plan":"pro"}}
+not json
+{"batch":[{"type":"page","userId":"c","name":"Home","properties":{}},{"type":"alias","userId":"c","previousId":"d"}]}
+"#;
+        let result = from_reader(ndjson.as_bytes(), &mut batcher, ReplayOptions::default()).await;
+        assert!(matches!(result, Err(Error::DeserializeError(_))));
+
+        let options = ReplayOptions {
+            events_per_second: Some(2),
+            skip_invalid: true,
+        };
+        let start = tokio::time::Instant::now();
+        let report = from_reader(ndjson.as_bytes(), &mut batcher, options)
+            .await
+            .unwrap();
+        assert_eq!(report.replayed, 4);
+        assert_eq!(report.skipped.len(), 1);
+        assert_eq!(report.skipped[0].0, 4);
+        // the bucket holds 2 messages, the 2 others wait for a second
+        assert!(start.elapsed() >= Duration::from_secs(1));
+
+        // the 2 messages pushed before the invalid line, then the 4 replayed
+        assert_eq!(client.sent_count(), 6);
+        let sent = client.sent.lock().unwrap();
+        let Message::Batch(batch) = &sent[0] else {
+            panic!("unexpected {:?}", sent[0]);
+        };
+        let BatchMessage::Track(track) = &batch.batch[0] else {
+            panic!("unexpected {:?}", batch.batch[0]);
+        };
+        assert_eq!(
+            track.timestamp,
+            Some(time::OffsetDateTime::from_unix_timestamp(1_704_067_200).unwrap())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_replay_batch_defaults_and_ids() {
+        let client = MockClient::default();
+        let mut batcher = AutoBatcher::new(client.clone(), Batcher::new(None), "key".to_owned());
+
+        let ndjson = r#"{"batch":[{"type":"track","userId":"a","event":"A","properties":{},"context":{"app":{"name":"mine"}}},{"type":"track","userId":"a","event":"A","properties":{},"messageId":"kept"}],"context":{"app":{"name":"theirs","version":"1"}},"integrations":{"All":false}}
+"#;
+        for _ in 0..2 {
+            from_reader(ndjson.as_bytes(), &mut batcher, ReplayOptions::default())
+                .await
+                .unwrap();
+        }
+
+        let sent = client.sent.lock().unwrap();
+        let msgs: Vec<&BatchMessage> = sent
+            .iter()
+            .flat_map(|msg| match msg {
+                Message::Batch(batch) => &batch.batch,
+                _ => panic!("unexpected {msg:?}"),
+            })
+            .collect();
+        assert_eq!(msgs.len(), 4);
+        let BatchMessage::Track(first) = msgs[0] else {
+            panic!("unexpected {:?}", msgs[0]);
+        };
+        assert_eq!(
+            first.context.as_ref().unwrap()["app"],
+            serde_json::json!({"name": "mine", "version": "1"})
+        );
+        assert_eq!(first.integrations, Some(serde_json::json!({"All": false})));
+        // the same on every replay, and unique in the file
+        let id = msgs[0].message_id().unwrap();
+        assert_eq!(msgs[2].message_id(), Some(id));
+        assert_eq!(msgs[1].message_id(), Some("kept"));
+        assert_eq!(msgs[3].message_id(), Some("kept"));
+    }
+}
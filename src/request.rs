@@ -0,0 +1,201 @@
+//! Context of the events extracted from an incoming HTTP request.
+
+use std::net::IpAddr;
+
+use http::header::{HeaderMap, HOST, REFERER, USER_AGENT};
+use http::request::Parts;
+use http::{Request, Uri};
+use serde_json::{json, Map, Value};
+
+use crate::message::{Page, User};
+
+/// The context of an incoming HTTP request, to attach to the events it
+/// triggers without copying the headers by hand.
+///
+/// The client IP is read from the `X-Forwarded-For` and `X-Real-IP` headers,
+/// which can be forged by the client unless a trusted proxy overrides them.
+/// The fields are public so that they can be overridden, for instance with
+/// the address of the peer.
+///
+/// Requires the `http` feature. Works with any framework built on the `http`
+/// crate, `axum::extract::Request` included.
+///
+/// ```
+/// use segment::RequestContext;
+/// use segment::message::{Track, User};
+///
+/// let request = http::Request::get("/pricing?plan=pro")
+///     .header("host", "example.com")
+///     .header("user-agent", "curl/8.0")
+///     .body(())
+///     .unwrap();
+///
+/// let context = RequestContext::from_request(&request);
+/// let track = Track {
+///     user: User::UserId { user_id: String::from("user") },
+///     event: "Viewed Pricing".to_owned(),
+///     context: Some(context.context()),
+///     ..Default::default()
+/// };
+///
+/// let page = context.page(User::UserId { user_id: String::from("user") }, "Pricing");
+/// assert_eq!(page.properties["url"], "https://example.com/pricing?plan=pro");
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RequestContext {
+    /// The IP of the client.
+    pub ip: Option<IpAddr>,
+
+    /// The `User-Agent` header.
+    pub user_agent: Option<String>,
+
+    /// The `Referer` header.
+    pub referrer: Option<String>,
+
+    /// The full URL of the request, if the host is known.
+    pub url: Option<String>,
+
+    /// The path of the request.
+    pub path: String,
+
+    /// The query string of the request, including the leading `?`, or empty.
+    pub search: String,
+}
+
+impl RequestContext {
+    /// Extract the context of `request`.
+    pub fn from_request<B>(request: &Request<B>) -> Self {
+        Self::new(request.uri(), request.headers())
+    }
+
+    /// Extract the context of the `parts` of a request.
+    pub fn from_parts(parts: &Parts) -> Self {
+        Self::new(&parts.uri, &parts.headers)
+    }
+
+    fn new(uri: &Uri, headers: &HeaderMap) -> Self {
+        let header = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+        };
+
+        let ip = header("x-forwarded-for")
+            .and_then(|forwarded| forwarded.split(',').next())
+            .or_else(|| header("x-real-ip"))
+            .and_then(|ip| ip.trim().parse().ok());
+
+        let path = uri.path().to_owned();
+        let search = uri
+            .query()
+            .map(|query| format!("?{query}"))
+            .unwrap_or_default();
+        let host = uri
+            .authority()
+            .map(|authority| authority.as_str())
+            .or_else(|| header(HOST.as_str()));
+        let scheme = header("x-forwarded-proto")
+            .or(uri.scheme_str())
+            .unwrap_or("https");
+        let url = host.map(|host| format!("{scheme}://{host}{path}{search}"));
+
+        Self {
+            ip,
+            user_agent: header(USER_AGENT.as_str()).map(str::to_owned),
+            referrer: header(REFERER.as_str()).map(str::to_owned),
+            url,
+            path,
+            search,
+        }
+    }
+
+    /// Returns the properties of a `page` event for this request, following
+    /// Segment's spec.
+    pub fn page_properties(&self) -> Value {
+        let mut properties = Map::new();
+        properties.insert("path".to_owned(), json!(self.path));
+        properties.insert("search".to_owned(), json!(self.search));
+        if let Some(url) = &self.url {
+            properties.insert("url".to_owned(), json!(url));
+        }
+        if let Some(referrer) = &self.referrer {
+            properties.insert("referrer".to_owned(), json!(referrer));
+        }
+        Value::Object(properties)
+    }
+
+    /// Returns the `context` of the events triggered by this request.
+    pub fn context(&self) -> Value {
+        let mut context = Map::new();
+        if let Some(ip) = self.ip {
+            context.insert("ip".to_owned(), json!(ip.to_string()));
+        }
+        if let Some(user_agent) = &self.user_agent {
+            context.insert("userAgent".to_owned(), json!(user_agent));
+        }
+        context.insert("page".to_owned(), self.page_properties());
+        Value::Object(context)
+    }
+
+    /// Returns a `page` event of `user` viewing this request.
+    pub fn page(&self, user: User, name: impl Into<String>) -> Page {
+        Page {
+            user,
+            name: name.into(),
+            properties: self.page_properties(),
+            context: Some(self.context()),
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_request() {
+        let request = Request::get("/docs?q=segment")
+            .header("host", "example.com")
+            .header("x-forwarded-proto", "http")
+            .header("x-forwarded-for", "203.0.113.7, 10.0.0.1")
+            .header("user-agent", "curl/8.0")
+            .header("referer", "https://search.example/")
+            .body(())
+            .unwrap();
+
+        let context = RequestContext::from_request(&request);
+        assert_eq!(
+            context.context(),
+            json!({
+                "ip": "203.0.113.7",
+                "userAgent": "curl/8.0",
+                "page": {
+                    "path": "/docs",
+                    "search": "?q=segment",
+                    "url": "http://example.com/docs?q=segment",
+                    "referrer": "https://search.example/",
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn test_missing_headers() {
+        let (parts, ()) = Request::get("/").body(()).unwrap().into_parts();
+        let context = RequestContext::from_parts(&parts);
+        assert_eq!(
+            context,
+            RequestContext {
+                path: "/".to_owned(),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            context.context(),
+            json!({ "page": { "path": "/", "search": "" } })
+        );
+    }
+}
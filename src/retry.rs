@@ -0,0 +1,228 @@
+//! Retries of the failed uploads.
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::errors::{Error, Result};
+use crate::runtime::Instant;
+
+/// The maximum number of retries a [`RetryBudget`] can accumulate.
+const MAX_BALANCE: f64 = 100.0;
+
+/// How the failed uploads are retried.
+///
+/// Only transient errors are retried: connection errors, timeouts, `408`,
+/// `429` and `5xx` responses. The delay between two attempts doubles after
+/// each attempt, from `initial_backoff` up to `max_backoff`.
+///
+/// By default the uploads are not retried, the failed batches are only sent
+/// again by [`AutoBatcher::shutdown`](crate::AutoBatcher::shutdown).
+///
+/// ```
+/// use segment::RetryPolicy;
+///
+/// let policy = RetryPolicy {
+///     max_attempts: 3,
+///     ..RetryPolicy::default()
+/// };
+/// ```
+///
+/// See [`AutoBatcher::set_retry_policy`](crate::AutoBatcher::set_retry_policy).
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
+pub struct RetryPolicy {
+    /// How many times an upload is attempted, including the first attempt.
+    pub max_attempts: u32,
+
+    /// The delay before the first retry.
+    pub initial_backoff: Duration,
+
+    /// The maximum delay between two attempts.
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 1,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy never retrying, the default.
+    pub fn never() -> Self {
+        Self::default()
+    }
+
+    /// Returns the delay to wait after the `attempt`th attempt failed,
+    /// starting at 1.
+    pub(crate) fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+}
+
+/// A budget limiting the fraction of the requests which may be retries,
+/// shared by all the batchers it is given to.
+///
+/// When the endpoint is struggling, retrying every failed request multiplies
+/// the load it receives. With a budget, each request sent deposits `ratio`
+/// retry, and each retry withdraws one: once the budget is exhausted the
+/// failures are not retried anymore. `min_retries_per_second` retries are
+/// allowed in any case, so that a low traffic can still be retried. The
+/// budget holds at most 100 retries.
+///
+/// The retries denied are counted in
+/// [`Stats::retry_budget_exhausted`](crate::Stats::retry_budget_exhausted).
+///
+/// ```
+/// use segment::{AutoBatcher, Batcher, HttpClient, RetryBudget};
+///
+/// // at most 20% of the requests may be retries, plus 1 retry per second
+/// let budget = RetryBudget::new(0.2, 1).unwrap();
+///
+/// let client = HttpClient::default();
+/// let mut batcher = AutoBatcher::new(client, Batcher::new(None), "your_write_key".to_string());
+/// batcher.set_retry_budget(budget.clone());
+/// ```
+#[derive(Clone, Debug)]
+pub struct RetryBudget {
+    inner: Arc<Mutex<Budget>>,
+}
+
+#[derive(Debug)]
+struct Budget {
+    ratio: f64,
+    min_per_second: f64,
+    balance: f64,
+    last_refill: Instant,
+}
+
+impl RetryBudget {
+    /// Construct a budget allowing `ratio` retries per request, plus
+    /// `min_retries_per_second`.
+    ///
+    /// Returns an error if `ratio` is not between 0 and 1.
+    pub fn new(ratio: f64, min_retries_per_second: u32) -> Result<Self> {
+        if !(0.0..=1.0).contains(&ratio) {
+            return Err(Error::InvalidConfiguration(format!(
+                "the retry ratio must be between 0 and 1, got {ratio}"
+            )));
+        }
+        Ok(Self {
+            inner: Arc::new(Mutex::new(Budget {
+                ratio,
+                min_per_second: min_retries_per_second as f64,
+                balance: 0.0,
+                last_refill: Instant::now(),
+            })),
+        })
+    }
+
+    /// Returns the number of retries currently allowed.
+    pub(crate) fn balance(&self) -> f64 {
+        self.inner.lock().unwrap().balance
+    }
+
+    /// Record a request sent for the first time.
+    pub(crate) fn deposit(&self) {
+        let mut budget = self.inner.lock().unwrap();
+        budget.balance = (budget.balance + budget.ratio).min(MAX_BALANCE);
+    }
+
+    /// Returns whether a retry is allowed, and withdraws it from the budget.
+    pub(crate) fn withdraw(&self) -> bool {
+        let mut budget = self.inner.lock().unwrap();
+        let now = Instant::now();
+        let elapsed = now.duration_since(budget.last_refill).as_secs_f64();
+        budget.balance = (budget.balance + elapsed * budget.min_per_second).min(MAX_BALANCE);
+        budget.last_refill = now;
+
+        if budget.balance >= 1.0 {
+            budget.balance -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl Error {
+    /// Returns whether the error is transient, and the request may succeed if
+    /// retried. [`Error::Undelivered`] is retryable if any of its batches is.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::NetworkError(err) => match err.status() {
+                Some(status) => {
+                    status.is_server_error()
+                        || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+                        || status == reqwest::StatusCode::REQUEST_TIMEOUT
+                }
+                // the other request errors, such as an invalid request or a
+                // failure while streaming the body, would fail again
+                #[cfg(not(target_arch = "wasm32"))]
+                None => err.is_connect() || err.is_timeout(),
+                // a failed fetch is only reported as a request error on wasm
+                #[cfg(target_arch = "wasm32")]
+                None => err.is_timeout() || err.is_request(),
+            },
+            Error::Undelivered { batches } => {
+                batches.iter().any(|batch| batch.error.is_retryable())
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::status_error;
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(1),
+        };
+        assert_eq!(policy.backoff(1), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(200));
+        assert_eq!(policy.backoff(4), Duration::from_millis(800));
+        assert_eq!(policy.backoff(5), Duration::from_secs(1));
+        assert_eq!(policy.backoff(100), Duration::from_secs(1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retry_budget() {
+        let budget = RetryBudget::new(0.5, 1).unwrap();
+        assert!(!budget.withdraw());
+
+        budget.deposit();
+        budget.deposit();
+        assert!(budget.withdraw());
+        assert!(!budget.withdraw());
+
+        tokio::time::advance(Duration::from_secs(2)).await;
+        assert!(budget.withdraw());
+        assert!(budget.withdraw());
+        assert!(!budget.withdraw());
+
+        assert!(RetryBudget::new(1.5, 0).is_err());
+    }
+
+    #[test]
+    fn test_retryable_errors() {
+        assert!(status_error(503).is_retryable());
+        assert!(status_error(429).is_retryable());
+        assert!(!status_error(400).is_retryable());
+        assert!(!Error::MessageTooLarge.is_retryable());
+
+        let invalid = reqwest::Client::new().get("not a url").build().unwrap_err();
+        assert!(!Error::NetworkError(invalid).is_retryable());
+    }
+}
@@ -0,0 +1,294 @@
+//! Filtering and transformation rules, loaded from the configuration.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::errors::Result;
+use crate::message::BatchMessage;
+
+/// The value replacing the redacted properties.
+pub const REDACTED: &str = "[REDACTED]";
+
+/// A list of rules applied, in order, to every pushed message, so that the
+/// flow of events can be adjusted from a configuration file without
+/// deploying code.
+///
+/// Every rule matching a message applies its action: the following rules see
+/// the renamed and redacted message, and a dropped message is not matched
+/// against the following rules. See
+/// [`Batcher::set_rules`](crate::Batcher::set_rules).
+///
+/// The rules deserialize from any format supported by serde, for instance
+/// from TOML with the `toml` crate, or from JSON with [Self::from_json]:
+///
+/// ```
+/// use segment::Rules;
+///
+/// let rules = Rules::from_json(r#"[
+///     { "match": { "event": "Debug Clicked" }, "action": "drop" },
+///     { "match": { "event": "signup" }, "action": "rename", "to": "Signed Up" },
+///     {
+///         "match": { "properties": { "plan": "enterprise" } },
+///         "action": "redact",
+///         "properties": ["email", "phone"]
+///     }
+/// ]"#).unwrap();
+/// assert_eq!(rules.len(), 3);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Rules(pub Vec<Rule>);
+
+/// A rule of [`Rules`].
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Rule {
+    /// Which messages the rule applies to.
+    #[serde(rename = "match", default)]
+    pub matcher: Matcher,
+
+    /// What to do with the matching messages.
+    #[serde(flatten)]
+    pub action: Action,
+}
+
+/// The conditions a message must all fulfil to match a [`Rule`]. An empty
+/// matcher matches every message.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Matcher {
+    /// The name of the event of a `track` message, or the name of a `page` or
+    /// `screen` message.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub event: Option<String>,
+
+    /// The properties (or traits) the message must have, with these values.
+    #[serde(skip_serializing_if = "Map::is_empty")]
+    pub properties: Map<String, Value>,
+}
+
+/// What a [`Rule`] does to the matching messages.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Action {
+    /// Drop the message.
+    Drop,
+
+    /// Rename the event (or the page, or the screen), and the given
+    /// properties (or traits), from their old name to the new one.
+    ///
+    /// A renamed property doesn't replace a property which already has the
+    /// new name, sent by the call sites already migrated.
+    Rename {
+        to: String,
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        properties: BTreeMap<String, String>,
+    },
+
+    /// Replace the value of these properties (or traits) with [`REDACTED`].
+    Redact { properties: Vec<String> },
+}
+
+impl Rules {
+    /// Parse rules from a JSON array.
+    pub fn from_json(json: &str) -> Result<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    /// Construct rules renaming the events from their old name to the new
+    /// one, for instance while the call sites of a tracking plan migration
+    /// are updated.
+    ///
+    /// ```
+    /// use segment::Rules;
+    ///
+    /// let rules = Rules::renames([("signup", "Signed Up"), ("login", "Logged In")]);
+    /// assert_eq!(rules.len(), 2);
+    /// ```
+    pub fn renames<I, K, V>(renames: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: Into<String>,
+    {
+        let rules = renames.into_iter().map(|(from, to)| Rule {
+            matcher: Matcher {
+                event: Some(from.into()),
+                ..Default::default()
+            },
+            action: Action::Rename {
+                to: to.into(),
+                properties: BTreeMap::new(),
+            },
+        });
+        Self(rules.collect())
+    }
+
+    /// Returns the number of rules.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Returns whether there is no rule.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Apply the rules selected by `filter` to `msg`, returns `false` if it
+    /// must be dropped.
+    pub(crate) fn apply(&self, msg: &mut BatchMessage, filter: impl Fn(&Rule) -> bool) -> bool {
+        for rule in self.0.iter().filter(|rule| filter(rule)) {
+            if !rule.matcher.matches(msg) {
+                continue;
+            }
+            match &rule.action {
+                Action::Drop => return false,
+                Action::Rename { to, properties } => {
+                    if let Some(name) = msg.event_name_mut() {
+                        name.clone_from(to);
+                    }
+                    if let Some(Value::Object(values)) = msg.properties_mut() {
+                        for (from, to) in properties {
+                            if let Some(value) = values.remove(from) {
+                                values.entry(to.as_str()).or_insert(value);
+                            }
+                        }
+                    }
+                }
+                Action::Redact { properties } => {
+                    if let Some(Value::Object(values)) = msg.properties_mut() {
+                        for property in properties {
+                            if let Some(value) = values.get_mut(property) {
+                                *value = Value::String(REDACTED.to_owned());
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        true
+    }
+}
+
+impl Rule {
+    /// Returns whether the rule depends on the properties of the messages.
+    pub(crate) fn reads_properties(&self) -> bool {
+        !self.matcher.properties.is_empty()
+            || match &self.action {
+                Action::Drop => false,
+                Action::Rename { properties, .. } => !properties.is_empty(),
+                Action::Redact { .. } => true,
+            }
+    }
+}
+
+impl Matcher {
+    fn matches(&self, msg: &BatchMessage) -> bool {
+        if let Some(event) = &self.event {
+            if msg.event_name() != Some(event.as_str()) {
+                return false;
+            }
+        }
+        if self.properties.is_empty() {
+            return true;
+        }
+        let Some(Value::Object(values)) = msg.properties() else {
+            return false;
+        };
+        self.properties
+            .iter()
+            .all(|(key, expected)| values.get(key) == Some(expected))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::{Identify, Track};
+    use serde_json::json;
+
+    fn track(event: &str, properties: Value) -> BatchMessage {
+        BatchMessage::Track(Track {
+            event: event.to_owned(),
+            properties,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_rules() {
+        let rules = Rules::from_json(
+            r#"[
+                { "match": { "event": "debug" }, "action": "drop" },
+                { "match": { "event": "signup" }, "action": "rename", "to": "Signed Up" },
+                {
+                    "match": { "properties": { "plan": "pro" } },
+                    "action": "redact",
+                    "properties": ["email"]
+                }
+            ]"#,
+        )
+        .unwrap();
+
+        assert!(!rules.apply(&mut track("debug", json!({})), |_| true));
+
+        let mut msg = track("signup", json!({ "plan": "pro", "email": "a@b.c" }));
+        assert!(rules.apply(&mut msg, |_| true));
+        assert_eq!(
+            msg,
+            track("Signed Up", json!({ "plan": "pro", "email": REDACTED }))
+        );
+
+        let mut msg = BatchMessage::Identify(Identify {
+            traits: json!({ "plan": "free", "email": "a@b.c" }),
+            ..Default::default()
+        });
+        let original = msg.clone();
+        assert!(rules.apply(&mut msg, |_| true));
+        assert_eq!(msg, original);
+    }
+
+    #[test]
+    fn test_renames() {
+        let mut rules = Rules::renames([("signup", "Signed Up")]);
+        rules.0.extend(
+            Rules::from_json(
+                r#"[{
+                    "match": { "event": "login" },
+                    "action": "rename",
+                    "to": "Logged In",
+                    "properties": { "method": "provider", "user": "userName" }
+                }]"#,
+            )
+            .unwrap()
+            .0,
+        );
+
+        let mut msg = track("signup", json!({ "plan": "pro" }));
+        assert!(rules.apply(&mut msg, |_| true));
+        assert_eq!(msg, track("Signed Up", json!({ "plan": "pro" })));
+
+        // the properties of the migrated call sites are kept
+        let mut msg = track(
+            "login",
+            json!({ "method": "google", "user": "ada", "userName": "lovelace" }),
+        );
+        assert!(rules.apply(&mut msg, |_| true));
+        assert_eq!(
+            msg,
+            track(
+                "Logged In",
+                json!({ "provider": "google", "userName": "lovelace" })
+            )
+        );
+        assert!(rules.0[1].reads_properties());
+        assert!(!rules.0[0].reads_properties());
+    }
+
+    #[test]
+    fn test_invalid_rules() {
+        assert!(Rules::from_json(r#"[{ "action": "explode" }]"#).is_err());
+        assert!(Rules::from_json(r#"[{ "action": "rename" }]"#).is_err());
+    }
+}
@@ -0,0 +1,339 @@
+//! The async runtime running the background tasks and the timers.
+
+use std::fmt;
+use std::future::Future;
+use std::pin::{pin, Pin};
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+use std::time::Duration;
+
+use futures_util::future::{self, Either};
+use tokio::sync::oneshot;
+
+#[cfg(all(not(feature = "tokio"), not(target_arch = "wasm32")))]
+pub(crate) use std::time::Instant;
+/// The clock of the deadlines and of the time windows: the one of tokio with
+/// the `tokio` feature, which the tests can pause.
+#[cfg(all(feature = "tokio", not(target_arch = "wasm32")))]
+pub(crate) use tokio::time::Instant;
+#[cfg(target_arch = "wasm32")]
+pub(crate) use web_time::Instant;
+
+/// Spawns the background tasks and runs the timers of the
+/// [`AutoBatcher`](crate::AutoBatcher) and of the [`Worker`](crate::Worker):
+/// the uploads, the retry backoffs, the rate limiting delays, the shutdown
+/// deadlines and the periodic flushes.
+///
+/// [`TokioRuntime`] is used by default, with the `tokio` feature. Another
+/// `Runtime` can spawn the tasks and run the timers elsewhere, for instance
+/// on a dedicated runtime or on async-std or smol, set with
+/// [`AutoBatcher::set_runtime`](crate::AutoBatcher::set_runtime). The
+/// channels and the semaphores of the batchers work with any runtime.
+///
+/// Without the `tokio` feature, and with [`TokioRuntime`] outside of a tokio
+/// runtime, the batches are uploaded in the foreground, the timers and the
+/// tasks of the workers run on threads of their own. The
+/// [`HttpClient`](crate::HttpClient) is built on reqwest and hyper, whose
+/// connections still need a tokio reactor: with another runtime, send from
+/// within a tokio runtime, or through a [`Client`](crate::Client) of your
+/// own.
+///
+/// ```
+/// use std::future::Future;
+/// use std::pin::Pin;
+/// use std::time::Duration;
+/// use segment::{AutoBatcher, Batcher, HttpClient, Runtime};
+/// use tokio::runtime::Handle;
+///
+/// /// Uploads on a dedicated tokio runtime.
+/// struct Dedicated(Handle);
+///
+/// impl Runtime for Dedicated {
+///     fn spawn(&self, task: Pin<Box<dyn Future<Output = ()> + Send>>) {
+///         self.0.spawn(task);
+///     }
+///
+///     fn sleep(&self, duration: Duration) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+///         // with the timers of the dedicated runtime
+///         let handle = self.0.clone();
+///         Box::pin(async move {
+///             let _ = handle.spawn(tokio::time::sleep(duration)).await;
+///         })
+///     }
+/// }
+///
+/// # fn run(handle: Handle) {
+/// let client = HttpClient::default();
+/// let mut batcher = AutoBatcher::new(client, Batcher::new(None), "your_write_key".to_string());
+/// batcher.set_runtime(Dedicated(handle));
+/// # }
+/// ```
+pub trait Runtime: Send + Sync {
+    /// Run `task` in the background, to completion.
+    fn spawn(&self, task: Pin<Box<dyn Future<Output = ()> + Send>>);
+
+    /// Returns a future completing after `duration`.
+    fn sleep(&self, duration: Duration) -> Pin<Box<dyn Future<Output = ()> + Send>>;
+
+    /// Returns whether [Self::spawn] runs the tasks on a runtime entered by
+    /// the current thread. Otherwise the batches are uploaded in the
+    /// foreground, and the messages of a dropped batcher are not sent with
+    /// the `flush-on-drop` feature.
+    fn can_spawn(&self) -> bool {
+        true
+    }
+}
+
+/// The default [`Runtime`], spawning the tasks on the tokio runtime they are
+/// spawned from.
+///
+/// Outside of a tokio runtime, for instance under another executor, the
+/// tasks and the timers run on threads of their own, see [`Runtime`].
+#[cfg(feature = "tokio")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TokioRuntime;
+
+#[cfg(feature = "tokio")]
+impl Runtime for TokioRuntime {
+    fn spawn(&self, task: Pin<Box<dyn Future<Output = ()> + Send>>) {
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) => drop(handle.spawn(task)),
+            Err(_) => Threads.spawn(task),
+        }
+    }
+
+    fn sleep(&self, duration: Duration) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+        match tokio::runtime::Handle::try_current() {
+            Ok(_) => Box::pin(tokio::time::sleep(duration)),
+            Err(_) => Threads.sleep(duration),
+        }
+    }
+
+    fn can_spawn(&self) -> bool {
+        tokio::runtime::Handle::try_current().is_ok()
+    }
+}
+
+/// The [`Runtime`] without an async runtime: each task runs on a thread of
+/// its own, and each timer waits on one.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct Threads;
+
+impl Runtime for Threads {
+    fn spawn(&self, task: Pin<Box<dyn Future<Output = ()> + Send>>) {
+        std::thread::spawn(move || block_on(task));
+    }
+
+    fn sleep(&self, duration: Duration) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+        let (done, slept) = oneshot::channel();
+        std::thread::spawn(move || {
+            std::thread::sleep(duration);
+            let _ = done.send(());
+        });
+        Box::pin(async move {
+            let _ = slept.await;
+        })
+    }
+
+    fn can_spawn(&self) -> bool {
+        false
+    }
+}
+
+/// Run `future` to completion on the current thread, parking it while the
+/// future is pending.
+pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+    struct Unpark(std::thread::Thread);
+
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    let waker = Waker::from(Arc::new(Unpark(std::thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    let mut future = pin!(future);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+        std::thread::park();
+    }
+}
+
+/// The [`Runtime`] of a batcher, shared with its uploads.
+#[derive(Clone)]
+pub(crate) struct SharedRuntime(Arc<dyn Runtime>);
+
+impl SharedRuntime {
+    pub(crate) fn new(runtime: impl Runtime + 'static) -> Self {
+        Self(Arc::new(runtime))
+    }
+
+    pub(crate) fn spawn(&self, task: impl Future<Output = ()> + Send + 'static) {
+        self.0.spawn(Box::pin(task));
+    }
+
+    pub(crate) fn can_spawn(&self) -> bool {
+        self.0.can_spawn()
+    }
+
+    pub(crate) async fn sleep(&self, duration: Duration) {
+        self.0.sleep(duration).await;
+    }
+
+    pub(crate) async fn sleep_until(&self, deadline: Instant) {
+        let duration = deadline.saturating_duration_since(Instant::now());
+        if !duration.is_zero() {
+            self.sleep(duration).await;
+        }
+    }
+
+    /// Returns the output of `future`, or `None` if it is not ready at
+    /// `deadline`.
+    pub(crate) async fn timeout_at<F: Future>(
+        &self,
+        deadline: Instant,
+        future: F,
+    ) -> Option<F::Output> {
+        // the future first, so that it completes even at the deadline
+        match future::select(pin!(future), pin!(self.sleep_until(deadline))).await {
+            Either::Left((output, _)) => Some(output),
+            Either::Right(((), _)) => None,
+        }
+    }
+}
+
+impl Default for SharedRuntime {
+    #[cfg(feature = "tokio")]
+    fn default() -> Self {
+        Self::new(TokioRuntime)
+    }
+
+    #[cfg(not(feature = "tokio"))]
+    fn default() -> Self {
+        Self::new(Threads)
+    }
+}
+
+impl fmt::Debug for SharedRuntime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SharedRuntime").finish_non_exhaustive()
+    }
+}
+
+/// Ticks every `period`, delaying the next ticks after a missed one rather
+/// than catching up with a burst.
+pub(crate) struct Interval {
+    runtime: SharedRuntime,
+    period: Duration,
+    next: Instant,
+    /// The timer of the next tick, kept across the cancelled waits so that a
+    /// single one runs per tick, see `Threads`.
+    sleep: Option<Pin<Box<dyn Future<Output = ()> + Send>>>,
+}
+
+impl Interval {
+    /// Returns an interval ticking first at `start`.
+    pub(crate) fn new(runtime: SharedRuntime, start: Instant, period: Duration) -> Self {
+        Self {
+            runtime,
+            period,
+            next: start,
+            sleep: None,
+        }
+    }
+
+    /// Wait for the next tick. Cancelling the wait doesn't skip the tick, nor
+    /// restart its timer.
+    pub(crate) async fn tick(&mut self) {
+        let duration = self.next.saturating_duration_since(Instant::now());
+        if self.sleep.is_none() && !duration.is_zero() {
+            self.sleep = Some(self.runtime.0.sleep(duration));
+        }
+        if let Some(sleep) = &mut self.sleep {
+            sleep.await;
+        }
+        self.sleep = None;
+        self.next = Instant::now() + self.period;
+    }
+}
+
+impl fmt::Debug for Interval {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Interval")
+            .field("period", &self.period)
+            .field("next", &self.next)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// A runtime counting its spawns and sleeps, delegating to tokio.
+    #[derive(Default)]
+    struct Counting {
+        spawned: Arc<AtomicUsize>,
+        slept: Arc<AtomicUsize>,
+    }
+
+    impl Runtime for Counting {
+        fn spawn(&self, task: Pin<Box<dyn Future<Output = ()> + Send>>) {
+            self.spawned.fetch_add(1, Ordering::Relaxed);
+            tokio::spawn(task);
+        }
+
+        fn sleep(&self, duration: Duration) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+            self.slept.fetch_add(1, Ordering::Relaxed);
+            Box::pin(tokio::time::sleep(duration))
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_timers() {
+        let counting = Counting::default();
+        let (spawned, slept) = (counting.spawned.clone(), counting.slept.clone());
+        let runtime = SharedRuntime::new(counting);
+
+        let (done, task) = tokio::sync::oneshot::channel();
+        runtime.spawn(async move { done.send(()).unwrap() });
+        task.await.unwrap();
+        assert_eq!(spawned.load(Ordering::Relaxed), 1);
+
+        let deadline = Instant::now() + Duration::from_secs(1);
+        assert_eq!(runtime.timeout_at(deadline, async { 1 }).await, Some(1));
+        let pending = std::future::pending::<()>();
+        assert_eq!(runtime.timeout_at(deadline, pending).await, None);
+        assert_eq!(Instant::now(), deadline);
+
+        let start = Instant::now();
+        let mut interval = Interval::new(runtime, start, Duration::from_secs(10));
+        interval.tick().await;
+        assert_eq!(Instant::now(), start);
+        interval.tick().await;
+        assert_eq!(Instant::now(), start + Duration::from_secs(10));
+        assert_eq!(slept.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn test_threads() {
+        let runtime = SharedRuntime::new(Threads);
+        assert!(!runtime.can_spawn());
+
+        let (done, task) = oneshot::channel();
+        runtime.spawn(async move { done.send(()).unwrap() });
+        block_on(async {
+            task.await.unwrap();
+
+            let start = std::time::Instant::now();
+            let deadline = Instant::now() + Duration::from_millis(10);
+            let pending = std::future::pending::<()>();
+            assert_eq!(runtime.timeout_at(deadline, pending).await, None);
+            assert!(start.elapsed() >= Duration::from_millis(10));
+        });
+    }
+}
@@ -0,0 +1,142 @@
+//! Deterministic sampling of the high-frequency events.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use sha2::{Digest, Sha256};
+
+use crate::errors::{Error, Result};
+use crate::message::BatchMessage;
+
+/// Only keeps a fraction of the events of some names, to control the volume
+/// and the cost of the high-frequency events.
+///
+/// The sampling is keyed on the user of the message, its user ID or else its
+/// anonymous ID: a given user is consistently kept or dropped for an event,
+/// across restarts and replicas, so that their funnels stay complete. It
+/// applies to the `track` events, and to the `page` and `screen` messages by
+/// their name.
+///
+/// A `Sampling` is cheap to clone, all the clones share the same count of
+/// dropped events.
+///
+/// ```
+/// use segment::{Batcher, Sampling};
+///
+/// let sampling = Sampling::new().event("Page Viewed", 0.1).unwrap();
+/// let mut batcher = Batcher::new(None);
+/// batcher.set_sampling(sampling);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Sampling {
+    rates: HashMap<String, f64>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl Sampling {
+    /// Construct a sampling keeping every event.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keep the fraction `rate` of the users sending the events named
+    /// `event`, between `0.0` (drop all of them) and `1.0` (keep all of them).
+    ///
+    /// Returns [`Error::InvalidConfiguration`] if `rate` is out of range.
+    pub fn event(mut self, event: impl Into<String>, rate: f64) -> Result<Self> {
+        if !(0.0..=1.0).contains(&rate) {
+            return Err(Error::InvalidConfiguration(format!(
+                "the sampling rate must be between 0 and 1, got {rate}"
+            )));
+        }
+        self.rates.insert(event.into(), rate);
+        Ok(self)
+    }
+
+    /// Returns the number of events dropped so far.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Returns whether `msg` should be kept, counting it otherwise.
+    pub(crate) fn allow(&self, msg: &BatchMessage) -> bool {
+        let Some(event) = msg.event_name() else {
+            return true;
+        };
+        let Some(&rate) = self.rates.get(event) else {
+            return true;
+        };
+        let keep = position(event, &msg.user().to_string()) < rate;
+        if !keep {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        keep
+    }
+
+    /// Returns a copy of this sampling counting its drops apart.
+    pub(crate) fn detached(&self) -> Self {
+        Self {
+            rates: self.rates.clone(),
+            dropped: Arc::default(),
+        }
+    }
+}
+
+/// Returns the position of `user` for `event`, uniformly distributed in
+/// `[0, 1)`.
+fn position(event: &str, user: &str) -> f64 {
+    let digest = Sha256::digest(format!("{event}:{user}"));
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&digest[..8]);
+    // the 53 bits a `f64` represents exactly
+    (u64::from_be_bytes(bytes) >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::{Track, User};
+
+    fn track(event: &str, user: usize) -> BatchMessage {
+        BatchMessage::Track(Track {
+            user: User::UserId {
+                user_id: format!("user-{user}"),
+            },
+            event: event.to_owned(),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_sampling() {
+        let sampling = Sampling::new().event("Page Viewed", 0.1).unwrap();
+
+        let kept: Vec<usize> = (0..10_000)
+            .filter(|&user| sampling.allow(&track("Page Viewed", user)))
+            .collect();
+        assert!((900..1100).contains(&kept.len()), "{}", kept.len());
+        assert_eq!(sampling.dropped(), 10_000 - kept.len() as u64);
+
+        // the same users are kept every time
+        for &user in &kept {
+            assert!(sampling.allow(&track("Page Viewed", user)));
+        }
+        // the other events are not sampled
+        assert!((0..100).all(|user| sampling.allow(&track("Signed Up", user))));
+    }
+
+    #[test]
+    fn test_rates() {
+        let none = Sampling::new().event("Page Viewed", 0.0).unwrap();
+        let all = Sampling::new().event("Page Viewed", 1.0).unwrap();
+        assert!((0..100).all(|user| !none.allow(&track("Page Viewed", user))));
+        assert!((0..100).all(|user| all.allow(&track("Page Viewed", user))));
+
+        assert!(matches!(
+            Sampling::new().event("Page Viewed", 1.5),
+            Err(Error::InvalidConfiguration(_))
+        ));
+        assert!(Sampling::new().event("Page Viewed", f64::NAN).is_err());
+    }
+}
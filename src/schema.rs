@@ -0,0 +1,299 @@
+//! Inference of the property schema of the tracked events.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::message::BatchMessage;
+use crate::warning::{Warning, Warnings};
+
+/// The type of a JSON value.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JsonType {
+    Null,
+    Bool,
+    Number,
+    /// A number without a fractional part, only expected by a schema: the
+    /// type of a value is [JsonType::Number].
+    Integer,
+    String,
+    Array,
+    Object,
+}
+
+impl JsonType {
+    /// Returns the type of `value`.
+    pub fn of(value: &Value) -> Self {
+        match value {
+            Value::Null => JsonType::Null,
+            Value::Bool(_) => JsonType::Bool,
+            Value::Number(_) => JsonType::Number,
+            Value::String(_) => JsonType::String,
+            Value::Array(_) => JsonType::Array,
+            Value::Object(_) => JsonType::Object,
+        }
+    }
+
+    /// Returns whether `value` has one of `types`, any value matching no
+    /// types. An integer matches [JsonType::Integer].
+    pub(crate) fn matches(types: &BTreeSet<JsonType>, value: &Value) -> bool {
+        types.is_empty()
+            || types.contains(&JsonType::of(value))
+            || (types.contains(&JsonType::Integer) && (value.is_i64() || value.is_u64()))
+    }
+}
+
+impl fmt::Display for JsonType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            JsonType::Null => "null",
+            JsonType::Bool => "bool",
+            JsonType::Number => "number",
+            JsonType::Integer => "integer",
+            JsonType::String => "string",
+            JsonType::Array => "array",
+            JsonType::Object => "object",
+        };
+        f.write_str(name)
+    }
+}
+
+/// A change of the schema of an event, reported as a
+/// [`Warning::SchemaDrift`].
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum SchemaChange {
+    /// A property that was never seen before on this event appeared.
+    NewProperty { found: JsonType },
+
+    /// A property was seen with a type it never had before.
+    TypeChanged {
+        expected: BTreeSet<JsonType>,
+        found: JsonType,
+    },
+}
+
+impl fmt::Display for SchemaChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SchemaChange::NewProperty { found } => write!(f, "new property of type {found}"),
+            SchemaChange::TypeChanged { expected, found } => {
+                let expected: Vec<_> = expected.iter().map(JsonType::to_string).collect();
+                write!(f, "expected {}, found {found}", expected.join(" or "))
+            }
+        }
+    }
+}
+
+/// The schema observed for an event.
+#[derive(PartialEq, Eq, Debug, Clone, Default, Serialize)]
+pub struct EventSchema {
+    /// How many times the event was seen.
+    pub count: u64,
+
+    /// The properties of the event.
+    pub properties: BTreeMap<String, PropertySchema>,
+}
+
+/// The schema observed for a property of an event.
+#[derive(PartialEq, Eq, Debug, Clone, Default, Serialize)]
+pub struct PropertySchema {
+    /// The types the property was seen with.
+    pub types: BTreeSet<JsonType>,
+
+    /// How many times the property was present.
+    pub count: u64,
+}
+
+impl EventSchema {
+    /// Returns whether the property is missing from some of the events.
+    pub fn is_optional(&self, property: &str) -> bool {
+        self.properties
+            .get(property)
+            .is_none_or(|schema| schema.count < self.count)
+    }
+}
+
+/// Records the schema of the properties of the `track` events, and reports
+/// the drift (new property, type change) as [`Warning`]s. Only the events
+/// enqueued in a batch are recorded, once each.
+///
+/// A `SchemaTracker` is cheap to clone, all the clones share the same
+/// observations. Keep one around to inspect the inferred schemas:
+///
+/// ```
+/// use segment::{Batcher, SchemaTracker};
+/// use segment::message::Track;
+/// use serde_json::json;
+///
+/// let schemas = SchemaTracker::default();
+/// let mut batcher = Batcher::new(None);
+/// batcher.set_schema_tracker(schemas.clone());
+///
+/// batcher.push(Track {
+///     event: "Signed Up".to_owned(),
+///     properties: json!({ "plan": "pro" }),
+///     ..Default::default()
+/// }).unwrap();
+///
+/// let schema = schemas.event("Signed Up").unwrap();
+/// assert_eq!(schema.count, 1);
+/// assert!(!schema.is_optional("plan"));
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct SchemaTracker {
+    events: Arc<Mutex<BTreeMap<String, EventSchema>>>,
+}
+
+impl SchemaTracker {
+    /// Returns the schema observed so far for `event`.
+    pub fn event(&self, event: &str) -> Option<EventSchema> {
+        self.events.lock().unwrap().get(event).cloned()
+    }
+
+    /// Returns the schemas observed so far for every event.
+    pub fn snapshot(&self) -> BTreeMap<String, EventSchema> {
+        self.events.lock().unwrap().clone()
+    }
+
+    /// Returns the shape of `msg` to [record](Self::record) once the message
+    /// is enqueued, `None` if it is not a `track` event.
+    pub(crate) fn shape(msg: &BatchMessage) -> Option<EventShape> {
+        let BatchMessage::Track(track) = msg else {
+            return None;
+        };
+        let properties = match &track.properties {
+            Value::Object(properties) => properties
+                .iter()
+                .map(|(name, value)| (name.clone(), JsonType::of(value)))
+                .collect(),
+            _ => Vec::new(),
+        };
+        Some(EventShape {
+            event: track.event.clone(),
+            properties,
+        })
+    }
+
+    pub(crate) fn record(&self, shape: EventShape, warnings: &Warnings) {
+        let mut events = self.events.lock().unwrap();
+        let schema = events.entry(shape.event.clone()).or_default();
+        let first_seen = schema.count == 0;
+        schema.count += 1;
+
+        for (name, found) in shape.properties {
+            let property = schema.properties.entry(name.clone()).or_default();
+
+            let change = if property.count == 0 {
+                (!first_seen).then_some(SchemaChange::NewProperty { found })
+            } else if !property.types.contains(&found) {
+                Some(SchemaChange::TypeChanged {
+                    expected: property.types.clone(),
+                    found,
+                })
+            } else {
+                None
+            };
+
+            property.count += 1;
+            property.types.insert(found);
+
+            if let Some(change) = change {
+                warnings.emit(Warning::SchemaDrift {
+                    event: shape.event.clone(),
+                    property: name,
+                    change,
+                });
+            }
+        }
+    }
+
+    /// Returns a copy of the schemas observed so far, recording apart.
+    pub(crate) fn detached(&self) -> Self {
+        Self {
+            events: Arc::new(Mutex::new(self.snapshot())),
+        }
+    }
+}
+
+/// The event name and the property types of a processed `track` event,
+/// recorded by the [`SchemaTracker`] once the event is enqueued.
+#[derive(Debug)]
+pub(crate) struct EventShape {
+    event: String,
+    properties: Vec<(String, JsonType)>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::Track;
+    use serde_json::json;
+
+    fn track(properties: Value) -> BatchMessage {
+        BatchMessage::Track(Track {
+            event: "Signed Up".to_owned(),
+            properties,
+            ..Default::default()
+        })
+    }
+
+    fn observe(tracker: &SchemaTracker, msg: &BatchMessage, warnings: &Warnings) {
+        tracker.record(SchemaTracker::shape(msg).unwrap(), warnings);
+    }
+
+    #[test]
+    fn test_drift() {
+        let reported = Arc::new(Mutex::new(Vec::new()));
+        let mut warnings = Warnings::default();
+        let sink = reported.clone();
+        warnings.set_handler(Arc::new(move |warning| {
+            sink.lock().unwrap().push(warning.clone())
+        }));
+
+        let tracker = SchemaTracker::default();
+        observe(
+            &tracker,
+            &track(json!({ "plan": "pro", "seats": 3 })),
+            &warnings,
+        );
+        observe(&tracker, &track(json!({ "plan": "free" })), &warnings);
+        assert!(reported.lock().unwrap().is_empty());
+
+        observe(
+            &tracker,
+            &track(json!({ "plan": 1, "coupon": "x" })),
+            &warnings,
+        );
+        let reported = reported.lock().unwrap();
+        assert_eq!(
+            *reported,
+            vec![
+                Warning::SchemaDrift {
+                    event: "Signed Up".to_owned(),
+                    property: "coupon".to_owned(),
+                    change: SchemaChange::NewProperty {
+                        found: JsonType::String
+                    },
+                },
+                Warning::SchemaDrift {
+                    event: "Signed Up".to_owned(),
+                    property: "plan".to_owned(),
+                    change: SchemaChange::TypeChanged {
+                        expected: [JsonType::String].into(),
+                        found: JsonType::Number,
+                    },
+                },
+            ]
+        );
+
+        let schema = tracker.event("Signed Up").unwrap();
+        assert_eq!(schema.count, 3);
+        assert!(!schema.is_optional("plan"));
+        assert!(schema.is_optional("seats"));
+        assert!(schema.is_optional("coupon"));
+    }
+}
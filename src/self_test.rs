@@ -0,0 +1,152 @@
+//! A smoke test of the whole pipeline, for deployments.
+
+use std::time::Duration;
+
+use serde_json::json;
+
+use crate::{
+    auto_batcher::AutoBatcher,
+    batcher::Batcher,
+    client::Client,
+    errors::Result,
+    message::{BatchMessage, Track, User},
+    runtime::Instant,
+};
+
+/// The name of the synthetic event sent by a self-test.
+pub const SELF_TEST_EVENT: &str = "Segment Self Test";
+
+/// What [`AutoBatcher::self_test`] checks.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SelfTestMode {
+    /// Only build and serialize a synthetic batch, without any network
+    /// access.
+    #[default]
+    DryRun,
+
+    /// Also send the synthetic batch to Segment, which checks the endpoint
+    /// and the write key.
+    ///
+    /// The event is sent with all the integrations disabled, which Segment
+    /// honors for most destinations, but not for all of them: the warehouses,
+    /// for instance, still receive it. Filter [`SELF_TEST_EVENT`] out of those
+    /// destinations to keep it away from them.
+    Send,
+}
+
+/// The outcome of a self-test.
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct SelfTestReport {
+    /// The size of the serialized synthetic batch, or why it could not be
+    /// built.
+    pub serialization: Result<usize>,
+
+    /// How long the synthetic batch took to be accepted by Segment, or why it
+    /// was rejected. `None` in [`SelfTestMode::DryRun`].
+    pub delivery: Option<Result<Duration>>,
+}
+
+impl SelfTestReport {
+    /// Returns whether every check succeeded.
+    pub fn is_ok(&self) -> bool {
+        self.serialization.is_ok() && self.delivery.as_ref().is_none_or(Result::is_ok)
+    }
+}
+
+impl<C> AutoBatcher<C>
+where
+    C: Client + Clone + Send + Sync + 'static,
+{
+    /// Check that the pipeline works with a synthetic `track` event named
+    /// [`SELF_TEST_EVENT`], for instance in the smoke tests of a deployment.
+    ///
+    /// The synthetic event is neither buffered nor processed by the batcher
+    /// (no event limit, no schema tracking), and is sent without retries nor
+    /// rate limiting. In [`SelfTestMode::Send`] it is sent with all the
+    /// integrations disabled, which keeps it away from most destinations, but
+    /// not from all of them, see [`SelfTestMode::Send`].
+    ///
+    /// ```
+    /// use segment::{AutoBatcher, Batcher, HttpClient, SelfTestMode};
+    ///
+    /// # async fn run() {
+    /// let client = HttpClient::default();
+    /// let batcher = AutoBatcher::new(client, Batcher::new(None), "your_write_key".to_string());
+    ///
+    /// let report = batcher.self_test(SelfTestMode::Send).await;
+    /// assert!(report.is_ok(), "{report:?}");
+    /// # }
+    /// ```
+    pub async fn self_test(&self, mode: SelfTestMode) -> SelfTestReport {
+        let mut batcher = Batcher::new(self.batcher.context.clone());
+        let serialization = batcher
+            .set_batch_limits(self.batcher.limits)
+            .and_then(|()| match mode {
+                SelfTestMode::DryRun => Ok(()),
+                SelfTestMode::Send => batcher.set_integrations(Some(json!({ "All": false }))),
+            })
+            .and_then(|()| {
+                let msg = batcher.serialize(&synthetic_event())?;
+                batcher.enqueue(msg);
+                Ok(batcher.byte_count)
+            });
+
+        let delivery = match (mode, &serialization) {
+            (SelfTestMode::DryRun, _) | (SelfTestMode::Send, Err(_)) => None,
+            (SelfTestMode::Send, Ok(_)) => {
+                let batch = batcher.into_serialized();
+                let start = Instant::now();
+                let result = self.client.send_batch(self.key.clone(), &batch).await;
+                Some(result.map(|()| start.elapsed()))
+            }
+        };
+
+        SelfTestReport {
+            serialization,
+            delivery,
+        }
+    }
+}
+
+fn synthetic_event() -> BatchMessage {
+    BatchMessage::Track(Track {
+        user: User::AnonymousId {
+            anonymous_id: "segment-self-test".to_owned(),
+        },
+        event: SELF_TEST_EVENT.to_owned(),
+        properties: json!({ "selfTest": true }),
+        timestamp: Some(time::OffsetDateTime::now_utc()),
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MockClient;
+    use crate::Message;
+    use std::sync::atomic::Ordering;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_self_test() {
+        let client = MockClient::default();
+        let batcher = AutoBatcher::new(client.clone(), Batcher::new(None), "key".to_owned());
+
+        let report = batcher.self_test(SelfTestMode::DryRun).await;
+        assert!(report.is_ok());
+        assert!(report.delivery.is_none());
+        assert_eq!(client.sent_count(), 0);
+
+        let report = batcher.self_test(SelfTestMode::Send).await;
+        assert!(matches!(report.delivery, Some(Ok(_))));
+        let Message::Batch(batch) = client.sent.lock().unwrap()[0].clone() else {
+            panic!("expected a batch");
+        };
+        assert_eq!(batch.integrations, Some(json!({ "All": false })));
+
+        client.failures.store(1, Ordering::SeqCst);
+        let report = batcher.self_test(SelfTestMode::Send).await;
+        assert!(!report.is_ok());
+    }
+}
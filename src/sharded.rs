@@ -0,0 +1,118 @@
+//! A client distributing the requests across several clients.
+
+use std::num::NonZeroU32;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::{
+    client::Client,
+    errors::{Error, Result},
+    http::HttpClient,
+    message::{Message, SerializedBatch},
+};
+
+/// A [`Client`] distributing the requests across several clients in
+/// proportion to their weights, for instance [`HttpClient`]s with distinct
+/// connection pools or egress IPs, to get past the throughput ceiling of a
+/// single connection during large imports.
+///
+/// The requests are assigned in a round-robin over the weights: with weights
+/// 2 and 1, every three requests the first client sends two and the second
+/// one. The rotation is shared by the clones of the client.
+///
+/// ```
+/// use std::num::NonZeroU32;
+/// use segment::{AutoBatcher, Batcher, HttpClient, ShardedClient};
+///
+/// let shards = (0..4).map(|_| (HttpClient::default(), NonZeroU32::new(1).unwrap()));
+/// let client = ShardedClient::new(shards).unwrap();
+///
+/// let batcher = AutoBatcher::new(client, Batcher::new(None), "your_write_key".to_string());
+/// ```
+#[derive(Clone, Debug)]
+pub struct ShardedClient<C = HttpClient> {
+    shards: Arc<[(C, u64)]>,
+    total_weight: u64,
+    next: Arc<AtomicU64>,
+}
+
+impl<C> ShardedClient<C> {
+    /// Construct a client distributing the requests across `shards`, pairs
+    /// of a client and its weight.
+    ///
+    /// Returns an error if there is no shard.
+    pub fn new(shards: impl IntoIterator<Item = (C, NonZeroU32)>) -> Result<Self> {
+        let shards: Arc<[(C, u64)]> = shards
+            .into_iter()
+            .map(|(client, weight)| (client, u64::from(weight.get())))
+            .collect();
+        if shards.is_empty() {
+            return Err(Error::InvalidConfiguration(
+                "a sharded client needs at least one shard".to_owned(),
+            ));
+        }
+        Ok(Self {
+            total_weight: shards.iter().map(|(_, weight)| weight).sum(),
+            shards,
+            next: Arc::default(),
+        })
+    }
+
+    /// Returns the number of shards.
+    pub fn len(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Returns whether there is no shard, which never happens.
+    pub fn is_empty(&self) -> bool {
+        self.shards.is_empty()
+    }
+
+    /// Returns the client of the next request.
+    fn pick(&self) -> &C {
+        let mut slot = self.next.fetch_add(1, Ordering::Relaxed) % self.total_weight;
+        for (client, weight) in self.shards.iter() {
+            if slot < *weight {
+                return client;
+            }
+            slot -= weight;
+        }
+        unreachable!("the slot is lower than the total weight")
+    }
+}
+
+impl<C> Client for ShardedClient<C>
+where
+    C: Client + Send + Sync,
+{
+    async fn send(&self, write_key: String, msg: Message) -> Result<()> {
+        self.pick().send(write_key, msg).await
+    }
+
+    async fn send_batch(&self, write_key: String, batch: &SerializedBatch) -> Result<()> {
+        self.pick().send_batch(write_key, batch).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MockClient;
+    use crate::Batcher;
+
+    #[tokio::test]
+    async fn test_weighted_shards() {
+        let clients = [MockClient::default(), MockClient::default()];
+        let weights = [3, 1].map(|weight| NonZeroU32::new(weight).unwrap());
+        let client = ShardedClient::new(clients.clone().into_iter().zip(weights)).unwrap();
+        let batch = Batcher::new(None).into_serialized();
+
+        for _ in 0..8 {
+            client.send_batch("key".to_owned(), &batch).await.unwrap();
+        }
+        assert_eq!(clients[0].sent.lock().unwrap().len(), 6);
+        assert_eq!(clients[1].sent.lock().unwrap().len(), 2);
+
+        assert!(ShardedClient::<MockClient>::new([]).is_err());
+    }
+}
@@ -0,0 +1,123 @@
+//! An [`AutoBatcher`] that can be shared between tasks.
+
+use std::sync::Arc;
+
+use tokio::sync::{broadcast, Mutex};
+
+use crate::{
+    auto_batcher::AutoBatcher, client::Client, delivery::DeliveryEvent, errors::Result,
+    http::HttpClient, message::BatchMessage,
+};
+
+/// A handle to an [`AutoBatcher`] that is `Clone + Send + Sync`, and can thus
+/// be stored directly in the state of a web framework.
+///
+/// All the clones push into the same underlying `AutoBatcher`, which is
+/// protected by an asynchronous mutex.
+///
+/// With many producers, the mutex becomes contended: prefer a
+/// [`Worker`](crate::Worker), whose queue is sharded and flushed by a single
+/// task, see `cargo bench --bench producers`.
+///
+/// ```
+/// use segment::{AutoBatcher, Batcher, HttpClient, SharedAutoBatcher};
+/// use segment::message::{Track, User};
+///
+/// let client = HttpClient::default();
+/// let batcher = Batcher::new(None);
+/// let batcher = AutoBatcher::new(client, batcher, "your_write_key".to_string());
+/// let shared = SharedAutoBatcher::new(batcher);
+///
+/// let handle = shared.clone();
+/// let msg = Track {
+///     user: User::UserId { user_id: String::from("user") },
+///     event: "Example".to_owned(),
+///     ..Default::default()
+/// };
+/// handle.push(msg); // .await
+/// ```
+#[derive(Clone, Debug)]
+pub struct SharedAutoBatcher<C = HttpClient>
+where
+    C: Client + Clone + Send + Sync + 'static,
+{
+    inner: Arc<Mutex<AutoBatcher<C>>>,
+}
+
+impl<C> SharedAutoBatcher<C>
+where
+    C: Client + Clone + Send + Sync + 'static,
+{
+    /// Wrap `batcher` so that it can be shared.
+    pub fn new(batcher: AutoBatcher<C>) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(batcher)),
+        }
+    }
+
+    /// Push a message into the batcher, see [`AutoBatcher::push`].
+    pub async fn push(&self, msg: impl Into<BatchMessage>) -> Result<()> {
+        self.inner.lock().await.push(msg).await
+    }
+
+    /// Send all the buffered messages, see [`AutoBatcher::flush`].
+    pub async fn flush(&self) -> Result<()> {
+        self.inner.lock().await.flush().await
+    }
+
+    /// Subscribe to the delivery events, see [`AutoBatcher::subscribe`].
+    pub async fn subscribe(&self) -> broadcast::Receiver<DeliveryEvent> {
+        self.inner.lock().await.subscribe()
+    }
+
+    /// Returns the number of messages in the batch buffer.
+    pub async fn len(&self) -> usize {
+        self.inner.lock().await.len()
+    }
+
+    /// Returns whether the batch is empty.
+    pub async fn is_empty(&self) -> bool {
+        self.inner.lock().await.is_empty()
+    }
+}
+
+impl<C> From<AutoBatcher<C>> for SharedAutoBatcher<C>
+where
+    C: Client + Clone + Send + Sync + 'static,
+{
+    fn from(batcher: AutoBatcher<C>) -> Self {
+        Self::new(batcher)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::Track;
+    use crate::Batcher;
+
+    fn assert_shareable<T: Clone + Send + Sync + 'static>() {}
+
+    #[test]
+    fn test_shareable() {
+        assert_shareable::<SharedAutoBatcher>();
+    }
+
+    #[tokio::test]
+    async fn test_push_from_tasks() {
+        let batcher = AutoBatcher::new(HttpClient::default(), Batcher::new(None), "key".to_owned());
+        let shared = SharedAutoBatcher::new(batcher);
+
+        let tasks: Vec<_> = (0..10)
+            .map(|_| {
+                let shared = shared.clone();
+                tokio::spawn(async move { shared.push(Track::default()).await })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap().unwrap();
+        }
+
+        assert_eq!(shared.len().await, 10);
+    }
+}
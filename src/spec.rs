@@ -0,0 +1,5 @@
+//! Typed events of the [Segment specs](https://segment.com/docs/connections/spec/semantic/),
+//! so that their names and properties always match the spec.
+
+pub mod ecommerce;
+pub mod mobile;
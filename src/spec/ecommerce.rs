@@ -0,0 +1,214 @@
+//! The [e-commerce spec](https://segment.com/docs/connections/spec/ecommerce/v2/).
+//!
+//! ```
+//! use segment::message::User;
+//! use segment::TrackEvent;
+//! use segment::spec::ecommerce::{OrderCompleted, Product};
+//! use serde_json::json;
+//!
+//! let order = OrderCompleted {
+//!     order_id: Some("50314b8e".to_owned()),
+//!     revenue: Some(25.0),
+//!     currency: Some("USD".to_owned()),
+//!     products: vec![Product {
+//!         product_id: Some("507f1f77".to_owned()),
+//!         price: Some(12.5),
+//!         quantity: Some(2),
+//!         ..Default::default()
+//!     }],
+//!     ..Default::default()
+//! };
+//!
+//! let track = order.into_track(User::UserId { user_id: "user".to_owned() });
+//! assert_eq!(track.event, "Order Completed");
+//! assert_eq!(
+//!     track.properties,
+//!     json!({
+//!         "order_id": "50314b8e",
+//!         "revenue": 25.0,
+//!         "currency": "USD",
+//!         "products": [{ "product_id": "507f1f77", "price": 12.5, "quantity": 2 }],
+//!     })
+//! );
+//! ```
+
+use serde::{Deserialize, Serialize};
+
+use crate::TrackEvent;
+
+/// A product, the properties of the product events and an item of the
+/// orders.
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize, Default)]
+pub struct Product {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub product_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sku: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub category: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub brand: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub variant: Option<String>,
+    /// The price of one unit, in the currency of the event.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub price: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quantity: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub coupon: Option<String>,
+    /// The position of the product in a list.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub position: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image_url: Option<String>,
+}
+
+/// `Product Viewed`: a user viewed the details of a product.
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize, Default)]
+pub struct ProductViewed {
+    #[serde(flatten)]
+    pub product: Product,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub currency: Option<String>,
+    /// The monetary value of the event.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<f64>,
+}
+
+impl TrackEvent for ProductViewed {
+    const EVENT: &'static str = "Product Viewed";
+}
+
+/// `Product Added`: a user added a product to their cart.
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize, Default)]
+pub struct ProductAdded {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cart_id: Option<String>,
+    #[serde(flatten)]
+    pub product: Product,
+}
+
+impl TrackEvent for ProductAdded {
+    const EVENT: &'static str = "Product Added";
+}
+
+/// `Checkout Started`: a user started the checkout of their cart.
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize, Default)]
+pub struct CheckoutStarted {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub order_id: Option<String>,
+    /// The store or affiliation the order comes from.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub affiliation: Option<String>,
+    /// The revenue, with the shipping and taxes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<f64>,
+    /// The revenue, without the shipping and taxes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub revenue: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shipping: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tax: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub discount: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub coupon: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub currency: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub products: Vec<Product>,
+}
+
+impl TrackEvent for CheckoutStarted {
+    const EVENT: &'static str = "Checkout Started";
+}
+
+/// `Order Completed`: a user completed an order.
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize, Default)]
+pub struct OrderCompleted {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub checkout_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub order_id: Option<String>,
+    /// The store or affiliation the order comes from.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub affiliation: Option<String>,
+    /// The revenue, with the shipping and taxes, minus the discount.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total: Option<f64>,
+    /// The revenue, with the discount, without the shipping and taxes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subtotal: Option<f64>,
+    /// The revenue, without the shipping and taxes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub revenue: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shipping: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tax: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub discount: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub coupon: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub currency: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub products: Vec<Product>,
+}
+
+impl TrackEvent for OrderCompleted {
+    const EVENT: &'static str = "Order Completed";
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::User;
+    use serde_json::json;
+
+    #[test]
+    fn test_product_events() {
+        let user = || User::AnonymousId {
+            anonymous_id: "anonymous".to_owned(),
+        };
+        let product = Product {
+            product_id: Some("507f1f77".to_owned()),
+            image_url: Some("https://example.com/p.png".to_owned()),
+            ..Default::default()
+        };
+
+        let track = ProductAdded {
+            cart_id: Some("cart".to_owned()),
+            product: product.clone(),
+        }
+        .into_track(user());
+        assert_eq!(track.event, "Product Added");
+        assert_eq!(
+            track.properties,
+            json!({
+                "cart_id": "cart",
+                "product_id": "507f1f77",
+                "image_url": "https://example.com/p.png",
+            })
+        );
+
+        let track = ProductViewed {
+            product,
+            value: Some(9.99),
+            ..Default::default()
+        }
+        .into_track(user());
+        assert_eq!(track.event, "Product Viewed");
+        assert_eq!(track.properties["value"], json!(9.99));
+
+        let track = CheckoutStarted::default().into_track(user());
+        assert_eq!(track.event, "Checkout Started");
+        assert_eq!(track.properties, json!({}));
+    }
+}
@@ -0,0 +1,175 @@
+//! The application lifecycle events of the [mobile
+//! spec](https://segment.com/docs/connections/spec/mobile/).
+//!
+//! ```
+//! use segment::message::User;
+//! use segment::TrackEvent;
+//! use segment::spec::mobile::ApplicationUpdated;
+//! use serde_json::json;
+//!
+//! let track = ApplicationUpdated::new("1.2.0", "120", "1.1.0", "110")
+//!     .into_track(User::AnonymousId { anonymous_id: "device".to_owned() });
+//! assert_eq!(track.event, "Application Updated");
+//! assert_eq!(
+//!     track.properties,
+//!     json!({
+//!         "version": "1.2.0",
+//!         "build": "120",
+//!         "previous_version": "1.1.0",
+//!         "previous_build": "110",
+//!     })
+//! );
+//! ```
+
+use serde::{Deserialize, Serialize};
+
+use crate::TrackEvent;
+
+/// `Application Installed`: the application was opened for the first time.
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize, Default)]
+pub struct ApplicationInstalled {
+    pub version: String,
+    pub build: String,
+}
+
+impl ApplicationInstalled {
+    /// Construct the event of the install of `version` and `build`.
+    pub fn new(version: impl Into<String>, build: impl Into<String>) -> Self {
+        Self {
+            version: version.into(),
+            build: build.into(),
+        }
+    }
+}
+
+impl TrackEvent for ApplicationInstalled {
+    const EVENT: &'static str = "Application Installed";
+}
+
+/// `Application Opened`: the application was launched, or brought to the
+/// foreground.
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize, Default)]
+pub struct ApplicationOpened {
+    /// Whether the application was in the background, rather than launched.
+    pub from_background: bool,
+    /// The application which opened this one, like through a deep link.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub referring_application: Option<String>,
+    /// The URL which opened the application.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    pub version: String,
+    pub build: String,
+}
+
+impl ApplicationOpened {
+    /// Construct the event of the launch of `version` and `build`.
+    pub fn new(version: impl Into<String>, build: impl Into<String>) -> Self {
+        Self {
+            version: version.into(),
+            build: build.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Construct the event of `version` and `build` being brought to the
+    /// foreground.
+    pub fn from_background(version: impl Into<String>, build: impl Into<String>) -> Self {
+        Self {
+            from_background: true,
+            ..Self::new(version, build)
+        }
+    }
+}
+
+impl TrackEvent for ApplicationOpened {
+    const EVENT: &'static str = "Application Opened";
+}
+
+/// `Application Backgrounded`: the application was sent to the background.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Serialize, Deserialize, Default)]
+pub struct ApplicationBackgrounded {}
+
+impl ApplicationBackgrounded {
+    /// Construct the event.
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl TrackEvent for ApplicationBackgrounded {
+    const EVENT: &'static str = "Application Backgrounded";
+}
+
+/// `Application Updated`: the application was opened for the first time
+/// after an update.
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize, Default)]
+pub struct ApplicationUpdated {
+    pub version: String,
+    pub build: String,
+    pub previous_version: String,
+    pub previous_build: String,
+}
+
+impl ApplicationUpdated {
+    /// Construct the event of the update from `previous_version` and
+    /// `previous_build` to `version` and `build`.
+    pub fn new(
+        version: impl Into<String>,
+        build: impl Into<String>,
+        previous_version: impl Into<String>,
+        previous_build: impl Into<String>,
+    ) -> Self {
+        Self {
+            version: version.into(),
+            build: build.into(),
+            previous_version: previous_version.into(),
+            previous_build: previous_build.into(),
+        }
+    }
+}
+
+impl TrackEvent for ApplicationUpdated {
+    const EVENT: &'static str = "Application Updated";
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::User;
+    use serde_json::json;
+
+    #[test]
+    fn test_lifecycle_events() {
+        let user = || User::AnonymousId {
+            anonymous_id: "device".to_owned(),
+        };
+
+        let track = ApplicationInstalled::new("1.0.0", "100").into_track(user());
+        assert_eq!(track.event, "Application Installed");
+        assert_eq!(
+            track.properties,
+            json!({ "version": "1.0.0", "build": "100" })
+        );
+
+        let track = ApplicationOpened {
+            url: Some("app://home".to_owned()),
+            ..ApplicationOpened::from_background("1.0.0", "100")
+        }
+        .into_track(user());
+        assert_eq!(track.event, "Application Opened");
+        assert_eq!(
+            track.properties,
+            json!({
+                "from_background": true,
+                "url": "app://home",
+                "version": "1.0.0",
+                "build": "100",
+            })
+        );
+
+        let track = ApplicationBackgrounded::new().into_track(user());
+        assert_eq!(track.event, "Application Backgrounded");
+        assert_eq!(track.properties, json!({}));
+    }
+}
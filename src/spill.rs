@@ -0,0 +1,359 @@
+//! Spill files, persisting the messages which could not be delivered.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Lines, Read, Write};
+use std::path::Path;
+
+use crate::errors::Result;
+use crate::message::BatchMessage;
+
+/// The magic number starting every zstd frame.
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// The magic number starting the (decompressed) spill files in a binary
+/// encoding, followed by the version of the format and the encoding.
+const BINARY_MAGIC: &[u8; 8] = b"SEGSPILL";
+
+/// The version of the binary format written, readers reject newer ones.
+const BINARY_VERSION: u8 = 1;
+
+/// How the messages of a spill file are encoded.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Encoding {
+    /// Newline-delimited JSON, which can be inspected with the usual tools.
+    #[default]
+    Json,
+
+    /// MessagePack, after a versioned header. It is about half the size of
+    /// JSON. Requires the `msgpack` feature.
+    #[cfg(feature = "msgpack")]
+    MessagePack,
+}
+
+impl Encoding {
+    /// The identifier of MessagePack in the header of a binary spill file.
+    const MESSAGE_PACK_ID: u8 = 1;
+
+    /// Returns the identifier of a binary encoding, `None` for JSON.
+    fn binary_id(self) -> Option<u8> {
+        match self {
+            Encoding::Json => None,
+            #[cfg(feature = "msgpack")]
+            Encoding::MessagePack => Some(Self::MESSAGE_PACK_ID),
+        }
+    }
+}
+
+/// How a spill file is compressed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Compression {
+    /// Plain newline-delimited JSON.
+    #[default]
+    None,
+
+    /// Newline-delimited JSON in zstd frames, at the given compression level
+    /// (1 to 22, 3 being zstd's default). Requires the `zstd` feature.
+    #[cfg(feature = "zstd")]
+    Zstd { level: i32 },
+}
+
+/// Writes messages into a spill file, one JSON message per line by default,
+/// for instance the messages returned by
+/// [`AutoBatcher::shutdown`](crate::AutoBatcher::shutdown) during an outage,
+/// or a journal of every pushed message.
+///
+/// The file is read back with a [`SpillReader`].
+///
+/// ```no_run
+/// use segment::{Compression, SpillReader, SpillWriter};
+/// use segment::message::Track;
+///
+/// # fn run() -> segment::Result<()> {
+/// let mut spill = SpillWriter::create("undelivered.ndjson", Compression::None)?;
+/// spill.write(&Track::default().into())?;
+/// spill.finish()?;
+///
+/// for msg in SpillReader::open("undelivered.ndjson")? {
+///     let msg = msg?;
+///     // already processed: send it again with `AutoBatcher::requeue`
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct SpillWriter<W: Write> {
+    sink: Sink<W>,
+    encoding: Encoding,
+}
+
+enum Sink<W: Write> {
+    Plain(BufWriter<W>),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::Encoder<'static, BufWriter<W>>),
+}
+
+impl SpillWriter<File> {
+    /// Create the spill file at `path`, truncating it if it exists.
+    pub fn create(path: impl AsRef<Path>, compression: Compression) -> Result<Self> {
+        Self::new(File::create(path)?, compression)
+    }
+
+    /// Create the spill file at `path` with the given encoding, truncating it
+    /// if it exists.
+    pub fn create_with_encoding(
+        path: impl AsRef<Path>,
+        compression: Compression,
+        encoding: Encoding,
+    ) -> Result<Self> {
+        Self::with_encoding(File::create(path)?, compression, encoding)
+    }
+}
+
+impl<W: Write> SpillWriter<W> {
+    /// Write a spill file into `writer`.
+    pub fn new(writer: W, compression: Compression) -> Result<Self> {
+        Self::with_encoding(writer, compression, Encoding::Json)
+    }
+
+    /// Write a spill file with the given encoding into `writer`.
+    pub fn with_encoding(writer: W, compression: Compression, encoding: Encoding) -> Result<Self> {
+        let writer = BufWriter::new(writer);
+        let sink = match compression {
+            Compression::None => Sink::Plain(writer),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd { level } => Sink::Zstd(zstd::Encoder::new(writer, level)?),
+        };
+        let mut spill = Self { sink, encoding };
+        spill.write_header()?;
+        Ok(spill)
+    }
+
+    /// Write the header of the binary encodings.
+    fn write_header(&mut self) -> io::Result<()> {
+        let Some(id) = self.encoding.binary_id() else {
+            return Ok(());
+        };
+        let writer = self.writer();
+        writer.write_all(BINARY_MAGIC)?;
+        writer.write_all(&[BINARY_VERSION, id])
+    }
+
+    #[cfg_attr(not(feature = "zstd"), allow(clippy::infallible_destructuring_match))]
+    fn writer(&mut self) -> &mut dyn Write {
+        match &mut self.sink {
+            Sink::Plain(writer) => writer,
+            #[cfg(feature = "zstd")]
+            Sink::Zstd(encoder) => encoder,
+        }
+    }
+
+    /// Append a message.
+    pub fn write(&mut self, msg: &BatchMessage) -> Result<()> {
+        let encoding = self.encoding;
+        let writer = self.writer();
+        match encoding {
+            Encoding::Json => {
+                serde_json::to_writer(&mut *writer, msg)?;
+                writer.write_all(b"\n")?;
+            }
+            #[cfg(feature = "msgpack")]
+            Encoding::MessagePack => {
+                let mut serializer = rmp_serde::Serializer::new(writer).with_struct_map();
+                serde::Serialize::serialize(msg, &mut serializer).map_err(invalid_data)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Flush the buffered messages and complete the compressed frame, then
+    /// returns the underlying writer.
+    ///
+    /// Dropping a `SpillWriter` without calling `finish` may lose the last
+    /// messages.
+    #[cfg_attr(not(feature = "zstd"), allow(clippy::infallible_destructuring_match))]
+    pub fn finish(self) -> Result<W> {
+        let writer = match self.sink {
+            Sink::Plain(writer) => writer,
+            #[cfg(feature = "zstd")]
+            Sink::Zstd(encoder) => encoder.finish()?,
+        };
+        writer.into_inner().map_err(|err| err.into_error().into())
+    }
+}
+
+/// Reads back the messages of a spill file written by a [`SpillWriter`], as
+/// an iterator.
+///
+/// The compression is detected from the content of the file, and compressed
+/// files are decompressed while they are read, so that replaying a large
+/// file doesn't load it in memory.
+pub struct SpillReader {
+    records: Records,
+}
+
+enum Records {
+    Json(Lines<Box<dyn BufRead + Send>>),
+    #[cfg(feature = "msgpack")]
+    MessagePack(Box<dyn BufRead + Send>),
+}
+
+impl SpillReader {
+    /// Open the spill file at `path`.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::new(File::open(path)?)
+    }
+
+    /// Read a spill file from `reader`.
+    ///
+    /// Returns an error if it is compressed and the `zstd` feature is
+    /// disabled, if it is encoded with MessagePack and the `msgpack` feature is
+    /// disabled, or if it was written by a newer version of this crate.
+    pub fn new(reader: impl Read + Send + 'static) -> Result<Self> {
+        let mut reader = BufReader::new(reader);
+        let compressed = reader.fill_buf()?.starts_with(&ZSTD_MAGIC);
+
+        let mut reader: Box<dyn BufRead + Send> = match compressed {
+            false => Box::new(reader),
+            #[cfg(feature = "zstd")]
+            true => Box::new(BufReader::new(zstd::Decoder::with_buffer(reader)?)),
+            #[cfg(not(feature = "zstd"))]
+            true => {
+                return Err(invalid_data(
+                    "the spill file is compressed with zstd, enable the `zstd` feature",
+                ))
+            }
+        };
+
+        if !reader.fill_buf()?.starts_with(BINARY_MAGIC) {
+            return Ok(Self {
+                records: Records::Json(reader.lines()),
+            });
+        }
+        let mut header = [0; BINARY_MAGIC.len() + 2];
+        reader.read_exact(&mut header)?;
+        let [.., version, encoding] = header;
+        if version > BINARY_VERSION {
+            return Err(invalid_data(format!(
+                "the spill file format version {version} is not supported, the latest is {BINARY_VERSION}"
+            )));
+        }
+        match encoding {
+            #[cfg(feature = "msgpack")]
+            Encoding::MESSAGE_PACK_ID => Ok(Self {
+                records: Records::MessagePack(reader),
+            }),
+            #[cfg(not(feature = "msgpack"))]
+            Encoding::MESSAGE_PACK_ID => Err(invalid_data(
+                "the spill file is encoded with MessagePack, enable the `msgpack` feature",
+            )),
+            _ => Err(invalid_data(format!(
+                "unknown spill file encoding {encoding}"
+            ))),
+        }
+    }
+}
+
+fn invalid_data(err: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> crate::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err).into()
+}
+
+impl Iterator for SpillReader {
+    type Item = Result<BatchMessage>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match &mut self.records {
+            Records::Json(lines) => loop {
+                let line = match lines.next()? {
+                    Ok(line) => line,
+                    Err(err) => return Some(Err(err.into())),
+                };
+                if !line.trim().is_empty() {
+                    return Some(serde_json::from_str(&line).map_err(Into::into));
+                }
+            },
+            #[cfg(feature = "msgpack")]
+            Records::MessagePack(reader) => {
+                match reader.fill_buf() {
+                    Ok([]) => return None,
+                    Ok(_) => {}
+                    Err(err) => return Some(Err(err.into())),
+                }
+                Some(rmp_serde::from_read(reader).map_err(invalid_data))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::{Track, User};
+
+    fn messages() -> Vec<BatchMessage> {
+        (0..10)
+            .map(|i| {
+                BatchMessage::Track(Track {
+                    user: User::UserId {
+                        user_id: format!("user-{i}"),
+                    },
+                    event: "Example".to_owned(),
+                    ..Default::default()
+                })
+            })
+            .collect()
+    }
+
+    fn roundtrip(compression: Compression) -> Vec<u8> {
+        roundtrip_with(compression, Encoding::Json)
+    }
+
+    fn roundtrip_with(compression: Compression, encoding: Encoding) -> Vec<u8> {
+        let mut spill = SpillWriter::with_encoding(Vec::new(), compression, encoding).unwrap();
+        for msg in messages() {
+            spill.write(&msg).unwrap();
+        }
+        let file = spill.finish().unwrap();
+
+        let read: Vec<_> = SpillReader::new(std::io::Cursor::new(file.clone()))
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(read, messages());
+        file
+    }
+
+    #[test]
+    fn test_plain() {
+        let file = roundtrip(Compression::None);
+        assert_eq!(file.iter().filter(|&&b| b == b'\n').count(), 10);
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn test_zstd() {
+        let plain = roundtrip(Compression::None);
+        let compressed = roundtrip(Compression::Zstd { level: 3 });
+        assert!(compressed.starts_with(&ZSTD_MAGIC));
+        assert!(compressed.len() < plain.len());
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[test]
+    fn test_msgpack() {
+        let json = roundtrip(Compression::None);
+        let msgpack = roundtrip_with(Compression::None, Encoding::MessagePack);
+        assert!(msgpack.starts_with(BINARY_MAGIC));
+        assert!(msgpack.len() < json.len());
+
+        let mut newer = msgpack.clone();
+        newer[BINARY_MAGIC.len()] = BINARY_VERSION + 1;
+        assert!(SpillReader::new(std::io::Cursor::new(newer)).is_err());
+    }
+
+    #[cfg(all(feature = "msgpack", feature = "zstd"))]
+    #[test]
+    fn test_compressed_msgpack() {
+        roundtrip_with(Compression::Zstd { level: 3 }, Encoding::MessagePack);
+    }
+}
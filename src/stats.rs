@@ -0,0 +1,112 @@
+//! Counters describing the activity of the batchers.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use serde::ser::SerializeMap;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+use crate::errors::Error;
+
+/// A snapshot of the counters of a batcher.
+#[derive(PartialEq, Eq, Debug, Clone, Default, serde::Serialize)]
+#[non_exhaustive]
+pub struct Stats {
+    /// Messages accepted in the buffer by
+    /// [`AutoBatcher::push`](crate::AutoBatcher::push), or committed by
+    /// [`AutoBatcher::commit`](crate::AutoBatcher::commit).
+    pub pushed: u64,
+
+    /// Messages of the batches accepted by Segment.
+    pub sent: u64,
+
+    /// Messages of the batches which could not be delivered, once retried.
+    pub failed: u64,
+
+    /// Size in bytes of the messages of the batches accepted by Segment.
+    pub bytes_sent: u64,
+
+    /// The error of the last batch which could not be delivered.
+    pub last_error: Option<String>,
+
+    /// When the last upload of a batch completed, successfully or not.
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub last_flush: Option<OffsetDateTime>,
+
+    /// Messages dropped because the buffer or queue was full, see
+    /// [`OverflowPolicy`](crate::OverflowPolicy).
+    pub dropped: u64,
+
+    /// Retries skipped because the [`RetryBudget`](crate::RetryBudget) was
+    /// exhausted.
+    pub retry_budget_exhausted: u64,
+
+    /// When the last heartbeat of each destination was accepted, see
+    /// [`AutoBatcher::probe_destinations`](crate::AutoBatcher::probe_destinations).
+    #[serde(serialize_with = "serialize_heartbeats")]
+    pub heartbeats: BTreeMap<String, OffsetDateTime>,
+}
+
+/// The live counters, shared by a batcher and its background tasks.
+#[derive(Debug, Default)]
+pub(crate) struct Counters {
+    pub(crate) pushed: AtomicU64,
+    pub(crate) sent: AtomicU64,
+    pub(crate) failed: AtomicU64,
+    pub(crate) bytes_sent: AtomicU64,
+    pub(crate) last_error: Mutex<Option<String>>,
+    pub(crate) last_flush: Mutex<Option<OffsetDateTime>>,
+    pub(crate) dropped: AtomicU64,
+    pub(crate) retry_budget_exhausted: AtomicU64,
+    pub(crate) heartbeats: Mutex<BTreeMap<String, OffsetDateTime>>,
+}
+
+impl Counters {
+    pub(crate) fn snapshot(&self) -> Stats {
+        Stats {
+            pushed: self.pushed.load(Ordering::Relaxed),
+            sent: self.sent.load(Ordering::Relaxed),
+            failed: self.failed.load(Ordering::Relaxed),
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            last_error: self.last_error.lock().unwrap().clone(),
+            last_flush: *self.last_flush.lock().unwrap(),
+            dropped: self.dropped.load(Ordering::Relaxed),
+            retry_budget_exhausted: self.retry_budget_exhausted.load(Ordering::Relaxed),
+            heartbeats: self.heartbeats.lock().unwrap().clone(),
+        }
+    }
+
+    /// Record the outcome of the upload of a batch of `messages`, holding
+    /// `bytes`.
+    pub(crate) fn uploaded(&self, messages: usize, bytes: usize, error: Option<&Error>) {
+        match error {
+            None => {
+                self.sent.fetch_add(messages as u64, Ordering::Relaxed);
+                self.bytes_sent.fetch_add(bytes as u64, Ordering::Relaxed);
+            }
+            Some(error) => {
+                self.failed.fetch_add(messages as u64, Ordering::Relaxed);
+                *self.last_error.lock().unwrap() = Some(error.to_string());
+            }
+        }
+        *self.last_flush.lock().unwrap() = Some(OffsetDateTime::now_utc());
+    }
+}
+
+/// Serialize the times of the heartbeats in RFC 3339.
+fn serialize_heartbeats<S>(
+    heartbeats: &BTreeMap<String, OffsetDateTime>,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    let mut map = serializer.serialize_map(Some(heartbeats.len()))?;
+    for (destination, at) in heartbeats {
+        let at = at.format(&Rfc3339).map_err(serde::ser::Error::custom)?;
+        map.serialize_entry(destination, &at)?;
+    }
+    map.end()
+}
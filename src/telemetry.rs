@@ -0,0 +1,107 @@
+//! Counters and histograms emitted through the [`metrics`] facade, so that
+//! operators can alert on the delivery health of the batchers.
+//!
+//! Nothing is recorded until a recorder, for instance a Prometheus exporter,
+//! is installed by the application.
+
+use std::time::Duration;
+
+use metrics::{counter, histogram};
+
+/// Counter of the messages accepted by [`AutoBatcher::push`](crate::AutoBatcher::push).
+pub const EVENTS_PUSHED: &str = "segment_events_pushed";
+
+/// Counter of the batches accepted by Segment.
+pub const BATCHES_SENT: &str = "segment_batches_sent";
+
+/// Counter of the batches which could not be delivered, once retried.
+pub const SEND_FAILURES: &str = "segment_send_failures";
+
+/// Counter of the attempts to send a batch again after a transient error.
+pub const RETRIES: &str = "segment_retries";
+
+/// Histogram of the size in bytes of the messages of each batch sent.
+pub const BATCH_SIZE_BYTES: &str = "segment_batch_size_bytes";
+
+/// Histogram of the duration in seconds of
+/// [`AutoBatcher::flush`](crate::AutoBatcher::flush).
+pub const FLUSH_DURATION: &str = "segment_flush_duration_seconds";
+
+pub(crate) fn event_pushed() {
+    counter!(EVENTS_PUSHED).increment(1);
+}
+
+pub(crate) fn batch_sent(bytes: usize) {
+    counter!(BATCHES_SENT).increment(1);
+    histogram!(BATCH_SIZE_BYTES).record(bytes as f64);
+}
+
+pub(crate) fn send_failure() {
+    counter!(SEND_FAILURES).increment(1);
+}
+
+pub(crate) fn retry() {
+    counter!(RETRIES).increment(1);
+}
+
+pub(crate) fn flushed(duration: Duration) {
+    histogram!(FLUSH_DURATION).record(duration);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::Track;
+    use crate::testing::MockClient;
+    use crate::{AutoBatcher, Batcher, RetryPolicy};
+    use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+    use std::collections::HashMap;
+    use std::sync::atomic::Ordering;
+
+    #[test]
+    fn test_metrics() {
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .start_paused(true)
+            .build()
+            .unwrap();
+
+        metrics::with_local_recorder(&recorder, || {
+            runtime.block_on(async {
+                let client = MockClient::default();
+                let mut batcher =
+                    AutoBatcher::new(client.clone(), Batcher::new(None), "key".to_owned());
+                batcher.set_retry_policy(RetryPolicy {
+                    max_attempts: 2,
+                    ..RetryPolicy::default()
+                });
+                client.failures.store(1, Ordering::SeqCst);
+                batcher.push(Track::default()).await.unwrap();
+                batcher.push(Track::default()).await.unwrap();
+                batcher.flush().await.unwrap();
+            })
+        });
+
+        let metrics: HashMap<_, _> = snapshotter
+            .snapshot()
+            .into_vec()
+            .into_iter()
+            .map(|(key, _, _, value)| (key.key().name().to_owned(), value))
+            .collect();
+        assert_eq!(metrics[EVENTS_PUSHED], DebugValue::Counter(2));
+        assert_eq!(metrics[BATCHES_SENT], DebugValue::Counter(1));
+        assert_eq!(metrics[RETRIES], DebugValue::Counter(1));
+        assert!(!metrics.contains_key(SEND_FAILURES));
+        let DebugValue::Histogram(sizes) = &metrics[BATCH_SIZE_BYTES] else {
+            panic!("expected a histogram");
+        };
+        assert_eq!(sizes.len(), 1);
+        assert!(sizes[0].into_inner() > 0.0);
+        let DebugValue::Histogram(durations) = &metrics[FLUSH_DURATION] else {
+            panic!("expected a histogram");
+        };
+        assert_eq!(durations.len(), 1);
+    }
+}
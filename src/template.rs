@@ -0,0 +1,163 @@
+//! Placeholders in the properties, resolved when the batches are sent.
+
+use serde_json::value::RawValue;
+use serde_json::Value;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+use crate::message::SerializedBatch;
+
+/// A property with this value is replaced by the time at which the batch is
+/// sent, in RFC 3339, see
+/// [`Batcher::set_send_time_templates`](crate::Batcher::set_send_time_templates).
+pub const SENT_AT: &str = "{sent_at}";
+
+/// A property with this value is replaced by the number of milliseconds
+/// between the `timestamp` of the message and the time at which the batch is
+/// sent, or `null` if the message has no timestamp, see
+/// [`Batcher::set_send_time_templates`](crate::Batcher::set_send_time_templates).
+pub const QUEUE_LATENCY_MS: &str = "{queue_latency_ms}";
+
+/// The most a resolved `{sent_at}` is longer than its placeholder, with
+/// nanoseconds and an offset.
+const SENT_AT_GROWTH: usize = "0000-00-00T00:00:00.000000000+00:00".len() - SENT_AT.len();
+
+/// Returns how much longer the serialized message `json` may be once its
+/// placeholders are resolved. A resolved `{queue_latency_ms}` is never longer.
+pub(crate) fn growth(json: &str) -> usize {
+    json.matches(&format!("\"{SENT_AT}\"")).count() * SENT_AT_GROWTH
+}
+
+/// Returns `batch` with the placeholders of the properties resolved for a
+/// batch sent at `sent_at`.
+pub(crate) fn resolve(batch: &SerializedBatch, sent_at: OffsetDateTime) -> SerializedBatch {
+    let messages = batch
+        .batch
+        .iter()
+        .map(|raw| resolve_message(raw, sent_at).unwrap_or_else(|| raw.clone()))
+        .collect();
+    SerializedBatch {
+        batch: messages,
+        templates: false,
+        ..batch.clone()
+    }
+}
+
+/// Returns the message with its placeholders resolved, `None` if it has
+/// none.
+fn resolve_message(raw: &RawValue, sent_at: OffsetDateTime) -> Option<Box<RawValue>> {
+    let json = raw.get();
+    if !json.contains(&format!("\"{SENT_AT}\""))
+        && !json.contains(&format!("\"{QUEUE_LATENCY_MS}\""))
+    {
+        return None;
+    }
+
+    let mut msg: Value = serde_json::from_str(json).ok()?;
+    let timestamp = msg
+        .get("timestamp")
+        .and_then(Value::as_str)
+        .and_then(|timestamp| OffsetDateTime::parse(timestamp, &Rfc3339).ok());
+    let sent_at_value = sent_at.format(&Rfc3339).map_or(Value::Null, Value::String);
+    let latency = timestamp.map_or(Value::Null, |timestamp| {
+        let latency = (sent_at - timestamp).whole_milliseconds().max(0);
+        Value::from(i64::try_from(latency).unwrap_or(i64::MAX))
+    });
+
+    let properties = msg.get_mut("properties")?;
+    let mut resolved = false;
+    replace(properties, &mut |value| {
+        let replacement = match value.as_str() {
+            Some(SENT_AT) => sent_at_value.clone(),
+            Some(QUEUE_LATENCY_MS) => latency.clone(),
+            _ => return,
+        };
+        *value = replacement;
+        resolved = true;
+    });
+    if !resolved {
+        return None;
+    }
+    serde_json::value::to_raw_value(&msg).ok()
+}
+
+/// Call `f` on every value nested in `value`.
+fn replace(value: &mut Value, f: &mut impl FnMut(&mut Value)) {
+    match value {
+        Value::Object(map) => map.values_mut().for_each(|value| replace(value, f)),
+        Value::Array(values) => values.iter_mut().for_each(|value| replace(value, f)),
+        value => f(value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::Track;
+    use crate::Batcher;
+    use serde_json::json;
+
+    #[test]
+    fn test_resolve() {
+        let sent_at = OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap();
+        let mut batcher = Batcher::new(None);
+        batcher.set_send_time_templates(true);
+        batcher
+            .push(Track {
+                event: "Job Done".to_owned(),
+                properties: json!({
+                    "sentAt": SENT_AT,
+                    "latency": { "queue": QUEUE_LATENCY_MS },
+                    "comment": "not {sent_at}",
+                }),
+                timestamp: Some(sent_at - time::Duration::milliseconds(1500)),
+                ..Default::default()
+            })
+            .unwrap();
+        batcher
+            .push(Track {
+                event: "No Template".to_owned(),
+                ..Default::default()
+            })
+            .unwrap();
+        let batch = batcher.into_serialized();
+        assert!(batch.templates);
+
+        let resolved = resolve(&batch, sent_at);
+        let messages = resolved.messages().unwrap();
+        let crate::message::BatchMessage::Track(track) = &messages[0] else {
+            panic!("expected a track");
+        };
+        assert_eq!(
+            track.properties,
+            json!({
+                "sentAt": "2023-11-14T22:13:20Z",
+                "latency": { "queue": 1500 },
+                "comment": "not {sent_at}",
+            })
+        );
+        assert_eq!(resolved.batch[1].get(), batch.batch[1].get());
+    }
+
+    #[test]
+    fn test_reserved_size() {
+        let mut batcher = Batcher::new(None);
+        batcher.set_send_time_templates(true);
+        batcher
+            .push(Track {
+                event: "Job Done".to_owned(),
+                properties: json!({ "start": SENT_AT, "end": SENT_AT }),
+                ..Default::default()
+            })
+            .unwrap();
+        let reserved = batcher.size_bytes();
+
+        let sent_at = OffsetDateTime::now_utc()
+            .replace_nanosecond(123_456_789)
+            .unwrap()
+            .to_offset(time::UtcOffset::from_hms(2, 0, 0).unwrap());
+        let resolved = resolve(&batcher.into_serialized(), sent_at);
+        assert!(!resolved.templates);
+        assert!(resolved.to_json().unwrap().len() <= reserved);
+    }
+}
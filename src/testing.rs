@@ -0,0 +1,129 @@
+//! Helpers shared by the tests.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::mpsc;
+
+use crate::message::{Track, User};
+use crate::{Client, Error, Message, Result};
+
+/// A client recording the batches it receives, and how many requests were
+/// running concurrently.
+#[derive(Clone, Default)]
+pub(crate) struct MockClient {
+    pub(crate) sent: Arc<Mutex<Vec<Message>>>,
+    /// The write keys of the batches received.
+    pub(crate) keys: Arc<Mutex<Vec<String>>>,
+    pub(crate) running: Arc<AtomicUsize>,
+    pub(crate) max_running: Arc<AtomicUsize>,
+    /// How many of the next requests fail with a `503`.
+    pub(crate) failures: Arc<AtomicUsize>,
+}
+
+impl MockClient {
+    /// Returns the number of messages received in all the batches.
+    pub(crate) fn sent_count(&self) -> usize {
+        self.sent
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|msg| match msg {
+                Message::Batch(batch) => batch.batch.len(),
+                _ => 1,
+            })
+            .sum()
+    }
+}
+
+impl Client for MockClient {
+    async fn send(&self, write_key: String, msg: Message) -> Result<()> {
+        let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+        self.max_running.fetch_max(running, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let failing = self
+            .failures
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+            .is_ok();
+        if failing {
+            self.running.fetch_sub(1, Ordering::SeqCst);
+            return Err(status_error(503));
+        }
+        self.sent.lock().unwrap().push(msg);
+        self.keys.lock().unwrap().push(write_key);
+        self.running.fetch_sub(1, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+/// A network error with the given HTTP status.
+pub(crate) fn status_error(status: u16) -> Error {
+    let response = http::Response::builder().status(status).body("").unwrap();
+    let err = reqwest::Response::from(response)
+        .error_for_status()
+        .unwrap_err();
+    Error::NetworkError(err)
+}
+
+/// A message large enough to fill a batch with a few of them.
+pub(crate) fn large_track(i: usize) -> Track {
+    Track {
+        user: User::UserId {
+            user_id: format!("{i}-{}", "a".repeat(1024 * 30 - 100)),
+        },
+        ..Default::default()
+    }
+}
+
+/// Start an HTTP server answering the requests with the `responses` in turn,
+/// the last one repeated. Returns the URL of the server, and the requests it
+/// received, body included.
+pub(crate) async fn serve(responses: Vec<String>) -> (String, mpsc::UnboundedReceiver<String>) {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (requests, received) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        let mut responses = responses.into_iter();
+        let mut response = String::new();
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let mut request = String::new();
+            let mut buf = [0; 4096];
+            // the body may come after the headers
+            while !complete(&request) {
+                let len = stream.read(&mut buf).await.unwrap_or(0);
+                if len == 0 {
+                    break;
+                }
+                request.push_str(&String::from_utf8_lossy(&buf[..len]));
+            }
+            let _ = requests.send(request);
+            if let Some(next) = responses.next() {
+                response = next;
+            }
+            let _ = stream.write_all(response.as_bytes()).await;
+        }
+    });
+    (format!("http://{addr}"), received)
+}
+
+/// A `200` response with the JSON `body`.
+pub(crate) fn json_response(body: &str) -> String {
+    format!(
+        "HTTP/1.1 200 OK\r\nconnection: close\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{body}",
+        body.len()
+    )
+}
+
+/// Returns whether the headers and the whole body of `request` were received.
+fn complete(request: &str) -> bool {
+    let Some((headers, body)) = request.split_once("\r\n\r\n") else {
+        return false;
+    };
+    let len = headers
+        .lines()
+        .find_map(|line| line.strip_prefix("content-length: "))
+        .map_or(0, |len| len.parse().unwrap());
+    body.len() >= len
+}
@@ -0,0 +1,76 @@
+//! Propagation of the OpenTelemetry context to the requests sent to Segment,
+//! as [W3C trace context](https://www.w3.org/TR/trace-context/) headers.
+
+use opentelemetry::trace::{SpanContext, TraceContextExt};
+use opentelemetry::Context;
+use reqwest::RequestBuilder;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+const TRACEPARENT: &str = "traceparent";
+const TRACESTATE: &str = "tracestate";
+
+/// Add the `traceparent` and `tracestate` headers of the current span to
+/// `request`, whether it is sampled or not. Outside of a trace, the request
+/// is left untouched.
+///
+/// The current `tracing` span is used if it is bridged to OpenTelemetry by a
+/// `tracing_opentelemetry` layer, the current OpenTelemetry context otherwise.
+pub(crate) fn inject(request: RequestBuilder) -> RequestBuilder {
+    let mut cx = tracing::Span::current().context();
+    if !cx.span().span_context().is_valid() {
+        cx = Context::current();
+    }
+    let span = cx.span();
+    let span_context = span.span_context();
+    if !span_context.is_valid() {
+        return request;
+    }
+
+    let request = request.header(TRACEPARENT, traceparent(span_context));
+    match span_context.trace_state().header() {
+        state if state.is_empty() => request,
+        state => request.header(TRACESTATE, state),
+    }
+}
+
+fn traceparent(span_context: &SpanContext) -> String {
+    format!(
+        "00-{}-{}-{:02x}",
+        span_context.trace_id(),
+        span_context.span_id(),
+        span_context.trace_flags().to_u8()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::trace::{SpanId, TraceFlags, TraceId, TraceState};
+
+    #[test]
+    fn test_inject() {
+        let client = reqwest::Client::new();
+        let headers = || {
+            let request = inject(client.post("https://api.segment.io/v1/batch"));
+            request.build().unwrap().headers().clone()
+        };
+        assert!(headers().get(TRACEPARENT).is_none());
+
+        let span_context = SpanContext::new(
+            TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap(),
+            SpanId::from_hex("00f067aa0ba902b7").unwrap(),
+            TraceFlags::SAMPLED,
+            true,
+            TraceState::from_key_value([("vendor", "value")]).unwrap(),
+        );
+        let _guard = Context::new()
+            .with_remote_span_context(span_context)
+            .attach();
+        let headers = headers();
+        assert_eq!(
+            headers[TRACEPARENT],
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+        );
+        assert_eq!(headers[TRACESTATE], "vendor=value");
+    }
+}
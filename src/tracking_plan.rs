@@ -0,0 +1,361 @@
+//! Validation of the `track` events against a Segment Tracking Plan.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::errors::{Error, Result};
+use crate::message::BatchMessage;
+use crate::schema::JsonType;
+use crate::validation::ValidationError;
+use crate::warning::{Warning, Warnings};
+
+/// What to do with the messages violating a [`TrackingPlan`], or some
+/// [`Validation`](crate::Validation) checks.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Enforcement {
+    /// Report the violations as [`Warning::PlanViolation`]s, and send the
+    /// events anyway.
+    #[default]
+    Warn,
+
+    /// Reject the events with [`Error::InvalidMessage`].
+    Enforce,
+}
+
+/// An event of a [`TrackingPlan`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PlannedEvent {
+    /// The types allowed for each property, the properties without a type
+    /// accept any value.
+    pub properties: BTreeMap<String, BTreeSet<JsonType>>,
+
+    /// The properties which must be present.
+    pub required: BTreeSet<String>,
+}
+
+/// The events allowed by a Segment Tracking Plan (Protocols), checked on
+/// push, see [`Batcher::set_tracking_plan`](crate::Batcher::set_tracking_plan).
+///
+/// Only the names of the `track` events and the types of their properties
+/// are checked. The properties which are not in the plan are allowed.
+///
+/// ```
+/// use segment::{Batcher, Enforcement, Error, TrackingPlan};
+/// use segment::message::Track;
+/// use serde_json::json;
+///
+/// let export = json!({
+///     "rules": {
+///         "events": [{
+///             "name": "Signed Up",
+///             "rules": {
+///                 "properties": {
+///                     "properties": {
+///                         "type": "object",
+///                         "properties": { "plan": { "type": ["string"] } },
+///                         "required": ["plan"]
+///                     }
+///                 }
+///             }
+///         }]
+///     }
+/// });
+/// let mut plan = TrackingPlan::from_json(&export.to_string()).unwrap();
+/// plan.enforcement = Enforcement::Enforce;
+///
+/// let mut batcher = Batcher::new(None);
+/// batcher.set_tracking_plan(plan);
+///
+/// let result = batcher.push(Track {
+///     event: "Signed Up".to_owned(),
+///     properties: json!({ "plan": 3 }),
+///     ..Default::default()
+/// });
+/// assert!(matches!(result, Err(Error::InvalidMessage(_))));
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TrackingPlan {
+    /// The planned events, by name.
+    pub events: BTreeMap<String, PlannedEvent>,
+
+    /// Whether the violations are reported or rejected.
+    pub enforcement: Enforcement,
+}
+
+/// The JSON export of a Tracking Plan, with the JSON schema of each event.
+#[derive(Deserialize)]
+struct Export {
+    rules: ExportRules,
+}
+
+#[derive(Deserialize)]
+struct ExportRules {
+    #[serde(default)]
+    events: Vec<ExportEvent>,
+}
+
+#[derive(Deserialize)]
+struct ExportEvent {
+    name: String,
+    #[serde(default)]
+    rules: Value,
+}
+
+impl TrackingPlan {
+    /// Load the JSON export of a Tracking Plan, as returned by the Segment
+    /// Public API, optionally wrapped in a `tracking_plan` object.
+    ///
+    /// The plan is in [`Enforcement::Warn`] mode.
+    pub fn from_json(json: &str) -> Result<Self> {
+        let mut export: Value = serde_json::from_str(json)?;
+        if let Some(plan) = export.get_mut("tracking_plan") {
+            export = plan.take();
+        }
+        let export = Export::deserialize(export)?;
+
+        let mut events = BTreeMap::new();
+        for event in export.rules.events {
+            let schema = &event.rules["properties"]["properties"];
+            let mut planned = PlannedEvent::default();
+            if let Some(properties) = schema["properties"].as_object() {
+                for (name, property) in properties {
+                    let types = json_schema_types(&property["type"]).map_err(|ty| {
+                        Error::InvalidConfiguration(format!(
+                            "unknown type `{ty}` for `{}.{name}`",
+                            event.name
+                        ))
+                    })?;
+                    planned.properties.insert(name.clone(), types);
+                }
+            }
+            if let Some(required) = schema["required"].as_array() {
+                planned.required = required
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .map(str::to_owned)
+                    .collect();
+            }
+            events.insert(event.name, planned);
+        }
+        Ok(Self {
+            events,
+            enforcement: Enforcement::default(),
+        })
+    }
+
+    /// Check `msg` against the plan, returns the first violation.
+    pub fn validate(&self, msg: &BatchMessage) -> std::result::Result<(), ValidationError> {
+        let BatchMessage::Track(track) = msg else {
+            return Ok(());
+        };
+        let Some(planned) = self.events.get(&track.event) else {
+            return Err(ValidationError::UnplannedEvent {
+                event: track.event.clone(),
+            });
+        };
+
+        let empty = serde_json::Map::new();
+        let properties = track.properties.as_object().unwrap_or(&empty);
+        if let Some(missing) = planned
+            .required
+            .iter()
+            .find(|name| !properties.contains_key(name.as_str()))
+        {
+            return Err(ValidationError::MissingProperty {
+                field: format!("properties.{missing}"),
+            });
+        }
+        for (name, value) in properties {
+            let Some(expected) = planned.properties.get(name) else {
+                continue;
+            };
+            if !JsonType::matches(expected, value) {
+                let found = JsonType::of(value);
+                return Err(ValidationError::InvalidType {
+                    field: format!("properties.{name}"),
+                    expected: expected.clone(),
+                    found,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Check `msg`, reporting or returning the violation depending on the
+    /// [`Enforcement`].
+    pub(crate) fn check(
+        &self,
+        msg: &BatchMessage,
+        warnings: &Warnings,
+    ) -> std::result::Result<(), ValidationError> {
+        match (self.validate(msg), self.enforcement) {
+            (Err(error), Enforcement::Warn) => {
+                warnings.emit(Warning::PlanViolation {
+                    event: msg.event_name().unwrap_or_default().to_owned(),
+                    error,
+                });
+                Ok(())
+            }
+            (result, _) => result,
+        }
+    }
+}
+
+/// Returns the types accepted by the JSON schema `type`, or the unknown type.
+fn json_schema_types(ty: &Value) -> std::result::Result<BTreeSet<JsonType>, String> {
+    let names: Vec<&str> = match ty {
+        Value::String(name) => vec![name],
+        Value::Array(names) => names.iter().filter_map(Value::as_str).collect(),
+        _ => Vec::new(),
+    };
+    names
+        .into_iter()
+        .map(|name| match name {
+            "null" => Ok(JsonType::Null),
+            "boolean" => Ok(JsonType::Bool),
+            "number" => Ok(JsonType::Number),
+            "integer" => Ok(JsonType::Integer),
+            "string" => Ok(JsonType::String),
+            "array" => Ok(JsonType::Array),
+            "object" => Ok(JsonType::Object),
+            other => Err(other.to_owned()),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::{Identify, Track};
+    use serde_json::json;
+    use std::sync::{Arc, Mutex};
+
+    fn plan() -> TrackingPlan {
+        let export = json!({
+            "tracking_plan": {
+                "display_name": "Product",
+                "rules": {
+                    "events": [{
+                        "name": "Order Completed",
+                        "version": 1,
+                        "rules": {
+                            "$schema": "http://json-schema.org/draft-07/schema#",
+                            "type": "object",
+                            "properties": {
+                                "properties": {
+                                    "type": "object",
+                                    "properties": {
+                                        "revenue": { "type": ["number", "null"] },
+                                        "coupon": { "type": "string" },
+                                        "quantity": { "type": "integer" },
+                                        "items": {}
+                                    },
+                                    "required": ["revenue"]
+                                }
+                            }
+                        }
+                    }]
+                }
+            }
+        });
+        TrackingPlan::from_json(&export.to_string()).unwrap()
+    }
+
+    fn track(event: &str, properties: Value) -> BatchMessage {
+        BatchMessage::Track(Track {
+            event: event.to_owned(),
+            properties,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_from_json() {
+        let plan = plan();
+        let event = &plan.events["Order Completed"];
+        assert_eq!(
+            event.properties["revenue"],
+            [JsonType::Null, JsonType::Number].into()
+        );
+        assert!(event.properties["items"].is_empty());
+        assert_eq!(event.required, ["revenue".to_owned()].into());
+
+        let export = json!({ "rules": { "events": [{
+            "name": "Signed Up",
+            "rules": { "properties": { "properties": {
+                "properties": { "plan": { "type": "text" } }
+            } } }
+        }] } });
+        assert!(matches!(
+            TrackingPlan::from_json(&export.to_string()),
+            Err(Error::InvalidConfiguration(_))
+        ));
+    }
+
+    #[test]
+    fn test_validate() {
+        let plan = plan();
+        let valid = json!({ "revenue": 10.5, "quantity": 2, "items": [1], "other": true });
+        assert_eq!(plan.validate(&track("Order Completed", valid)), Ok(()));
+        assert_eq!(
+            plan.validate(&BatchMessage::Identify(Identify::default())),
+            Ok(())
+        );
+
+        assert_eq!(
+            plan.validate(&track("Order Placed", json!({}))),
+            Err(ValidationError::UnplannedEvent {
+                event: "Order Placed".to_owned()
+            })
+        );
+        assert_eq!(
+            plan.validate(&track("Order Completed", json!({ "coupon": "x" }))),
+            Err(ValidationError::MissingProperty {
+                field: "properties.revenue".to_owned()
+            })
+        );
+        assert_eq!(
+            plan.validate(&track("Order Completed", json!({ "revenue": "10" }))),
+            Err(ValidationError::InvalidType {
+                field: "properties.revenue".to_owned(),
+                expected: [JsonType::Null, JsonType::Number].into(),
+                found: JsonType::String,
+            })
+        );
+        assert_eq!(
+            plan.validate(&track(
+                "Order Completed",
+                json!({ "revenue": 10, "quantity": 1.5 })
+            )),
+            Err(ValidationError::InvalidType {
+                field: "properties.quantity".to_owned(),
+                expected: [JsonType::Integer].into(),
+                found: JsonType::Number,
+            })
+        );
+    }
+
+    #[test]
+    fn test_enforcement() {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let mut warnings = Warnings::default();
+        let sink = received.clone();
+        warnings.set_handler(Arc::new(move |warning| {
+            sink.lock().unwrap().push(warning.clone())
+        }));
+
+        let mut plan = plan();
+        let msg = track("Order Placed", json!({}));
+        assert_eq!(plan.check(&msg, &warnings), Ok(()));
+        assert!(matches!(
+            &received.lock().unwrap()[..],
+            [Warning::PlanViolation { event, .. }] if event == "Order Placed"
+        ));
+
+        plan.enforcement = Enforcement::Enforce;
+        assert!(plan.check(&msg, &warnings).is_err());
+        assert_eq!(received.lock().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,220 @@
+//! Typed traits of the `identify` and `group` messages, following [Segment's
+//! spec](https://segment.com/docs/connections/spec/identify/#traits).
+//!
+//! ```
+//! use segment::message::{Identify, User};
+//! use segment::traits::{Address, Traits};
+//! use serde_json::json;
+//!
+//! let traits = Traits::new()
+//!     .email("ada@example.com")
+//!     .first_name("Ada")
+//!     .plan("pro")
+//!     .address(Address {
+//!         city: Some("London".to_owned()),
+//!         ..Default::default()
+//!     })
+//!     .custom("favoriteLanguage", json!("Rust"));
+//!
+//! let identify = Identify {
+//!     user: User::UserId { user_id: "ada".to_owned() },
+//!     traits: traits.into(),
+//!     ..Default::default()
+//! };
+//! assert_eq!(
+//!     identify.traits,
+//!     json!({
+//!         "email": "ada@example.com",
+//!         "firstName": "Ada",
+//!         "plan": "pro",
+//!         "address": { "city": "London" },
+//!         "favoriteLanguage": "Rust",
+//!     })
+//! );
+//! ```
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+/// The traits of a user or a group, built from the reserved traits of the
+/// spec and custom ones.
+#[derive(PartialEq, Eq, Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Traits(Map<String, Value>);
+
+/// The address of a user or a group.
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct Address {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub street: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub city: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub postal_code: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub country: Option<String>,
+}
+
+/// The company of a user.
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct Company {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub industry: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub employee_count: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub plan: Option<String>,
+}
+
+macro_rules! string_traits {
+    ($($method:ident => $key:literal),+ $(,)?) => {
+        $(
+            #[doc = concat!("Set the `", $key, "` trait.")]
+            pub fn $method(self, $method: impl Into<String>) -> Self {
+                self.custom($key, Value::String($method.into()))
+            }
+        )+
+    };
+}
+
+impl Traits {
+    /// Construct empty traits.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    string_traits! {
+        avatar => "avatar",
+        description => "description",
+        email => "email",
+        first_name => "firstName",
+        gender => "gender",
+        id => "id",
+        industry => "industry",
+        last_name => "lastName",
+        name => "name",
+        phone => "phone",
+        plan => "plan",
+        title => "title",
+        username => "username",
+        website => "website",
+    }
+
+    /// Set the `age` trait.
+    pub fn age(self, age: u32) -> Self {
+        self.custom("age", age.into())
+    }
+
+    /// Set the `employees` trait of a group.
+    pub fn employees(self, employees: u64) -> Self {
+        self.custom("employees", employees.into())
+    }
+
+    /// Set the `birthday` trait, in RFC 3339.
+    ///
+    /// Returns an error if `birthday` can't be formatted, only the years
+    /// beyond 9999 can't.
+    pub fn birthday(self, birthday: OffsetDateTime) -> Result<Self, time::error::Format> {
+        self.date("birthday", birthday)
+    }
+
+    /// Set the `createdAt` trait, in RFC 3339.
+    ///
+    /// Returns an error if `created_at` can't be formatted, only the years
+    /// beyond 9999 can't.
+    pub fn created_at(self, created_at: OffsetDateTime) -> Result<Self, time::error::Format> {
+        self.date("createdAt", created_at)
+    }
+
+    fn date(self, key: &str, date: OffsetDateTime) -> Result<Self, time::error::Format> {
+        Ok(self.custom(key, date.format(&Rfc3339)?.into()))
+    }
+
+    /// Set the `address` trait.
+    pub fn address(self, address: Address) -> Self {
+        let address = serde_json::to_value(address).expect("an address always serializes");
+        self.custom("address", address)
+    }
+
+    /// Set the `company` trait of a user.
+    pub fn company(self, company: Company) -> Self {
+        let company = serde_json::to_value(company).expect("a company always serializes");
+        self.custom("company", company)
+    }
+
+    /// Set a custom trait, or override a reserved one.
+    pub fn custom(mut self, key: impl Into<String>, value: Value) -> Self {
+        self.0.insert(key.into(), value);
+        self
+    }
+
+    /// Returns the value of the trait `key`.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.0.get(key)
+    }
+}
+
+impl From<Traits> for Value {
+    fn from(traits: Traits) -> Self {
+        Value::Object(traits.0)
+    }
+}
+
+impl TryFrom<Value> for Traits {
+    type Error = serde_json::Error;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        serde_json::from_value(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_traits() {
+        let traits = Traits::new()
+            .name("Acme")
+            .employees(42)
+            .created_at(OffsetDateTime::UNIX_EPOCH)
+            .unwrap()
+            .company(Company {
+                name: Some("Acme".to_owned()),
+                employee_count: Some(42),
+                ..Default::default()
+            })
+            .custom("name", json!("Acme Inc."));
+        assert_eq!(traits.get("name"), Some(&json!("Acme Inc.")));
+        assert_eq!(
+            Value::from(traits.clone()),
+            json!({
+                "name": "Acme Inc.",
+                "employees": 42,
+                "createdAt": "1970-01-01T00:00:00Z",
+                "company": { "name": "Acme", "employeeCount": 42 },
+            })
+        );
+        assert_eq!(
+            Traits::try_from(Value::from(traits.clone())).unwrap(),
+            traits
+        );
+        assert!(Traits::try_from(json!([])).is_err());
+
+        // the years beyond 9999 need the `large-dates` feature of time
+        if let Ok(far) = OffsetDateTime::UNIX_EPOCH.replace_year(10_000) {
+            assert!(Traits::new().birthday(far).is_err());
+        }
+    }
+}
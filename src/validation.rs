@@ -0,0 +1,463 @@
+//! Client-side validation of the messages.
+
+use std::collections::BTreeSet;
+
+use serde_json::Value;
+use thiserror::Error;
+
+use crate::extra::ExtraSchema;
+use crate::message::{BatchMessage, Message, User};
+use crate::schema::JsonType;
+use crate::tracking_plan::Enforcement;
+use crate::warning::{Warning, Warnings};
+
+/// The keys of the envelope of the messages, which are mistakes in their
+/// properties or traits, to reject with [`Validation::reserved_keys`].
+pub const RESERVED_KEYS: [&str; 12] = [
+    "anonymousId",
+    "context",
+    "event",
+    "integrations",
+    "messageId",
+    "originalTimestamp",
+    "receivedAt",
+    "sentAt",
+    "timestamp",
+    "type",
+    "userId",
+    "writeKey",
+];
+
+/// The checks applied to the pushed messages, which would otherwise only be
+/// rejected by Segment, see
+/// [`Batcher::set_validation`](crate::Batcher::set_validation).
+///
+/// ```
+/// use segment::{Batcher, Error, Validation, ValidationError};
+/// use segment::message::Track;
+///
+/// let mut batcher = Batcher::new(None);
+/// batcher.set_validation(Validation::default());
+///
+/// let result = batcher.push(Track {
+///     event: "Signed Up".to_owned(),
+///     ..Default::default()
+/// });
+/// assert!(matches!(result, Err(Error::InvalidMessage(ValidationError::EmptyAnonymousId))));
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Validation {
+    /// Reject the empty user IDs, anonymous IDs and event names, see
+    /// [`BatchMessage::validate`].
+    pub identifiers: bool,
+
+    /// Reject the properties (or traits) with one of these keys, by default
+    /// none, for instance the [`RESERVED_KEYS`].
+    pub reserved_keys: Vec<String>,
+
+    /// Whether the reserved keys are rejected, the default, or only reported
+    /// as [`Warning::ReservedKey`]s.
+    pub reserved_keys_enforcement: Enforcement,
+
+    /// Reject the event (or page, or screen) names longer than this, in
+    /// bytes.
+    pub max_event_name_len: Option<usize>,
+
+    /// Reject the messages larger than this once serialized, in bytes, by
+    /// default Segment's limit of 32KB. Unlike
+    /// [`Error::MessageTooLarge`](crate::Error::MessageTooLarge), the error
+    /// names the largest field.
+    pub max_message_bytes: Option<usize>,
+
+    /// Reject the custom top-level fields which don't match the schema, see
+    /// [`ExtraSchema`].
+    pub extra_fields: Option<ExtraSchema>,
+}
+
+impl Default for Validation {
+    fn default() -> Self {
+        Self {
+            identifiers: true,
+            reserved_keys: Vec::new(),
+            reserved_keys_enforcement: Enforcement::Enforce,
+            max_event_name_len: Some(200),
+            max_message_bytes: Some(crate::batcher::MAX_MESSAGE_SIZE),
+            extra_fields: None,
+        }
+    }
+}
+
+/// Why a message is invalid.
+#[derive(Error, Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ValidationError {
+    /// The `userId` is empty.
+    #[error("the user ID is empty")]
+    EmptyUserId,
+    /// The `anonymousId` is empty.
+    #[error("the anonymous ID is empty")]
+    EmptyAnonymousId,
+    /// The name of a `track` event is empty.
+    #[error("the event name is empty")]
+    EmptyEvent,
+    /// A property (or trait) has a reserved key.
+    #[error("`{field}` is a reserved key")]
+    ReservedKey {
+        /// The path of the property, like `properties.userId`.
+        field: String,
+    },
+    /// The event (or page, or screen) name is too long.
+    #[error("the name is {len} bytes long, more than {max}")]
+    NameTooLong {
+        /// The name of the field, `event` or `name`.
+        field: String,
+        /// The length of the name, in bytes.
+        len: usize,
+        /// The maximum length, in bytes.
+        max: usize,
+    },
+    /// The serialized message is too large.
+    #[error("the message is {size} bytes, more than {max}, `{field}` being the largest field")]
+    MessageTooLarge {
+        /// The path of the largest field, like `properties.payload`.
+        field: String,
+        /// The size of the serialized message, in bytes.
+        size: usize,
+        /// The maximum size, in bytes.
+        max: usize,
+    },
+    /// The event is not in the [`TrackingPlan`](crate::TrackingPlan).
+    #[error("`{event}` is not in the tracking plan")]
+    UnplannedEvent {
+        /// The name of the event.
+        event: String,
+    },
+    /// A property required by the [`TrackingPlan`](crate::TrackingPlan), or
+    /// a field required by the [`ExtraSchema`], is missing.
+    #[error("`{field}` is missing")]
+    MissingProperty {
+        /// The path of the property, like `properties.revenue`.
+        field: String,
+    },
+    /// A top-level field is not in the [`ExtraSchema`].
+    #[error("`{field}` is not an allowed field")]
+    UnknownField {
+        /// The name of the field.
+        field: String,
+    },
+    /// A property doesn't have a type allowed by the
+    /// [`TrackingPlan`](crate::TrackingPlan), or a field by the
+    /// [`ExtraSchema`].
+    #[error("`{field}` is {found}, expected {}", join(expected))]
+    InvalidType {
+        /// The path of the property, like `properties.revenue`.
+        field: String,
+        /// The allowed types.
+        expected: BTreeSet<JsonType>,
+        /// The type of the property.
+        found: JsonType,
+    },
+}
+
+fn join(types: &BTreeSet<JsonType>) -> String {
+    let types: Vec<_> = types.iter().map(JsonType::to_string).collect();
+    types.join(" or ")
+}
+
+impl ValidationError {
+    /// Returns the path of the offending field of the message.
+    pub fn field(&self) -> &str {
+        match self {
+            Self::EmptyUserId => "userId",
+            Self::EmptyAnonymousId => "anonymousId",
+            Self::EmptyEvent | Self::UnplannedEvent { .. } => "event",
+            Self::ReservedKey { field }
+            | Self::NameTooLong { field, .. }
+            | Self::MessageTooLarge { field, .. }
+            | Self::MissingProperty { field }
+            | Self::UnknownField { field }
+            | Self::InvalidType { field, .. } => field,
+        }
+    }
+}
+
+impl Validation {
+    /// Check `msg`, returns the first failed check.
+    pub(crate) fn check(
+        &self,
+        msg: &BatchMessage,
+        warnings: &Warnings,
+    ) -> Result<(), ValidationError> {
+        if self.identifiers {
+            msg.validate()?;
+        }
+
+        if let (Some(max), Some(name)) = (self.max_event_name_len, msg.event_name()) {
+            if name.len() > max {
+                let field = match msg {
+                    BatchMessage::Track(_) => "event",
+                    _ => "name",
+                };
+                return Err(ValidationError::NameTooLong {
+                    field: field.to_owned(),
+                    len: name.len(),
+                    max,
+                });
+            }
+        }
+
+        if let Some(Value::Object(properties)) = msg.properties() {
+            let parent = match msg {
+                BatchMessage::Identify(_) | BatchMessage::Group(_) => "traits",
+                _ => "properties",
+            };
+            let mut reserved = self
+                .reserved_keys
+                .iter()
+                .filter(|key| properties.contains_key(key.as_str()))
+                .map(|key| format!("{parent}.{key}"));
+            match self.reserved_keys_enforcement {
+                Enforcement::Enforce => {
+                    if let Some(field) = reserved.next() {
+                        return Err(ValidationError::ReservedKey { field });
+                    }
+                }
+                Enforcement::Warn => {
+                    for field in reserved {
+                        warnings.emit(Warning::ReservedKey { field });
+                    }
+                }
+            }
+        }
+
+        if let Some(schema) = &self.extra_fields {
+            schema.check(msg)?;
+        }
+
+        Ok(())
+    }
+
+    /// Check the size of `msg`, serialized in `size` bytes.
+    pub(crate) fn check_size(
+        &self,
+        msg: &BatchMessage,
+        size: usize,
+    ) -> Result<(), ValidationError> {
+        match self.max_message_bytes {
+            Some(max) if size > max => Err(ValidationError::MessageTooLarge {
+                field: largest_field(msg),
+                size,
+                max,
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Returns the path of the largest field of `msg`, looking into the objects
+/// at its top level.
+fn largest_field(msg: &BatchMessage) -> String {
+    fn largest(object: &serde_json::Map<String, Value>) -> Option<(&String, &Value)> {
+        object
+            .iter()
+            .max_by_key(|(_, value)| serde_json::to_vec(value).map_or(0, |json| json.len()))
+    }
+
+    let Ok(Value::Object(msg)) = serde_json::to_value(msg) else {
+        return String::new();
+    };
+    match largest(&msg) {
+        Some((key, Value::Object(object))) => match largest(object) {
+            Some((nested, _)) => format!("{key}.{nested}"),
+            None => key.clone(),
+        },
+        Some((key, _)) => key.clone(),
+        None => String::new(),
+    }
+}
+
+impl BatchMessage {
+    /// Check that the user IDs and anonymous IDs of the message, and the
+    /// event name of a `track` message, are not empty.
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        let user = match self {
+            Self::Identify(identify) => &identify.user,
+            Self::Track(track) => {
+                if track.event.is_empty() {
+                    return Err(ValidationError::EmptyEvent);
+                }
+                &track.user
+            }
+            Self::Page(page) => &page.user,
+            Self::Screen(screen) => &screen.user,
+            Self::Group(group) => &group.user,
+            Self::Alias(alias) => &alias.user,
+        };
+        match user {
+            User::UserId { user_id } | User::Both { user_id, .. } if user_id.is_empty() => {
+                Err(ValidationError::EmptyUserId)
+            }
+            User::AnonymousId { anonymous_id } | User::Both { anonymous_id, .. }
+                if anonymous_id.is_empty() =>
+            {
+                Err(ValidationError::EmptyAnonymousId)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Message {
+    /// Check the message, or every message of a batch, see
+    /// [`BatchMessage::validate`].
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        match self {
+            Message::Identify(identify) => BatchMessage::Identify(identify.clone()).validate(),
+            Message::Track(track) => BatchMessage::Track(track.clone()).validate(),
+            Message::Page(page) => BatchMessage::Page(page.clone()).validate(),
+            Message::Screen(screen) => BatchMessage::Screen(screen.clone()).validate(),
+            Message::Group(group) => BatchMessage::Group(group.clone()).validate(),
+            Message::Alias(alias) => BatchMessage::Alias(alias.clone()).validate(),
+            Message::Batch(batch) => batch.batch.iter().try_for_each(BatchMessage::validate),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::{Batch, Identify, Track};
+    use serde_json::json;
+    use std::sync::{Arc, Mutex};
+
+    fn track(user: User, event: &str) -> BatchMessage {
+        BatchMessage::Track(Track {
+            user,
+            event: event.to_owned(),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_validate() {
+        let user = || User::UserId {
+            user_id: "user".to_owned(),
+        };
+        assert_eq!(track(user(), "Signed Up").validate(), Ok(()));
+        assert_eq!(
+            track(user(), "").validate(),
+            Err(ValidationError::EmptyEvent)
+        );
+        let both = User::Both {
+            user_id: "user".to_owned(),
+            anonymous_id: String::new(),
+        };
+        assert_eq!(
+            track(both, "Signed Up").validate(),
+            Err(ValidationError::EmptyAnonymousId)
+        );
+
+        let batch = Message::Batch(Batch {
+            batch: vec![
+                track(user(), "Signed Up"),
+                BatchMessage::Identify(Identify {
+                    user: User::UserId {
+                        user_id: String::new(),
+                    },
+                    ..Default::default()
+                }),
+            ],
+            ..Default::default()
+        });
+        assert_eq!(batch.validate(), Err(ValidationError::EmptyUserId));
+    }
+
+    #[test]
+    fn test_check() {
+        let validation = Validation {
+            reserved_keys: RESERVED_KEYS.map(str::to_owned).to_vec(),
+            ..Default::default()
+        };
+        let warnings = Warnings::default();
+        let user = || User::UserId {
+            user_id: "user".to_owned(),
+        };
+        let track = |event: String, properties| {
+            BatchMessage::Track(Track {
+                user: user(),
+                event,
+                properties,
+                ..Default::default()
+            })
+        };
+
+        let msg = track("Signed Up".to_owned(), json!({ "plan": "pro" }));
+        assert_eq!(validation.check(&msg, &warnings), Ok(()));
+
+        let msg = track("a".repeat(201), json!({}));
+        assert_eq!(
+            validation.check(&msg, &warnings).unwrap_err().field(),
+            "event"
+        );
+
+        let msg = BatchMessage::Identify(Identify {
+            user: user(),
+            traits: json!({ "userId": "other" }),
+            ..Default::default()
+        });
+        assert_eq!(
+            validation.check(&msg, &warnings),
+            Err(ValidationError::ReservedKey {
+                field: "traits.userId".to_owned()
+            })
+        );
+
+        // opt-in
+        let msg = track("Signed Up".to_owned(), json!({ "type": "pro" }));
+        assert_eq!(Validation::default().check(&msg, &warnings), Ok(()));
+
+        let properties = json!({ "small": "a", "blob": "a".repeat(40_000) });
+        let msg = track("Signed Up".to_owned(), properties);
+        assert_eq!(validation.check(&msg, &warnings), Ok(()));
+        assert_eq!(
+            validation.check_size(&msg, 40_100).unwrap_err().field(),
+            "properties.blob"
+        );
+    }
+
+    #[test]
+    fn test_reserved_keys_warning() {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let mut warnings = Warnings::default();
+        let sink = received.clone();
+        warnings.set_handler(Arc::new(move |warning| {
+            sink.lock().unwrap().push(warning.clone())
+        }));
+
+        let validation = Validation {
+            reserved_keys: RESERVED_KEYS.map(str::to_owned).to_vec(),
+            reserved_keys_enforcement: Enforcement::Warn,
+            ..Default::default()
+        };
+        let msg = BatchMessage::Track(Track {
+            user: User::UserId {
+                user_id: "user".to_owned(),
+            },
+            event: "Signed Up".to_owned(),
+            properties: json!({ "event": "Signed Up", "timestamp": 0, "plan": "pro" }),
+            ..Default::default()
+        });
+        assert_eq!(validation.check(&msg, &warnings), Ok(()));
+        assert_eq!(
+            *received.lock().unwrap(),
+            vec![
+                Warning::ReservedKey {
+                    field: "properties.event".to_owned()
+                },
+                Warning::ReservedKey {
+                    field: "properties.timestamp".to_owned()
+                },
+            ]
+        );
+    }
+}
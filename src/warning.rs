@@ -0,0 +1,116 @@
+//! Non-fatal issues detected while processing messages.
+
+use std::fmt;
+use std::sync::Arc;
+
+use crate::hooks::catch_panic;
+use crate::logging;
+use crate::schema::SchemaChange;
+use crate::validation::ValidationError;
+
+/// A non-fatal issue detected while processing a message.
+///
+/// Warnings are always logged, with `tracing` or `log`, and are forwarded to
+/// the handler registered with
+/// [`Batcher::set_warning_handler`](crate::Batcher::set_warning_handler).
+#[derive(PartialEq, Eq, Debug, Clone)]
+#[non_exhaustive]
+pub enum Warning {
+    /// The properties of an event don't match the schema observed so far, see
+    /// [`SchemaTracker`](crate::SchemaTracker).
+    SchemaDrift {
+        /// The name of the event.
+        event: String,
+        /// The name of the property.
+        property: String,
+        /// How the property changed.
+        change: SchemaChange,
+    },
+
+    /// A hook was skipped for a message, its changes were reverted.
+    HookSkipped {
+        /// The name of the hook.
+        hook: String,
+        /// Why it was skipped.
+        reason: String,
+    },
+
+    /// A property (or trait) collided with another one once flattened, only
+    /// the value of the least nested one was kept, see
+    /// [`FlattenOptions`](crate::FlattenOptions).
+    FlattenCollision {
+        /// The flattened key of the property.
+        property: String,
+    },
+
+    /// A property (or trait) has a reserved key, and was sent anyway, see
+    /// [`Validation::reserved_keys_enforcement`](crate::Validation::reserved_keys_enforcement).
+    ReservedKey {
+        /// The path of the property, like `properties.userId`.
+        field: String,
+    },
+
+    /// An event violates the [`TrackingPlan`](crate::TrackingPlan), and was
+    /// sent anyway, see [`Enforcement::Warn`](crate::Enforcement::Warn).
+    PlanViolation {
+        /// The name of the event.
+        event: String,
+        /// The violation.
+        error: ValidationError,
+    },
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Warning::SchemaDrift {
+                event,
+                property,
+                change,
+            } => write!(f, "schema drift on `{event}.{property}`: {change}"),
+            Warning::HookSkipped { hook, reason } => write!(f, "hook `{hook}` skipped: {reason}"),
+            Warning::FlattenCollision { property } => {
+                write!(
+                    f,
+                    "`{property}` collided with another property once flattened"
+                )
+            }
+            Warning::ReservedKey { field } => write!(f, "`{field}` is a reserved key"),
+            Warning::PlanViolation { event, error } => {
+                write!(f, "tracking plan violation on `{event}`: {error}")
+            }
+        }
+    }
+}
+
+/// A callback receiving the [`Warning`]s.
+pub(crate) type WarningHandler = Arc<dyn Fn(&Warning) + Send + Sync>;
+
+/// Where the warnings are reported.
+#[derive(Clone, Default)]
+pub(crate) struct Warnings {
+    handler: Option<WarningHandler>,
+}
+
+impl Warnings {
+    pub(crate) fn set_handler(&mut self, handler: WarningHandler) {
+        self.handler = Some(handler);
+    }
+
+    pub(crate) fn emit(&self, warning: Warning) {
+        logging::warn!(%warning, "segment warning");
+        if let Some(handler) = &self.handler {
+            if let Err(reason) = catch_panic(|| handler(&warning)) {
+                logging::error!(reason, "the warning handler failed");
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Warnings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Warnings")
+            .field("handler", &self.handler.is_some())
+            .finish()
+    }
+}
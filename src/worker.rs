@@ -0,0 +1,664 @@
+//! A background task batching and sending the messages pushed into a queue.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::{mpsc, oneshot};
+
+use crate::{
+    auto_batcher::AutoBatcher,
+    client::Client,
+    errors::{Error, Result},
+    heartbeat::HealthProbes,
+    hooks,
+    integrations::Integrations,
+    logging,
+    message::BatchMessage,
+    queue::{OverflowPolicy, Queue},
+    runtime::{Instant, Interval},
+    stats::{Counters, Stats},
+};
+
+/// Configuration of a [`Worker`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WorkerConfig {
+    /// How many messages the queue holds before applying the overflow
+    /// policy.
+    pub capacity: usize,
+
+    /// What [`WorkerHandle::push`] does when the queue is full.
+    pub overflow_policy: OverflowPolicy,
+
+    /// How often the buffered messages are flushed, even if the batch is not
+    /// full. `None` only flushes full batches.
+    ///
+    /// The messages an interval flush could not deliver are buffered again,
+    /// to be sent by the next flush or returned by [`Worker::shutdown`].
+    pub flush_interval: Option<Duration>,
+
+    /// Shift the interval flushes by a random phase, so that the replicas
+    /// started at the same time don't all flush at the same instant. The
+    /// first flush then happens at a random time within the first interval.
+    pub flush_jitter: bool,
+
+    /// Send heartbeat events to the destinations periodically, see
+    /// [`HealthProbes`].
+    pub health_probes: Option<HealthProbes>,
+}
+
+impl Default for WorkerConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 10_000,
+            overflow_policy: OverflowPolicy::Block,
+            flush_interval: Some(Duration::from_secs(10)),
+            flush_jitter: false,
+            health_probes: None,
+        }
+    }
+}
+
+/// A background task consuming a bounded queue of messages and sending them
+/// with an [`AutoBatcher`].
+///
+/// Producers push into the queue through cloneable [`WorkerHandle`]s, so the
+/// production of the events is separated from their delivery latency. When
+/// the queue is full, [`WorkerHandle::push`] applies the configured
+/// [`OverflowPolicy`], waiting for some room by default.
+///
+/// ```
+/// use std::time::Duration;
+/// use segment::{AutoBatcher, Batcher, HttpClient, Worker, WorkerConfig};
+/// use segment::message::{Track, User};
+///
+/// # async fn run() -> segment::Result<()> {
+/// let client = HttpClient::default();
+/// let batcher = AutoBatcher::new(client, Batcher::new(None), "your_write_key".to_string());
+/// let worker = Worker::spawn(batcher, WorkerConfig::default());
+///
+/// let handle = worker.handle();
+/// handle.try_push(Track {
+///     user: User::UserId { user_id: String::from("user") },
+///     event: "Example".to_owned(),
+///     ..Default::default()
+/// })?;
+///
+/// // send what remains within 5 seconds and stop the worker
+/// let undelivered = worker.shutdown(Duration::from_secs(5)).await;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Dropping the worker without [Self::shutdown] closes the queue: the task
+/// sends the queued messages in the background within 5 seconds,
+/// logs the ones it could not deliver, and stops.
+#[derive(Debug)]
+pub struct Worker {
+    handle: WorkerHandle,
+    /// Sends the shutdown deadline to the task, dropped with the worker.
+    stop: Option<oneshot::Sender<Instant>>,
+    /// Receives the undelivered messages once the task stops, or the message
+    /// of its panic.
+    task: oneshot::Receiver<std::result::Result<Vec<BatchMessage>, String>>,
+}
+
+/// How long the task of a dropped [`Worker`] keeps sending the queued
+/// messages.
+const DROP_DEADLINE: Duration = Duration::from_secs(5);
+
+/// A cloneable handle pushing messages into the queue of a [`Worker`].
+#[derive(Clone, Debug)]
+pub struct WorkerHandle {
+    queue: Arc<Queue>,
+    overflow_policy: OverflowPolicy,
+    /// Whether the messages are timestamped when they are queued.
+    auto_timestamp: bool,
+    commands: mpsc::Sender<Command>,
+    stats: Arc<Counters>,
+}
+
+/// A request sent to the worker task.
+#[derive(Debug)]
+enum Command {
+    /// Flush, and send back the result.
+    Flush(oneshot::Sender<Result<()>>),
+    /// Send the next batches with this write key.
+    SetWriteKey(String),
+}
+
+impl Worker {
+    /// Spawn a worker sending the queued messages with `batcher`, on the
+    /// [`Runtime`](crate::Runtime) of `batcher`.
+    pub fn spawn<C>(batcher: AutoBatcher<C>, config: WorkerConfig) -> Self
+    where
+        C: Client + Clone + Send + Sync + 'static,
+    {
+        let counters = batcher.counters();
+        let auto_timestamp = batcher.batcher.auto_timestamp;
+        let queue = Arc::new(Queue::new(config.capacity, counters.clone()));
+        let (commands, command_receiver) = mpsc::channel(1);
+        let runtime = batcher.runtime.clone();
+        let flush_interval = config.flush_interval.map(|period| {
+            let start = match config.flush_jitter {
+                true => random_phase(period),
+                false => period,
+            };
+            Interval::new(runtime.clone(), Instant::now() + start, period)
+        });
+        let probes = config.health_probes.map(|probes| {
+            let interval = Interval::new(runtime.clone(), Instant::now(), probes.interval);
+            (probes.integrations, interval)
+        });
+        let (done, task) = oneshot::channel();
+        let (stop, stopped) = oneshot::channel();
+        let run = run(
+            batcher,
+            queue.clone(),
+            command_receiver,
+            stopped,
+            flush_interval,
+            probes,
+        );
+        let closing = queue.clone();
+        runtime.spawn(async move {
+            let result = hooks::catch_async_panic(run).await;
+            // the producers waiting for room are released if the task panicked
+            closing.close();
+            if let Err(Ok(undelivered)) = done.send(result) {
+                if !undelivered.is_empty() {
+                    logging::warn!(
+                        lost = undelivered.len(),
+                        "worker dropped with undelivered messages, call `shutdown` before dropping it"
+                    );
+                }
+            }
+        });
+
+        Self {
+            handle: WorkerHandle {
+                queue,
+                overflow_policy: config.overflow_policy,
+                auto_timestamp,
+                commands,
+                stats: counters,
+            },
+            stop: Some(stop),
+            task,
+        }
+    }
+
+    /// Returns a new handle to push messages into the queue.
+    pub fn handle(&self) -> WorkerHandle {
+        self.handle.clone()
+    }
+
+    /// Stop accepting new messages, send the queued ones within `deadline`
+    /// and wait for the worker to stop.
+    ///
+    /// Returns the messages that could not be delivered, see
+    /// [`AutoBatcher::shutdown`]. If the task panicked, or was dropped by its
+    /// [`Runtime`](crate::Runtime), its messages are lost and an error is
+    /// logged.
+    pub async fn shutdown(mut self, deadline: Duration) -> Vec<BatchMessage> {
+        let deadline = Instant::now() + deadline;
+        self.handle.queue.close();
+        if let Some(stop) = self.stop.take() {
+            let _ = stop.send(deadline);
+        }
+        match (&mut self.task).await {
+            Ok(Ok(undelivered)) => undelivered,
+            Ok(Err(reason)) => {
+                logging::error!(%reason, "worker task failed, its messages are lost");
+                Vec::new()
+            }
+            Err(_) => {
+                logging::error!("worker task dropped by its runtime, its messages are lost");
+                Vec::new()
+            }
+        }
+    }
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        // the task then stops, see `run`
+        self.handle.queue.close();
+    }
+}
+
+impl WorkerHandle {
+    /// Push a message into the queue, applying the [`OverflowPolicy`] if it
+    /// is full.
+    ///
+    /// The message is timestamped when it is queued, unless the batcher was
+    /// configured [without auto timestamp](crate::Batcher::without_auto_timestamp).
+    ///
+    /// Returns [`Error::Closed`] if the worker is stopped.
+    pub async fn push(&self, msg: impl Into<BatchMessage>) -> Result<()> {
+        self.queue
+            .push(self.stamp(msg.into()), self.overflow_policy)
+            .await
+    }
+
+    /// Push a message into the queue without waiting.
+    ///
+    /// Returns [`Error::QueueFull`] if the queue is full and the
+    /// [`OverflowPolicy`] would wait, and [`Error::Closed`] if the worker is
+    /// stopped.
+    pub fn try_push(&self, msg: impl Into<BatchMessage>) -> Result<()> {
+        self.queue
+            .try_push(self.stamp(msg.into()), self.overflow_policy)
+    }
+
+    fn stamp(&self, mut msg: BatchMessage) -> BatchMessage {
+        if self.auto_timestamp {
+            msg.stamp();
+        }
+        msg
+    }
+
+    /// Send the messages queued so far, and wait for the delivery.
+    pub async fn flush(&self) -> Result<()> {
+        let (ack, done) = oneshot::channel();
+        self.commands
+            .send(Command::Flush(ack))
+            .await
+            .map_err(|_| Error::Closed)?;
+        done.await.map_err(|_| Error::Closed)?
+    }
+
+    /// Send the batches flushed from now on with the write `key`, see
+    /// [`AutoBatcher::set_write_key`]. The messages still queued are sent with
+    /// the new key.
+    pub async fn set_write_key(&self, key: String) -> Result<()> {
+        self.commands
+            .send(Command::SetWriteKey(key))
+            .await
+            .map_err(|_| Error::Closed)
+    }
+
+    /// Returns the counters of the worker.
+    pub fn stats(&self) -> Stats {
+        self.stats.snapshot()
+    }
+}
+
+async fn run<C>(
+    mut batcher: AutoBatcher<C>,
+    queue: Arc<Queue>,
+    mut commands: mpsc::Receiver<Command>,
+    mut stopped: oneshot::Receiver<Instant>,
+    mut flush_interval: Option<Interval>,
+    probes: Option<(Integrations, Interval)>,
+) -> Vec<BatchMessage>
+where
+    C: Client + Clone + Send + Sync + 'static,
+{
+    let (destinations, mut probe_interval) = probes.unzip();
+    loop {
+        tokio::select! {
+            Some(msg) = queue.pop() => push(&mut batcher, msg).await,
+            Some(command) = commands.recv() => match command {
+                Command::Flush(ack) => {
+                    for msg in queue.drain() {
+                        push(&mut batcher, msg).await;
+                    }
+                    let _ = ack.send(batcher.flush().await);
+                }
+                Command::SetWriteKey(key) => batcher.set_write_key(key),
+            },
+            // sent by `Worker::shutdown`, or dropped with the worker
+            deadline = &mut stopped => {
+                let deadline = deadline.unwrap_or_else(|_| Instant::now() + DROP_DEADLINE);
+                return batcher.shutdown_at(queue.drain(), deadline).await;
+            }
+            _ = tick(&mut flush_interval) => {
+                // the errors are logged by the client, and reported to the
+                // delivery subscribers; the undelivered messages are sent
+                // again by the next flush, or returned at shutdown
+                if let Err(Error::Undelivered { batches }) = batcher.flush().await {
+                    let msgs = batches.into_iter().flat_map(|batch| batch.messages);
+                    if let Err(err) = batcher.requeue(msgs).await {
+                        logging::error!(
+                            err = &err as &(dyn std::error::Error + 'static),
+                            "dropping undelivered messages"
+                        );
+                    }
+                }
+            }
+            _ = tick(&mut probe_interval) => {
+                if let Some(destinations) = &destinations {
+                    batcher.probe_destinations(destinations).await;
+                }
+            }
+        }
+    }
+}
+
+async fn push<C>(batcher: &mut AutoBatcher<C>, msg: BatchMessage)
+where
+    C: Client + Clone + Send + Sync + 'static,
+{
+    if let Err(err) = batcher.push(msg).await {
+        logging::error!(
+            err = &err as &(dyn std::error::Error + 'static),
+            "dropping message"
+        );
+    }
+}
+
+/// Returns a random duration in `(0, period]`.
+fn random_phase(period: Duration) -> Duration {
+    // 53 bits, which fit in the mantissa of a f64, out of the 62 low bits of
+    // a v4 UUID which are all random: the version and variant bits are above
+    let bits = uuid::Uuid::new_v4().as_u128() as u64 & ((1 << 53) - 1);
+    let fraction = bits as f64 / (1u64 << 53) as f64;
+    period.mul_f64(1.0 - fraction)
+}
+
+async fn tick(interval: &mut Option<Interval>) {
+    match interval {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::Track;
+    use crate::testing::MockClient;
+    use crate::{Batcher, Message, RetryPolicy};
+    use std::sync::atomic::Ordering;
+    use time::OffsetDateTime;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_queue_and_shutdown() {
+        let client = MockClient::default();
+        let batcher = AutoBatcher::new(client.clone(), Batcher::new(None), "key".to_owned());
+        let config = WorkerConfig {
+            capacity: 2,
+            flush_interval: None,
+            ..Default::default()
+        };
+        let worker = Worker::spawn(batcher, config);
+        let handle = worker.handle();
+
+        handle.try_push(Track::default()).unwrap();
+        handle.try_push(Track::default()).unwrap();
+        assert!(matches!(
+            handle.try_push(Track::default()),
+            Err(Error::QueueFull)
+        ));
+        handle.push(Track::default()).await.unwrap();
+
+        handle.flush().await.unwrap();
+        assert_eq!(client.sent_count(), 3);
+
+        handle.push(Track::default()).await.unwrap();
+        assert!(worker.shutdown(Duration::from_secs(1)).await.is_empty());
+        assert_eq!(client.sent_count(), 4);
+        assert_eq!(handle.stats().pushed, 4);
+        assert!(matches!(
+            handle.try_push(Track::default()),
+            Err(Error::Closed)
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_interval_flush_failure() {
+        let client = MockClient::default();
+        client.failures.store(usize::MAX, Ordering::SeqCst);
+        let mut batcher = AutoBatcher::new(client.clone(), Batcher::new(None), "key".to_owned());
+        batcher.set_retry_policy(RetryPolicy::never());
+        let config = WorkerConfig {
+            flush_interval: Some(Duration::from_secs(1)),
+            ..Default::default()
+        };
+        let worker = Worker::spawn(batcher, config);
+        let handle = worker.handle();
+        handle.push(Track::default()).await.unwrap();
+        handle.push(Track::default()).await.unwrap();
+
+        // a few interval flushes fail
+        tokio::time::sleep(Duration::from_millis(2500)).await;
+        assert_eq!(client.sent_count(), 0);
+
+        let undelivered = worker.shutdown(Duration::from_secs(1)).await;
+        assert_eq!(undelivered.len(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_dropped_worker() {
+        let client = MockClient::default();
+        let batcher = AutoBatcher::new(client.clone(), Batcher::new(None), "key".to_owned());
+        let config = WorkerConfig {
+            capacity: 1,
+            flush_interval: None,
+            ..Default::default()
+        };
+        let worker = Worker::spawn(batcher, config);
+        let handle = worker.handle();
+        handle.push(Track::default()).await.unwrap();
+        let blocked = tokio::spawn({
+            let handle = handle.clone();
+            async move { handle.push(Track::default()).await }
+        });
+
+        drop(worker);
+        // the blocked push is released, and the queued messages are sent
+        assert!(matches!(
+            blocked.await.unwrap(),
+            Ok(()) | Err(Error::Closed)
+        ));
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(client.sent_count() >= 1);
+        assert!(matches!(
+            handle.try_push(Track::default()),
+            Err(Error::Closed)
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_drop_when_full() {
+        let client = MockClient::default();
+        let batcher = AutoBatcher::new(client.clone(), Batcher::new(None), "key".to_owned());
+        let config = WorkerConfig {
+            capacity: 1,
+            overflow_policy: OverflowPolicy::DropNewest,
+            flush_interval: None,
+            flush_jitter: false,
+            health_probes: None,
+        };
+        let worker = Worker::spawn(batcher, config);
+        let handle = worker.handle();
+
+        // the worker doesn't get a chance to pop from the queue
+        handle.push(Track::default()).await.unwrap();
+        handle.push(Track::default()).await.unwrap();
+        handle.try_push(Track::default()).unwrap();
+        assert_eq!(handle.stats().dropped, 2);
+
+        assert!(worker.shutdown(Duration::from_secs(1)).await.is_empty());
+        assert_eq!(client.sent_count(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_timestamp_when_queued() {
+        let client = MockClient::default();
+        let batcher = AutoBatcher::new(client.clone(), Batcher::new(None), "key".to_owned());
+        let worker = Worker::spawn(batcher, WorkerConfig::default());
+
+        worker.handle().try_push(Track::default()).unwrap();
+        // the worker didn't get a chance to pop the message yet
+        let queued = OffsetDateTime::now_utc();
+        assert!(worker.shutdown(Duration::from_secs(1)).await.is_empty());
+
+        let Message::Batch(batch) = client.sent.lock().unwrap()[0].clone() else {
+            panic!("expected a batch");
+        };
+        let BatchMessage::Track(track) = &batch.batch[0] else {
+            panic!("expected a track");
+        };
+        assert!(track.timestamp.unwrap() <= queued);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_flush_interval() {
+        let client = MockClient::default();
+        let batcher = AutoBatcher::new(client.clone(), Batcher::new(None), "key".to_owned());
+        let config = WorkerConfig {
+            capacity: 10,
+            flush_interval: Some(Duration::from_secs(1)),
+            ..Default::default()
+        };
+        let worker = Worker::spawn(batcher, config);
+
+        worker.handle().push(Track::default()).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert_eq!(client.sent_count(), 0);
+        tokio::time::sleep(Duration::from_millis(600)).await;
+        assert_eq!(client.sent_count(), 1);
+
+        assert!(worker.shutdown(Duration::from_secs(1)).await.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_flush_jitter() {
+        let period = Duration::from_secs(1);
+        let phases: Vec<_> = (0..200).map(|_| random_phase(period)).collect();
+        assert!(phases
+            .iter()
+            .all(|phase| *phase > Duration::ZERO && *phase <= period));
+        // spread over the whole period
+        assert!(phases.iter().any(|phase| *phase < period / 4));
+        assert!(phases.iter().any(|phase| *phase > period * 3 / 4));
+
+        let client = MockClient::default();
+        let batcher = AutoBatcher::new(client.clone(), Batcher::new(None), "key".to_owned());
+        let config = WorkerConfig {
+            capacity: 10,
+            flush_interval: Some(period),
+            flush_jitter: true,
+            ..Default::default()
+        };
+        let worker = Worker::spawn(batcher, config);
+
+        worker.handle().push(Track::default()).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        assert_eq!(client.sent_count(), 1);
+
+        assert!(worker.shutdown(Duration::from_secs(1)).await.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_health_probes() {
+        let client = MockClient::default();
+        let batcher = AutoBatcher::new(client.clone(), Batcher::new(None), "key".to_owned());
+        let config = WorkerConfig {
+            flush_interval: None,
+            health_probes: Some(HealthProbes {
+                integrations: Integrations::new().enable("Amplitude"),
+                interval: Duration::from_secs(60),
+            }),
+            ..Default::default()
+        };
+        let worker = Worker::spawn(batcher, config);
+
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert_eq!(client.sent_count(), 1);
+        let first = worker.handle().stats().heartbeats["Amplitude"];
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        assert_eq!(client.sent_count(), 2);
+        assert!(worker.handle().stats().heartbeats["Amplitude"] > first);
+
+        assert!(worker.shutdown(Duration::from_secs(1)).await.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_set_write_key() {
+        let client = MockClient::default();
+        let batcher = AutoBatcher::new(client.clone(), Batcher::new(None), "old".to_owned());
+        let worker = Worker::spawn(batcher, WorkerConfig::default());
+        let handle = worker.handle();
+
+        handle.push(Track::default()).await.unwrap();
+        handle.flush().await.unwrap();
+        handle.set_write_key("new".to_owned()).await.unwrap();
+        handle.push(Track::default()).await.unwrap();
+        handle.flush().await.unwrap();
+        assert_eq!(*client.keys.lock().unwrap(), ["old", "new"]);
+
+        assert!(worker.shutdown(Duration::from_secs(1)).await.is_empty());
+        assert!(matches!(
+            handle.set_write_key("newer".to_owned()).await,
+            Err(Error::Closed)
+        ));
+    }
+
+    #[test]
+    fn test_bounded_timer_threads() {
+        use crate::runtime::{block_on, Threads};
+        use crate::Runtime;
+        use std::future::Future;
+        use std::pin::Pin;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        /// Counts the timers of `Threads`, each waiting on a thread of its own.
+        #[derive(Clone, Default)]
+        struct CountingThreads(Arc<AtomicUsize>);
+
+        impl Runtime for CountingThreads {
+            fn spawn(&self, task: Pin<Box<dyn Future<Output = ()> + Send>>) {
+                Threads.spawn(task);
+            }
+
+            fn sleep(&self, duration: Duration) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+                self.0.fetch_add(1, Ordering::SeqCst);
+                Threads.sleep(duration)
+            }
+
+            fn can_spawn(&self) -> bool {
+                false
+            }
+        }
+
+        /// Counts the messages sent, without the timers of tokio.
+        #[derive(Clone, Default)]
+        struct Sent(Arc<AtomicUsize>);
+
+        impl Client for Sent {
+            async fn send(&self, _: String, msg: Message) -> Result<()> {
+                if let Message::Batch(batch) = msg {
+                    self.0.fetch_add(batch.batch.len(), Ordering::SeqCst);
+                }
+                Ok(())
+            }
+        }
+
+        let runtime = CountingThreads::default();
+        let client = Sent::default();
+        let mut batcher = AutoBatcher::new(client.clone(), Batcher::new(None), "key".to_owned());
+        batcher.set_runtime(runtime.clone());
+        let config = WorkerConfig {
+            flush_interval: Some(Duration::from_secs(60)),
+            ..Default::default()
+        };
+        let worker = Worker::spawn(batcher, config);
+        let handle = worker.handle();
+
+        block_on(async {
+            // the worker waits on its timer between each message
+            for _ in 0..1_000 {
+                handle.push(Track::default()).await.unwrap();
+                handle.flush().await.unwrap();
+            }
+            assert!(worker.shutdown(Duration::from_secs(1)).await.is_empty());
+        });
+        assert_eq!(client.0.load(Ordering::SeqCst), 1_000);
+        // the timer of the flush interval, and the one of the shutdown
+        assert!(runtime.0.load(Ordering::SeqCst) <= 2);
+    }
+}